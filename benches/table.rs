@@ -0,0 +1,54 @@
+//! # Table Benchmark
+//!
+//! Time table-heavy scripts, which are dominated by hashing and comparing
+//! string keys. Run with `cargo bench --bench table`.
+
+use rua::{parse::ParseProto, utils::New, vm::ExeState};
+use std::time::Instant;
+
+const SCRIPTS: [(&str, &str); 3] = [
+  (
+    "fields",
+    r#"
+local t = { x = 1, y = 2, name = "point", description = "a point in the plane" }
+for i = 1, 1000000 do
+  t.x = t.x + t.y
+  t.description = t.name
+end
+"#,
+  ),
+  (
+    "globals",
+    r#"
+count = 0
+step = 1
+for i = 1, 1000000 do
+  count = count + step
+end
+"#,
+  ),
+  (
+    "string keys",
+    r#"
+local t = {}
+for i = 1, 100000 do
+  t["key" .. i] = i
+end
+local sum = 0
+for round = 1, 5 do
+  for i = 1, 100000 do
+    sum = sum + t["key" .. i]
+  end
+end
+"#,
+  ),
+];
+
+fn main() {
+  for (name, script) in SCRIPTS {
+    let proto = ParseProto::load(script.as_bytes(), &format!("={name}"));
+    let start = Instant::now();
+    ExeState::new().execute(&proto).unwrap();
+    println!("{name:<12} {:>8.2?}", start.elapsed());
+  }
+}
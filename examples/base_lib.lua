@@ -0,0 +1,35 @@
+print(type(nil), type(1), type("s"), type({}), type(print), type(type))
+print(_VERSION)
+
+print(tonumber("10"), tonumber("  0x1F  "), tonumber("1e2"), tonumber("abc"))
+print(tonumber(42), tonumber({}), tonumber("9223372036854775808"))
+print(tonumber("ff", 16), tonumber("-101", 2), tonumber("zz", 36), tonumber("8", 8))
+print(pcall(tonumber, "10", 99))
+print(pcall(tonumber, 10, 16))
+
+print(assert(1, "unused", 3))
+print(pcall(assert, false))
+print(pcall(assert, nil, "custom message"))
+local ok, err = pcall(assert, false, { code = 1 })
+print(err.code)
+
+print(select("#"))
+print(select("#", 1, nil, 3))
+print(select(2, "a", "b", "c"))
+print(select(-1, "a", "b", "c"))
+print(pcall(select, -5, "a"))
+
+local t = setmetatable({}, {
+  __index = function() return "meta" end,
+  __newindex = function() error("no") end,
+})
+print(t.x, rawget(t, "x"))
+rawset(t, "x", 1)
+print(t.x, rawlen({ 1, 2 }), rawlen("abc"))
+print(rawequal(t, t), rawequal(1, 1.0), rawequal("a", "b"))
+print(pcall(rawlen, 1))
+
+warn("@on")
+warn("warning ", "on")
+warn("@off")
+warn("not shown")
@@ -0,0 +1,135 @@
+-- compile-time constants are folded
+local N <const> = 10
+local NAME <const> = "rua"
+local t = { [N * 2] = NAME }
+assert(t[20] == "rua")
+
+-- read-only locals with non-constant values
+local tab <const> = { 1, 2, 3 }
+tab[1] = 100
+assert(tab[1] == 100)
+
+-- the names of the values closed, and the errors they are closed with
+local log = {}
+local function closable(name)
+  return setmetatable({}, {
+    __close = function(_, err)
+      log[#log + 1] = name
+      if err ~= nil then
+        log[#log + 1] = err
+      end
+    end,
+  })
+end
+
+-- check and reset the log
+local function closed(...)
+  local expected = { ... }
+  assert(#log == #expected, table.concat(log, ", "))
+  for i, name in ipairs(expected) do
+    assert(log[i] == name, table.concat(log, ", "))
+  end
+  log = {}
+end
+
+-- closed at the end of the block, in reverse order
+do
+  local a <close> = closable("a")
+  local b <close> = closable("b")
+  local none <close> = nil
+  closed()
+end
+closed("b", "a")
+
+-- on break
+for i = 1, 3 do
+  local c <close> = closable("loop " .. i)
+  if i == 2 then
+    break
+  end
+end
+closed("loop 1", "loop 2")
+
+-- on goto out of the loop
+for i = 1, 3 do
+  local c <close> = closable("goto " .. i)
+  if i == 2 then
+    goto done
+  end
+end
+::done::
+closed("goto 1", "goto 2")
+
+-- on goto out of nested blocks, in reverse order
+do
+  local outer <close> = closable("outer")
+  do
+    local inner <close> = closable("inner")
+    goto out
+  end
+end
+::out::
+closed("inner", "outer")
+
+-- on backward goto, once per round
+do
+  local round = 0
+  ::again::
+  local r <close> = closable("round " .. round)
+  round = round + 1
+  if round < 3 then
+    goto again
+  end
+end
+closed("round 0", "round 1", "round 2")
+
+-- captured locals are fresh after goto
+local getters = {}
+for i = 1, 3 do
+  local v = i * 10
+  getters[i] = function()
+    return v
+  end
+  if i == 2 then
+    goto captured
+  end
+end
+::captured::
+assert(getters[1]() == 10 and getters[2]() == 20)
+
+-- on return, after the results are evaluated
+local function f()
+  local d <close> = closable("return")
+  closed()
+  return "result"
+end
+assert(f() == "result")
+closed("return")
+
+-- on error, with the error object
+local ok, err = pcall(function()
+  local e <close> = closable("error")
+  error("boom", 0)
+end)
+assert(not ok and err == "boom")
+closed("error", "boom")
+
+-- an error in `__close` replaces the original one
+ok, err = pcall(function()
+  local g <close> = setmetatable({}, { __close = function() error("in close", 0) end })
+  error("original", 0)
+end)
+assert(not ok and err == "in close")
+
+-- non-closable values are errors
+ok, err = pcall(function()
+  local h <close> = {}
+end)
+assert(not ok and err:find(":%d+: variable 'h' got a non%-closable value$"))
+
+-- constants are captured by value
+local K <const> = 42
+local function get_k()
+  return K
+end
+assert(get_k() == 42)
@@ -0,0 +1,40 @@
+-- closures share upvalues, and each loop iteration has fresh locals
+local function counter()
+  local n = 0
+  return function()
+    n = n + 1
+    return n
+  end
+end
+local c1, c2 = counter(), counter()
+c1()
+c1()
+print(c1())
+print(c2())
+
+local fns = {}
+for i = 1, 3 do
+  fns[i] = function() return i end
+end
+print(fns[1]() + fns[2]() * 10 + fns[3]() * 100)
+
+local function fact(n)
+  if n <= 1 then return 1 end
+  return n * fact(n - 1)
+end
+print(fact(10))
+
+-- globals live in `_G`, accessed through `_ENV`
+x = 10
+print(_G.x)
+_G.y = 20
+print(y)
+print(_G._G == _G)
+
+local function sandbox()
+  local _ENV = { z = 5 }
+  w = z + 1
+  return _ENV
+end
+print(sandbox().w)
+print(w)
@@ -0,0 +1,129 @@
+-- information about the running functions
+local function f(a, b, ...)
+  local c = a + b
+  local info = debug.getinfo(1, "nSlutf")
+  assert(info.name == "f" and info.namewhat == "local" and info.what == "Lua")
+  assert(info.short_src == "examples/debug.lua" and info.currentline == 4)
+  assert(info.linedefined == 2 and info.lastlinedefined == 21 and info.nups == 2)
+  assert(info.nparams == 2 and info.isvararg)
+  assert(not info.istailcall and info.func == f)
+  -- locals, temporaries and varargs
+  for i, name in ipairs({ "a", "b", "c" }) do
+    local n, v = debug.getlocal(1, i)
+    assert(n == name and v == i)
+  end
+  local n, v = debug.getlocal(1, -1)
+  assert(n == "(vararg)" and v == "x")
+  assert(debug.getlocal(1, -3) == nil)
+  assert(debug.setlocal(1, 3, 100) == "c" and c == 100)
+  assert(debug.setlocal(1, -2, "z") == "(vararg)" and select(2, ...) == "z")
+  return c
+end
+assert(f(1, 2, "x", "y") == 100)
+assert(debug.getlocal(f, 1) == "a" and debug.getlocal(f, 2) == "b")
+assert(debug.getlocal(f, 3) == nil)
+
+function global_fn()
+  local info = debug.getinfo(1, "n")
+  return info.name, info.namewhat
+end
+local name, namewhat = global_fn()
+assert(name == "global_fn" and namewhat == "global")
+local obj = {}
+function obj:method()
+  local info = debug.getinfo(2, "Sl")
+  return info.what, info.currentline
+end
+local what, line = obj:method()
+assert(what == "main" and line == 37)
+
+local info = debug.getinfo(print)
+assert(info.what == "C" and info.short_src == "[C]" and info.source == "=[C]")
+assert(info.currentline == -1 and info.nparams == 0 and info.isvararg)
+assert(debug.getinfo(100) == nil)
+local lines = debug.getinfo(f, "L").activelines
+assert(lines[3] and lines[4] and not lines[1] and not lines[2])
+
+local function tail()
+  return debug.getinfo(1, "t").istailcall
+end
+local function caller()
+  return tail()
+end
+assert(caller())
+
+local function fails(msg, ...)
+  local ok, err = pcall(...)
+  assert(not ok and err == msg, err)
+end
+fails("bad argument #2 to 'debug.getinfo' (invalid option '>')", debug.getinfo, 1, ">S")
+fails("bad argument #2 to 'debug.getinfo' (invalid option)", debug.getinfo, 1, "X")
+fails("bad argument #1 to 'debug.getlocal' (level out of range)", debug.getlocal, 100, 1)
+
+-- upvalues
+local up1, up2 = 1, 2
+local function g() return up1 + up2 end
+local function h() return up1 end
+local n1, v1 = debug.getupvalue(g, 1)
+local n2, v2 = debug.getupvalue(g, 2)
+assert(n1 == "up1" and v1 == 1 and n2 == "up2" and v2 == 2)
+assert(select("#", debug.getupvalue(g, 3)) == 0)
+assert(debug.setupvalue(g, 2, 20) == "up2" and up2 == 20 and g() == 21)
+assert(debug.upvalueid(g, 1) == debug.upvalueid(h, 1))
+assert(debug.upvalueid(g, 2) ~= debug.upvalueid(h, 1) and debug.upvalueid(g, 3) == nil)
+assert(type(debug.upvalueid(g, 1)) == "userdata")
+debug.upvaluejoin(h, 1, g, 2)
+assert(h() == 20 and debug.upvalueid(g, 2) == debug.upvalueid(h, 1))
+fails("bad argument #4 to 'debug.upvaluejoin' (invalid upvalue index)",
+  debug.upvaluejoin, h, 1, print, 1)
+fails("bad argument #2 to 'debug.upvaluejoin' (invalid upvalue index)",
+  debug.upvaluejoin, h, 3, g, 1)
+
+-- metatables, bypassing `__metatable`
+local locked = setmetatable({}, { __metatable = "locked" })
+assert(getmetatable(locked) == "locked" and type(debug.getmetatable(locked)) == "table")
+assert(debug.setmetatable(locked, nil) == locked and getmetatable(locked) == nil)
+
+-- the other types share a metatable per type
+assert(debug.setmetatable(1, { __index = math }) == 1)
+assert((2.5).floor(2.5) == 2 and (-3).abs(-3) == 3)
+assert(getmetatable(1.5) == getmetatable(0) and getmetatable("") ~= getmetatable(0))
+debug.setmetatable(true, { __call = function(b, x) return b and x end })
+assert((true)(1) == 1 and (false)(1) == false)
+debug.setmetatable(nil, { __len = function() return 0 end })
+assert(#nil == 0)
+debug.setmetatable(print, { __index = { kind = "function" } })
+assert(print.kind == "function" and f.kind == "function")
+for _, v in ipairs({ 1, true, print }) do
+  debug.setmetatable(v, nil)
+  assert(getmetatable(v) == nil)
+end
+debug.setmetatable(nil, nil)
+assert(not pcall(function() return #nil end))
+
+local registry = debug.getregistry()
+assert(registry[2] == _G and registry._LOADED == package.loaded)
+assert(registry["FILE*"] == debug.getmetatable(io.stdout))
+
+-- hooks
+assert(debug.gethook() == nil)
+local events = {}
+debug.sethook(function(event, line)
+  events[#events + 1] = line and event .. ":" .. line or event
+end, "crl")
+local x = 1
+x = x + math.abs(-1)
+debug.sethook()
+assert(table.concat(events, " ") == "return line:114 line:115 call return line:116 call")
+
+local count = 0
+debug.sethook(function() count = count + 1 end, "", 10)
+local _, mask, n = debug.gethook()
+assert(mask == "" and n == 10)
+for _ = 1, 100 do end
+debug.sethook()
+assert(count > 0 and debug.gethook() == nil)
+
+assert(debug.traceback("message", 1) == [[message
+stack traceback:
+	examples/debug.lua:127: in main chunk]])
@@ -0,0 +1,83 @@
+-- finalizers run when the objects are collected
+local log = {}
+local mt = { __gc = function(o) log[#log + 1] = o.name end }
+local a = setmetatable({ name = "a" }, mt)
+local b = setmetatable({ name = "b" }, mt)
+a, b = nil, nil
+collectgarbage()
+assert(#log == 2 and log[1] == "b" and log[2] == "a")
+
+-- in reverse order of marking
+log = {}
+for i = 1, 3 do
+  setmetatable({ name = i }, mt)
+end
+collectgarbage()
+assert(#log == 3 and log[1] == 3 and log[2] == 2 and log[3] == 1)
+
+-- only marked when `__gc` is present at `setmetatable`
+log = {}
+local late = {}
+local t = setmetatable({ name = "not marked" }, late)
+late.__gc = mt.__gc
+t = nil
+collectgarbage()
+assert(#log == 0)
+
+-- resurrection: the object and what it refers to are alive again
+saved = nil
+local r = setmetatable({ name = "resurrected", data = { 42 } }, {
+  __gc = function(o) saved = o end,
+})
+r = nil
+collectgarbage()
+assert(saved.data[1] == 42)
+-- it's not finalized again, unless marked again
+local finalized = 0
+getmetatable(saved).__gc = function() finalized = finalized + 1 end
+saved = nil
+collectgarbage()
+assert(finalized == 0)
+
+-- removed from weak values before finalized, but not from weak keys
+local wv = setmetatable({}, { __mode = "v" })
+local wk = setmetatable({}, { __mode = "k" })
+local seen
+local obj = setmetatable({}, {
+  __gc = function(o) seen = { wv[1], wk[o] } end,
+})
+wv[1] = obj
+wk[obj] = "key"
+obj = nil
+collectgarbage()
+assert(seen[1] == nil and seen[2] == "key")
+
+-- errors in finalizers are ignored
+setmetatable({}, { __gc = function() error("ignored") end })
+collectgarbage()
+
+-- userdata are finalized too, given the metatable at creation or later
+finalized = {}
+local function files()
+  local f = io.tmpfile()
+  debug.setmetatable(f, { __gc = function() finalized[#finalized + 1] = "tmpfile" end })
+end
+files()
+collectgarbage()
+assert(finalized[1] == "tmpfile")
+local name = os.tmpname()
+local function write()
+  io.open(name, "w"):write("flushed by __gc")
+end
+write()
+collectgarbage()
+assert(io.open(name):read("a") == "flushed by __gc")
+os.remove(name)
+
+-- pending finalizers run when the state is closed
+keep = setmetatable({ name = "closed" }, {
+  __gc = function(o)
+    io.write(o.name, "\n")
+  end,
+})
+print("end")
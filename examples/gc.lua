@@ -0,0 +1,127 @@
+-- cyclic garbage is collected
+collectgarbage()
+local before = collectgarbage("count")
+for i = 1, 10000 do
+  local a, b = {}, {}
+  a.other = b
+  b.other = a
+  local function f() return f end
+end
+collectgarbage()
+assert(collectgarbage("count") - before < 10)
+
+-- so are cycles through userdata metatables and native closure upvalues
+local weak = setmetatable({}, { __mode = "k" })
+local function userdata_cycles()
+  local f = io.tmpfile()
+  debug.setmetatable(f, { self = f })
+  weak[f] = "userdata"
+  local g = io.tmpfile()
+  local lines = g:lines()
+  debug.setmetatable(g, { lines = lines })
+  weak[lines] = "native closure"
+end
+userdata_cycles()
+-- files are finalized first, and stay weak keys until collected again
+collectgarbage()
+collectgarbage()
+assert(next(weak) == nil)
+
+-- reachable cycles survive
+local keep = {}
+keep.self = keep
+keep.data = "still here"
+collectgarbage()
+assert(keep.self.data == "still here")
+
+-- the collector runs automatically, in both modes
+assert(collectgarbage("isrunning"))
+assert(collectgarbage("generational") == "incremental")
+local base = collectgarbage("count")
+for i = 1, 50000 do
+  local a = {}
+  a.self = a
+end
+assert(collectgarbage("count") - base < 1000)
+assert(collectgarbage("incremental") == "generational")
+base = collectgarbage("count")
+for i = 1, 50000 do
+  local a = {}
+  a.self = a
+end
+assert(collectgarbage("count") - base < 1000)
+
+-- an incremental cycle is spread over steps, while the program moves the
+-- references around: a part of a list cut off while marking is kept alive
+local N = 10000
+local head = {}
+do
+  local node = head
+  for i = 1, N do
+    node.next = { i = i }
+    node = node.next
+    node.self = node
+  end
+end
+local function cut()
+  local node = head
+  for _ = 1, N // 2 do
+    node = node.next
+  end
+  local rest = node.next
+  node.next = nil
+  return rest
+end
+local function check(rest)
+  local node = head
+  while node.next do
+    node = node.next
+  end
+  node.next = rest
+  local count = 0
+  node = head.next
+  while node do
+    count = count + 1
+    assert(node.self == node and node.i == count)
+    node = node.next
+  end
+  assert(count == N)
+end
+collectgarbage()
+local steps = 1
+while not collectgarbage("step") do
+  steps = steps + 1
+end
+assert(steps > 10)
+for at = steps // 2, steps, 8 do
+  collectgarbage()
+  local rest
+  for step = 1, math.huge do
+    if step == at then
+      rest = cut()
+    end
+    if collectgarbage("step") then
+      break
+    end
+  end
+  check(rest)
+end
+head = nil
+
+-- and it finds the cyclic garbage by itself
+collectgarbage()
+base = collectgarbage("count")
+for i = 1, 10000 do
+  local a = {}
+  a.self = a
+end
+repeat until collectgarbage("step")
+repeat until collectgarbage("step")
+assert(collectgarbage("count") - base < 100)
+
+collectgarbage("stop")
+assert(not collectgarbage("isrunning"))
+collectgarbage("restart")
+assert(collectgarbage("isrunning"))
+assert(type(collectgarbage("step")) == "boolean")
+print("ok")
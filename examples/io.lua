@@ -0,0 +1,143 @@
+local name = "target/io_example.txt"
+
+-- write and read back
+local f = assert(io.open(name, "w"))
+assert(io.type(f) == "file")
+assert(f:write("first line\n", 42, " ", 3.5, " ", 1.0, "\n") == f)
+f:write("0x1F -7 1e2 .5 nope\n", "last line without newline")
+assert(f:close() == true)
+assert(io.type(f) == "closed file")
+assert(tostring(f) == "file (closed)")
+print(pcall(f.write, f, "x"))
+
+f = assert(io.open(name))
+assert(tostring(f):find("^file %(0x"))
+assert(f:read() == "first line")
+assert(f:read("L") == "42 3.5 1\n")
+local a, b, c, d = f:read("n", "n", "n", "n")
+print(a, b, c, d, math.type(a), math.type(c))
+-- a failed numeral consumes the spaces only
+assert(f:read("n") == nil)
+assert(f:read(4) == "nope")
+assert(f:read(0) == "")
+assert(f:read("l") == "")
+assert(f:read("a") == "last line without newline")
+assert(f:read("a") == "")
+assert(f:read("l") == nil and f:read(0) == nil and f:read(1) == nil)
+-- the `*` of Lua 5.2 still works
+assert(f:seek("set") == 0)
+assert(f:read("*l") == "first line")
+assert(f:seek("end") == 65)
+print(pcall(f.read, f, "x"))
+f:close()
+
+-- lines
+local lines = {}
+for line in io.lines(name) do
+  lines[#lines + 1] = line
+end
+assert(#lines == 4 and lines[4] == "last line without newline")
+for l1, n in io.lines(name, "L", 1) do
+  assert(l1 == "first line\n" and n == "4")
+  break
+end
+f = assert(io.open(name))
+local count = 0
+for line in f:lines() do
+  count = count + 1
+end
+assert(count == 4 and io.type(f) == "file")
+f:close()
+print(pcall(io.lines, "target/no_such_file"))
+
+-- open failures follow the nil, message, errno convention
+print(io.open("target/no_such_dir/x"))
+print(pcall(io.open, name, "rw"))
+assert(io.open(name, "r+b")):close()
+
+-- append and update modes
+f = assert(io.open(name, "w+"))
+f:write("hello")
+f:seek("set")
+assert(f:read("a") == "hello")
+f:write(" world")
+f:seek("set", 0)
+assert(f:read("a") == "hello world")
+f:close()
+f = assert(io.open(name, "a"))
+f:write("!")
+f:close()
+f = assert(io.open(name, "a+"))
+f:write("?")
+f:seek("set")
+assert(f:read("a") == "hello world!?")
+f:close()
+
+-- reading a file opened for writing fails
+f = assert(io.open(name, "w"))
+print(f:read())
+print(f:seek("set", -1))
+f:close()
+
+-- buffering
+f = assert(io.open(name, "w"))
+assert(f:setvbuf("no") and f:setvbuf("full", 1024) and f:setvbuf("line"))
+f:write("buffered")
+assert(f:flush())
+assert(io.open(name):read("a") == "buffered")
+f:close()
+
+-- default input and output
+assert(io.input() == io.stdin and io.output() == io.stdout)
+io.output(name)
+io.write("one\n", 2, "\n")
+assert(io.output() ~= io.stdout)
+io.close()
+print(pcall(io.write, "x"))
+io.output(io.stdout)
+io.input(name)
+assert(io.read("l", "n") == "one")
+io.input():close()
+print(pcall(io.read))
+io.input(io.stdin)
+io.write("written by io.write\n")
+io.stdout:write("written by io.stdout\n")
+print(io.stdout:close())
+print(io.type(io.stdin), io.type(42), io.type(io.stderr))
+
+-- temporary files
+f = assert(io.tmpfile())
+f:write("temporary")
+f:seek("set")
+assert(f:read("a") == "temporary")
+f:close()
+
+-- commands
+f = assert(io.popen("echo hello; echo world"))
+assert(f:read("a") == "hello\nworld\n")
+print(f:close())
+f = assert(io.popen("exit 3"))
+print(f:close())
+f = assert(io.popen("cat > " .. name, "w"))
+f:write("piped\n")
+f:close()
+assert(io.open(name):read("a") == "piped\n")
+print(pcall(io.popen, "ls", "rw"))
+
+-- files are closed when collected, or at the end of a block
+do
+  local g <close> = assert(io.open(name, "w"))
+  g:write("closed by <close>")
+  f = g
+end
+assert(io.type(f) == "closed file")
+assert(io.open(name):read("a") == "closed by <close>")
+f = assert(io.open(name, "w"))
+f:write("closed when collected")
+f = nil
+collectgarbage()
+assert(io.open(name):read("a") == "closed when collected")
+
+print(pcall(io.type))
+print(pcall(io.stdin.read, 42))
+print("ok")
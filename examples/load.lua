@@ -0,0 +1,125 @@
+-- load from a string
+local f = load("return 1 + 2")
+assert(f() == 3)
+
+local add = load("local a, b = ...; return a + b")
+assert(add(3, 4) == 7)
+
+-- syntax errors are returned, not raised
+local f, msg = load("x = = 1", "=chunk")
+assert(f == nil)
+print(msg)
+
+f, msg = load("return 'unfinished")
+assert(f == nil)
+print(msg)
+assert(msg == [[[string "return 'unfinished"]:1: unfinished string]])
+
+-- load from a reader function
+local pieces = { "return ", "10 ", "* 2" }
+local i = 0
+f = load(function()
+  i = i + 1
+  return pieces[i]
+end)
+assert(f() == 20)
+
+f, msg = load(function() return {} end)
+assert(f == nil and msg == "reader function must return a string")
+
+f, msg = load(function() error("reader failed", 0) end)
+assert(f == nil and msg == "reader failed")
+
+-- modes
+f, msg = load("return 1", "text", "b")
+assert(f == nil and msg == "attempt to load a text chunk (mode is 'b')")
+f, msg = load("\27Lua", "binary", "t")
+assert(f == nil and msg == "attempt to load a binary chunk (mode is 't')")
+assert(load("return 1", "text", "t")() == 1)
+
+-- binary chunks, with fresh upvalues of which the first is `_ENV`
+local function counter(n)
+  return function(step, ...)
+    n = n + step
+    return n, select("#", ...), "n" .. n
+  end
+end
+local chunk = string.dump(counter(10))
+assert(chunk:sub(1, 4) == "\27Lua")
+f = load(chunk, "=dumped", "b")
+assert(debug.getupvalue(f, 1) == "n" and debug.getupvalue(f, 2) == "_ENV")
+debug.setupvalue(f, 1, 5)
+debug.setupvalue(f, 2, _ENV)
+local n, count, s = f(1, "a", "b")
+assert(n == 6 and count == 2 and s == "n6")
+local main = load("local t = {} for i = 1, 3 do t[i] = i * 2.5 end return t[3], ...", "=main")
+f = load(string.dump(main))
+assert(select("#", f(7)) == 2 and f(7) == 7.5 and select(2, f(7)) == 7)
+assert(debug.getinfo(f, "S").source == "=main" and debug.getinfo(f, "l").currentline == -1)
+-- stripped ones have no debug information
+local stripped = string.dump(main, true)
+assert(#stripped < #string.dump(main) and load(stripped)() == 7.5)
+assert(debug.getinfo(load(stripped), "S").source == "=?")
+f = load(string.dump(counter(0), true))
+assert(debug.getupvalue(f, 1) == "(no name)")
+f, msg = load(chunk:sub(1, 20), "=cut")
+assert(f == nil and msg == "cut: bad binary format (truncated chunk)")
+f, msg = load("\27Lux", "=bad")
+assert(f == nil and msg == "bad: bad binary format (not a binary chunk)")
+f, msg = load(chunk:sub(1, 5) .. "\0" .. chunk:sub(7), "=official")
+assert(f == nil and msg == "official: bad binary format (format mismatch)")
+local ok, err = pcall(string.dump, print)
+assert(not ok and err == "unable to dump given function")
+ok, err = pcall(string.dump, 1)
+assert(not ok and err == "bad argument #1 to 'string.dump' (function expected, got number)")
+
+-- environment
+local env = { x = 42 }
+f = load("y = x + 1; return x", "=env", "t", env)
+assert(f() == 42)
+assert(env.y == 43 and y == nil)
+
+x = "global"
+assert(load("return x")() == "global")
+f = load("return x", "=nil env", "t", nil)
+assert(not pcall(f))
+
+-- loadfile and dofile
+local nofile
+f, nofile = loadfile("/no/such/file.lua")
+assert(f == nil)
+assert(nofile == "cannot open /no/such/file.lua: No such file or directory")
+
+assert(type(loadfile("examples/hello_world.lua")) == "function")
+f, msg = loadfile("examples/hello_world.lua", "b")
+assert(f == nil and msg == "attempt to load a text chunk (mode is 'b')")
+dofile("examples/hello_world.lua")
+
+local ok, err = pcall(dofile, "/no/such/file.lua")
+assert(not ok and err == nofile)
+
+-- too deep nesting is a syntax error, not a stack overflow
+local function nested(open, mid, close, n)
+  return "return " .. string.rep(open, n) .. mid .. string.rep(close, n)
+end
+assert(load(nested("(", "1", ")", 190))() == 1)
+for _, parts in ipairs({ { "(", "1", ")" }, { "{", "", "}" }, { "f(", "", ")" } }) do
+  f, msg = load(nested(parts[1], parts[2], parts[3], 100000))
+  assert(f == nil and msg:find(":1: chunk has too many C levels$"))
+end
+f, msg = load(string.rep("do ", 100000) .. string.rep("end ", 100000))
+assert(f == nil and msg:find(":1: chunk has too many C levels$"))
+
+-- tokens are shown as they are written
+for chunk, near in pairs({
+  ["x ="] = "unexpected symbol near <eof>",
+  ["= 1"] = "unexpected symbol near '='",
+  ["local x 't'"] = "unexpected symbol near 't'",
+  ["x 1.5"] = "'=' expected near '1.5'",
+  ["do x = 1"] = "'end' expected near <eof>",
+  ["t = {1 2}"] = "'}' expected near '2'",
+}) do
+  f, msg = load(chunk, "=chunk")
+  assert(f == nil and msg == "chunk:1: " .. near, msg)
+end
+print("ok")
@@ -0,0 +1,34 @@
+-- long chains are freed without recursion, which would overflow the stack
+local list
+for i = 1, 100000 do
+  list = { next = list }
+end
+list = nil
+
+local f
+for i = 1, 100000 do
+  local prev = f
+  f = function()
+    return prev
+  end
+end
+f = nil
+
+local mt
+for i = 1, 100000 do
+  mt = setmetatable({}, { __index = mt })
+end
+mt = nil
+
+-- and so are long cycles, by the collector
+local first = {}
+local last = first
+for i = 1, 100000 do
+  last.next = {}
+  last = last.next
+end
+last.next = first
+first, last = nil, nil
+collectgarbage()
+assert(collectgarbage("count") < 1000)
+print("ok")
@@ -0,0 +1,108 @@
+-- constants
+assert(math.pi > 3.14159 and math.pi < 3.1416)
+assert(math.huge > math.maxinteger and -math.huge < math.mininteger)
+assert(math.maxinteger + 1 == math.mininteger)
+assert(math.type(math.maxinteger) == "integer")
+print(math.pi, math.huge, -math.huge, math.maxinteger, math.mininteger)
+
+-- floor and ceil keep integers when representable
+assert(math.type(math.floor(3.7)) == "integer" and math.floor(3.7) == 3)
+assert(math.type(math.ceil(3.2)) == "integer" and math.ceil(3.2) == 4)
+assert(math.floor(-3.5) == -4 and math.ceil(-3.5) == -3)
+assert(math.floor(5) == 5 and math.type(math.floor(5)) == "integer")
+assert(math.floor("2.5") == 2)
+assert(math.type(math.floor(1e100)) == "float" and math.floor(1e100) == 1e100)
+assert(math.floor(math.huge) == math.huge)
+
+-- abs
+assert(math.abs(-3) == 3 and math.type(math.abs(-3)) == "integer")
+assert(math.abs(-2.5) == 2.5)
+assert(math.abs(math.mininteger) == math.mininteger)
+
+-- fmod rounds towards zero, unlike %
+assert(math.fmod(7, 3) == 1 and math.fmod(-7, 3) == -1)
+assert(math.type(math.fmod(7, 3)) == "integer")
+assert(math.fmod(-7, 3.0) == -1.0 and -7 % 3 == 2)
+assert(math.fmod(math.mininteger, -1) == 0)
+assert(math.fmod(5.5, 2) == 1.5)
+print(pcall(math.fmod, 1, 0))
+
+-- modf
+local i, f = math.modf(3.75)
+assert(i == 3.0 and f == 0.75 and math.type(i) == "float")
+i, f = math.modf(-3.75)
+assert(i == -3.0 and f == -0.75)
+i, f = math.modf(5)
+assert(i == 5 and f == 0.0 and math.type(i) == "integer")
+i, f = math.modf(math.huge)
+assert(i == math.huge and f == 0.0)
+
+-- sqrt, exp and log
+assert(math.sqrt(16) == 4.0 and math.type(math.sqrt(16)) == "float")
+assert(math.exp(0) == 1.0)
+assert(math.log(1) == 0.0)
+assert(math.log(8, 2) == 3.0)
+assert(math.log(1000, 10) == 3.0)
+assert(math.abs(math.log(81, 3) - 4.0) < 1e-12)
+assert(math.abs(math.log(math.exp(2)) - 2.0) < 1e-12)
+
+-- trigonometry
+assert(math.sin(0) == 0.0 and math.cos(0) == 1.0 and math.tan(0) == 0.0)
+assert(math.abs(math.sin(math.pi / 2) - 1.0) < 1e-12)
+assert(math.abs(math.asin(1) - math.pi / 2) < 1e-12)
+assert(math.acos(1) == 0.0)
+assert(math.abs(math.atan(1) - math.pi / 4) < 1e-12)
+assert(math.abs(math.atan(1, -1) - 3 * math.pi / 4) < 1e-12)
+assert(math.abs(math.deg(math.pi) - 180) < 1e-12)
+assert(math.abs(math.rad(180) - math.pi) < 1e-12)
+
+-- min and max
+assert(math.max(3, 7.5, -1) == 7.5 and math.min(3, 7.5, -1) == -1)
+assert(math.type(math.max(1, 2)) == "integer")
+assert(math.max(1, 1.0) == 1 and math.type(math.max(1, 1.0)) == "integer")
+print(pcall(math.max))
+
+-- tointeger, type and ult
+assert(math.tointeger(3.0) == 3 and math.type(math.tointeger(3.0)) == "integer")
+assert(math.tointeger(3.5) == nil)
+assert(math.tointeger(2^63) == nil)
+assert(math.tointeger({}) == nil)
+assert(math.type(1) == "integer" and math.type(1.0) == "float")
+assert(math.type("1") == nil)
+print(pcall(math.type))
+assert(math.ult(1, 2) and not math.ult(2, 1))
+assert(math.ult(1, -1) and not math.ult(-1, 1))
+
+-- random with a seed is reproducible
+math.randomseed(42)
+local seq = {}
+for k = 1, 10 do
+  seq[k] = math.random(1, 100)
+end
+math.randomseed(42)
+for k = 1, 10 do
+  assert(math.random(1, 100) == seq[k])
+end
+print(table.concat(seq, " "))
+assert(select("#", math.randomseed(7, 3)) == 2)
+assert(select(2, math.randomseed(7, 3)) == 3)
+
+-- ranges
+for _ = 1, 1000 do
+  local r = math.random()
+  assert(r >= 0 and r < 1 and math.type(r) == "float")
+  r = math.random(6)
+  assert(r >= 1 and r <= 6 and math.type(r) == "integer")
+  r = math.random(-3, 3)
+  assert(r >= -3 and r <= 3)
+  r = math.random(math.mininteger, math.maxinteger)
+  assert(math.type(r) == "integer")
+end
+assert(math.random(5, 5) == 5)
+assert(math.type(math.random(0)) == "integer")
+print(pcall(math.random, 2, 1))
+print(pcall(math.random, 1, 2, 3))
+
+-- an unseeded generator is seeded randomly
+math.randomseed()
+print("ok")
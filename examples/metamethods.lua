@@ -0,0 +1,94 @@
+-- arithmetic, bitwise, comparison and concatenation metamethods
+local Vec = {}
+Vec.__index = Vec
+
+local function vec(x, y)
+  return setmetatable({ x = x, y = y }, Vec)
+end
+
+Vec.__add = function(a, b) return vec(a.x + b.x, a.y + b.y) end
+Vec.__sub = function(a, b) return vec(a.x - b.x, a.y - b.y) end
+Vec.__mul = function(a, b)
+  -- the number may be on either side
+  if type(a) == "number" then
+    return vec(a * b.x, a * b.y)
+  end
+  return vec(a.x * b, a.y * b)
+end
+Vec.__unm = function(a) return vec(-a.x, -a.y) end
+Vec.__eq = function(a, b) return a.x == b.x and a.y == b.y end
+Vec.__lt = function(a, b) return a.x * a.x + a.y * a.y < b.x * b.x + b.y * b.y end
+Vec.__le = function(a, b) return not (b < a) end
+Vec.__concat = function(a, b)
+  local function str(v)
+    return type(v) == "table" and "(" .. v.x .. "," .. v.y .. ")" or tostring(v)
+  end
+  return str(a) .. str(b)
+end
+
+local a, b = vec(1, 2), vec(3, 4)
+print("a + b = " .. (a + b))
+print(b - a .. "", 2 * a .. "", a * 3 .. "", -a .. "")
+print(a == vec(1, 2), a ~= vec(1, 2), a == b, rawequal(a, vec(1, 2)))
+print(a < b, a <= b, a > b, a >= b)
+
+-- all the other arithmetic and bitwise events, with the operands given
+local ops = setmetatable({}, {
+  __div = function(_, x) return "div " .. x end,
+  __mod = function(_, x) return "mod " .. x end,
+  __pow = function(_, x) return "pow " .. x end,
+  __idiv = function(_, x) return "idiv " .. x end,
+  __band = function(_, x) return "band " .. x end,
+  __bor = function(_, x) return "bor " .. x end,
+  __bxor = function(_, x) return "bxor " .. x end,
+  __shl = function(_, x) return "shl " .. x end,
+  __shr = function(_, x) return "shr " .. x end,
+  __bnot = function(v, w) return rawequal(v, w) and "bnot" end,
+})
+print(ops / 1, ops % 2, ops ^ 3, ops // 4)
+print(ops & 5, ops | 6, ops ~ 7, ops << 8, ops >> 9, ~ops)
+
+-- a float without integer representation is handed to the metamethod
+print(ops & 1.5)
+
+-- `__eq` is only for two tables or two userdata, and its result is a boolean
+local always = setmetatable({}, { __eq = function() return "yes" end })
+print(always == {}, always == 1, {} == always)
+
+-- sorting uses `__lt` without an order function
+local list = { vec(3, 3), vec(1, 0), vec(2, 1), vec(0, 2) }
+table.sort(list)
+for _, v in ipairs(list) do
+  io.write(v .. " ")
+end
+print()
+
+-- errors without metamethods
+print(pcall(function() return {} + 1 end))
+print(pcall(function() return {} < {} end))
+print(pcall(function() return {} .. "x" end))
+print(pcall(table.sort, { {}, {} }))
+
+-- values with `__call` are called with themselves as the first argument
+local callable = setmetatable({}, { __call = function(self, a, b) return self, a, b end })
+local s, a, b = callable(1, 2)
+assert(s == callable and a == 1 and b == 2)
+assert(select(3, pcall(callable, "x")) == "x")
+local function tail(...) return callable(...) end
+assert(select(3, tail(3, 4)) == 4)
+local chained = setmetatable({}, { __call = callable })
+s, a, b = chained(5)
+assert(s == callable and a == chained and b == 5)
+local sum = 0
+local iter = setmetatable({}, {
+  __call = function(_, _, i)
+    i = (i or 0) + 1
+    if i <= 3 then return i end
+  end,
+})
+for i in iter do
+  sum = sum + i
+end
+assert(sum == 6)
+local ok, err = pcall(function() local t = {} t() end)
+assert(not ok and err:find("attempt to call a table value %(local 't'%)"))
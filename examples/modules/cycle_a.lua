@@ -0,0 +1,5 @@
+-- set the module early, so cycle_b can require it back
+local M = { name = "a" }
+package.loaded[...] = M
+M.b = require "cycle_b"
+return M
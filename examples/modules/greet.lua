@@ -0,0 +1,9 @@
+local name, path = ...
+local M = {}
+
+function M.hello(who)
+  return "hello, " .. who .. " from " .. name
+end
+
+M.path = path
+return M
@@ -0,0 +1 @@
+counter = (counter or 0) + 1
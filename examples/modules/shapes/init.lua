@@ -0,0 +1 @@
+return { name = ..., square = function(n) return n * n end }
@@ -0,0 +1,24 @@
+-- `host.fs` is registered by the host, and built on the first require
+print(package.loaded["host.fs"])
+local fs, extra = require "host.fs"
+print(type(fs), extra, fs.sep, fs.version)
+print(require "host.fs" == fs, package.loaded["host.fs"] == fs)
+
+print(fs.exists("examples/native_module.lua"), fs.exists("examples/nowhere.lua"))
+print(#fs.read("examples/hello_world.lua") > 0)
+print(pcall(fs.read, {}))
+
+-- the name given to `require`
+local info = require "host.info"
+print(info.name(), info.answer)
+
+-- removed from `package.loaded`, it's built again
+package.loaded["host.info"] = nil
+local again = require "host.info"
+print(again ~= info, again.name())
+
+-- files in `package.path` come first, as C libraries
+package.path = "examples/modules/?.lua"
+print(require("greet").hello("host"))
+
+print(select(2, pcall(require, "host.net")))
@@ -0,0 +1,77 @@
+-- os.date in UTC, with strftime conversions
+local t = 86400 * 365 + 3661 -- 1971-01-01 01:01:01 UTC
+assert(os.date("!%Y-%m-%d %H:%M:%S", t) == "1971-01-01 01:01:01")
+assert(os.date("!%c", 0) == "Thu Jan  1 00:00:00 1970")
+assert(os.date("!%A %B %j %p %y %%", t) == "Friday January 001 AM 71 %")
+assert(os.date("!%Ey %Od", t) == "71 01")
+assert(os.date("!x\0y", t) == "x\0y")
+print(os.date("!%x %X %D %T %F %e %a %b %u %w %V %G %Z", t))
+
+local d = os.date("!*t", t)
+assert(d.year == 1971 and d.month == 1 and d.day == 1)
+assert(d.hour == 1 and d.min == 1 and d.sec == 1)
+assert(d.yday == 1 and d.wday == 6 and d.isdst == false)
+
+-- local dates round-trip through os.time
+local now = os.time()
+assert(math.type(now) == "integer")
+local lt = os.date("*t", now)
+assert(os.time(lt) == now)
+assert(type(os.date()) == "string")
+
+-- os.time normalizes the fields of the table
+local date = { year = 2023, month = 14, day = 31, hour = 25 }
+local t2 = os.time(date)
+assert(date.year == 2024 and date.month == 3 and date.day == 3)
+assert(date.hour == 1 and date.min == 0 and date.sec == 0)
+assert(date.yday == 63 and date.wday == 1)
+assert(os.date("%Y-%m-%d %H:%M", t2) == "2024-03-03 01:00")
+-- hour defaults to noon
+assert(os.date("*t", os.time({ year = 2000, month = 1, day = 1 })).hour == 12)
+
+print(pcall(os.time, { year = 2000, month = 1 }))
+print(pcall(os.time, { year = 2000, month = 1, day = "x" }))
+print(pcall(os.time, { year = 2000, month = 1, day = 1.5 }))
+print(pcall(os.time, { year = 2000, month = 1, day = 2 ^ 31 }))
+print(pcall(os.date, "%Q is not a conversion"))
+print(pcall(os.date, "%E"))
+
+-- difftime and clock
+assert(os.difftime(t2, t2 - 90) == 90.0)
+assert(math.type(os.difftime(1, 0)) == "float")
+local c = os.clock()
+assert(math.type(c) == "float" and c >= 0)
+local x = 0
+for i = 1, 100000 do
+  x = x + i
+end
+assert(os.clock() >= c)
+
+-- environment
+assert(type(os.getenv("PATH")) == "string")
+assert(os.getenv("RUA_SURELY_NOT_DEFINED") == nil)
+assert(os.getenv("") == nil)
+
+-- files
+local name = os.tmpname()
+assert(io.open(name)):close()
+local renamed = name .. ".renamed"
+assert(os.rename(name, renamed) == true)
+local ok, msg, errno = os.rename(name, renamed)
+assert(ok == nil and msg == name .. ": No such file or directory" and errno == 2)
+assert(os.remove(renamed) == true)
+ok, msg, errno = os.remove(renamed)
+assert(ok == nil and msg == renamed .. ": No such file or directory" and errno == 2)
+assert(io.open(renamed) == nil)
+
+-- commands
+assert(os.execute() == true)
+print(os.execute("exit 0"))
+print(os.execute("exit 7"))
+print(os.execute("kill -9 $$"))
+
+assert(os.setlocale() == "C")
+assert(os.setlocale("C", "numeric") == "C")
+assert(os.setlocale("no_such_locale") == nil)
+print(pcall(os.setlocale, "C", "nothing"))
+print("ok")
@@ -0,0 +1,102 @@
+-- the array part first, then the other keys in order of insertion
+local t = { 10, 20, 30, x = "a", y = "b" }
+local keys, values = {}, {}
+for k, v in pairs(t) do
+  keys[#keys + 1] = k
+  values[#values + 1] = v
+end
+assert(table.concat(keys, " ") == "1 2 3 x y")
+assert(table.concat(values, " ") == "10 20 30 a b")
+
+-- ipairs stops at the first nil
+local last = 0
+for i, v in ipairs({ "a", "b", nil, "d" }) do
+  last = i
+end
+assert(last == 2)
+
+-- fields can be cleared during traversal
+local big = {}
+for i = 1, 50 do
+  big["k" .. i] = i
+  big[i] = i
+end
+local n = 0
+for k in pairs(big) do
+  big[k] = nil
+  n = n + 1
+end
+assert(n == 100 and next(big) == nil)
+
+assert(next({}, nil) == nil)
+local ok, err = pcall(next, {}, "absent")
+assert(not ok and err == "invalid key to 'next'")
+
+-- __pairs
+local mt = {
+  __pairs = function(t)
+    return function(_, i)
+      if i < 3 then
+        return i + 1
+      end
+    end, t, 0
+  end,
+}
+local seen = {}
+for i in pairs(setmetatable({}, mt)) do
+  seen[#seen + 1] = i
+end
+assert(table.concat(seen, " ") == "1 2 3")
+
+-- the closing value is closed when the loop ends, breaking out or not
+local closed = 0
+local closing = setmetatable({}, { __close = function() closed = closed + 1 end })
+for i in next, { 1, 2, 3 }, nil, closing do
+  if i == 2 then
+    break
+  end
+end
+assert(closed == 1)
+for i in next, { 1, 2, 3 }, nil, closing do
+end
+assert(closed == 2)
+
+-- removed keys are not kept alive by their dead entries
+local finalized = false
+local set = {}
+local function add_remove()
+  local o = setmetatable({}, { __gc = function() finalized = true end })
+  set[o] = true
+  set[o] = nil
+end
+add_remove()
+collectgarbage()
+assert(finalized)
+
+-- and the traversal goes on, with the keys it holds
+local objs = {}
+for i = 1, 100 do
+  objs[{}] = i
+end
+local n = 0
+for k in pairs(objs) do
+  objs[k] = nil
+  collectgarbage()
+  n = n + 1
+end
+assert(n == 100 and next(objs) == nil)
+
+-- ipairs indexes with metamethods
+local proxy = setmetatable({}, {
+  __index = function(_, i)
+    if i <= 3 then
+      return i * 10
+    end
+  end,
+})
+local sum = 0
+for i, v in ipairs(proxy) do
+  assert(v == i * 10)
+  sum = sum + v
+end
+assert(sum == 60)
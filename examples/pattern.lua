@@ -0,0 +1,130 @@
+-- find, plain and with patterns
+assert(string.find("hello world", "o w") == 5)
+local s, e = ("hello world"):find("wor")
+assert(s == 7 and e == 9)
+assert(("a.b"):find(".", 1, true) == 2)
+assert(("a+b"):find("+", 1, true) == 2)
+assert(("abc"):find("") == 1)
+assert(("abc"):find("", 10) == nil)
+assert(("abc"):find("", 4) == 4)
+assert(("abc"):find("b", -1) == nil)
+s, e = ("hello"):find("l+")
+assert(s == 3 and e == 4)
+local s, e, c = ("key = value"):find("(%w+)%s*=")
+assert(s == 1 and e == 5 and c == "key")
+
+-- anchors
+assert(("hello"):find("^h") == 1)
+assert(("hello"):find("^e") == nil)
+assert(("hello"):match("o$") == "o")
+assert(("hello"):match("l$") == nil)
+assert(("a$b"):match("%$b") == "$b")
+assert(("a^b"):match("a^b") == "a^b")
+
+-- classes and sets
+assert(("  abc123  "):match("%a+") == "abc")
+assert(("abc123"):match("%d+") == "123")
+assert(("x = 0x1F"):match("0x(%x+)") == "1F")
+assert(("hello, world!"):match("%p") == ",")
+assert(("tab\there"):match("%s") == "\t")
+assert(("ABCdef"):match("%u+") == "ABC")
+assert(("ABCdef"):match("%l+") == "def")
+assert(("abc DEF"):match("%S+$") == "DEF")
+assert(("2024-01-15"):match("[%d-]+") == "2024-01-15")
+assert(("hello"):match("[^aeiou]+") == "h")
+assert(("a]b"):match("[]]") == "]")
+assert(("a-b"):match("[a%-]+") == "a-")
+assert(("xyz"):match("[a-y]+") == "xy")
+assert(("f(a)"):match("[%(%)]") == "(")
+
+-- repetitions
+assert(("aaa"):match("a-") == "")
+assert(("aaab"):match("a-b") == "aaab")
+assert(("<<a>>"):match("<(.-)>") == "<a")
+assert(("<<a>>"):match("<(.*)>") == "<a>")
+assert(("color colour"):gsub("colou?r", "C") == "C C")
+assert(("abc"):match("a?b?c?d?") == "abc")
+
+-- captures
+local y, m, d = ("2024-01-15"):match("(%d+)-(%d+)-(%d+)")
+assert(y == "2024" and m == "01" and d == "15")
+local p1, w, p2 = ("hello world"):match("()(%w+)()", 6)
+assert(p1 == 7 and w == "world" and p2 == 12)
+assert(("hello"):match("()ll()") == 3)
+assert(select("#", ("hello"):match("()ll()")) == 2)
+local q, inner = ([[say "hi" now]]):match("([\"'])(.-)%1")
+assert(q == '"' and inner == "hi")
+assert(("abcabc"):match("(abc)%1") == "abc")
+-- a position capture never matches as a back-reference
+assert(string.find("abc", "()a%1") == nil)
+assert(("a(b(c)d)e"):match("%b()") == "(b(c)d)")
+assert(("THE (quick) fox"):find("%f[%a]%a+%f[%A]") == 1)
+local words = {}
+for w in ("THE (quick) fox"):gmatch("%f[%a]%a+") do
+  words[#words + 1] = w
+end
+assert(#words == 3 and words[2] == "quick")
+
+-- gmatch
+local t = {}
+for k, v in ("a=1, b=2, c=3"):gmatch("(%w+)=(%w+)") do
+  t[k] = tonumber(v)
+end
+assert(t.a == 1 and t.b == 2 and t.c == 3)
+local n = 0
+for w in ("one two three"):gmatch("%a+") do
+  n = n + 1
+end
+assert(n == 3)
+local it = ("abc"):gmatch(".")
+assert(it() == "a" and it() == "b" and it() == "c" and it() == nil)
+local parts = {}
+for w in ("one two three"):gmatch("%a+", 5) do
+  parts[#parts + 1] = w
+end
+assert(#parts == 2 and parts[1] == "two")
+-- no empty match right at the end of the last match
+local count = 0
+for _ in ("abc"):gmatch("%a*") do
+  count = count + 1
+end
+assert(count == 1)
+
+-- gsub with string, table and function replacements
+assert(("hello world"):gsub("o", "0") == "hell0 w0rld")
+local r, k = ("hello world"):gsub("o", "0", 1)
+assert(r == "hell0 world" and k == 1)
+assert(("hello world"):gsub("(%w+)", "<%1>") == "<hello> <world>")
+assert(("hello world"):gsub("%w+", "%0 %0", 1) == "hello hello world")
+assert(("hello"):gsub("", "-") == "-h-e-l-l-o-")
+assert(("abc"):gsub("%w", "%%") == "%%%")
+assert(("$name is $age"):gsub("%$(%w+)", { name = "Lua", age = 30 }) == "Lua is 30")
+assert(("$x and $y"):gsub("%$(%w+)", { x = "1" }) == "1 and $y")
+assert(("1 2 3"):gsub("%d", function(d) return d * 2 end) == "2 4 6")
+assert(("keep"):gsub("%w+", function() return nil end) == "keep")
+r, k = ("abc"):gsub("^a", "A")
+assert(r == "Abc" and k == 1)
+r, k = ("aaa"):gsub("^a", "A")
+assert(r == "Aaa" and k == 1)
+assert(("x"):gsub("x", "%1") == "x")
+
+-- errors
+local function check_error(f)
+  local ok, msg = pcall(f)
+  assert(not ok)
+  print(msg)
+end
+check_error(function() return ("a"):find("%") end)
+check_error(function() return ("a"):find("[a") end)
+check_error(function() return ("a"):find("(a") end)
+check_error(function() return ("a"):match("a)") end)
+check_error(function() return ("a"):find("%1") end)
+check_error(function() return ("a"):find("%f") end)
+check_error(function() return ("a"):find("%b") end)
+check_error(function() return ("a"):gsub("a", "%2") end)
+check_error(function() return ("a"):gsub("a", "%x") end)
+check_error(function() return ("a"):gsub("a", true) end)
+check_error(function() return ("a"):gsub("a", function() return {} end) end)
+check_error(function() return (("a"):rep(300)):match(("a?"):rep(300) .. ("a"):rep(300)) end)
+check_error(function() return (("a"):rep(33)):match(("(a)"):rep(33)) end)
+print("ok")
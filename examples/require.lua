@@ -0,0 +1,56 @@
+package.path = "examples/modules/?.lua;examples/modules/?/init.lua"
+
+local greet, path = require "greet"
+assert(greet.hello("rua") == "hello, rua from greet")
+assert(path == "examples/modules/greet.lua" and greet.path == path)
+assert(require "greet" == greet and package.loaded.greet == greet)
+
+local shapes = require "shapes"
+assert(shapes.name == "shapes" and shapes.square(7) == 49)
+
+-- modules returning nothing are loaded once as `true`
+assert(require "no_result" == true and require "no_result" == true and counter == 1)
+
+-- loops
+local a = require "cycle_a"
+assert(a.name == "a" and a.b.name == "b" and a.b.a == a)
+local ok, err = pcall(require, "loop")
+assert(not ok and err == "examples/modules/loop.lua:1: loop loading module 'loop'")
+
+-- preload
+package.preload.answer = function(name, extra)
+  return { name = name, extra = extra, value = 42 }
+end
+local answer, extra = require "answer"
+assert(answer.name == "answer" and answer.extra == ":preload:" and answer.value == 42)
+assert(extra == ":preload:")
+
+-- custom searchers
+table.insert(package.searchers, function(name)
+  if name:sub(1, 4) == "mem." then
+    return function(n) return "in memory " .. n end, "memory"
+  end
+  return "no memory module '" .. name .. "'"
+end)
+local value, data = require "mem.x"
+assert(value == "in memory mem.x" and data == "memory")
+
+-- errors
+ok, err = pcall(require, "nowhere")
+assert(not ok and err == [[module 'nowhere' not found:
+	no field package.preload['nowhere']
+	no file 'examples/modules/nowhere.lua'
+	no file 'examples/modules/nowhere/init.lua'
+	no native module 'nowhere'
+	no memory module 'nowhere']])
+ok, err = pcall(require, "bad_syntax")
+assert(not ok and err == [[error loading module 'bad_syntax' from file 'examples/modules/bad_syntax.lua':
+	examples/modules/bad_syntax.lua:2: unexpected symbol near <eof>]])
+
+local found, tried = package.searchpath("a.b", "x/?.lua;y/?/init.lua")
+assert(found == nil and tried == "no file 'x/a/b.lua'\n\tno file 'y/a/b/init.lua'")
+assert(package.searchpath("greet", package.path) == "examples/modules/greet.lua")
+found, tried = package.searchpath("a_b", "?.x", "_", "-")
+assert(found == nil and tried == "no file 'a-b.x'")
+assert(package.loaded.string == string and package.loaded._G == _G)
+assert(package.config == "/\n;\n?\n!\n-\n")
@@ -0,0 +1,15 @@
+-- a runtime error raised through nested calls
+function inner(x)
+  return x + 1
+end
+
+function outer()
+  local y = inner(nil)
+  return y
+end
+
+local function entry()
+  return outer()
+end
+
+entry()
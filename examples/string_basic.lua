@@ -0,0 +1,56 @@
+local s = "Hello, Lua"
+
+assert(string.len(s) == 10 and #s == 10 and s:len() == 10)
+assert(string.len("") == 0 and string.len("a\0b") == 3)
+assert(string.len(123) == 3)
+
+-- sub with Lua's index rules
+assert(s:sub(1, 5) == "Hello")
+assert(s:sub(8) == "Lua")
+assert(s:sub(-3) == "Lua")
+assert(s:sub(-3, -2) == "Lu")
+assert(s:sub(0) == s)
+assert(s:sub(-100, 2) == "He")
+assert(s:sub(5, 100) == "o, Lua")
+assert(s:sub(6, 5) == "")
+assert(s:sub(11) == "")
+
+assert(s:upper() == "HELLO, LUA")
+assert(s:lower() == "hello, lua")
+assert(("\200x"):upper() == "\200X")
+
+-- rep with separator
+assert(("ab"):rep(3) == "ababab")
+assert(("ab"):rep(3, ", ") == "ab, ab, ab")
+assert(("ab"):rep(0) == "" and ("ab"):rep(-1, ",") == "")
+assert(("x"):rep(1, ",") == "x")
+
+assert(s:reverse() == "auL ,olleH")
+assert((""):reverse() == "")
+
+-- byte and char
+assert(s:byte() == 72)
+assert(s:byte(-1) == 97)
+local a, b, c = s:byte(1, 3)
+assert(a == 72 and b == 101 and c == 108)
+assert(select("#", s:byte(3, 2)) == 0)
+assert(select("#", s:byte(1, -1)) == 10)
+assert(string.char() == "")
+assert(string.char(72, 105, 0, 255) == "Hi\0\255")
+assert(string.char(s:byte(1, -1)) == s)
+
+local ok, msg = pcall(string.char, 256)
+assert(not ok)
+print(msg)
+ok, msg = pcall(string.rep)
+print(msg)
+
+-- the shared metatable
+assert(getmetatable("").__index == string)
+assert(getmetatable("a") == getmetatable("b"))
+function string.twice(s) return s .. s end
+assert(("ab"):twice() == "abab")
+
+ok, msg = pcall(function() return ("x"):nosuch() end)
+print(msg)
+print("ok")
@@ -0,0 +1,98 @@
+local function check(expected, fmt, ...)
+  local got = string.format(fmt, ...)
+  if got ~= expected then
+    error(string.format("format(%q): expected %q, got %q", fmt, expected, got))
+  end
+end
+
+-- integers
+check("42", "%d", 42)
+check("   42|42   |00042", "%5d|%-5d|%05d", 42, 42, 42)
+check("+5  5 -5", "%+d % d %d", 5, 5, -5)
+check("007 -007", "%.3d %.3d", 7, -7)
+check("", "%.0d", 0)
+check("3", "%i", 3.0)
+check("18446744073709551615", "%u", -1)
+check("ff FF 0xff 0XFF 10 010 0", "%x %X %#x %#X %o %#o %#x", 255, 255, 255, 255, 8, 8, 0)
+check("ffffffffffffffff", "%x", -1)
+check("  0x1f", "%#6x", 31)
+
+-- characters
+check("A", "%c", 65)
+check("  A|A  |", "%3c|%-3c|", 65, 65)
+check("a\0b", "%c%c%c", 97, 0, 98)
+
+-- floats
+check("3.141590", "%f", 3.14159)
+check("3.14", "%.2f", 3.14159)
+check("     3.142", "%10.3f", 3.14159)
+check("3.1       |", "%-10.1f|", 3.14159)
+check("+3.1 -3.1", "%+.1f %+.1f", 3.14159, -3.14159)
+check("00003.14", "%08.2f", 3.14159)
+check("3.", "%#.0f", 3)
+check("1.234568e+04", "%e", 12345.678)
+check("1.23E+04", "%.2E", 12345.678)
+check("1e+100", "%.0e", 1e100)
+check("100000 1e+06 0.0001 1e-05", "%g %g %g %g", 100000, 1000000, 0.0001, 0.00001)
+check("3.14159", "%g", 3.14159265)
+check("1.00000", "%#g", 1.0)
+check("2 1E-10", "%.3g %G", 2.0, 1e-10)
+check("0.1", "%g", 0.1)
+check("0", "%g", 0)
+check("-0", "%g", -0.0)
+check("0x1p+0 0x1p-1 0x0p+0", "%a %a %a", 1.0, 0.5, 1.0 - 1.0)
+check("0x1.999999999999ap-4", "%a", 0.1)
+check("0x1.00p+0", "%.2a", 1)
+check("0X1.FFP+7", "%A", 255.5)
+check("0x2p+0", "%.0a", 1.5)
+check("0x1.8p+1", "%.1a", 3)
+check("       inf|-inf      |  INF", "%10.4f|%-10f|%5G", 1/0, -1/0, 1/0)
+
+-- strings
+check("hello world", "%s %s", "hello", "world")
+check("    a|ab   |", "%5.1s|%-5s|", "abc", "ab")
+check("1 2.5 true nil", "%s %s %s %s", 1, 2.5, true, nil)
+local obj = setmetatable({}, { __tostring = function() return "OBJ" end })
+check("[OBJ]", "[%s]", obj)
+check("[  OBJ]", "[%5s]", obj)
+check("100%", "%d%%", 100)
+local long = string.rep("x", 200)
+check(long, "%10s", long)
+assert(string.format("%p", obj):sub(1, 2) == "0x")
+check("(null)", "%p", 1)
+
+-- %q reads back
+check([["a\
+b\"c\\d\0"]], "%q", 'a\nb"c\\d\0')
+check([["\0001\13"]], "%q", "\0" .. "1\r")
+check("0x8000000000000000", "%q", -9223372036854775807 - 1)
+check("1e9999 -1e9999 (0/0)", "%q %q %q", 1/0, -1/0, 0/0)
+for _, v in ipairs({ "a\0b\1\0012\255", 42, -7, 0.1, -2.5e-300, 1/3, 1e300, -9223372036854775807 - 1 }) do
+  local lit = string.format("%q", v)
+  local back = load("return " .. lit)()
+  assert(back == v, lit)
+  assert(type(back) == type(v))
+end
+assert(load("return " .. string.format("%q", 1/0))() == 1/0)
+assert(string.format("%q", 2^63) == "0x1p+63")
+check("nil true", "%q %q", nil, true)
+
+-- errors
+local function check_error(...)
+  local ok, msg = pcall(string.format, ...)
+  assert(not ok)
+  print(msg)
+end
+check_error("%d")
+check_error("%d", 3.5)
+check_error("%d", "x")
+check_error("%y", 1)
+check_error("%123d", 1)
+check_error("%#d", 1)
+check_error("%.3c", 65)
+check_error("%10q", "x")
+check_error("%q", {})
+check_error("%10s", "a\0b")
+check_error("%", 1)
+check_error("%-----------------------d", 1)
+print("ok")
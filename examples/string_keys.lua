@@ -0,0 +1,35 @@
+-- strings built at runtime are the same keys as the literal ones
+local t = { key1 = "short" }
+print(t["key" .. 1])
+
+-- and so are the ones made by the libraries
+t[string.rep("ab", 2)] = "from rep"
+print(t.abab, table.pack(1, 2).n)
+
+-- long strings are keys by content too
+local long = "a long string which is not interned, for it has more than 40 bytes"
+t[long] = "long"
+print(t["a long string which is not interned, " .. "for it has more than 40 bytes"])
+
+-- equality of strings of all lengths
+local a, b = "", ""
+for i = 1, 50 do
+  a = a .. "x"
+  b = b .. "x"
+  if a ~= b then
+    print(i)
+  end
+end
+print(a == b)
+print(a == b .. "y")
+
+-- strings still alive after they are dropped and created again
+for i = 1, 1000 do
+  t["tmp" .. i] = i
+end
+for i = 1, 1000 do
+  t["tmp" .. i] = nil
+end
+collectgarbage()
+t["tmp" .. 7] = 7
+print(t.tmp7)
@@ -0,0 +1,86 @@
+local pack, unpack, packsize = string.pack, string.unpack, string.packsize
+
+local function hex(s)
+  return (s:gsub(".", function(c) return string.format("%02x", c:byte()) end))
+end
+
+-- integers with endianness
+assert(hex(pack("<i4", 1)) == "01000000")
+assert(hex(pack(">i4", 1)) == "00000001")
+assert(hex(pack("<h", -2)) == "feff")
+assert(hex(pack(">I3", 0x010203)) == "010203")
+assert(hex(pack("b B", -1, 255)) == "ffff")
+assert(hex(pack("<i16", -1)) == string.rep("ff", 16))
+assert(hex(pack(">i12", 1)) == string.rep("00", 11) .. "01")
+assert(unpack("<i4", pack("<i4", -123456)) == -123456)
+assert(unpack(">I2", "\1\2") == 258)
+assert(unpack("<i16", pack("<i16", -5)) == -5)
+assert(unpack("<j", pack("<j", -9223372036854775807 - 1)) == -9223372036854775807 - 1)
+assert(unpack("B", "\255") == 255 and unpack("b", "\255") == -1)
+
+-- floats
+assert(unpack("<d", pack("<d", 3.25)) == 3.25)
+assert(unpack(">n", pack(">n", -0.1)) == -0.1)
+assert(unpack("f", pack("f", 0.5)) == 0.5)
+assert(#pack("f", 1) == 4 and #pack("d", 1) == 8)
+
+-- strings
+assert(pack("z", "abc") == "abc\0")
+assert(pack("s1", "hi") == "\2hi")
+assert(hex(pack(">s2", "hi")) == "00026869")
+assert(pack("c5", "ab") == "ab\0\0\0")
+local s, n = unpack("z", "hello\0world")
+assert(s == "hello" and n == 7)
+s, n = unpack("s1", "\3abcdef")
+assert(s == "abc" and n == 5)
+s = unpack("c3", "abcdef", 2)
+assert(s == "bcd")
+
+-- several values and the next position
+local packed = pack("<i2 i4 z d", 7, -8, "str", 1.5)
+local a, b, c, d, nxt = unpack("<i2 i4 z d", packed)
+assert(a == 7 and b == -8 and c == "str" and d == 1.5 and nxt == #packed + 1)
+local p1, p2, pos = unpack("BB", "\1\2\3", 2)
+assert(p1 == 2 and p2 == 3 and pos == 4)
+assert(unpack("B", "\1\2\3", -1) == 3)
+
+-- alignment and padding
+assert(hex(pack("!4 b i4", 1, 2)) == "0100000002000000")
+assert(hex(pack("!2 b i4", 1, 2)) == "010002000000")
+assert(hex(pack("b x h", 1, 2)) == "01000200")
+assert(hex(pack("!4 b Xi4 b", 1, 2)) == "0100000002")
+assert(#pack("!8 b d", 1, 2) == 16)
+assert(packsize("!8 b d") == 16)
+assert(packsize("i4 i8 b") == 13)
+assert(packsize("i3") == 3)
+assert(packsize("") == 0)
+assert(packsize("c10") == 10)
+
+-- errors
+local function check_error(f, ...)
+  local ok, msg = pcall(f, ...)
+  assert(not ok)
+  print(msg)
+end
+check_error(pack, "i17", 1)
+check_error(pack, "i0", 1)
+check_error(pack, "b", 200)
+check_error(pack, "B", -1)
+check_error(pack, "I2", 65536)
+check_error(pack, "c2", "abc")
+check_error(pack, "z", "a\0b")
+check_error(pack, "s1", string.rep("x", 256))
+check_error(pack, "y", 1)
+check_error(pack, "c", "a")
+check_error(pack, "i4")
+check_error(pack, "X", 1)
+check_error(pack, "!3 i4", 1)
+check_error(packsize, "!4 i3")
+check_error(packsize, "s")
+check_error(packsize, string.rep("c214748364", 11))
+check_error(unpack, "i4", "abc")
+check_error(unpack, "z", "abc")
+check_error(unpack, "s1", "\5ab")
+check_error(unpack, "i9", string.rep("\255", 8) .. "\1")
+check_error(unpack, "b", "a", 3)
+print("ok")
@@ -0,0 +1,42 @@
+-- appended keys live in the array part
+local t = {}
+for i = 1, 100 do
+  t[#t + 1] = i * 2
+end
+print(#t)
+print(t[1])
+print(t[100])
+
+-- filled backwards, migrated into the array part at last
+local r = {}
+for i = 100, 1, -1 do
+  r[i] = i
+end
+print(#r)
+print(r[1])
+print(r[100])
+
+-- sparse keys stay in the map part
+local s = {}
+s[1] = "a"
+s[1000] = "b"
+s[1000000] = "c"
+print(#s)
+print(s[1000])
+print(s[1000000])
+s[2] = "x"
+s[3] = "y"
+print(#s)
+
+-- removing from the end
+for i = 100, 51, -1 do
+  t[i] = nil
+end
+print(#t)
+t[51] = 0
+print(#t)
+
+-- positional fields win over keyed ones
+local c = { [2] = "keyed", "a", "b" }
+print(c[2])
+print(#c)
@@ -0,0 +1,32 @@
+-- floats with integer values are the same keys as integers
+local t = {}
+t[1.0] = "one"
+t[2] = "two"
+print(t[1])
+print(t[2.0])
+print(#t)
+t[2^53] = "big"
+print(t[2^53 | 0])
+t[-0.0] = "zero"
+print(t[0])
+t[1.5] = "float"
+print(t[1.5])
+
+-- numbers are equal across subtypes
+print(1 == 1.0)
+print(-0.0 == 0)
+print(2^53 == 9007199254740992)
+print(print == print)
+print(pcall == print)
+
+-- but zeros keep their signs, as constants too
+assert(tostring(0.0) == "0.0" and tostring(-0.0) == "-0.0")
+assert(1 / (0.0 * -1) == -math.huge and 1 / 0.0 == math.huge)
+
+-- invalid keys
+local ok, err = pcall(function() t[nil] = 1 end)
+print(err)
+ok, err = pcall(function() t[0/0] = 1 end)
+print(err)
+print(t[nil])
+print(t[0/0])
@@ -0,0 +1,64 @@
+local t = { 1, 2, 3 }
+table.insert(t, 4)
+table.insert(t, 1, 0)
+assert(table.concat(t, ",") == "0,1,2,3,4")
+assert(table.remove(t) == 4)
+assert(table.remove(t, 1) == 0)
+assert(table.concat(t, ", ", 2, 3) == "2, 3")
+assert(table.concat({}) == "")
+local ok, err = pcall(table.insert, t, 10, 5)
+assert(not ok and err == "bad argument #2 to 'table.insert' (position out of bounds)")
+ok, err = pcall(table.insert, t, 1, 2, 3)
+assert(not ok and err == "wrong number of arguments to 'insert'")
+ok, err = pcall(table.concat, { 1, {}, 3 })
+assert(not ok and err == "invalid value (at index 2) in table for 'concat'")
+
+local p = table.pack(1, nil, 3)
+assert(p.n == 3 and p[1] == 1 and p[2] == nil and p[3] == 3)
+local a, b, c = table.unpack({ 1, 2, 3 })
+assert(a == 1 and b == 2 and c == 3)
+a, b = table.unpack({ 1, 2, 3 }, 2)
+assert(a == 2 and b == 3)
+
+-- move, overlapping too
+local m = table.move({ 1, 2, 3, 4, 5 }, 1, 3, 3)
+assert(table.concat(m, " ") == "1 2 1 2 3")
+m = table.move({ 1, 2, 3, 4, 5 }, 2, 5, 1)
+assert(table.concat(m, " ") == "2 3 4 5 5")
+local dst = table.move({ "a", "b" }, 1, 2, 2, { "x" })
+assert(table.concat(dst, " ") == "x a b")
+
+-- sort, with the default order and a comparator
+local s = { 5, 3, 8, 1, 9, 2, 7, 4, 6, 0 }
+table.sort(s)
+assert(table.concat(s, " ") == "0 1 2 3 4 5 6 7 8 9")
+table.sort(s, function(x, y) return x > y end)
+assert(table.concat(s, " ") == "9 8 7 6 5 4 3 2 1 0")
+local words = { "pear", "apple", "fig", "banana" }
+table.sort(words)
+assert(table.concat(words, " ") == "apple banana fig pear")
+local big = {}
+for i = 1, 1000 do
+  big[i] = (i * 7919) % 1000
+end
+table.sort(big)
+for i = 2, #big do
+  assert(big[i - 1] <= big[i])
+end
+ok, err = pcall(table.sort, { 3, 1, "x" })
+assert(not ok and err:find("^attempt to compare"))
+ok, err = pcall(table.sort, big, function(x, y) return true end)
+assert(not ok and err == "invalid order function for sorting")
+
+-- through metamethods
+local proxy = setmetatable({}, {
+  __index = function(_, k) return k * 10 end,
+  __len = function() return 3 end,
+})
+assert(table.concat(proxy, " ") == "10 20 30")
+local log = {}
+local logged = setmetatable({}, {
+  __newindex = function(_, k, v) log[k] = v end,
+})
+table.insert(logged, "v")
+assert(log[1] == "v" and rawget(logged, 1) == nil)
@@ -0,0 +1,33 @@
+-- print takes any number of values, separated by tabs
+print("several", "values", 1, nil, true)
+print()
+
+-- numbers as %.14g, floats keep a fraction
+local function strings(...)
+  local s = {}
+  for i = 1, select("#", ...) do
+    s[i] = tostring((select(i, ...)))
+  end
+  return table.concat(s, " ")
+end
+assert(strings(1, 1.0, -0.0, 100 / 2) == "1 1.0 -0.0 50.0")
+assert(strings(1 / 0, -1 / 0) == "inf -inf")
+assert(tostring(0 / 0):find("^%-?nan$"))
+assert(strings(3.14159265358979, 0.1, 1e-5, 1e15, 2 ^ 63)
+  == "3.1415926535898 0.1 1e-05 1e+15 9.2233720368548e+18")
+assert(1 .. "" == "1" and 1.5 .. "" == "1.5")
+
+assert(tostring(nil) == "nil" and tostring(false) == "false")
+
+local point = setmetatable({ x = 1, y = 2 }, {
+  __tostring = function(p) return "(" .. p.x .. ", " .. p.y .. ")" end,
+})
+assert(tostring(point) == "(1, 2)")
+print(point)
+
+local named = setmetatable({}, { __name = "Named" })
+assert(tostring(named):find("^Named: 0x%x+$"))
+assert(tostring({}):find("^table: 0x%x+$"))
+
+local ok, err = pcall(tostring, setmetatable({}, { __tostring = function() return {} end }))
+assert(not ok and err == "'__tostring' must return a string")
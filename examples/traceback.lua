@@ -0,0 +1,44 @@
+-- tracebacks of errors, across Lua and native functions
+function fail(msg)
+  error(msg)
+end
+
+function level2()
+  fail("boom")
+end
+
+function level1()
+  level2()
+end
+
+local ok, msg = xpcall(level1, debug.traceback)
+print(msg)
+ok, msg = pcall(level1)
+print(msg)
+ok, msg = pcall(error, { "an object" })
+print(msg)
+print(debug.traceback("message"))
+
+function deep(n)
+  if n == 0 then
+    return debug.traceback("deep", 1)
+  end
+  local s = deep(n - 1)
+  return s
+end
+print(deep(30))
+
+-- Lua calls are limited by the stack size only
+local function depth(n)
+  if n == 0 then
+    return 0
+  end
+  return 1 + depth(n - 1)
+end
+print(depth(100000))
+print(pcall(depth, math.huge))
+
+-- numbers are messages, other values are returned untouched
+assert(debug.traceback(12):find("^12\nstack traceback:\n"))
+local t = {}
+assert(debug.traceback(t) == t)
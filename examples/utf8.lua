@@ -0,0 +1,59 @@
+local s = "大家好啊，我是电棍！"
+assert(#s == 30 and utf8.len(s) == 10)
+
+-- characters with their byte positions
+local positions, chars = {}, {}
+for p, c in utf8.codes(s) do
+  positions[#positions + 1] = p
+  chars[#chars + 1] = utf8.char(c)
+end
+assert(table.concat(positions, " ") == "1 4 7 10 13 16 19 22 25 28")
+assert(table.concat(chars) == s)
+
+local c1, c2 = utf8.codepoint(s, 1, 4)
+assert(c1 == 22823 and c2 == 23478)
+assert(utf8.char(72, 105, 0x4E2D, 0x6587) == "Hi中文")
+assert(utf8.char() == "")
+
+-- the n-th character
+assert(utf8.offset(s, 3) == 7 and utf8.offset(s, -1) == 28)
+assert(utf8.offset(s, 11) == 31 and utf8.offset(s, 12) == nil)
+assert(utf8.offset(s, 0, 5) == 4)
+assert(string.sub(s, utf8.offset(s, 3), utf8.offset(s, 5) - 1) == "好啊")
+
+-- invalid sequences
+local fail, pos = utf8.len("abc\xE4\xB8def")
+assert(fail == nil and pos == 4)
+fail, pos = utf8.len("\xFF")
+assert(fail == nil and pos == 1)
+assert(utf8.len(s, 4) == 9 and utf8.len(s, 4, 9) == 2)
+local function fails(msg, f, ...)
+  local ok, err = pcall(f, ...)
+  assert(not ok and err:sub(-#msg) == msg, err)
+end
+local ok, r1, r2 = pcall(utf8.len, s, 2)
+assert(ok and r1 == nil and r2 == 2)
+fails("bad argument #2 to 'utf8.len' (initial position out of bounds)", utf8.len, s, 100)
+fails("invalid UTF-8 code", utf8.codepoint, "\xE4\xB8")
+fails("initial position is a continuation byte", utf8.offset, s, 1, 2)
+fails("bad argument #1 to 'utf8.char' (value out of range)", utf8.char, -1)
+fails("bad argument #1 to 'utf8.codes' (invalid UTF-8 code)", utf8.codes, "\x80")
+fails("utf8.lua:42: invalid UTF-8 code", function()
+  for _ in utf8.codes("a\xE4\xB8") do end
+end)
+
+-- lax accepts surrogates and the sequences beyond Unicode
+local big = utf8.char(0x7FFFFFFF)
+assert(#big == 6 and utf8.len(big) == nil and utf8.len(big, 1, -1, true) == 1)
+assert(utf8.codepoint(big, 1, 1, true) == 0x7FFFFFFF)
+fails("invalid UTF-8 code", utf8.codepoint, big)
+for _, c in utf8.codes(utf8.char(0xD800), true) do
+  assert(c == 0xD800)
+end
+
+-- charpattern matches one character
+local n = 0
+for c in string.gmatch(s, utf8.charpattern) do
+  n = n + 1
+end
+assert(n == 10)
@@ -0,0 +1,60 @@
+-- weak values: cached objects are dropped once unused elsewhere
+local cache = setmetatable({}, { __mode = "v" })
+local kept = {}
+cache[1] = kept
+cache[2] = {}
+cache.name = "strings are values, never removed"
+collectgarbage()
+print(cache[1] == kept)
+print(cache[2])
+print(cache.name)
+
+-- weak keys: data attached to objects
+local data = setmetatable({}, { __mode = "k" })
+local obj = {}
+data[obj] = "attached"
+data[{}] = "lost"
+collectgarbage()
+print(data[obj])
+
+-- ephemerons: a value referring to its own key does not keep it alive
+local eph = setmetatable({}, { __mode = "k" })
+local key = {}
+eph[key] = { ref = key }
+do
+  local k2 = {}
+  eph[k2] = { ref = k2 }
+end
+collectgarbage()
+print(eph[key].ref == key)
+
+-- keys used nowhere else are removed, as are cycles through keys
+local function attach()
+  for i = 1, 10 do
+    data[{}] = i
+    local t = {}
+    t[t] = true
+  end
+end
+attach()
+collectgarbage()
+local count = 0
+for k in pairs(data) do
+  count = count + 1
+end
+assert(count == 1 and data[obj] == "attached")
+
+-- a chain: value of one entry is the key of another
+local chain = setmetatable({}, { __mode = "k" })
+local head = {}
+local mid = {}
+chain[head] = mid
+chain[mid] = { last = true }
+mid = nil
+collectgarbage()
+print(chain[chain[head]].last)
+
+-- the metatable can be protected
+local p = setmetatable({}, { __metatable = "locked" })
+print(getmetatable(p))
+print(pcall(setmetatable, p, {}))
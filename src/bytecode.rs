@@ -7,23 +7,40 @@ use std::fmt::Debug;
 /// ## ByteCode
 ///
 /// ByteCode of rua.
-#[derive(Clone, Copy)]
+///
+/// Operands named `index`/`dst`/`src` are register indexes relative to the
+/// base of current function. For `Call`/`Return`/`VarArgs`, a count `n` is
+/// encoded as `n + 1`, and `0` means "up to the top of the stack".
+#[derive(Clone, Copy, PartialEq)]
 pub enum ByteCode {
   /// ### format
-  /// (target stack index, global index)
-  GetGlobal(u8, u8),
+  /// (target stack index, upvalue index)
+  GetUpval(u8, u8),
   /// ### format
-  /// (target stack index, global index)
-  SetGlobal(u8, u8),
+  /// (upvalue index, source stack index)
+  SetUpval(u8, u8),
   /// ### format
-  /// (target stack index, const index)
-  SetGlobalConst(u8, u8),
+  /// (target stack index, table<upvalue>.index, key<literal>.index)
+  ///
+  /// Globals are accessed by this, with the upvalue `_ENV`.
+  GetTabUp(u8, u8, u8),
+  /// ### format
+  /// (table<upvalue>.index, key<literal>.index, value.index)
+  SetTabUp(u8, u8, u8),
+  /// ### format
+  /// (first stack index)
+  ///
+  /// Close the upvalues and to-be-closed variables of the registers from
+  /// the index on.
+  Close(u8),
   /// ### format
-  /// (target stack index, global index)
-  SetGlobalGlobal(u8, u8),
+  /// (variable stack index)
+  ///
+  /// Mark the variable to be closed.
+  Tbc(u8),
   /// ### format
   /// (target stack index, const index)
-  LoadConst(u8, u8),
+  LoadConst(u8, u16),
   /// ### format
   /// (target stack index)
   LoadNil(u8),
@@ -34,8 +51,14 @@ pub enum ByteCode {
   /// (target stack index, int if in range of i16)
   LoadInt(u8, i16),
   /// ### format
-  /// (target stack index, arg count)
-  Call(u8, u8),
+  /// (func index, arg count + 1, result count + 1)
+  Call(u8, u8, u8),
+  /// ### format
+  /// (func index, arg count + 1)
+  TailCall(u8, u8),
+  /// ### format
+  /// (first result index, result count + 1)
+  Return(u8, u8),
   /// ### format
   /// (destination index, source index)
   Move(u8, u8),
@@ -49,44 +72,296 @@ pub enum ByteCode {
   /// (table.index, key<literal>.index, value.index)
   SetField(u8, u8, u8),
   /// ### format
-  /// (table.index, item.count)
+  /// (table.index, key<integer>, value.index)
+  SetInt(u8, u8, u8),
+  /// ### format
+  /// (table.index, item.count, `0` for up to top)
   SetList(u8, u8),
+  /// ### format
+  /// (target stack index, table.index, key<on_stack>.index)
+  GetTable(u8, u8, u8),
+  /// ### format
+  /// (target stack index, table.index, key<literal>.index)
+  GetField(u8, u8, u8),
+  /// ### format
+  /// (target stack index, table.index, key<integer>)
+  GetInt(u8, u8, u8),
+  /// ### format
+  /// (target stack index, table.index, key<literal>.index)
+  ///
+  /// `target = table[key]; target + 1 = table`
+  Method(u8, u8, u8),
+  /// ### format
+  /// (target stack index, inner proto index)
+  Closure(u8, u16),
+  /// ### format
+  /// (target stack index, count + 1)
+  VarArgs(u8, u8),
+  /// ### format
+  /// (pc offset)
+  Jump(i16),
+  /// ### format
+  /// (condition index, pc offset)
+  JumpIfTrue(u8, i16),
+  /// ### format
+  /// (condition index, pc offset)
+  JumpIfFalse(u8, i16),
+  /// ### format
+  /// (loop base index, pc offset to skip the loop)
+  ForPrepare(u8, u16),
+  /// ### format
+  /// (loop base index, pc offset back to the body)
+  ForLoop(u8, u16),
+  /// ### format
+  /// (loop base index, variable count)
+  ///
+  /// Call the iterator of a generic for loop, whose registers are
+  /// `(f, s, control, closing, var...)`, as `var... = f(s, control)`.
+  ForCall(u8, u8),
+  /// ### format
+  /// (loop base index, pc offset back to the body)
+  ///
+  /// Continue the generic for loop unless the first variable is `nil`.
+  ForGenericLoop(u8, u16),
+  /// ### format
+  /// (target stack index, operand index)
+  Neg(u8, u8),
+  /// ### format
+  /// (target stack index, operand index)
+  Not(u8, u8),
+  /// ### format
+  /// (target stack index, operand index)
+  BitNot(u8, u8),
+  /// ### format
+  /// (target stack index, operand index)
+  Len(u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  Add(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  Sub(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  Mul(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  Div(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  Idiv(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  Mod(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  Pow(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  BitAnd(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  BitOr(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  BitXor(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  ShiftL(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  ShiftR(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  Concat(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  Equal(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  NotEq(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  Less(u8, u8, u8),
+  /// ### format
+  /// (target stack index, left operand index, right operand index)
+  LesEq(u8, u8, u8),
+}
+
+impl ByteCode {
+  /// Encode it in 4 bytes for binary chunks: the opcode, then the operands
+  /// in order, little-endian and padded with zeros.
+  pub fn encode(self) -> [u8; 4] {
+    let (op, x) = match self {
+      Self::GetUpval(a, b) => (0, [a, b, 0]),
+      Self::SetUpval(a, b) => (1, [a, b, 0]),
+      Self::GetTabUp(a, b, c) => (2, [a, b, c]),
+      Self::SetTabUp(a, b, c) => (3, [a, b, c]),
+      Self::Close(a) => (4, [a, 0, 0]),
+      Self::Tbc(a) => (5, [a, 0, 0]),
+      Self::LoadConst(a, b) => (6, [a, b as u8, (b >> 8) as u8]),
+      Self::LoadNil(a) => (7, [a, 0, 0]),
+      Self::LoadBool(a, b) => (8, [a, b as u8, 0]),
+      Self::LoadInt(a, b) => (9, [a, b as u8, (b >> 8) as u8]),
+      Self::Call(a, b, c) => (10, [a, b, c]),
+      Self::TailCall(a, b) => (11, [a, b, 0]),
+      Self::Return(a, b) => (12, [a, b, 0]),
+      Self::Move(a, b) => (13, [a, b, 0]),
+      Self::NewTable(a, b, c) => (14, [a, b, c]),
+      Self::SetTable(a, b, c) => (15, [a, b, c]),
+      Self::SetField(a, b, c) => (16, [a, b, c]),
+      Self::SetInt(a, b, c) => (17, [a, b, c]),
+      Self::SetList(a, b) => (18, [a, b, 0]),
+      Self::GetTable(a, b, c) => (19, [a, b, c]),
+      Self::GetField(a, b, c) => (20, [a, b, c]),
+      Self::GetInt(a, b, c) => (21, [a, b, c]),
+      Self::Method(a, b, c) => (22, [a, b, c]),
+      Self::Closure(a, b) => (23, [a, b as u8, (b >> 8) as u8]),
+      Self::VarArgs(a, b) => (24, [a, b, 0]),
+      Self::Jump(a) => (25, [a as u8, (a >> 8) as u8, 0]),
+      Self::JumpIfTrue(a, b) => (26, [a, b as u8, (b >> 8) as u8]),
+      Self::JumpIfFalse(a, b) => (27, [a, b as u8, (b >> 8) as u8]),
+      Self::ForPrepare(a, b) => (28, [a, b as u8, (b >> 8) as u8]),
+      Self::ForLoop(a, b) => (29, [a, b as u8, (b >> 8) as u8]),
+      Self::ForCall(a, b) => (30, [a, b, 0]),
+      Self::ForGenericLoop(a, b) => (31, [a, b as u8, (b >> 8) as u8]),
+      Self::Neg(a, b) => (32, [a, b, 0]),
+      Self::Not(a, b) => (33, [a, b, 0]),
+      Self::BitNot(a, b) => (34, [a, b, 0]),
+      Self::Len(a, b) => (35, [a, b, 0]),
+      Self::Add(a, b, c) => (36, [a, b, c]),
+      Self::Sub(a, b, c) => (37, [a, b, c]),
+      Self::Mul(a, b, c) => (38, [a, b, c]),
+      Self::Div(a, b, c) => (39, [a, b, c]),
+      Self::Idiv(a, b, c) => (40, [a, b, c]),
+      Self::Mod(a, b, c) => (41, [a, b, c]),
+      Self::Pow(a, b, c) => (42, [a, b, c]),
+      Self::BitAnd(a, b, c) => (43, [a, b, c]),
+      Self::BitOr(a, b, c) => (44, [a, b, c]),
+      Self::BitXor(a, b, c) => (45, [a, b, c]),
+      Self::ShiftL(a, b, c) => (46, [a, b, c]),
+      Self::ShiftR(a, b, c) => (47, [a, b, c]),
+      Self::Concat(a, b, c) => (48, [a, b, c]),
+      Self::Equal(a, b, c) => (49, [a, b, c]),
+      Self::NotEq(a, b, c) => (50, [a, b, c]),
+      Self::Less(a, b, c) => (51, [a, b, c]),
+      Self::LesEq(a, b, c) => (52, [a, b, c]),
+    };
+    [op, x[0], x[1], x[2]]
+  }
+
+  /// Decode the bytes given by [`ByteCode::encode`], `None` for an
+  /// unknown opcode.
+  pub fn decode(code: [u8; 4]) -> Option<Self> {
+    let x = &code[1..];
+    Some(match code[0] {
+      0 => Self::GetUpval(x[0], x[1]),
+      1 => Self::SetUpval(x[0], x[1]),
+      2 => Self::GetTabUp(x[0], x[1], x[2]),
+      3 => Self::SetTabUp(x[0], x[1], x[2]),
+      4 => Self::Close(x[0]),
+      5 => Self::Tbc(x[0]),
+      6 => Self::LoadConst(x[0], u16::from_le_bytes([x[1], x[2]])),
+      7 => Self::LoadNil(x[0]),
+      8 => Self::LoadBool(x[0], x[1] != 0),
+      9 => Self::LoadInt(x[0], i16::from_le_bytes([x[1], x[2]])),
+      10 => Self::Call(x[0], x[1], x[2]),
+      11 => Self::TailCall(x[0], x[1]),
+      12 => Self::Return(x[0], x[1]),
+      13 => Self::Move(x[0], x[1]),
+      14 => Self::NewTable(x[0], x[1], x[2]),
+      15 => Self::SetTable(x[0], x[1], x[2]),
+      16 => Self::SetField(x[0], x[1], x[2]),
+      17 => Self::SetInt(x[0], x[1], x[2]),
+      18 => Self::SetList(x[0], x[1]),
+      19 => Self::GetTable(x[0], x[1], x[2]),
+      20 => Self::GetField(x[0], x[1], x[2]),
+      21 => Self::GetInt(x[0], x[1], x[2]),
+      22 => Self::Method(x[0], x[1], x[2]),
+      23 => Self::Closure(x[0], u16::from_le_bytes([x[1], x[2]])),
+      24 => Self::VarArgs(x[0], x[1]),
+      25 => Self::Jump(i16::from_le_bytes([x[0], x[1]])),
+      26 => Self::JumpIfTrue(x[0], i16::from_le_bytes([x[1], x[2]])),
+      27 => Self::JumpIfFalse(x[0], i16::from_le_bytes([x[1], x[2]])),
+      28 => Self::ForPrepare(x[0], u16::from_le_bytes([x[1], x[2]])),
+      29 => Self::ForLoop(x[0], u16::from_le_bytes([x[1], x[2]])),
+      30 => Self::ForCall(x[0], x[1]),
+      31 => Self::ForGenericLoop(x[0], u16::from_le_bytes([x[1], x[2]])),
+      32 => Self::Neg(x[0], x[1]),
+      33 => Self::Not(x[0], x[1]),
+      34 => Self::BitNot(x[0], x[1]),
+      35 => Self::Len(x[0], x[1]),
+      36 => Self::Add(x[0], x[1], x[2]),
+      37 => Self::Sub(x[0], x[1], x[2]),
+      38 => Self::Mul(x[0], x[1], x[2]),
+      39 => Self::Div(x[0], x[1], x[2]),
+      40 => Self::Idiv(x[0], x[1], x[2]),
+      41 => Self::Mod(x[0], x[1], x[2]),
+      42 => Self::Pow(x[0], x[1], x[2]),
+      43 => Self::BitAnd(x[0], x[1], x[2]),
+      44 => Self::BitOr(x[0], x[1], x[2]),
+      45 => Self::BitXor(x[0], x[1], x[2]),
+      46 => Self::ShiftL(x[0], x[1], x[2]),
+      47 => Self::ShiftR(x[0], x[1], x[2]),
+      48 => Self::Concat(x[0], x[1], x[2]),
+      49 => Self::Equal(x[0], x[1], x[2]),
+      50 => Self::NotEq(x[0], x[1], x[2]),
+      51 => Self::Less(x[0], x[1], x[2]),
+      52 => Self::LesEq(x[0], x[1], x[2]),
+      _ => return None,
+    })
+  }
 }
 
 impl Debug for ByteCode {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     const TO: &str = "To.Stack.Index";
     const FROM: &str = "From.Constants.Index";
+    const SRC: &str = "From.Stack.Index";
+    const UPVAL: &str = "Upvalue.Index";
     const FUNC: &str = "Func.Index";
     const ARG: &str = "Func.Arg.Count";
+    const RESULT: &str = "Func.Result.Count";
     const TABLE: &str = "Table.Index";
     const NARRAY: &str = "Table.Array.Len";
     const NMAP: &str = "Table.Map.Len";
     const CKEY: &str = "Key<Constants>.Index";
     const VKEY: &str = "Key<Stack>.Index";
+    const IKEY: &str = "Key<Integer>";
     const VALUE: &str = "Value.Index";
     const ITEM: &str = "Inserted.Item.Count";
+    const PROTO: &str = "Proto.Index";
+    const JUMP: &str = "Jump.Offset";
+    const COND: &str = "Condition.Index";
+    const LOOP: &str = "Loop.Index";
+    const VARS: &str = "Loop.Var.Count";
+    const LEFT: &str = "Left.Index";
+    const RIGHT: &str = "Right.Index";
     match self {
-      Self::GetGlobal(arg0, arg1) => f
-        .debug_struct("GetGlobal")
+      Self::GetUpval(arg0, arg1) => f
+        .debug_struct("GetUpval")
         .field(TO, arg0)
-        .field(FROM, arg1)
+        .field(UPVAL, arg1)
         .finish(),
-      Self::SetGlobal(arg0, arg1) => f
-        .debug_struct("SetGlobal")
-        .field(TO, arg0)
-        .field(FROM, arg1)
+      Self::SetUpval(arg0, arg1) => f
+        .debug_struct("SetUpval")
+        .field(UPVAL, arg0)
+        .field(SRC, arg1)
         .finish(),
-      Self::SetGlobalConst(arg0, arg1) => f
-        .debug_struct("SetGlobalConst")
+      Self::GetTabUp(arg0, arg1, arg2) => f
+        .debug_struct("GetTabUp")
         .field(TO, arg0)
-        .field(FROM, arg1)
+        .field(UPVAL, arg1)
+        .field(CKEY, arg2)
         .finish(),
-      Self::SetGlobalGlobal(arg0, arg1) => f
-        .debug_struct("SetGlobalGlobal")
-        .field(TO, arg0)
-        .field(FROM, arg1)
+      Self::SetTabUp(arg0, arg1, arg2) => f
+        .debug_struct("SetTabUp")
+        .field(UPVAL, arg0)
+        .field(CKEY, arg1)
+        .field(VALUE, arg2)
         .finish(),
+      Self::Close(arg0) => f.debug_struct("Close").field(SRC, arg0).finish(),
+      Self::Tbc(arg0) => f.debug_struct("Tbc").field(SRC, arg0).finish(),
       Self::LoadConst(arg0, arg1) => f
         .debug_struct("LoadConst")
         .field(TO, arg0)
@@ -103,15 +378,26 @@ impl Debug for ByteCode {
         .field(TO, arg0)
         .field(FROM, arg1)
         .finish(),
-      Self::Call(arg0, arg1) => f
+      Self::Call(arg0, arg1, arg2) => f
         .debug_struct("Call")
         .field(FUNC, arg0)
         .field(ARG, arg1)
+        .field(RESULT, arg2)
+        .finish(),
+      Self::TailCall(arg0, arg1) => f
+        .debug_struct("TailCall")
+        .field(FUNC, arg0)
+        .field(ARG, arg1)
+        .finish(),
+      Self::Return(arg0, arg1) => f
+        .debug_struct("Return")
+        .field(SRC, arg0)
+        .field(RESULT, arg1)
         .finish(),
       Self::Move(arg0, arg1) => f
         .debug_struct("Move")
         .field(TO, arg0)
-        .field(FROM, arg1)
+        .field(SRC, arg1)
         .finish(),
       Self::NewTable(arg0, arg1, arg2) => f
         .debug_struct("NewTable")
@@ -131,11 +417,204 @@ impl Debug for ByteCode {
         .field(CKEY, arg1)
         .field(VALUE, arg2)
         .finish(),
+      Self::SetInt(arg0, arg1, arg2) => f
+        .debug_struct("SetInt")
+        .field(TABLE, arg0)
+        .field(IKEY, arg1)
+        .field(VALUE, arg2)
+        .finish(),
       Self::SetList(arg0, arg1) => f
         .debug_struct("SetList")
         .field(TABLE, arg0)
         .field(ITEM, arg1)
         .finish(),
+      Self::GetTable(arg0, arg1, arg2) => f
+        .debug_struct("GetTable")
+        .field(TO, arg0)
+        .field(TABLE, arg1)
+        .field(VKEY, arg2)
+        .finish(),
+      Self::GetField(arg0, arg1, arg2) => f
+        .debug_struct("GetField")
+        .field(TO, arg0)
+        .field(TABLE, arg1)
+        .field(CKEY, arg2)
+        .finish(),
+      Self::GetInt(arg0, arg1, arg2) => f
+        .debug_struct("GetInt")
+        .field(TO, arg0)
+        .field(TABLE, arg1)
+        .field(IKEY, arg2)
+        .finish(),
+      Self::Method(arg0, arg1, arg2) => f
+        .debug_struct("Method")
+        .field(TO, arg0)
+        .field(TABLE, arg1)
+        .field(CKEY, arg2)
+        .finish(),
+      Self::Closure(arg0, arg1) => f
+        .debug_struct("Closure")
+        .field(TO, arg0)
+        .field(PROTO, arg1)
+        .finish(),
+      Self::VarArgs(arg0, arg1) => f
+        .debug_struct("VarArgs")
+        .field(TO, arg0)
+        .field(RESULT, arg1)
+        .finish(),
+      Self::Jump(arg0) => f.debug_struct("Jump").field(JUMP, arg0).finish(),
+      Self::JumpIfTrue(arg0, arg1) => f
+        .debug_struct("JumpIfTrue")
+        .field(COND, arg0)
+        .field(JUMP, arg1)
+        .finish(),
+      Self::JumpIfFalse(arg0, arg1) => f
+        .debug_struct("JumpIfFalse")
+        .field(COND, arg0)
+        .field(JUMP, arg1)
+        .finish(),
+      Self::ForPrepare(arg0, arg1) => f
+        .debug_struct("ForPrepare")
+        .field(LOOP, arg0)
+        .field(JUMP, arg1)
+        .finish(),
+      Self::ForLoop(arg0, arg1) => f
+        .debug_struct("ForLoop")
+        .field(LOOP, arg0)
+        .field(JUMP, arg1)
+        .finish(),
+      Self::ForCall(arg0, arg1) => f
+        .debug_struct("ForCall")
+        .field(LOOP, arg0)
+        .field(VARS, arg1)
+        .finish(),
+      Self::ForGenericLoop(arg0, arg1) => f
+        .debug_struct("ForGenericLoop")
+        .field(LOOP, arg0)
+        .field(JUMP, arg1)
+        .finish(),
+      Self::Neg(arg0, arg1) => f
+        .debug_struct("Neg")
+        .field(TO, arg0)
+        .field(SRC, arg1)
+        .finish(),
+      Self::Not(arg0, arg1) => f
+        .debug_struct("Not")
+        .field(TO, arg0)
+        .field(SRC, arg1)
+        .finish(),
+      Self::BitNot(arg0, arg1) => f
+        .debug_struct("BitNot")
+        .field(TO, arg0)
+        .field(SRC, arg1)
+        .finish(),
+      Self::Len(arg0, arg1) => f
+        .debug_struct("Len")
+        .field(TO, arg0)
+        .field(SRC, arg1)
+        .finish(),
+      Self::Add(arg0, arg1, arg2) => f
+        .debug_struct("Add")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::Sub(arg0, arg1, arg2) => f
+        .debug_struct("Sub")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::Mul(arg0, arg1, arg2) => f
+        .debug_struct("Mul")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::Div(arg0, arg1, arg2) => f
+        .debug_struct("Div")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::Idiv(arg0, arg1, arg2) => f
+        .debug_struct("Idiv")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::Mod(arg0, arg1, arg2) => f
+        .debug_struct("Mod")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::Pow(arg0, arg1, arg2) => f
+        .debug_struct("Pow")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::BitAnd(arg0, arg1, arg2) => f
+        .debug_struct("BitAnd")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::BitOr(arg0, arg1, arg2) => f
+        .debug_struct("BitOr")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::BitXor(arg0, arg1, arg2) => f
+        .debug_struct("BitXor")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::ShiftL(arg0, arg1, arg2) => f
+        .debug_struct("ShiftL")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::ShiftR(arg0, arg1, arg2) => f
+        .debug_struct("ShiftR")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::Concat(arg0, arg1, arg2) => f
+        .debug_struct("Concat")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::Equal(arg0, arg1, arg2) => f
+        .debug_struct("Equal")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::NotEq(arg0, arg1, arg2) => f
+        .debug_struct("NotEq")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::Less(arg0, arg1, arg2) => f
+        .debug_struct("Less")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
+      Self::LesEq(arg0, arg1, arg2) => f
+        .debug_struct("LesEq")
+        .field(TO, arg0)
+        .field(LEFT, arg1)
+        .field(RIGHT, arg2)
+        .finish(),
     }
   }
 }
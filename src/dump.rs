@@ -0,0 +1,369 @@
+//! # Dump
+//!
+//! Binary chunks of rua, as `string.dump` gives and `load` takes.
+//!
+//! A chunk is a header followed by the main function. The header starts
+//! with `"\x1bLua"` and the version, as the chunks of Lua do, but the
+//! format byte differs since the bytecodes of rua are its own, so the
+//! chunks of one are rejected by the other. Sizes are written as varints,
+//! numbers little-endian, and each bytecode in the 4 bytes of
+//! [`ByteCode::encode`]. The debug information (source, lines, local
+//! variables and upvalue names) is left out of stripped chunks.
+
+use crate::{
+  bytecode::ByteCode,
+  parse::{FuncProto, LocVar, UpvalDesc},
+  value::Value,
+};
+use std::rc::Rc;
+
+/// Signature of binary chunks, as `LUA_SIGNATURE`.
+pub const SIGNATURE: &[u8] = b"\x1bLua";
+/// Version of the chunks, 5.4 as `LUAC_VERSION`.
+const VERSION: u8 = 0x54;
+/// Format of the chunks, `0` is the official one of Lua.
+const FORMAT: u8 = 0x72;
+/// Data to catch the conversions of text files, as `LUAC_DATA`.
+const DATA: &[u8] = b"\x19\x93\r\n\x1a\n";
+/// Integer to check the byte order, as `LUAC_INT`.
+const CHECK_INT: i64 = 0x5678;
+/// Float to check the float format, as `LUAC_NUM`.
+const CHECK_NUM: f64 = 370.5;
+
+/// Tags of constants, as the variant tags of Lua.
+const TAG_NIL: u8 = 0x00;
+const TAG_FALSE: u8 = 0x01;
+const TAG_TRUE: u8 = 0x11;
+const TAG_INT: u8 = 0x03;
+const TAG_FLOAT: u8 = 0x13;
+const TAG_STR: u8 = 0x04;
+
+/// Dump `proto` to a binary chunk, without the debug information if `strip`.
+pub fn dump(proto: &FuncProto, strip: bool) -> Vec<u8> {
+  let mut dumper = Dumper {
+    buf: Vec::new(),
+    strip,
+  };
+  dumper.header();
+  dumper.buf.push(proto.upvalues.len() as u8);
+  dumper.function(proto, None);
+  dumper.buf
+}
+
+/// Load the binary chunk, return the message `chunkname: bad binary format
+/// (why)` if it's malformed.
+pub fn undump(chunk: &[u8], chunkname: &str) -> Result<Rc<FuncProto>, String> {
+  let mut undumper = Undumper { chunk, pos: 0 };
+  let proto = undumper
+    .header()
+    .and_then(|()| undumper.byte())
+    .and_then(|_| undumper.function(""))
+    .map_err(|why| {
+      let chunk_id = crate::vm::chunk_id(chunkname);
+      format!("{chunk_id}: bad binary format ({why})")
+    })?;
+  Ok(Rc::new(proto))
+}
+
+struct Dumper {
+  buf: Vec<u8>,
+  strip: bool,
+}
+
+impl Dumper {
+  fn header(&mut self) {
+    self.buf.extend_from_slice(SIGNATURE);
+    self.buf.push(VERSION);
+    self.buf.push(FORMAT);
+    self.buf.extend_from_slice(DATA);
+    self.buf.push(std::mem::size_of::<u32>() as u8);
+    self.buf.push(std::mem::size_of::<i64>() as u8);
+    self.buf.push(std::mem::size_of::<f64>() as u8);
+    self.integer(CHECK_INT);
+    self.float(CHECK_NUM);
+  }
+
+  /// A size in 7-bit groups, most significant first, with the high bit set
+  /// in the last one, as `dumpSize`.
+  fn size(&mut self, n: usize) {
+    let mut groups = vec![(n & 0x7f) as u8 | 0x80];
+    let mut n = n >> 7;
+    while n != 0 {
+      groups.push((n & 0x7f) as u8);
+      n >>= 7;
+    }
+    self.buf.extend(groups.iter().rev());
+  }
+
+  fn integer(&mut self, i: i64) {
+    self.buf.extend_from_slice(&i.to_le_bytes());
+  }
+
+  fn float(&mut self, f: f64) {
+    self.buf.extend_from_slice(&f.to_le_bytes());
+  }
+
+  /// A string as its size plus one, `0` for none.
+  fn string(&mut self, s: Option<&[u8]>) {
+    match s {
+      Some(s) => {
+        self.size(s.len() + 1);
+        self.buf.extend_from_slice(s);
+      }
+      None => self.size(0),
+    }
+  }
+
+  /// Dump `proto`, whose source is left out if the same as its parent's.
+  fn function(&mut self, proto: &FuncProto, parent_source: Option<&str>) {
+    match self.strip || parent_source == Some(&proto.source) {
+      true => self.string(None),
+      false => self.string(Some(proto.source.as_bytes())),
+    }
+    self.size(proto.line_defined);
+    self.size(proto.last_line_defined);
+    self.size(proto.nparam);
+    self.buf.push(proto.is_vararg as u8);
+    self.size(proto.max_stack);
+
+    self.size(proto.bytecodes.len());
+    for code in &proto.bytecodes {
+      self.buf.extend_from_slice(&code.encode());
+    }
+
+    self.size(proto.constants.len());
+    for k in &proto.constants {
+      match k {
+        Value::Nil => self.buf.push(TAG_NIL),
+        Value::Boolean(false) => self.buf.push(TAG_FALSE),
+        Value::Boolean(true) => self.buf.push(TAG_TRUE),
+        Value::Integer(i) => {
+          self.buf.push(TAG_INT);
+          self.integer(*i);
+        }
+        Value::Float(f) => {
+          self.buf.push(TAG_FLOAT);
+          self.float(*f);
+        }
+        v if v.is_str() => {
+          self.buf.push(TAG_STR);
+          self.string(Some(v.into()));
+        }
+        v => unreachable!("constant {v:?}"),
+      }
+    }
+
+    self.size(proto.upvalues.len());
+    for up in &proto.upvalues {
+      self.buf.push(up.in_stack as u8);
+      self.size(up.index);
+    }
+
+    self.size(proto.protos.len());
+    for p in &proto.protos {
+      self.function(p, Some(&proto.source));
+    }
+
+    // debug information
+    let lineinfo: &[usize] = if self.strip { &[] } else { &proto.lineinfo };
+    self.size(lineinfo.len());
+    for &line in lineinfo {
+      self.size(line);
+    }
+    let locvars: &[LocVar] = if self.strip { &[] } else { &proto.locvars };
+    self.size(locvars.len());
+    for var in locvars {
+      self.string(Some(var.name.as_bytes()));
+      self.size(var.start_pc);
+      self.size(var.end_pc);
+    }
+    let nnames = if self.strip { 0 } else { proto.upvalues.len() };
+    self.size(nnames);
+    for up in &proto.upvalues[..nnames] {
+      self.string(Some(up.name.as_bytes()));
+    }
+  }
+}
+
+struct Undumper<'a> {
+  chunk: &'a [u8],
+  pos: usize,
+}
+
+impl Undumper<'_> {
+  fn header(&mut self) -> Result<(), &'static str> {
+    if self.block(SIGNATURE.len())? != SIGNATURE {
+      return Err("not a binary chunk");
+    }
+    if self.byte()? != VERSION {
+      return Err("version mismatch");
+    }
+    if self.byte()? != FORMAT {
+      return Err("format mismatch");
+    }
+    if self.block(DATA.len())? != DATA {
+      return Err("corrupted chunk");
+    }
+    if self.byte()? as usize != std::mem::size_of::<u32>() {
+      return Err("Instruction size mismatch");
+    }
+    if self.byte()? as usize != std::mem::size_of::<i64>() {
+      return Err("lua_Integer size mismatch");
+    }
+    if self.byte()? as usize != std::mem::size_of::<f64>() {
+      return Err("lua_Number size mismatch");
+    }
+    if self.integer()? != CHECK_INT {
+      return Err("integer format mismatch");
+    }
+    if self.float()? != CHECK_NUM {
+      return Err("float format mismatch");
+    }
+    Ok(())
+  }
+
+  fn block(&mut self, n: usize) -> Result<&[u8], &'static str> {
+    let block = self
+      .chunk
+      .get(self.pos..)
+      .and_then(|rest| rest.get(..n))
+      .ok_or("truncated chunk")?;
+    self.pos += n;
+    Ok(block)
+  }
+
+  fn byte(&mut self) -> Result<u8, &'static str> {
+    Ok(self.block(1)?[0])
+  }
+
+  fn size(&mut self, limit: usize) -> Result<usize, &'static str> {
+    let mut n: usize = 0;
+    loop {
+      let b = self.byte()?;
+      n = n
+        .checked_mul(0x80)
+        .map(|n| n | (b & 0x7f) as usize)
+        .filter(|&n| n <= limit)
+        .ok_or("integer overflow")?;
+      if b & 0x80 != 0 {
+        return Ok(n);
+      }
+    }
+  }
+
+  /// A count of items, which can't be more than the bytes left.
+  fn count(&mut self) -> Result<usize, &'static str> {
+    let left = self.chunk.len() - self.pos;
+    self.size(left).map_err(|_| "truncated chunk")
+  }
+
+  fn integer(&mut self) -> Result<i64, &'static str> {
+    Ok(i64::from_le_bytes(self.block(8)?.try_into().unwrap()))
+  }
+
+  fn float(&mut self) -> Result<f64, &'static str> {
+    Ok(f64::from_le_bytes(self.block(8)?.try_into().unwrap()))
+  }
+
+  fn string(&mut self) -> Result<Option<&[u8]>, &'static str> {
+    match self.count()? {
+      0 => Ok(None),
+      n => self.block(n - 1).map(Some),
+    }
+  }
+
+  fn name(&mut self) -> Result<String, &'static str> {
+    let s = self.string()?.unwrap_or_default();
+    Ok(String::from_utf8_lossy(s).into_owned())
+  }
+
+  fn function(&mut self, parent_source: &str) -> Result<FuncProto, &'static str> {
+    let source = match self.string()? {
+      Some(s) => String::from_utf8_lossy(s).into_owned(),
+      None if parent_source.is_empty() => "=?".to_owned(),
+      None => parent_source.to_owned(),
+    };
+    let line_defined = self.size(usize::MAX)?;
+    let last_line_defined = self.size(usize::MAX)?;
+    let nparam = self.size(u8::MAX as usize)?;
+    let is_vararg = self.byte()? != 0;
+    let max_stack = self.size(u8::MAX as usize + 1)?;
+
+    let n = self.count()?;
+    let mut bytecodes = Vec::with_capacity(n);
+    for _ in 0..n {
+      let code = self.block(4)?.try_into().unwrap();
+      bytecodes.push(ByteCode::decode(code).ok_or("corrupted chunk")?);
+    }
+
+    let n = self.count()?;
+    let mut constants = Vec::with_capacity(n);
+    for _ in 0..n {
+      constants.push(match self.byte()? {
+        TAG_NIL => Value::Nil,
+        TAG_FALSE => Value::Boolean(false),
+        TAG_TRUE => Value::Boolean(true),
+        TAG_INT => Value::Integer(self.integer()?),
+        TAG_FLOAT => Value::Float(self.float()?),
+        TAG_STR => self.string()?.ok_or("corrupted chunk")?.into(),
+        _ => return Err("corrupted chunk"),
+      });
+    }
+
+    let n = self.count()?;
+    let mut upvalues = Vec::with_capacity(n);
+    for _ in 0..n {
+      upvalues.push(UpvalDesc {
+        name: String::new(),
+        in_stack: self.byte()? != 0,
+        index: self.size(usize::MAX)?,
+      });
+    }
+
+    let n = self.count()?;
+    let mut protos = Vec::with_capacity(n);
+    for _ in 0..n {
+      protos.push(Rc::new(self.function(&source)?));
+    }
+
+    let n = self.count()?;
+    if n != 0 && n != bytecodes.len() {
+      return Err("corrupted chunk");
+    }
+    let mut lineinfo = Vec::with_capacity(n);
+    for _ in 0..n {
+      lineinfo.push(self.size(usize::MAX)?);
+    }
+    let n = self.count()?;
+    let mut locvars = Vec::with_capacity(n);
+    for _ in 0..n {
+      locvars.push(LocVar {
+        name: self.name()?,
+        start_pc: self.size(usize::MAX)?,
+        end_pc: self.size(usize::MAX)?,
+      });
+    }
+    let n = self.count()?;
+    if n > upvalues.len() {
+      return Err("corrupted chunk");
+    }
+    for up in &mut upvalues[..n] {
+      up.name = self.name()?;
+    }
+
+    Ok(FuncProto {
+      source,
+      line_defined,
+      last_line_defined,
+      nparam,
+      is_vararg,
+      max_stack,
+      constants,
+      bytecodes,
+      lineinfo,
+      protos,
+      locvars,
+      upvalues,
+    })
+  }
+}
@@ -0,0 +1,47 @@
+//! # Error
+//!
+//! Definition of the error raised by rua at runtime.
+
+use crate::value::Value;
+use std::fmt::{self, Debug, Display};
+
+/// ## LuaError
+///
+/// An error object thrown by `error()` or by the vm itself, with the stack
+/// traceback captured at the point where it was raised.
+#[derive(Clone)]
+pub struct LuaError {
+  /// The error object, which is a message string in most cases.
+  pub value: Value,
+  /// Lua-style traceback: `"stack traceback:\n\t..."`.
+  pub traceback: String,
+}
+
+impl LuaError {
+  pub fn new(value: Value, traceback: String) -> Self {
+    Self { value, traceback }
+  }
+
+  /// The error object as a message, as the standalone `lua` shows it.
+  pub fn message(&self) -> String {
+    match &self.value {
+      Value::ShortStr(_) | Value::LongStr(_) => (&self.value).into(),
+      Value::Integer(_) | Value::Float(_) => self.value.to_string(),
+      v => format!("(error object is a {} value)", v.type_name()),
+    }
+  }
+}
+
+impl Display for LuaError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}\n{}", self.message(), self.traceback)
+  }
+}
+
+impl Debug for LuaError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    Display::fmt(self, f)
+  }
+}
+
+impl std::error::Error for LuaError {}
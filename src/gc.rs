@@ -0,0 +1,1077 @@
+//! # GC
+//!
+//! Garbage collector of rua.
+//!
+//! Objects are reference counted, which frees most of them as soon as they
+//! are unused, but not the ones in reference cycles. So the collectable
+//! objects (tables, closures, native closures, userdata and upvalues) are
+//! also registered in the `Heap`, which finds the unreachable ones by
+//! tracing, and clears them to break the cycles.
+//!
+//! Roots are not enumerated. As CPython does, an object is referenced from
+//! outside of the traced objects (the stack, the host, ...) if its strong
+//! count is more than the count of references from the traced objects, and
+//! everything reachable from these objects is alive. The data of userdata
+//! is opaque, so only their metatables are traced; a userdata is freed,
+//! which drops its data, when its last reference goes or its metatable is
+//! cleared as garbage.
+//!
+//! Weak tables (with `__mode` in the metatable) are traced as ephemerons:
+//! their weak references are counted as internal ones but not followed when
+//! marking, a value in a weak-keys table is marked only after its key is,
+//! and the entries referring to the garbage are removed before the garbage
+//! is cleared.
+//!
+//! Objects with a `__gc` metamethod when their metatables are set are
+//! marked for finalization, and held by the heap. When they become
+//! unreachable, they are resurrected with everything they refer to, and
+//! queued for the VM to call the finalizers; later collections free them as
+//! usual unless they are marked again.
+//!
+//! The memory in use by the objects of a state, strings included, is
+//! accounted to its heap on allocation/free, and the collector runs when it
+//! exceeds a threshold:
+//! - in incremental mode, a cycle is spread over steps interleaved with the
+//!   program: the references between objects are counted, the objects
+//!   referenced from outside are marked, and then the ones reachable from
+//!   them, a bounded amount of objects per step. As the program changes the
+//!   references meanwhile, the marks are only a hint: an atomic step traces
+//!   again the objects not marked and the tables to be cleared, keeping the
+//!   ones referenced from any other object, and the garbage found is
+//!   cleared in the following steps;
+//! - in generational mode, a minor collection traces only the objects
+//!   created since the last collection (older objects referring to them
+//!   count as outside references), and a major one traces all objects when
+//!   the memory grows too much since the last major one.
+
+use crate::{
+  string::{LongString, StringTable, SHORT_STR_MAX},
+  table::Table,
+  value::{LuaClosure, NativeClosure, Upvalue, UserData, Value},
+};
+use std::{
+  cell::{Cell, OnceCell, RefCell},
+  collections::{HashMap, HashSet, VecDeque},
+  mem,
+  ops::Range,
+  rc::{Rc, Weak},
+};
+
+/// Bytes allocated by the objects of a state, shared with the objects,
+/// which update it when they are resized or freed.
+#[derive(Debug, Clone, Default)]
+pub struct Allocated(Rc<Cell<usize>>);
+
+impl Allocated {
+  pub fn get(&self) -> usize {
+    self.0.get()
+  }
+
+  pub(crate) fn alloc(&self, size: usize) {
+    self.0.set(self.0.get() + size);
+  }
+
+  pub(crate) fn free(&self, size: usize) {
+    self.0.set(self.0.get().saturating_sub(size));
+  }
+}
+
+/// The accounting an object is charged to, which is set when a state takes
+/// the object. Objects created out of a state, as the constants of chunks,
+/// are not charged.
+#[derive(Debug, Default)]
+pub struct Charge(OnceCell<Allocated>);
+
+impl Charge {
+  /// Charge `size` bytes to `allocated`, return `false` if charged already.
+  pub(crate) fn set(&self, allocated: &Allocated, size: usize) -> bool {
+    let set = self.0.set(allocated.clone()).is_ok();
+    if set {
+      allocated.alloc(size);
+    }
+    set
+  }
+
+  pub(crate) fn is_set(&self) -> bool {
+    self.0.get().is_some()
+  }
+
+  /// Update the charge of an object resized from `old` to `new` bytes.
+  pub(crate) fn resize(&self, old: usize, new: usize) {
+    if let Some(allocated) = self.0.get() {
+      allocated.alloc(new);
+      allocated.free(old);
+    }
+  }
+
+  pub(crate) fn free(&self, size: usize) {
+    self.resize(size, 0);
+  }
+}
+
+/// A reference dropped by `drop_refs`.
+pub(crate) enum DropRef {
+  Value(Value),
+  Upvalue(Rc<RefCell<Upvalue>>),
+}
+
+/// Drop `refs`, moving the references held by the objects they free onto
+/// the worklist first, so a long chain of objects is freed iteratively
+/// instead of by recursive drops, which overflow the stack.
+pub(crate) fn drop_refs(mut refs: Vec<DropRef>) {
+  while let Some(r) = refs.pop() {
+    match &r {
+      DropRef::Value(Value::Table(t)) if Rc::strong_count(t) == 1 => {
+        if let Ok(mut t) = t.try_borrow_mut() {
+          t.take_refs(&mut refs);
+        }
+      }
+      DropRef::Value(Value::LuaFunction(c)) if Rc::strong_count(c) == 1 => {
+        c.take_refs(&mut refs);
+      }
+      DropRef::Value(Value::NativeClosure(c)) if Rc::strong_count(c) == 1 => {
+        if let Ok(mut upvalues) = c.upvalues.try_borrow_mut() {
+          refs.extend(upvalues.drain(..).map(DropRef::Value));
+        }
+      }
+      DropRef::Value(Value::UserData(u)) if Rc::strong_count(u) == 1 => {
+        if let Ok(mut mt) = u.metatable.try_borrow_mut() {
+          refs.extend(mt.take().map(|mt| DropRef::Value(Value::Table(mt))));
+        }
+      }
+      DropRef::Upvalue(u) if Rc::strong_count(u) == 1 => {
+        if let Ok(mut u) = u.try_borrow_mut() {
+          u.take_refs(&mut refs);
+        }
+      }
+      _ => (),
+    }
+    // dropped with nothing left to drop recursively
+    drop(r);
+  }
+}
+
+/// Collector modes, as `collectgarbage("incremental"|"generational")` sets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GcMode {
+  Incremental,
+  Generational,
+}
+
+impl GcMode {
+  pub fn name(&self) -> &'static str {
+    match self {
+      GcMode::Incremental => "incremental",
+      GcMode::Generational => "generational",
+    }
+  }
+}
+
+/// Whether the keys and the values of table `t` are weak, as its
+/// metatable's `__mode` says.
+fn weak_mode(t: &Table) -> (bool, bool) {
+  let Some(mode) = t.metatable.as_ref().and_then(|mt| {
+    let mt = mt.try_borrow().ok()?;
+    let mode = mt.get(&"__mode".into());
+    mode.is_str().then(|| <&[u8]>::from(&mode).to_vec())
+  }) else {
+    return (false, false);
+  };
+  (mode.contains(&b'k'), mode.contains(&b'v'))
+}
+
+/// A registered object.
+#[derive(Clone)]
+enum GcObject {
+  Table(Weak<RefCell<Table>>),
+  Closure(Weak<LuaClosure>),
+  NativeClosure(Weak<NativeClosure>),
+  UserData(Weak<UserData>),
+  Upvalue(Weak<RefCell<Upvalue>>),
+}
+
+impl GcObject {
+  fn upgrade(&self) -> Option<GcRef> {
+    match self {
+      GcObject::Table(t) => t.upgrade().map(GcRef::Table),
+      GcObject::Closure(c) => c.upgrade().map(GcRef::Closure),
+      GcObject::NativeClosure(c) => c.upgrade().map(GcRef::NativeClosure),
+      GcObject::UserData(u) => u.upgrade().map(GcRef::UserData),
+      GcObject::Upvalue(u) => u.upgrade().map(GcRef::Upvalue),
+    }
+  }
+
+  /// Address of the object, which is not reused while it's referenced,
+  /// even if the object is freed.
+  fn addr(&self) -> usize {
+    match self {
+      GcObject::Table(t) => Weak::as_ptr(t) as *const () as usize,
+      GcObject::Closure(c) => Weak::as_ptr(c) as *const () as usize,
+      GcObject::NativeClosure(c) => Weak::as_ptr(c) as *const () as usize,
+      GcObject::UserData(u) => Weak::as_ptr(u) as *const () as usize,
+      GcObject::Upvalue(u) => Weak::as_ptr(u) as *const () as usize,
+    }
+  }
+}
+
+/// A strong reference to a registered object, during a collection.
+#[derive(Clone)]
+enum GcRef {
+  Table(Rc<RefCell<Table>>),
+  Closure(Rc<LuaClosure>),
+  NativeClosure(Rc<NativeClosure>),
+  UserData(Rc<UserData>),
+  Upvalue(Rc<RefCell<Upvalue>>),
+}
+
+/// Address of the object `v` refers to, if it's collectable.
+fn value_addr(v: &Value) -> Option<usize> {
+  match v {
+    Value::Table(t) => Some(Rc::as_ptr(t) as *const () as usize),
+    Value::LuaFunction(c) => Some(Rc::as_ptr(c) as *const () as usize),
+    Value::NativeClosure(c) => Some(Rc::as_ptr(c) as *const () as usize),
+    Value::UserData(u) => Some(Rc::as_ptr(u) as *const () as usize),
+    _ => None,
+  }
+}
+
+impl GcRef {
+  fn addr(&self) -> usize {
+    match self {
+      GcRef::Table(t) => Rc::as_ptr(t) as *const () as usize,
+      GcRef::Closure(c) => Rc::as_ptr(c) as *const () as usize,
+      GcRef::NativeClosure(c) => Rc::as_ptr(c) as *const () as usize,
+      GcRef::UserData(u) => Rc::as_ptr(u) as *const () as usize,
+      GcRef::Upvalue(u) => Rc::as_ptr(u) as *const () as usize,
+    }
+  }
+
+  fn strong_count(&self) -> usize {
+    match self {
+      GcRef::Table(t) => Rc::strong_count(t),
+      GcRef::Closure(c) => Rc::strong_count(c),
+      GcRef::NativeClosure(c) => Rc::strong_count(c),
+      GcRef::UserData(u) => Rc::strong_count(u),
+      GcRef::Upvalue(u) => Rc::strong_count(u),
+    }
+  }
+
+  fn downgrade(&self) -> GcObject {
+    match self {
+      GcRef::Table(t) => GcObject::Table(Rc::downgrade(t)),
+      GcRef::Closure(c) => GcObject::Closure(Rc::downgrade(c)),
+      GcRef::NativeClosure(c) => GcObject::NativeClosure(Rc::downgrade(c)),
+      GcRef::UserData(u) => GcObject::UserData(Rc::downgrade(u)),
+      GcRef::Upvalue(u) => GcObject::Upvalue(Rc::downgrade(u)),
+    }
+  }
+
+  /// Visit the addresses of objects it refers to.
+  ///
+  /// Return `false` if it can't be inspected now, i.e. it's being borrowed.
+  fn children(&self, visit: &mut impl FnMut(usize)) -> bool {
+    match self {
+      GcRef::Table(t) => {
+        let Ok(t) = t.try_borrow() else {
+          return false;
+        };
+        // the index of the map holds another clone of each key
+        let values = t
+          .array
+          .iter()
+          .chain(t.map.entries().flat_map(|(k, v)| [k, k, v]));
+        values.filter_map(value_addr).for_each(&mut *visit);
+        if let Some(mt) = &t.metatable {
+          visit(Rc::as_ptr(mt) as *const () as usize);
+        }
+      }
+      GcRef::Closure(c) => {
+        for upvalue in c.upvalues.borrow().iter() {
+          visit(Rc::as_ptr(upvalue) as *const () as usize);
+        }
+      }
+      GcRef::NativeClosure(c) => {
+        let Ok(upvalues) = c.upvalues.try_borrow() else {
+          return false;
+        };
+        upvalues.iter().filter_map(value_addr).for_each(visit);
+      }
+      GcRef::UserData(u) => {
+        let Ok(mt) = u.metatable.try_borrow() else {
+          return false;
+        };
+        if let Some(mt) = &*mt {
+          visit(Rc::as_ptr(mt) as *const () as usize);
+        }
+      }
+      GcRef::Upvalue(u) => {
+        let Ok(u) = u.try_borrow() else {
+          return false;
+        };
+        if let Upvalue::Closed(v) = &*u {
+          value_addr(v).into_iter().for_each(visit);
+        }
+      }
+    }
+    true
+  }
+
+  /// Visit the addresses of objects it keeps alive, skipping the weak
+  /// references. `is_marked` tells whether an object is known alive.
+  fn traverse(
+    &self,
+    is_marked: &impl Fn(usize) -> bool,
+    visit: &mut impl FnMut(usize),
+  ) -> Traversal {
+    let mut traversal = Traversal::default();
+    let GcRef::Table(t) = self else {
+      self.children(visit);
+      return traversal;
+    };
+    let Ok(t) = t.try_borrow() else {
+      return traversal;
+    };
+    if let Some(mt) = &t.metatable {
+      visit(Rc::as_ptr(mt) as *const () as usize);
+    }
+    let (weak_k, weak_v) = weak_mode(&t);
+    traversal.clearable = weak_k || weak_v;
+    if !weak_v {
+      t.array.iter().filter_map(value_addr).for_each(&mut *visit);
+    }
+    for (k, v) in t.map.entries() {
+      // the dead entries do not keep their keys alive, for `next` needs
+      // only the keys its caller holds
+      if v.is_nil() {
+        traversal.clearable |= value_addr(k).is_some();
+        continue;
+      }
+      match value_addr(k) {
+        Some(addr) if weak_k => {
+          if weak_v {
+            continue;
+          } else if !is_marked(addr) {
+            traversal.pending = true;
+            continue;
+          }
+        }
+        Some(addr) => visit(addr),
+        None => (),
+      }
+      if !weak_v {
+        value_addr(v).into_iter().for_each(&mut *visit);
+      }
+    }
+    traversal
+  }
+
+  /// Remove the entries of a weak table whose values refer to dead
+  /// objects, and if `by_keys`, the ones whose keys do, and the dead entries
+  /// of dead keys in any table.
+  fn clear_weak(&self, is_dead: &impl Fn(&Value) -> bool, by_keys: bool) {
+    let GcRef::Table(t) = self else {
+      return;
+    };
+    let Ok(mut t) = t.try_borrow_mut() else {
+      return;
+    };
+    let (weak_k, weak_v) = weak_mode(&t);
+    let weak_k = weak_k && by_keys;
+    if !by_keys && !weak_v {
+      return;
+    }
+    let mut removed = vec![];
+    if weak_v {
+      for v in t.array.iter_mut().filter(|v| is_dead(v)) {
+        removed.push(mem::replace(v, Value::Nil));
+      }
+    }
+    // entries of dead keys are purged, while the ones of dead values are
+    // left as dead entries, for their keys may be used by a traversal; so
+    // are the dead entries of any table
+    let purged = |k: &Value, v: &Value| (weak_k || (by_keys && v.is_nil())) && is_dead(k);
+    let mut dead_keys = vec![];
+    let mut dead_values = vec![];
+    for (k, v) in t.map.entries() {
+      if purged(k, v) {
+        dead_keys.push(k.clone());
+      } else if weak_v && is_dead(v) {
+        dead_values.push(k.clone());
+      }
+    }
+    for k in dead_keys {
+      removed.extend(t.map.purge(&k).into_iter().flat_map(|(k, v)| [k, v]));
+    }
+    for k in dead_values {
+      removed.extend(t.map.remove(&k));
+    }
+    drop(t);
+    drop(removed);
+  }
+
+  /// Drop the references it holds, to break the cycles.
+  fn clear(&self) {
+    match self {
+      GcRef::Table(t) => {
+        let mut refs = vec![];
+        if let Ok(mut t) = t.try_borrow_mut() {
+          t.take_refs(&mut refs);
+        }
+        drop_refs(refs);
+      }
+      GcRef::Closure(_) => (),
+      GcRef::NativeClosure(c) => {
+        let mut refs = vec![];
+        if let Ok(mut upvalues) = c.upvalues.try_borrow_mut() {
+          refs.extend(upvalues.drain(..).map(DropRef::Value));
+        }
+        drop_refs(refs);
+      }
+      GcRef::UserData(u) => {
+        let mut refs = vec![];
+        if let Ok(mut mt) = u.metatable.try_borrow_mut() {
+          refs.extend(mt.take().map(|mt| DropRef::Value(Value::Table(mt))));
+        }
+        drop_refs(refs);
+      }
+      GcRef::Upvalue(u) => {
+        let mut refs = vec![];
+        if let Ok(mut u) = u.try_borrow_mut() {
+          u.take_refs(&mut refs);
+        }
+        drop_refs(refs);
+      }
+    }
+  }
+}
+
+/// What `GcRef::traverse` finds beyond the objects it visits.
+#[derive(Default)]
+struct Traversal {
+  /// Some values of a weak-keys table are skipped, for their keys are not
+  /// marked yet
+  pending: bool,
+  /// It's a table with weak references or dead entries, to be cleared of
+  /// the garbage
+  clearable: bool,
+}
+
+/// The marks of a collection over objects indexed by address. The objects
+/// are taken by `get`, from their indexes.
+#[derive(Default)]
+struct Marking {
+  index: HashMap<usize, usize>,
+  marked: Vec<bool>,
+  /// Objects marked but not traversed yet
+  gray: Vec<usize>,
+  /// Weak-keys tables with values not marked yet
+  ephemerons: Vec<usize>,
+  /// Tables traversed to be cleared, see `Traversal::clearable`
+  clearable: Vec<usize>,
+}
+
+impl Marking {
+  fn new(n: usize) -> Self {
+    Self {
+      index: HashMap::with_capacity(n),
+      marked: vec![false; n],
+      ..Default::default()
+    }
+  }
+
+  /// Whether the object at `addr` is known alive; objects not traced this
+  /// time are.
+  fn is_marked(&self, addr: usize) -> bool {
+    self.index.get(&addr).is_none_or(|&j| self.marked[j])
+  }
+
+  /// Whether the value refers to an object not marked.
+  fn is_dead(&self, v: &Value) -> bool {
+    value_addr(v).is_some_and(|addr| !self.is_marked(addr))
+  }
+
+  /// Mark the object `j` to be traversed, return whether it's newly marked.
+  fn mark(&mut self, j: usize) -> bool {
+    let newly = !mem::replace(&mut self.marked[j], true);
+    if newly {
+      self.gray.push(j);
+    }
+    newly
+  }
+
+  /// Traverse the object `i`, marking the objects it keeps alive, return
+  /// whether any is newly marked.
+  fn blacken(&mut self, i: usize, o: &GcRef) -> bool {
+    let mut children = vec![];
+    let traversal = o.traverse(&|addr| self.is_marked(addr), &mut |addr| {
+      children.push(addr)
+    });
+    if traversal.pending {
+      self.ephemerons.push(i);
+    }
+    if traversal.clearable {
+      self.clearable.push(i);
+    }
+    let mut progress = false;
+    for addr in children {
+      if let Some(&j) = self.index.get(&addr) {
+        progress |= self.mark(j);
+      }
+    }
+    progress
+  }
+
+  /// Mark the objects reachable from the gray ones, return whether any
+  /// object is newly marked.
+  fn propagate(&mut self, get: &impl Fn(usize) -> Option<GcRef>) -> bool {
+    let mut progress = false;
+    while let Some(i) = self.gray.pop() {
+      if let Some(o) = get(i) {
+        progress |= self.blacken(i, &o);
+      }
+    }
+    progress
+  }
+
+  /// Revisit the ephemerons until no more key is marked.
+  fn converge(&mut self, get: &impl Fn(usize) -> Option<GcRef>) {
+    while !self.ephemerons.is_empty() {
+      let pending = mem::take(&mut self.ephemerons);
+      self.gray.extend(pending);
+      if !self.propagate(get) {
+        break;
+      }
+    }
+  }
+
+  /// Tables to be cleared, each once.
+  fn clearable(&mut self) -> &[usize] {
+    self.clearable.sort_unstable();
+    self.clearable.dedup();
+    &self.clearable
+  }
+
+  /// Clear the tables to be cleared of the objects not marked, see
+  /// `GcRef::clear_weak`.
+  fn clear_weak(&mut self, get: &impl Fn(usize) -> Option<GcRef>, by_keys: bool) {
+    self.clearable();
+    let is_dead = |v: &Value| self.is_dead(v);
+    for o in self.clearable.iter().filter_map(|&i| get(i)) {
+      o.clear_weak(&is_dead, by_keys);
+    }
+  }
+}
+
+/// Split `objects` into the reachable ones and the garbage.
+///
+/// `finalizable` are the addresses of objects marked for finalization, in
+/// order of marking, each referenced once more by the heap. The unreachable
+/// ones of them are resurrected with all they refer to, and returned to be
+/// finalized.
+fn trace(objects: Vec<GcRef>, finalizable: &[usize]) -> (Vec<GcRef>, Vec<GcRef>, Vec<usize>) {
+  let mut marking = Marking::new(objects.len());
+  for (i, o) in objects.iter().enumerate() {
+    marking.index.insert(o.addr(), i);
+  }
+  let index = &marking.index;
+
+  // count the references from outside, excluding the one of `objects`
+  // and the one of the heap for finalization
+  let mut outside: Vec<isize> = objects
+    .iter()
+    .map(|o| o.strong_count() as isize - 1)
+    .collect();
+  for addr in finalizable {
+    if let Some(&j) = index.get(addr) {
+      outside[j] -= 1;
+    }
+  }
+  for (i, o) in objects.iter().enumerate() {
+    let inspected = o.children(&mut |addr| {
+      if let Some(&j) = index.get(&addr) {
+        outside[j] -= 1;
+      }
+    });
+    if !inspected {
+      // being used by someone, and so are its children
+      outside[i] = outside[i].max(1);
+    }
+  }
+
+  // mark from the objects referenced from outside
+  let get = |i: usize| Some(objects[i].clone());
+  for i in (0..objects.len()).filter(|&i| outside[i] > 0) {
+    marking.mark(i);
+  }
+  marking.propagate(&get);
+  marking.converge(&get);
+
+  // the resurrected objects are removed from weak values, but not from
+  // weak keys until they are really collected
+  marking.clear_weak(&get, false);
+
+  // resurrect the unreachable objects to be finalized
+  let to_finalize: Vec<usize> = finalizable
+    .iter()
+    .filter(|&&addr| !marking.is_marked(addr))
+    .copied()
+    .collect();
+  for addr in to_finalize.iter() {
+    marking.mark(marking.index[addr]);
+  }
+  marking.propagate(&get);
+  marking.converge(&get);
+
+  // remove the dead entries of weak tables, before anyone sees them
+  marking.clear_weak(&get, true);
+
+  let mut alive = vec![];
+  let mut garbage = vec![];
+  for (o, marked) in objects.into_iter().zip(marking.marked) {
+    match marked {
+      true => alive.push(o),
+      false => garbage.push(o),
+    }
+  }
+  (alive, garbage, to_finalize)
+}
+
+/// Phases of an incremental cycle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+  /// Index the objects by address
+  Index,
+  /// Count the references between the objects
+  Count,
+  /// Mark the objects referenced from outside
+  Roots,
+  /// Mark the objects reachable from the marked ones
+  Mark,
+  /// Move the survivors back to the heap, and clear the garbage
+  Sweep,
+}
+
+/// A cycle of the incremental collector, whose objects are traced in steps
+/// interleaved with the program.
+///
+/// The marks are not exact, as the program changes the references between
+/// the steps: a marked object may be unreachable now, and is collected by
+/// the next cycle, and an object not marked may be reachable by a path the
+/// marking missed. So the objects not marked are traced again at once, by
+/// `trace`, where any reference from the other objects is from outside.
+struct Cycle {
+  phase: Phase,
+  /// The objects traced, whose addresses are not reused during the cycle
+  objects: Vec<GcObject>,
+  marking: Marking,
+  /// References to each object from the others, as counted
+  internal: Vec<usize>,
+  /// Addresses of the objects marked for finalization, when roots are found
+  finalizable: HashSet<usize>,
+  /// Next object of the phase
+  cursor: usize,
+}
+
+impl Cycle {
+  fn new(objects: Vec<GcObject>) -> Self {
+    Self {
+      phase: Phase::Index,
+      marking: Marking::new(objects.len()),
+      internal: vec![0; objects.len()],
+      objects,
+      finalizable: HashSet::new(),
+      cursor: 0,
+    }
+  }
+
+  /// Take the next objects of the phase, as many as `work` allows.
+  fn next_objects(&mut self, work: &mut usize) -> Range<usize> {
+    let start = self.cursor;
+    self.cursor = self.objects.len().min(start + *work);
+    *work -= self.cursor - start;
+    start..self.cursor
+  }
+
+  /// Go to the next phase if the objects of this one are all done.
+  fn next_phase(&mut self, phase: Phase) {
+    if self.cursor == self.objects.len() {
+      self.phase = phase;
+      self.cursor = 0;
+    }
+  }
+
+  /// Mark the objects, spending at most `work` units, one per object
+  /// handled. Return whether the marking is done, but for the atomic step.
+  fn mark(&mut self, work: &mut usize, finobj: &[Value]) -> bool {
+    while *work > 0 {
+      match self.phase {
+        Phase::Index => {
+          for j in self.next_objects(work) {
+            self.marking.index.insert(self.objects[j].addr(), j);
+          }
+          self.next_phase(Phase::Count);
+        }
+        Phase::Count => {
+          for j in self.next_objects(work) {
+            let Some(o) = self.objects[j].upgrade() else {
+              continue;
+            };
+            let (index, internal) = (&self.marking.index, &mut self.internal);
+            let inspected = o.children(&mut |addr| {
+              if let Some(&k) = index.get(&addr) {
+                internal[k] += 1;
+              }
+            });
+            if !inspected {
+              // being used by someone, and so are its children
+              self.marking.mark(j);
+            }
+          }
+          if self.cursor == self.objects.len() {
+            self.finalizable = finobj.iter().filter_map(value_addr).collect();
+          }
+          self.next_phase(Phase::Roots);
+        }
+        Phase::Roots => {
+          for j in self.next_objects(work) {
+            let Some(o) = self.objects[j].upgrade() else {
+              continue;
+            };
+            // excluding the reference of `o`, and the one of the heap for
+            // finalization
+            let fin = self.finalizable.contains(&o.addr()) as usize;
+            if o.strong_count() > 1 + fin + self.internal[j] {
+              self.marking.mark(j);
+            }
+          }
+          self.next_phase(Phase::Mark);
+        }
+        Phase::Mark => {
+          let Some(i) = self.marking.gray.pop() else {
+            return true;
+          };
+          if let Some(o) = self.objects[i].upgrade() {
+            self.marking.blacken(i, &o);
+          }
+          *work -= 1;
+        }
+        Phase::Sweep => return true,
+      }
+    }
+    false
+  }
+
+  /// The atomic step: finish the marking, and take the objects to be traced
+  /// again, the ones not marked and the tables to be cleared, which are
+  /// moved back to the heap by the trace instead of the sweep.
+  fn atomic(&mut self) -> Vec<GcRef> {
+    let objects = &self.objects;
+    self.marking.converge(&|i| objects[i].upgrade());
+    let mut retrace: Vec<GcRef> = objects
+      .iter()
+      .zip(self.marking.marked.iter())
+      .filter(|(_, &marked)| !marked)
+      .filter_map(|(o, _)| o.upgrade())
+      .collect();
+    for &i in self.marking.clearable() {
+      retrace.extend(objects[i].upgrade());
+    }
+    for i in mem::take(&mut self.marking.clearable) {
+      self.marking.marked[i] = false;
+    }
+    self.phase = Phase::Sweep;
+    self.cursor = 0;
+    retrace
+  }
+
+  /// Move the marked objects back to `old`, at most `work` of them, return
+  /// whether all are moved.
+  fn sweep(&mut self, work: &mut usize, old: &mut Vec<GcObject>) -> bool {
+    for j in self.next_objects(work) {
+      if self.marking.marked[j] {
+        old.push(self.objects[j].clone());
+      }
+    }
+    self.cursor == self.objects.len()
+  }
+}
+
+/// ## Heap
+///
+/// The registry of collectable objects, and the state of the collector.
+pub struct Heap {
+  /// Objects survived the last collection
+  old: Vec<GcObject>,
+  /// Objects created since the last collection
+  young: Vec<GcObject>,
+  /// Garbage found but not cleared yet
+  garbage: Vec<GcRef>,
+  /// Objects marked for finalization, in order of marking
+  finobj: Vec<Value>,
+  /// Addresses of the objects in `finobj` and `tobefnz`
+  finalizable: HashSet<usize>,
+  /// Unreachable objects to be finalized, in order of calling
+  tobefnz: VecDeque<Value>,
+  pub mode: GcMode,
+  /// Whether the collector runs automatically
+  pub running: bool,
+  /// Run the collector when the memory in use exceeds it
+  threshold: usize,
+  /// Memory in use after the last major collection
+  major_base: usize,
+  /// Wait until the memory in use grows to `pause`% of the one after a
+  /// cycle, before starting a new cycle
+  pub pause: usize,
+  /// Speed of the collector relative to allocation: objects handled by a
+  /// phase of the cycle for each KB allocated
+  pub stepmul: usize,
+  /// Log2 of the bytes allocated between steps
+  pub stepsize: usize,
+  /// Run a minor collection when the memory in use grows by `minormul`%
+  pub minormul: usize,
+  /// Run a major collection when the memory in use grows by `majormul`%
+  pub majormul: usize,
+  /// Bytes allocated by the objects of the state
+  allocated: Allocated,
+  /// The interned short strings
+  strings: StringTable,
+  /// The incremental cycle in progress
+  cycle: Option<Cycle>,
+}
+
+impl Default for Heap {
+  fn default() -> Self {
+    Self {
+      old: vec![],
+      young: vec![],
+      garbage: vec![],
+      finobj: vec![],
+      finalizable: HashSet::new(),
+      tobefnz: VecDeque::new(),
+      mode: GcMode::Incremental,
+      running: true,
+      threshold: 0,
+      major_base: 0,
+      pause: 200,
+      stepmul: 100,
+      stepsize: 13,
+      minormul: 20,
+      majormul: 100,
+      allocated: Allocated::default(),
+      strings: StringTable::default(),
+      cycle: None,
+    }
+  }
+}
+
+impl Heap {
+  /// Bytes allocated by the objects of the state.
+  pub fn allocated(&self) -> usize {
+    self.allocated.get()
+  }
+
+  /// The string value of `bytes`, which is interned if short.
+  pub fn new_string(&mut self, bytes: Vec<u8>) -> Value {
+    if bytes.len() <= SHORT_STR_MAX {
+      Value::ShortStr(self.strings.intern(&bytes, &self.allocated))
+    } else {
+      let s = Rc::new(LongString::new(bytes));
+      s.charge_to(&self.allocated);
+      Value::LongStr(s)
+    }
+  }
+
+  /// Take the string `v` created out of the state, as by `Value::from`:
+  /// intern it if short, and charge it to the state. Other values are
+  /// returned as they are.
+  pub fn own(&mut self, v: Value) -> Value {
+    match v {
+      Value::ShortStr(s) => Value::ShortStr(self.strings.intern_string(s, &self.allocated)),
+      Value::LongStr(s) => {
+        s.charge_to(&self.allocated);
+        Value::LongStr(s)
+      }
+      v => v,
+    }
+  }
+
+  pub fn register_table(&mut self, t: &Rc<RefCell<Table>>) {
+    t.borrow().charge_to(&self.allocated);
+    self.young.push(GcObject::Table(Rc::downgrade(t)));
+  }
+
+  pub fn register_closure(&mut self, c: &Rc<LuaClosure>) {
+    c.charge_to(&self.allocated);
+    self.young.push(GcObject::Closure(Rc::downgrade(c)));
+  }
+
+  pub fn register_native_closure(&mut self, c: &Rc<NativeClosure>) {
+    self.young.push(GcObject::NativeClosure(Rc::downgrade(c)));
+  }
+
+  pub fn register_userdata(&mut self, u: &Rc<UserData>) {
+    self.young.push(GcObject::UserData(Rc::downgrade(u)));
+  }
+
+  pub fn register_upvalue(&mut self, u: &Rc<RefCell<Upvalue>>) {
+    self.young.push(GcObject::Upvalue(Rc::downgrade(u)));
+  }
+
+  /// Mark `obj` for finalization if its metatable `mt` has `__gc`, as
+  /// `luaC_checkfinalizer`.
+  pub fn check_finalizer(&mut self, obj: &Value, mt: Option<&Rc<RefCell<Table>>>) {
+    if mt.is_some_and(|mt| !mt.borrow().get(&"__gc".into()).is_nil()) {
+      self.set_finalizer(obj.clone());
+    }
+  }
+
+  /// Mark `obj` for finalization, if it's not yet.
+  pub fn set_finalizer(&mut self, obj: Value) {
+    if let Some(addr) = value_addr(&obj) {
+      if self.finalizable.insert(addr) {
+        self.finobj.push(obj);
+      }
+    }
+  }
+
+  /// Take the next object to be finalized, which is not marked any more.
+  pub fn next_finalizer(&mut self) -> Option<Value> {
+    let obj = self.tobefnz.pop_front()?;
+    self.finalizable.remove(&value_addr(&obj).unwrap());
+    Some(obj)
+  }
+
+  /// Queue all objects marked for finalization, reachable or not, as when
+  /// the state is closed.
+  pub fn finalize_all(&mut self) {
+    let finobj = mem::take(&mut self.finobj);
+    self.tobefnz.extend(finobj.into_iter().rev());
+  }
+
+  /// Whether it's time to run a step.
+  pub fn should_step(&self) -> bool {
+    self.running && self.allocated.get() >= self.threshold
+  }
+
+  /// Find the garbage of `objects`, keep the survivors as old objects.
+  fn collect(&mut self, objects: Vec<GcRef>) {
+    let finalizable: Vec<usize> = self.finobj.iter().filter_map(value_addr).collect();
+    let (alive, garbage, to_finalize) = trace(objects, &finalizable);
+    if !to_finalize.is_empty() {
+      let to_finalize: HashSet<usize> = to_finalize.into_iter().collect();
+      let (finalized, finobj): (Vec<Value>, Vec<Value>) = mem::take(&mut self.finobj)
+        .into_iter()
+        .partition(|obj| value_addr(obj).is_some_and(|addr| to_finalize.contains(&addr)));
+      self.finobj = finobj;
+      // called in reverse order of marking
+      self.tobefnz.extend(finalized.into_iter().rev());
+    }
+    self.old.extend(alive.iter().map(GcRef::downgrade));
+    self.garbage.extend(garbage);
+  }
+
+  /// Clear at most `n` garbage objects, return whether all are cleared.
+  fn sweep(&mut self, n: usize) -> bool {
+    let start = self.garbage.len().saturating_sub(n);
+    let garbage = self.garbage.split_off(start);
+    garbage.iter().for_each(GcRef::clear);
+    drop(garbage);
+    self.garbage.is_empty()
+  }
+
+  /// Take all objects registered, old and young.
+  fn take_objects(&mut self) -> Vec<GcObject> {
+    let mut objects = mem::take(&mut self.old);
+    objects.append(&mut self.young);
+    objects
+  }
+
+  /// Run `work` units of the incremental cycle, starting one over all
+  /// objects if there is none, return whether the cycle is finished.
+  fn advance_cycle(&mut self, mut work: usize) -> bool {
+    let mut cycle = match self.cycle.take() {
+      Some(cycle) => cycle,
+      None => Cycle::new(self.take_objects()),
+    };
+    if cycle.phase != Phase::Sweep && cycle.mark(&mut work, &self.finobj) {
+      let retrace = cycle.atomic();
+      self.collect(retrace);
+    }
+    if cycle.phase == Phase::Sweep {
+      let n = self.garbage.len().min(work);
+      work -= n;
+      if self.sweep(n) && cycle.sweep(&mut work, &mut self.old) {
+        return true;
+      }
+    }
+    self.cycle = Some(cycle);
+    false
+  }
+
+  /// Give up the incremental cycle in progress, if any, its objects not
+  /// swept yet return to the heap.
+  fn abandon_cycle(&mut self) {
+    let Some(mut cycle) = self.cycle.take() else {
+      return;
+    };
+    match cycle.phase {
+      Phase::Sweep => {
+        cycle.sweep(&mut cycle.objects.len(), &mut self.old);
+      }
+      _ => self.old.append(&mut cycle.objects),
+    }
+  }
+
+  /// Run a basic step, return whether a cycle is finished.
+  ///
+  /// In incremental mode, it's a part of the cycle, whose size is set by
+  /// `stepsize` and `stepmul`.
+  pub fn step(&mut self) -> bool {
+    match self.mode {
+      GcMode::Incremental => {
+        let work = (1 << self.stepsize) / 1024 * self.stepmul;
+        let finished = self.advance_cycle(work.max(1));
+        self.threshold = match finished {
+          true => self.allocated.get() / 100 * self.pause,
+          false => self.allocated.get() + (1 << self.stepsize),
+        };
+        finished
+      }
+      GcMode::Generational => {
+        if self.allocated.get() > self.major_base / 100 * (100 + self.majormul) {
+          self.full_collect();
+        } else {
+          let young = mem::take(&mut self.young);
+          self.collect(young.iter().filter_map(GcObject::upgrade).collect());
+          self.sweep(usize::MAX);
+        }
+        self.threshold = self.allocated.get() / 100 * (100 + self.minormul);
+        true
+      }
+    }
+  }
+
+  /// Run a full collection.
+  pub fn full_collect(&mut self) {
+    // finish the pending cycle first
+    self.abandon_cycle();
+    self.sweep(usize::MAX);
+    let objects = self.take_objects();
+    self.collect(objects.iter().filter_map(GcObject::upgrade).collect());
+    self.sweep(usize::MAX);
+    self.strings.clean();
+    self.major_base = self.allocated.get();
+    self.threshold = match self.mode {
+      GcMode::Incremental => self.allocated.get() / 100 * self.pause,
+      GcMode::Generational => self.allocated.get() / 100 * (100 + self.minormul),
+    };
+  }
+
+  /// Switch to `mode`, return the previous one.
+  pub fn set_mode(&mut self, mode: GcMode) -> GcMode {
+    if mode != self.mode {
+      self.full_collect();
+    }
+    mem::replace(&mut self.mode, mode)
+  }
+}
@@ -7,16 +7,17 @@
 //! Input -> TokenStream
 
 use std::{
-  io::{Bytes, Read},
+  fmt,
+  io::{BufReader, Bytes, Read},
   iter::Peekable,
-  mem,
+  mem, panic,
 };
 
-use crate::utils::TokenIterator;
+use crate::{utils::TokenIterator, vm::arith::float_to_string};
 
 pub mod lexing_methods;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Default)]
 pub enum Token {
   /* keywords */
   And,
@@ -82,41 +83,149 @@ pub enum Token {
   /* name of vars or table_keys */
   Name(String),
   /* end */
+  #[default]
   Eos,
 }
 
-impl Default for Token {
-  fn default() -> Self {
-    Self::Eos
+/// Tokens as syntax errors show them, quoted like in the source,
+/// e.g. `'='`, `'end'`, `'t'` or `<eof>`.
+impl fmt::Display for Token {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let text = match self {
+      Token::And => "and",
+      Token::Break => "break",
+      Token::Do => "do",
+      Token::Else => "else",
+      Token::Elseif => "elseif",
+      Token::End => "end",
+      Token::False => "false",
+      Token::For => "for",
+      Token::Function => "function",
+      Token::Goto => "goto",
+      Token::If => "if",
+      Token::In => "in",
+      Token::Local => "local",
+      Token::Nil => "nil",
+      Token::Not => "not",
+      Token::Or => "or",
+      Token::Repeat => "repeat",
+      Token::Return => "return",
+      Token::Then => "then",
+      Token::True => "true",
+      Token::Until => "until",
+      Token::While => "while",
+      Token::Add => "+",
+      Token::Sub => "-",
+      Token::Mul => "*",
+      Token::Div => "/",
+      Token::Mod => "%",
+      Token::Pow => "^",
+      Token::Len => "#",
+      Token::BitAnd => "&",
+      Token::BitXor => "~",
+      Token::BitOr => "|",
+      Token::ShiftL => "<<",
+      Token::ShiftR => ">>",
+      Token::Idiv => "//",
+      Token::Equal => "==",
+      Token::NotEq => "~=",
+      Token::LesEq => "<=",
+      Token::GreEq => ">=",
+      Token::Less => "<",
+      Token::Greater => ">",
+      Token::Assign => "=",
+      Token::ParL => "(",
+      Token::ParR => ")",
+      Token::CurlyL => "{",
+      Token::CurlyR => "}",
+      Token::SqurL => "[",
+      Token::SqurR => "]",
+      Token::DoubColon => "::",
+      Token::SemiColon => ";",
+      Token::Colon => ":",
+      Token::Comma => ",",
+      Token::Dot => ".",
+      Token::Concat => "..",
+      Token::Dots => "...",
+      Token::Integer(i) => return write!(f, "'{i}'"),
+      Token::Float(n) => return write!(f, "'{}'", float_to_string(*n)),
+      Token::String(s) => return write!(f, "'{}'", String::from_utf8_lossy(s)),
+      Token::Name(name) => return write!(f, "'{name}'"),
+      Token::Eos => return write!(f, "<eof>"),
+    };
+    write!(f, "'{text}'")
   }
 }
 
+/// ## SyntaxError
+///
+/// Error found while lexing or parsing a chunk. It unwinds out of the
+/// front end by [`syntax_error`], and is caught by
+/// [`ParseProto::try_load`](crate::parse::ParseProto::try_load) which
+/// prefixes the chunk name.
+#[derive(Debug)]
+pub struct SyntaxError {
+  pub line: usize,
+  pub msg: String,
+}
+
+/// Abort lexing or parsing with a [`SyntaxError`] at `line`.
+pub fn syntax_error(line: usize, msg: impl Into<String>) -> ! {
+  // `resume_unwind` skips the panic hook, so nothing gets printed
+  panic::resume_unwind(Box::new(SyntaxError {
+    line,
+    msg: msg.into(),
+  }))
+}
+
 #[derive(Debug)]
 pub struct Lex<R: Read> {
   /// source file
-  input: Peekable<Bytes<R>>,
+  input: Peekable<Bytes<BufReader<R>>>,
   /// token which is lexed from input file but shouldn't get returned
   ahead: Token,
+  /// current line number (1-based) of the source
+  line: usize,
+  /// line number where the last returned token is located
+  token_line: usize,
+  /// line number where the `ahead` token is located
+  ahead_line: usize,
 }
 
 impl<R: Read> Lex<R> {
   pub fn new(input: R) -> Self {
     Self {
-      input: input.bytes().peekable(),
+      input: BufReader::new(input).bytes().peekable(),
       ahead: Token::Eos,
+      line: 1,
+      token_line: 1,
+      ahead_line: 1,
     }
   }
 }
 
 impl<R: Read> Lex<R> {
   pub fn expect(&mut self, t: Token) {
-    assert_eq!(self.next(), t);
+    let got = self.next();
+    if got != t {
+      self.error(format!("{t} expected near {got}"));
+    }
+  }
+
+  /// Raise a [`SyntaxError`] at the current line.
+  fn error(&self, msg: impl Into<String>) -> ! {
+    syntax_error(self.line, msg)
+  }
+
+  /// Line number of the last token taken out by `next`.
+  pub fn token_line(&self) -> usize {
+    self.token_line
   }
 
   fn read_byte(&mut self) -> u8 {
     match self.input.next() {
       Some(Ok(c)) => c,
-      Some(_) => panic!("lex read error"),
+      Some(Err(err)) => self.error(format!("read error: {err}")),
       None => b'\0',
     }
   }
@@ -124,13 +233,22 @@ impl<R: Read> Lex<R> {
   fn peek_byte(&mut self) -> u8 {
     match self.input.peek() {
       Some(Ok(next)) => *next,
-      Some(_) => panic!("lex read error"),
+      // take the error out and raise it
+      Some(Err(_)) => self.read_byte(),
       None => b'\0',
     }
   }
 
   fn next_byte(&mut self) -> Option<u8> {
-    self.input.next().map(|r| r.unwrap())
+    let c = match self.input.next() {
+      Some(Ok(c)) => Some(c),
+      Some(Err(err)) => self.error(format!("read error: {err}")),
+      None => None,
+    };
+    if c == Some(b'\n') {
+      self.line += 1;
+    }
+    c
   }
 }
 
@@ -140,8 +258,11 @@ impl<R: Read> TokenIterator for Lex<R> {
   /// Take out the next token. (with updating `ahead`)
   fn next(&mut self) -> Self::Output {
     if self.ahead == Token::Eos {
-      self.do_next()
+      let token = self.do_next();
+      self.token_line = self.line;
+      token
     } else {
+      self.token_line = self.ahead_line;
       mem::replace(&mut self.ahead, Token::Eos)
     }
   }
@@ -150,6 +271,7 @@ impl<R: Read> TokenIterator for Lex<R> {
   fn peek(&mut self) -> &Self::Output {
     if self.ahead == Token::Eos {
       self.ahead = self.do_next();
+      self.ahead_line = self.line;
     }
     &self.ahead
   }
@@ -170,7 +292,10 @@ impl<R: Read> TokenIterator for Lex<R> {
         b')' => Token::ParR,
         b'{' => Token::CurlyL,
         b'}' => Token::CurlyR,
-        b'[' => Token::SqurL,
+        b'[' => match self.peek_byte() {
+          b'[' | b'=' => self.lex_long_string(),
+          _ => Token::SqurL,
+        },
         b']' => Token::SqurR,
         b';' => Token::SemiColon,
         b',' => Token::Comma,
@@ -186,7 +311,7 @@ impl<R: Read> TokenIterator for Lex<R> {
         b'>' => self.check_ahead(
           vec![b'=', b'>'],
           vec![Token::GreEq, Token::ShiftR],
-          Token::Less,
+          Token::Greater,
         ),
         b'-' => {
           if self.peek_byte() == b'-' {
@@ -208,12 +333,13 @@ impl<R: Read> TokenIterator for Lex<R> {
               Token::Concat
             }
           }
-          b'0'..=b'9' => self.lex_number_fraction(0),
+          b'0'..=b'9' => self.lex_number(c),
           _ => Token::Dot,
         },
         b'0'..=b'9' => self.lex_number(c),
         b'A'..=b'Z' | b'a'..=b'z' | b'_' => self.lex_name(c),
-        _ => panic!("unexpected char: {}", c),
+        b'\x0b' | b'\x0c' => self.do_next(),
+        _ => self.error(format!("unexpected symbol near '{}'", c as char)),
       }
     } else {
       Token::Eos
@@ -1,6 +1,6 @@
 use super::*;
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
+use std::{collections::HashMap, str};
 
 static KEYWORDS_MAP: Lazy<HashMap<&str, Token>> = Lazy::new(|| {
   vec![
@@ -28,7 +28,6 @@ static KEYWORDS_MAP: Lazy<HashMap<&str, Token>> = Lazy::new(|| {
     ("while", Token::While),
   ]
   .into_iter()
-  .map(|(k, v)| (k, v))
   .collect::<HashMap<_, _>>()
 });
 
@@ -73,33 +72,69 @@ impl<R: Read> Lex<R> {
   pub(super) fn lex_string(&mut self, ending: u8) -> Token {
     let mut string = vec![];
     loop {
-      match self.next_byte().expect("unfinished string") {
-        b'\n' => string.push(self.lex_string_escape()),
-        b'\\' => unimplemented!("string escape"),
-        c if c == ending => break,
-        c => string.push(c),
+      match self.next_byte() {
+        None | Some(b'\n') => self.error("unfinished string"),
+        Some(b'\\') => self.lex_string_escape(&mut string),
+        Some(c) if c == ending => break,
+        Some(c) => string.push(c),
       }
     }
     Token::String(string)
   }
 
-  fn lex_string_escape(&mut self) -> u8 {
-    match self.next_byte().expect("string escape") {
+  fn lex_string_escape(&mut self, string: &mut Vec<u8>) {
+    let escaped = match match self.next_byte() {
+      Some(c) => c,
+      None => self.error("unfinished string"),
+    } {
       b'a' => 0x07,
       b'b' => 0x08,
       b'f' => 0x0c,
       b'v' => 0x0b,
-      b'n' => b'\n',
+      b'n' | b'\n' => b'\n',
       b'r' => b'\r',
       b't' => b'\t',
       b'\\' => b'\\',
       b'"' => b'"',
       b'\'' => b'\'',
+      b'z' => {
+        // `\z` skips the following span of white-space characters
+        while self.peek_byte().is_ascii_whitespace() {
+          self.next_byte();
+        }
+        return;
+      }
       b'x' => {
         // format: \xXX
-        let lhs = char::to_digit(self.next_byte().unwrap() as char, 16).unwrap();
-        let rhs = char::to_digit(self.next_byte().unwrap() as char, 16).unwrap();
-        (lhs * 16 + rhs) as u8
+        let mut hex = || {
+          let c = self.next_byte().unwrap_or(b'\0');
+          char::to_digit(c as char, 16).unwrap_or_else(|| self.error("hexadecimal digit expected"))
+        };
+        (hex() * 16 + hex()) as u8
+      }
+      b'u' => {
+        // format: \u{XXX}
+        if self.next_byte() != Some(b'{') {
+          self.error("missing '{' in \\u{xxxx}");
+        }
+        let mut code = 0u32;
+        loop {
+          match self.next_byte() {
+            Some(b'}') => break,
+            Some(c) => {
+              let digit = char::to_digit(c as char, 16)
+                .unwrap_or_else(|| self.error("hexadecimal digit expected"));
+              code = code
+                .checked_mul(16)
+                .filter(|c| *c < 0x8000_0000)
+                .unwrap_or_else(|| self.error("UTF-8 value too large"))
+                + digit;
+            }
+            None => self.error("missing '}' in \\u{xxxx}"),
+          }
+        }
+        utf8_encode(code, string);
+        return;
       }
       ch @ b'0'..=b'9' => {
         // format: \d[d[d]]
@@ -112,42 +147,80 @@ impl<R: Read> Lex<R> {
             scanned = scanned * 10 + d;
           }
         }
-        u8::try_from(scanned).expect("decimal escape too large")
+        u8::try_from(scanned).unwrap_or_else(|_| self.error("decimal escape too large"))
       }
-      _ => panic!("invalid string escape"),
-    }
+      _ => self.error("invalid escape sequence"),
+    };
+    string.push(escaped);
   }
 
-  pub(super) fn lex_comment(&mut self) {
-    /* `--` has been read */
-    match self.next_byte() {
-      Some(b'[') => self.lex_long_comment(),
-      None => (),
-      Some(_) => loop {
-        /* single line comment (end at `\n` or `\0`) */
-        while let Some(c) = self.next_byte() {
-          if c == b'\n' {
-            return;
-          }
-        }
-      },
+  /// Read `[=*[` (the first `[` has been read), return the level of the bracket.
+  fn lex_long_bracket_level(&mut self) -> Option<usize> {
+    let mut level = 0;
+    while self.peek_byte() == b'=' {
+      self.next_byte();
+      level += 1;
+    }
+    if self.peek_byte() == b'[' {
+      self.next_byte();
+      Some(level)
+    } else {
+      None
     }
   }
 
-  fn lex_long_comment(&mut self) {
-    /* `--[` has been read */
-    let mut level = 0;
+  /// Read the content of a long bracket until `]=*]` with the same level.
+  fn lex_long_bracket_content(&mut self, level: usize) -> Vec<u8> {
+    let mut content = vec![];
+    // a newline immediately following the opening bracket is skipped
+    if self.peek_byte() == b'\r' {
+      self.next_byte();
+    }
+    if self.peek_byte() == b'\n' {
+      self.next_byte();
+    }
     loop {
       match self.next_byte() {
-        Some(b'[') => level += 1,
         Some(b']') => {
-          if level == 0 {
-            return;
+          let mut closing = 0;
+          while self.peek_byte() == b'=' {
+            self.next_byte();
+            closing += 1;
+          }
+          if closing == level && self.peek_byte() == b']' {
+            self.next_byte();
+            return content;
           }
-          level -= 1;
+          content.push(b']');
+          content.resize(content.len() + closing, b'=');
         }
-        None => panic!("unfinished long comment"),
-        _ => (),
+        Some(c) => content.push(c),
+        None => self.error("unfinished long string/comment"),
+      }
+    }
+  }
+
+  pub(super) fn lex_long_string(&mut self) -> Token {
+    /* `[` has been read */
+    match self.lex_long_bracket_level() {
+      Some(level) => Token::String(self.lex_long_bracket_content(level)),
+      None => self.error("invalid long string delimiter"),
+    }
+  }
+
+  pub(super) fn lex_comment(&mut self) {
+    /* `--` has been read */
+    if self.peek_byte() == b'[' {
+      self.next_byte();
+      if let Some(level) = self.lex_long_bracket_level() {
+        self.lex_long_bracket_content(level);
+        return;
+      }
+    }
+    /* single line comment (end at `\n` or `\0`) */
+    while let Some(c) = self.next_byte() {
+      if c == b'\n' {
+        return;
       }
     }
   }
@@ -176,20 +249,17 @@ impl<R: Read> Lex<R> {
     // check following
     let following = self.peek_byte() as char;
     if following.is_alphabetic() || following == '.' {
-      panic!("malformed number");
+      self.error("malformed number");
     }
     // Ok
     Token::Integer(scanned)
   }
 
+  /// Lex a numeral, following the same rule of `llex.c`: read all the
+  /// chars which may form a numeral, then convert it by [`str_to_number`].
   pub(super) fn lex_number(&mut self, first: u8) -> Token {
     if first == b'0' {
-      let second = self.peek_byte();
-      match second {
-        b'x' | b'X' => {
-          self.next_byte();
-          return self.lex_number_in_radix(16);
-        }
+      match self.peek_byte() {
         b'b' | b'B' => {
           self.next_byte();
           return self.lex_number_in_radix(2);
@@ -201,59 +271,34 @@ impl<R: Read> Lex<R> {
         _ => {}
       }
     }
-    let mut scanned = (first - b'0') as i64;
-    loop {
-      let c = self.peek_byte();
-      let curr = char::to_digit(c as char, 10);
-      match curr {
-        Some(curr) => {
-          self.next_byte();
-          scanned = scanned * 10 + curr as i64
-        }
-        None => match c {
-          b'.' => {
-            self.next_byte();
-            return self.lex_number_fraction(scanned);
-          }
-          b'e' | b'E' => {
-            self.next_byte();
-            return self.lex_number_exponent(scanned as f64);
-          }
-          _ => {
-            break;
-          }
-        },
-      }
-    }
-    // check following
-    let following = self.peek_byte() as char;
-    if following.is_alphabetic() || following == '.' {
-      panic!("malformed number");
-    }
-    // Ok
-    Token::Integer(scanned)
-  }
-
-  pub(super) fn lex_number_exponent(&mut self, original: f64) -> Token {
-    let first = self.read_byte();
-    let mut scanned = 0;
-    let mut sign = 1;
-    match first {
-      b'+' => {}
-      b'-' => sign = -1,
-      _ => scanned = char::to_digit(first as char, 10).unwrap() as i64,
+    let mut numeral = vec![first];
+    let mut exponent = [b'e', b'E'];
+    if first == b'0' && matches!(self.peek_byte(), b'x' | b'X') {
+      numeral.push(self.read_byte());
+      exponent = [b'p', b'P'];
     }
     loop {
       let c = self.peek_byte();
-      let curr = char::to_digit(c as char, 10);
-      if let Some(curr) = curr {
-        self.next_byte();
-        scanned = scanned * 10 + curr as i64
+      if exponent.contains(&c) {
+        numeral.push(self.read_byte());
+        // optional exponent sign
+        if matches!(self.peek_byte(), b'+' | b'-') {
+          numeral.push(self.read_byte());
+        }
+      } else if c.is_ascii_alphanumeric() || c == b'.' || c == b'_' {
+        numeral.push(self.read_byte());
       } else {
         break;
       }
     }
-    Token::Float(original * 10f64.powi(scanned as i32 * sign))
+    match str_to_number(&numeral) {
+      Some(Number::Integer(i)) => Token::Integer(i),
+      Some(Number::Float(f)) => Token::Float(f),
+      None => self.error(format!(
+        "malformed number near '{}'",
+        String::from_utf8_lossy(&numeral)
+      )),
+    }
   }
 
   fn lex_number_fraction_in_radix(&mut self, original: i64, radix: u32) -> Token {
@@ -272,29 +317,164 @@ impl<R: Read> Lex<R> {
     }
     Token::Float(original as f64 + scanned as f64 / bits)
   }
+}
 
-  pub(super) fn lex_number_fraction(&mut self, original: i64) -> Token {
-    let mut scanned = 0;
-    let mut bits = 1.0;
-    loop {
-      let c = self.peek_byte();
-      match char::to_digit(c as char, 10) {
-        Some(curr) => {
-          self.next_byte();
-          scanned = scanned * 10 + curr as i64;
-          bits *= 10.0;
-        }
-        None => match c {
-          b'e' | b'E' => {
-            self.next_byte();
-            return self.lex_number_exponent(original as f64 + scanned as f64 / bits);
-          }
-          _ => {
-            break;
-          }
-        },
+/// A numeral converted by [`str_to_number`].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Number {
+  Integer(i64),
+  Float(f64),
+}
+
+/// Convert a numeral (with optional leading/trailing spaces and sign) into
+/// a [`Number`], following Lua's `lua_stringtonumber` rules:
+///
+/// - decimal integers that overflow become floats
+/// - hexadecimal integers wrap around
+/// - hexadecimal floats use binary exponent `p`
+pub fn str_to_number(s: &[u8]) -> Option<Number> {
+  let s = s.trim_ascii();
+  let (neg, body) = match s.first() {
+    Some(b'-') => (true, &s[1..]),
+    Some(b'+') => (false, &s[1..]),
+    _ => (false, s),
+  };
+  if body.len() > 2 && body[0] == b'0' && matches!(body[1], b'x' | b'X') {
+    return hex_to_number(&body[2..], neg);
+  }
+  if body.is_empty()
+    || !body
+      .iter()
+      .all(|c| c.is_ascii_alphanumeric() || b".+-".contains(c))
+  {
+    return None;
+  }
+  // reject the special words which are accepted by rust's float parser
+  if !body[0].is_ascii_digit() && body[0] != b'.' {
+    return None;
+  }
+  let text = str::from_utf8(body).ok()?;
+  if body.iter().all(|c| c.is_ascii_digit()) {
+    if let Ok(i) = text.parse::<i64>() {
+      return Some(Number::Integer(if neg { i.wrapping_neg() } else { i }));
+    }
+    // `-9223372036854775808`
+    if neg && text == "9223372036854775808" {
+      return Some(Number::Integer(i64::MIN));
+    }
+  }
+  let f = text.parse::<f64>().ok()?;
+  Some(Number::Float(if neg { -f } else { f }))
+}
+
+/// Convert an integer numeral in `base` (2 to 36), with optional
+/// leading/trailing spaces and `-`, as `tonumber(s, base)` does. It wraps
+/// around on overflow.
+pub fn str_to_int_base(s: &[u8], base: u32) -> Option<i64> {
+  let s = s.trim_ascii();
+  let (neg, digits) = match s.first() {
+    Some(b'-') => (true, &s[1..]),
+    _ => (false, s),
+  };
+  if digits.is_empty() {
+    return None;
+  }
+  let mut n: i64 = 0;
+  for &c in digits {
+    let d = (c as char).to_digit(base)?;
+    n = n.wrapping_mul(base as i64).wrapping_add(d as i64);
+  }
+  Some(if neg { n.wrapping_neg() } else { n })
+}
+
+fn hex_to_number(s: &[u8], neg: bool) -> Option<Number> {
+  let mut mantissa: u64 = 0;
+  let mut float_mantissa = 0.0;
+  let mut exponent: i64 = 0;
+  let mut any_digit = false;
+  let mut seen_dot = false;
+  let mut is_float = false;
+  let mut i = 0;
+  while i < s.len() {
+    let c = s[i];
+    if c == b'.' {
+      if seen_dot {
+        return None;
       }
+      seen_dot = true;
+      is_float = true;
+    } else if let Some(d) = char::to_digit(c as char, 16) {
+      any_digit = true;
+      mantissa = mantissa.wrapping_mul(16).wrapping_add(d as u64);
+      float_mantissa = float_mantissa * 16.0 + d as f64;
+      if seen_dot {
+        exponent -= 4;
+      }
+    } else {
+      break;
+    }
+    i += 1;
+  }
+  if !any_digit {
+    return None;
+  }
+  if i < s.len() {
+    if !matches!(s[i], b'p' | b'P') {
+      return None;
+    }
+    is_float = true;
+    let rest = str::from_utf8(&s[i + 1..]).ok()?;
+    let rest = rest.strip_prefix('+').unwrap_or(rest);
+    if rest.is_empty()
+      || !rest
+        .trim_start_matches('-')
+        .bytes()
+        .all(|c| c.is_ascii_digit())
+    {
+      return None;
+    }
+    exponent += rest.parse::<i64>().ok()?;
+  }
+  if is_float {
+    let f = ldexp(float_mantissa, exponent.clamp(-3000, 3000) as i32);
+    Some(Number::Float(if neg { -f } else { f }))
+  } else {
+    let i = mantissa as i64;
+    Some(Number::Integer(if neg { i.wrapping_neg() } else { i }))
+  }
+}
+
+/// `m * 2^exp`, scaled in steps to not underflow or overflow halfway.
+fn ldexp(m: f64, exp: i32) -> f64 {
+  let (mut m, mut exp) = (m, exp);
+  while exp > 1000 {
+    m *= 2f64.powi(1000);
+    exp -= 1000;
+  }
+  while exp < -1000 {
+    m *= 2f64.powi(-1000);
+    exp += 1000;
+  }
+  m * 2f64.powi(exp)
+}
+
+/// Encode `code` into (extended, up to 6 bytes) UTF-8 like Lua does.
+pub(crate) fn utf8_encode(mut code: u32, buf: &mut Vec<u8>) {
+  if code < 0x80 {
+    buf.push(code as u8);
+    return;
+  }
+  let mut tail = vec![];
+  // max that fits in the first byte
+  let mut mfb = 0x3f;
+  loop {
+    tail.push((0x80 | (code & 0x3f)) as u8);
+    code >>= 6;
+    mfb >>= 1;
+    if code <= mfb {
+      break;
     }
-    Token::Float(original as f64 + scanned as f64 / bits)
   }
+  buf.push(((!mfb << 1) | code) as u8);
+  buf.extend(tail.into_iter().rev());
 }
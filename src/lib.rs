@@ -1,6 +1,10 @@
 pub mod bytecode;
+pub mod dump;
+pub mod error;
+pub mod gc;
 pub mod lex;
 pub mod parse;
+pub mod string;
 pub mod table;
 pub mod utils;
 pub mod value;
@@ -1,6 +1,6 @@
 use once_cell::sync::Lazy;
 use rua::{parse, utils::New, vm};
-use std::{env::args, fs::File, io::BufReader};
+use std::{env::args, fs::File, io::BufReader, process::exit};
 
 static PROJECT_ROOT: Lazy<String> = Lazy::new(|| {
   project_root::get_project_root()
@@ -23,8 +23,24 @@ fn main() {
   }
 
   let file = open_file(&args[1]);
-  let proto = parse::ParseProto::load(BufReader::new(file));
-  vm::ExeState::new().execute(&proto);
+  let chunkname = format!("@{}", args[1].trim_start_matches('/'));
+  let proto = match parse::ParseProto::try_load(BufReader::new(file), &chunkname) {
+    Ok(proto) => proto,
+    Err(msg) => {
+      eprintln!("rua: {msg}");
+      exit(1);
+    }
+  };
+  let mut state = vm::ExeState::new();
+  let result = state.execute(&proto);
+  if let Err(err) = &result {
+    eprintln!("rua: {err}");
+  }
+  // run the pending finalizers before exiting
+  drop(state);
+  if result.is_err() {
+    exit(1);
+  }
 }
 
 #[cfg(test)]
@@ -38,18 +54,257 @@ mod simple_test {
   #[test]
   fn hello_world() {
     let file = open_file("/examples/hello_world.lua");
-    vm::ExeState::new().execute(&parse::ParseProto::load(file));
+    let proto = parse::ParseProto::load(file, "@examples/hello_world.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
   }
 
   #[test]
   fn print_single_argument() {
     let file = open_file("/examples/print_single_arg.lua");
-    vm::ExeState::new().execute(&parse::ParseProto::load(file));
+    let proto = parse::ParseProto::load(file, "@examples/print_single_arg.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
   }
 
   #[test]
   fn scientific_notation() {
     let file = open_file("/examples/scientific_notation.lua");
-    vm::ExeState::new().execute(&parse::ParseProto::load(file));
+    let proto = parse::ParseProto::load(file, "@examples/scientific_notation.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn traceback() {
+    let file = open_file("/examples/traceback.lua");
+    let proto = parse::ParseProto::load(file, "@examples/traceback.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn closure_env() {
+    let file = open_file("/examples/closure_env.lua");
+    let proto = parse::ParseProto::load(file, "@examples/closure_env.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn gc() {
+    let file = open_file("/examples/gc.lua");
+    let proto = parse::ParseProto::load(file, "@examples/gc.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn weak_table() {
+    let file = open_file("/examples/weak_table.lua");
+    let proto = parse::ParseProto::load(file, "@examples/weak_table.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn finalizer() {
+    let file = open_file("/examples/finalizer.lua");
+    let proto = parse::ParseProto::load(file, "@examples/finalizer.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn close_const() {
+    let file = open_file("/examples/close_const.lua");
+    let proto = parse::ParseProto::load(file, "@examples/close_const.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn string_keys() {
+    let file = open_file("/examples/string_keys.lua");
+    let proto = parse::ParseProto::load(file, "@examples/string_keys.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn table_keys() {
+    let file = open_file("/examples/table_keys.lua");
+    let proto = parse::ParseProto::load(file, "@examples/table_keys.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn table_array() {
+    let file = open_file("/examples/table_array.lua");
+    let proto = parse::ParseProto::load(file, "@examples/table_array.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn pairs() {
+    let file = open_file("/examples/pairs.lua");
+    let proto = parse::ParseProto::load(file, "@examples/pairs.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn table_lib() {
+    let file = open_file("/examples/table_lib.lua");
+    let proto = parse::ParseProto::load(file, "@examples/table_lib.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn tostring() {
+    let file = open_file("/examples/tostring.lua");
+    let proto = parse::ParseProto::load(file, "@examples/tostring.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn base_lib() {
+    let file = open_file("/examples/base_lib.lua");
+    let proto = parse::ParseProto::load(file, "@examples/base_lib.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn load() {
+    let file = open_file("/examples/load.lua");
+    let proto = parse::ParseProto::load(file, "@examples/load.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn string_basic() {
+    let file = open_file("/examples/string_basic.lua");
+    let proto = parse::ParseProto::load(file, "@examples/string_basic.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn pattern() {
+    let file = open_file("/examples/pattern.lua");
+    let proto = parse::ParseProto::load(file, "@examples/pattern.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn string_format() {
+    let file = open_file("/examples/string_format.lua");
+    let proto = parse::ParseProto::load(file, "@examples/string_format.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn string_pack() {
+    let file = open_file("/examples/string_pack.lua");
+    let proto = parse::ParseProto::load(file, "@examples/string_pack.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn math() {
+    let file = open_file("/examples/math.lua");
+    let proto = parse::ParseProto::load(file, "@examples/math.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn io() {
+    let file = open_file("/examples/io.lua");
+    let proto = parse::ParseProto::load(file, "@examples/io.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn os() {
+    let file = open_file("/examples/os.lua");
+    let proto = parse::ParseProto::load(file, "@examples/os.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn utf8() {
+    let file = open_file("/examples/utf8.lua");
+    let proto = parse::ParseProto::load(file, "@examples/utf8.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn require() {
+    let file = open_file("/examples/require.lua");
+    let proto = parse::ParseProto::load(file, "@examples/require.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn native_module() {
+    fn fs_exists(state: &mut vm::ExeState) -> Result<i32, rua::error::LuaError> {
+      let path = state.check_string(1)?;
+      let path = String::from_utf8_lossy(&path).into_owned();
+      state.push(std::path::Path::new(&path).exists());
+      Ok(1)
+    }
+    fn fs_read(state: &mut vm::ExeState) -> Result<i32, rua::error::LuaError> {
+      let path = state.check_string(1)?;
+      match std::fs::read(String::from_utf8_lossy(&path).as_ref()) {
+        Ok(content) => state.push(content),
+        Err(err) => return Err(state.error(err.to_string())),
+      }
+      Ok(1)
+    }
+    fn name(state: &mut vm::ExeState) -> Result<i32, rua::error::LuaError> {
+      state.push("rua");
+      Ok(1)
+    }
+
+    let file = open_file("/examples/native_module.lua");
+    let proto = parse::ParseProto::load(file, "@examples/native_module.lua");
+    let mut state = vm::ExeState::new();
+    state.register_module(
+      "host.fs",
+      vm::ModuleBuilder::new()
+        .functions(&[("exists", fs_exists), ("read", fs_read)])
+        .constant("sep", "/")
+        .constant("version", 1),
+    );
+    let info = vm::ModuleBuilder::new()
+      .function("name", name)
+      .constant("answer", 42);
+    state.register_module("host.info", info);
+    state.execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn debug() {
+    let file = open_file("/examples/debug.lua");
+    let proto = parse::ParseProto::load(file, "@examples/debug.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn metamethods() {
+    let file = open_file("/examples/metamethods.lua");
+    let proto = parse::ParseProto::load(file, "@examples/metamethods.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn long_chain() {
+    let file = open_file("/examples/long_chain.lua");
+    let proto = parse::ParseProto::load(file, "@examples/long_chain.lua");
+    vm::ExeState::new().execute(&proto).unwrap();
+  }
+
+  #[test]
+  fn runtime_error_traceback() {
+    let file = open_file("/examples/runtime_error.lua");
+    let proto = parse::ParseProto::load(file, "@examples/runtime_error.lua");
+    let err = vm::ExeState::new().execute(&proto).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "examples/runtime_error.lua:3: attempt to perform arithmetic on a nil value (local 'x')
+stack traceback:
+\texamples/runtime_error.lua:3: in function 'inner'
+\texamples/runtime_error.lua:7: in function 'outer'
+\t(...tail calls...)
+\texamples/runtime_error.lua:15: in main chunk"
+    );
   }
 }
@@ -8,13 +8,173 @@
 
 use crate::{
   bytecode::ByteCode,
-  lex::{Lex, Token},
+  lex::{self, Lex, SyntaxError, Token},
   utils::TokenIterator,
   value::Value,
 };
-use std::{io::Read, vec};
+use std::{
+  io::Read,
+  mem,
+  panic::{self, AssertUnwindSafe},
+  rc::Rc,
+};
 
 pub mod exp_desc;
+mod expression;
+mod statement;
+
+/// Max count of registers could be used in a function.
+const MAX_REGISTERS: usize = 250;
+
+/// Max depth of nested blocks and expressions, as `LUAI_MAXCCALLS`, which
+/// the recursive descent parses within the Rust stack.
+const MAX_LEVELS: usize = 200;
+
+/// ## FuncProto
+///
+/// The compiled function: bytecodes, constants, inner functions and
+/// debug information (lines and local variables).
+#[derive(Debug, Default, Clone)]
+pub struct FuncProto {
+  /// Name of the chunk, `@path` for files, `=name` for literal names
+  pub source: String,
+  /// Line where the definition starts, `0` for the main chunk
+  pub line_defined: usize,
+  /// Line where the definition ends
+  pub last_line_defined: usize,
+  /// Count of fixed parameters
+  pub nparam: usize,
+  /// Whether it accepts `...`
+  pub is_vararg: bool,
+  /// Count of registers it needs
+  pub max_stack: usize,
+  /// Constants vec
+  pub constants: Vec<Value>,
+  /// Bytecodes/Instructions vec
+  pub bytecodes: Vec<ByteCode>,
+  /// Source line of each bytecode
+  pub lineinfo: Vec<usize>,
+  /// Functions defined inside
+  pub protos: Vec<Rc<FuncProto>>,
+  /// Local variables in order of declaration, with their live ranges
+  pub locvars: Vec<LocVar>,
+  /// Upvalues, i.e. the variables of enclosing functions it refers to
+  pub upvalues: Vec<UpvalDesc>,
+}
+
+/// ## LocVar
+///
+/// Debug information of a local variable, which is alive in `[start_pc, end_pc)`.
+#[derive(Debug, Clone)]
+pub struct LocVar {
+  pub name: String,
+  pub start_pc: usize,
+  pub end_pc: usize,
+}
+
+/// ## UpvalDesc
+///
+/// Where the closure finds an upvalue when it is created: a local variable
+/// (register) of the enclosing function, or an upvalue of it.
+#[derive(Debug, Clone)]
+pub struct UpvalDesc {
+  pub name: String,
+  pub in_stack: bool,
+  pub index: usize,
+}
+
+/// ## LoopState
+///
+/// A loop in parsing.
+#[derive(Debug, Default)]
+struct LoopState {
+  /// Count of active local variables outside of the loop
+  nlocals: usize,
+  /// Pending `break` jumps
+  breaks: Vec<usize>,
+  /// Whether some local variable inside the loop needs closing
+  needs_close: bool,
+}
+
+/// ## BlockState
+///
+/// A block in parsing, which is the scope of the labels in it.
+#[derive(Debug, Default)]
+struct BlockState {
+  /// Count of active local variables outside of the block
+  nlocals: usize,
+  /// Index of the first label of the block in `FuncState::labels`
+  first_label: usize,
+  /// Index of the first pending `goto` of the block in `FuncState::gotos`
+  first_goto: usize,
+}
+
+/// ## LabelDesc
+///
+/// A label, or a pending `goto` to a label, as `Labeldesc`.
+#[derive(Debug)]
+struct LabelDesc {
+  name: String,
+  /// pc of the label, or of the jump of the `goto`
+  pc: usize,
+  /// Line where it appears
+  line: usize,
+  /// Count of active local variables where it appears
+  nlocals: usize,
+  /// Whether the `goto` leaves the scope of some local variable which
+  /// needs closing
+  needs_close: bool,
+}
+
+/// ## VarKind
+///
+/// Attribute of a local variable.
+#[derive(Debug, Clone, PartialEq)]
+enum VarKind {
+  Regular,
+  /// `<const>`, read-only
+  Const,
+  /// `<const>` with a value known at compile time, which the references
+  /// are folded into
+  CompileTimeConst(Value),
+  /// `<close>`, read-only and closed when it goes out of scope
+  ToClose,
+}
+
+/// A variable resolved by name.
+enum Var {
+  Local(usize),
+  Upval(usize),
+  Const(Value),
+}
+
+/// ## FuncState
+///
+/// Intermediate state of a function in parsing.
+#[derive(Debug, Default)]
+struct FuncState {
+  /// The proto in building
+  proto: FuncProto,
+  /// Active local variables, `locals[i]` lives in register `i`
+  locals: Vec<String>,
+  /// Index (of `proto.locvars`) of each active local variable
+  locvar_ids: Vec<usize>,
+  /// Attribute of each active local variable
+  kinds: Vec<VarKind>,
+  /// Whether each active local variable needs closing when it goes out of
+  /// scope: captured by a closure, or to be closed
+  needs_close: Vec<bool>,
+  /// Stack pointer, the first free register
+  sp: usize,
+  /// Enclosing loops
+  loops: Vec<LoopState>,
+  /// Enclosing blocks
+  blocks: Vec<BlockState>,
+  /// Labels visible in the current block
+  labels: Vec<LabelDesc>,
+  /// Pending `goto`s, to labels not seen yet
+  gotos: Vec<LabelDesc>,
+}
 
 /// ## ParseProto
 ///
@@ -23,26 +183,31 @@ pub mod exp_desc;
 /// Proto <=> Instructions + Constants + locals (Intermediate)
 #[derive(Debug)]
 pub struct ParseProto<R: Read> {
-  /// Constants vec
-  pub constants: Vec<Value>,
-  /// Bytecodes/Instructions vec
-  pub bytecodes: Vec<ByteCode>,
-  /// Local variable pool
-  locals: Vec<String>,
+  /// Function in parsing
+  fs: FuncState,
+  /// Functions enclosing `fs`, the outermost first
+  enclosing: Vec<FuncState>,
   /// Lexing Machine
   lexer: Lex<R>,
-  /// Stack pointer
-  sp: usize,
+  /// Depth of the nested blocks and expressions in parsing
+  level: usize,
 }
 
 impl<R: Read> ParseProto<R> {
-  pub fn new(input: R) -> Self {
+  pub fn new(input: R, chunkname: &str) -> Self {
+    let mut fs = FuncState::default();
+    fs.proto.source = chunkname.to_owned();
+    // the main chunk always has the upvalue `_ENV`, given when it's loaded
+    fs.proto.upvalues.push(UpvalDesc {
+      name: "_ENV".to_owned(),
+      in_stack: true,
+      index: 0,
+    });
     Self {
-      constants: vec![],
-      bytecodes: vec![],
-      locals: vec![],
+      fs,
+      enclosing: vec![],
       lexer: Lex::new(input),
-      sp: 0,
+      level: 0,
     }
   }
 }
@@ -53,166 +218,268 @@ impl<R: Read> ParseProto<R> {
   /// Return the index.
   pub fn add_const<T: Into<Value>>(&mut self, constant: T) -> usize {
     let constant = constant.into();
-    let constants = &mut self.constants;
-    constants
-      .iter()
-      .position(|v| *v == constant)
-      .unwrap_or_else(|| {
-        constants.push(constant);
-        constants.len() - 1
-      })
+    let constants = &mut self.fs.proto.constants;
+    // floats by bits, for `0.0` and `-0.0` are equal but distinct
+    let same = |v: &Value| match (v, &constant) {
+      (Value::Float(f0), Value::Float(f1)) => f0.to_bits() == f1.to_bits(),
+      (v, c) => v == c && mem::discriminant(v) == mem::discriminant(c),
+    };
+    constants.iter().position(same).unwrap_or_else(|| {
+      constants.push(constant);
+      constants.len() - 1
+    })
   }
 
   /// Generate bytecode of `LoadConst`, with given `dst` and `correct const_table index`
   /// leading by calling `add_const` with `constant` argument.
   pub fn load_const(&mut self, dst: usize, c: Value) -> ByteCode {
-    ByteCode::LoadConst(dst as u8, self.add_const(c) as u8)
-  }
-
-  pub fn load_exp(&mut self, dst: usize) {
-    let code = match self.lexer.next() {
-      Token::Nil => ByteCode::LoadNil(dst as u8),
-      Token::True => ByteCode::LoadBool(dst as u8, true),
-      Token::False => ByteCode::LoadBool(dst as u8, false),
-      Token::Integer(i) => {
-        if let Ok(i_16) = i16::try_from(i) {
-          // do not need to add current integer into the const table,
-          // just move it into the bytecode itself.
-          ByteCode::LoadInt(dst as u8, i_16)
-        } else {
-          self.load_const(dst, Value::Integer(i))
+    let index = self.add_const(c);
+    if index > u16::MAX as usize {
+      self.syntax_error("too many constants");
+    }
+    ByteCode::LoadConst(dst as u8, index as u16)
+  }
+
+  /// Add a constant which is going to be referred by an `u8` operand.
+  fn add_const_u8<T: Into<Value>>(&mut self, constant: T) -> Option<u8> {
+    u8::try_from(self.add_const(constant)).ok()
+  }
+
+  /// Push a bytecode, record its line, return its pc.
+  fn code(&mut self, code: ByteCode) -> usize {
+    self.fs.proto.bytecodes.push(code);
+    self.fs.proto.lineinfo.push(self.lexer.token_line());
+    self.fs.proto.bytecodes.len() - 1
+  }
+
+  /// pc of the next bytecode
+  fn pc(&self) -> usize {
+    self.fs.proto.bytecodes.len()
+  }
+
+  /// Point the jump bytecode at `pc` to `target`.
+  fn patch_jump(&mut self, pc: usize, target: usize) {
+    let offset = i16::try_from(target as isize - pc as isize - 1)
+      .unwrap_or_else(|_| self.syntax_error("control structure too long"));
+    let code = &mut self.fs.proto.bytecodes[pc];
+    *code = match *code {
+      ByteCode::Jump(_) => ByteCode::Jump(offset),
+      ByteCode::JumpIfTrue(cond, _) => ByteCode::JumpIfTrue(cond, offset),
+      ByteCode::JumpIfFalse(cond, _) => ByteCode::JumpIfFalse(cond, offset),
+      _ => unreachable!("not a jump"),
+    };
+  }
+
+  /// Allocate a register on the top.
+  fn alloc_reg(&mut self) -> usize {
+    let reg = self.fs.sp;
+    self.set_sp(reg + 1);
+    reg
+  }
+
+  fn set_sp(&mut self, sp: usize) {
+    if sp > MAX_REGISTERS {
+      self.syntax_error("function or expression needs too many registers");
+    }
+    self.fs.sp = sp;
+    self.fs.proto.max_stack = self.fs.proto.max_stack.max(sp);
+  }
+
+  /// The function in parsing at `depth`, `0` for the main chunk.
+  fn fs_at(&mut self, depth: usize) -> &mut FuncState {
+    match depth.cmp(&self.enclosing.len()) {
+      std::cmp::Ordering::Less => &mut self.enclosing[depth],
+      _ => &mut self.fs,
+    }
+  }
+
+  /// Resolve variable `name` in the function at `depth`, as `singlevaraux`.
+  ///
+  /// Variables of the enclosing functions are added as upvalues on the way.
+  fn find_var(&mut self, depth: usize, name: &str) -> Option<Var> {
+    let fs = self.fs_at(depth);
+    if let Some(reg) = fs.locals.iter().rposition(|v| v == name) {
+      if let VarKind::CompileTimeConst(value) = &fs.kinds[reg] {
+        return Some(Var::Const(value.clone()));
+      }
+      return Some(Var::Local(reg));
+    }
+    if let Some(index) = fs.proto.upvalues.iter().position(|u| u.name == name) {
+      return Some(Var::Upval(index));
+    }
+    let desc = match self.find_var(depth.checked_sub(1)?, name)? {
+      Var::Const(value) => return Some(Var::Const(value)),
+      Var::Local(reg) => {
+        self.fs_at(depth - 1).needs_close[reg] = true;
+        UpvalDesc {
+          name: name.to_owned(),
+          in_stack: true,
+          index: reg,
         }
       }
-      Token::Float(f) => self.load_const(dst, Value::Float(f)),
-      Token::String(s) => self.load_const(dst, s.into()),
-      Token::Name(var) => self.load_var(dst, var),
-      _ => panic!("invalid argument"),
+      Var::Upval(index) => UpvalDesc {
+        name: name.to_owned(),
+        in_stack: false,
+        index,
+      },
     };
-    self.bytecodes.push(code);
+    if self.fs_at(depth).proto.upvalues.len() > u8::MAX as usize {
+      self.syntax_error("too many upvalues");
+    }
+    let upvalues = &mut self.fs_at(depth).proto.upvalues;
+    upvalues.push(desc);
+    Some(Var::Upval(upvalues.len() - 1))
+  }
+
+  /// Resolve variable `name` in the function in parsing.
+  fn get_var(&mut self, name: &str) -> Option<Var> {
+    self.find_var(self.enclosing.len(), name)
   }
 
-  pub fn load_var(&mut self, dst: usize, name: String) -> ByteCode {
-    if let Some(i) = self.locals.iter().rposition(|v| *v == name) {
-      // it's a local var
-      ByteCode::Move(dst as u8, i as u8)
-    } else {
-      // it's a global var
-      let i = self.add_const(name);
-      ByteCode::GetGlobal(dst as u8, i as u8)
+  /// Whether the upvalue `index` of the function at `depth` refers to a
+  /// read-only variable.
+  fn upval_readonly(&self, depth: usize, index: usize) -> bool {
+    let fs = |depth: usize| match depth < self.enclosing.len() {
+      true => &self.enclosing[depth],
+      false => &self.fs,
+    };
+    let desc = &fs(depth).proto.upvalues[index];
+    match (depth.checked_sub(1), desc.in_stack) {
+      // `_ENV` of the main chunk
+      (None, _) => false,
+      (Some(parent), true) => fs(parent).kinds[desc.index] != VarKind::Regular,
+      (Some(parent), false) => self.upval_readonly(parent, desc.index),
     }
   }
 
-  fn get_local(&self, name: &str) -> Option<usize> {
-    self.locals.iter().rposition(|v| v == name)
+  /// Add a local variable, which is alive from the next bytecode.
+  fn add_local(&mut self, name: String) {
+    self.add_local_kind(name, VarKind::Regular);
   }
-}
 
-impl<R: Read> ParseProto<R> {
-  /// FuncName(expression) / FuncName LiteralString
-  fn function_call(&mut self, name: String) {
-    let func_index = self.locals.len();
-    let arg_index = func_index + 1;
+  fn add_local_kind(&mut self, name: String, kind: VarKind) {
+    let locvars = &mut self.fs.proto.locvars;
+    locvars.push(LocVar {
+      name: name.clone(),
+      start_pc: self.fs.proto.bytecodes.len(),
+      end_pc: 0,
+    });
+    self.fs.locvar_ids.push(locvars.len() - 1);
+    self.fs.locals.push(name);
+    self.fs.needs_close.push(kind == VarKind::ToClose);
+    self.fs.kinds.push(kind);
+    let nlocals = self.fs.locals.len();
+    self.set_sp(nlocals);
+  }
 
-    // function, var
-    let code = self.load_var(func_index, name);
-    self.bytecodes.push(code);
+  /// Whether some local variable declared after the first `nlocals` ones
+  /// needs closing: captured by a closure, or to be closed.
+  fn needs_close(&self, nlocals: usize) -> bool {
+    self.fs.needs_close[nlocals..].contains(&true)
+  }
 
-    // argument, (expression) or "literal_string"
-    match self.lexer.next() {
-      // '('
-      Token::ParL => {
-        // expression
-        self.load_exp(arg_index);
-        // ')'
-        if self.lexer.next() != Token::ParR {
-          panic!("expected `)`");
+  /// Remove the local variables declared after the first `nlocals` ones.
+  ///
+  /// Their upvalues and to-be-closed variables are closed, so the registers
+  /// can be reused. The pending `goto`s leaving their scope will have to
+  /// close them at the label.
+  fn close_scope(&mut self, nlocals: usize) {
+    let needs_close = self.needs_close(nlocals);
+    for goto in self.fs.gotos.iter_mut().filter(|g| g.nlocals > nlocals) {
+      goto.nlocals = nlocals;
+      goto.needs_close |= needs_close;
+    }
+    if needs_close {
+      self.code(ByteCode::Close(nlocals as u8));
+      for lp in self.fs.loops.iter_mut() {
+        if lp.nlocals <= nlocals {
+          lp.needs_close = true;
         }
       }
-      // "literal_string"
-      Token::String(s) => {
-        let code = self.load_const(arg_index, s.into());
-        self.bytecodes.push(code);
-      }
-      _ => panic!("expected 'string' or (expression)"),
     }
-
-    self.bytecodes.push(ByteCode::Call(func_index as u8, 1));
+    let pc = self.pc();
+    for id in self.fs.locvar_ids.drain(nlocals..) {
+      self.fs.proto.locvars[id].end_pc = pc;
+    }
+    self.fs.locals.truncate(nlocals);
+    self.fs.kinds.truncate(nlocals);
+    self.fs.needs_close.truncate(nlocals);
+    self.fs.sp = nlocals;
   }
 
-  // local name = expression
-  fn local_bind(&mut self) {
-    let var = if let Token::Name(var) = self.lexer.next() {
-      var
-    } else {
-      panic!("expected variable_name after `local` keyword")
-    };
+  fn expect(&mut self, token: Token, what: &str) {
+    let got = self.lexer.next();
+    if got != token {
+      self.syntax_error(&format!("'{what}' expected near {got}"));
+    }
+  }
 
-    if self.lexer.next() != Token::Assign {
-      panic!("expected `=` after variable_name")
-    }
-
-    self.load_exp(self.locals.len());
-
-    // add to locals after load_expression
-    self.locals.push(var);
-  }
-
-  /// var = var | const
-  fn assignment(&mut self, l_var: String) {
-    // consume `=`
-    self.lexer.next();
-    // expression
-    if let Some(dst) = self.get_local(&l_var) {
-      // l_var := local var
-      self.load_exp(dst);
-    } else {
-      // l_var := global var
-      let dst = self.add_const(l_var) as u8;
-      let code = match self.lexer.next() {
-        // from const values
-        Token::Nil => ByteCode::SetGlobalConst(dst, self.add_const(Value::Nil) as u8),
-        Token::True => ByteCode::SetGlobalConst(dst, self.add_const(true) as u8),
-        Token::False => ByteCode::SetGlobalConst(dst, self.add_const(false) as u8),
-        Token::Integer(i) => ByteCode::SetGlobalConst(dst, self.add_const(i) as u8),
-        Token::Float(f) => ByteCode::SetGlobalConst(dst, self.add_const(f) as u8),
-        Token::String(s) => ByteCode::SetGlobalConst(dst, self.add_const(s) as u8),
-        // from variable
-        Token::Name(r_var) => {
-          if let Some(i) = self.get_local(&r_var) {
-            // from local variable
-            ByteCode::SetGlobal(dst, i as u8)
-          } else {
-            // from global variable
-            ByteCode::SetGlobalGlobal(dst, self.add_const(r_var) as u8)
-          }
-        }
-        _ => panic!("invalid argument"),
-      };
-      self.bytecodes.push(code);
+  fn expect_name(&mut self) -> String {
+    match self.lexer.next() {
+      Token::Name(name) => name,
+      t => self.syntax_error(&format!("<name> expected near {t}")),
     }
   }
 
-  fn chunk(&mut self) {
-    loop {
-      match self.lexer.next() {
-        Token::Name(name) => {
-          if self.lexer.peek() == &Token::Assign {
-            self.assignment(name);
-          } else {
-            self.function_call(name);
-          }
-        }
-        Token::Local => self.local_bind(),
-        Token::Eos => break,
-        t => panic!("unexpected token: {:?}", t),
-      }
+  fn syntax_error(&self, msg: &str) -> ! {
+    lex::syntax_error(self.lexer.token_line(), msg)
+  }
+
+  /// Enter a nested block or expression, as `enterlevel`.
+  fn enter_level(&mut self) {
+    self.level += 1;
+    if self.level > MAX_LEVELS {
+      self.syntax_error("chunk has too many C levels");
     }
   }
 
-  pub fn load(input: R) -> Self {
-    let mut proto = Self::new(input);
+  fn leave_level(&mut self) {
+    self.level -= 1;
+  }
+}
+
+impl<R: Read> ParseProto<R> {
+  /// Start parsing an inner function, defined at `line`.
+  fn open_function(&mut self, line: usize) {
+    let mut fs = FuncState::default();
+    fs.proto.source = self.fs.proto.source.clone();
+    fs.proto.line_defined = line;
+    let parent = mem::replace(&mut self.fs, fs);
+    self.enclosing.push(parent);
+  }
+
+  /// Finish parsing the inner function, return its index in the protos of the parent.
+  fn close_function(&mut self) -> usize {
+    self.fs.proto.last_line_defined = self.lexer.token_line();
+    self.code(ByteCode::Return(0, 1));
+    // upvalues and to-be-closed variables are closed by `Return`
+    self.fs.needs_close.fill(false);
+    self.close_scope(0);
+    let parent = self.enclosing.pop().expect("no enclosing function");
+    let fs = mem::replace(&mut self.fs, parent);
+    self.fs.proto.protos.push(Rc::new(fs.proto));
+    self.fs.proto.protos.len() - 1
+  }
+
+  /// Parse the chunk, panic on syntax error.
+  pub fn load(input: R, chunkname: &str) -> Rc<FuncProto> {
+    Self::try_load(input, chunkname).unwrap_or_else(|msg| panic!("{msg}"))
+  }
 
-    proto.chunk();
+  /// Parse the chunk, return the message `chunkname:line: msg` on syntax
+  /// error.
+  pub fn try_load(input: R, chunkname: &str) -> Result<Rc<FuncProto>, String> {
+    let mut proto = Self::new(input, chunkname);
+    match panic::catch_unwind(AssertUnwindSafe(|| proto.chunk())) {
+      Ok(()) => {}
+      Err(payload) => match payload.downcast::<SyntaxError>() {
+        Ok(err) => {
+          let chunk_id = crate::vm::chunk_id(chunkname);
+          return Err(format!("{}:{}: {}", chunk_id, err.line, err.msg));
+        }
+        Err(payload) => panic::resume_unwind(payload),
+      },
+    }
 
     #[cfg(feature = "debug")]
     {
@@ -220,17 +487,17 @@ impl<R: Read> ParseProto<R> {
       println!("{} {} {}", "=".repeat(8), title, "=".repeat(8));
       #[cfg(not(feature = "layered_debug"))]
       {
-        println!("constants: {:?}\n", &proto.constants);
+        println!("constants: {:?}\n", &proto.fs.proto.constants);
         println!("bytecodes: [");
-        for c in proto.bytecodes.iter() {
+        for c in proto.fs.proto.bytecodes.iter() {
           println!("    {:?},", c);
         }
         println!("]");
       }
       #[cfg(feature = "layered_debug")]
       {
-        dbg!(&proto.constants);
-        dbg!(&proto.bytecodes);
+        dbg!(&proto.fs.proto.constants);
+        dbg!(&proto.fs.proto.bytecodes);
       }
       println!(
         "{}={}={}",
@@ -238,9 +505,9 @@ impl<R: Read> ParseProto<R> {
         "=".repeat(title.len()),
         "=".repeat(8)
       );
+      println!();
     }
-    println!();
 
-    proto
+    Ok(Rc::new(proto.fs.proto))
   }
 }
@@ -6,10 +6,8 @@
 //! the expression (with it's `constant/variable` composition) that
 //! will be used to generate the bytecode.
 
-#[allow(unused_imports)]
 use super::*;
 
-#[allow(dead_code)]
 #[derive(Debug, PartialEq)]
 pub(super) enum ExpDesc {
   Nil,
@@ -17,10 +15,167 @@ pub(super) enum ExpDesc {
   Integer(i64),
   Float(f64),
   String(Vec<u8>),
-  Local(usize),  // on stack, including local/temporary variables
-  Global(usize), // global variable
+  Local(usize), // on stack, including local/temporary variables
+  Upval(usize), // upvalue
   Index(usize, usize),
   IndexField(usize, usize),
   IndexInt(usize, u8),
-  Call,
+  IndexUpval(usize, usize), // table in upvalue (mostly `_ENV`), key in constants
+  Call(usize),              // pc of the `Call` bytecode
+  VarArgs(usize),           // pc of the `VarArgs` bytecode
+  Function(usize),          // index of the inner proto
+}
+
+impl ExpDesc {
+  /// The constant value known at compile time, as `luaK_exp2const`.
+  pub(super) fn to_const(&self) -> Option<Value> {
+    match self {
+      ExpDesc::Nil => Some(Value::Nil),
+      ExpDesc::Boolean(b) => Some(Value::Boolean(*b)),
+      ExpDesc::Integer(i) => Some(Value::Integer(*i)),
+      ExpDesc::Float(f) => Some(Value::Float(*f)),
+      ExpDesc::String(s) => Some(s.as_slice().into()),
+      _ => None,
+    }
+  }
+
+  /// The expression of a constant value from [`ExpDesc::to_const`].
+  pub(super) fn from_const(value: Value) -> Self {
+    match value {
+      Value::Nil => ExpDesc::Nil,
+      Value::Boolean(b) => ExpDesc::Boolean(b),
+      Value::Integer(i) => ExpDesc::Integer(i),
+      Value::Float(f) => ExpDesc::Float(f),
+      v => ExpDesc::String(<&[u8]>::from(&v).to_vec()),
+    }
+  }
+
+  /// Whether it may produce multiple values.
+  pub(super) fn is_multi(&self) -> bool {
+    matches!(self, ExpDesc::Call(_) | ExpDesc::VarArgs(_))
+  }
+}
+
+impl<R: Read> ParseProto<R> {
+  /// The register of the function called by the `Call` bytecode at `pc`,
+  /// or the target of the `VarArgs` bytecode at `pc`.
+  fn multi_reg(&self, pc: usize) -> usize {
+    match self.fs.proto.bytecodes[pc] {
+      ByteCode::Call(func, _, _) | ByteCode::VarArgs(func, _) => func as usize,
+      _ => unreachable!("not a multiple-value bytecode"),
+    }
+  }
+
+  /// Set the count of values that a `Call`/`VarArgs` at `pc` produces,
+  /// `None` for all of them.
+  pub(super) fn set_nresults(&mut self, pc: usize, n: Option<usize>) {
+    let n = n.map_or(0, |n| n + 1) as u8;
+    let code = &mut self.fs.proto.bytecodes[pc];
+    *code = match *code {
+      ByteCode::Call(func, nargs, _) => ByteCode::Call(func, nargs, n),
+      ByteCode::VarArgs(dst, _) => ByteCode::VarArgs(dst, n),
+      _ => unreachable!("not a multiple-value bytecode"),
+    };
+  }
+
+  /// Release the temporary registers occupied by `desc`.
+  pub(super) fn free_desc(&mut self, desc: &ExpDesc) {
+    let regs = match desc {
+      ExpDesc::Local(r) | ExpDesc::IndexField(r, _) | ExpDesc::IndexInt(r, _) => [*r, *r],
+      ExpDesc::Index(t, k) => [*t, *k],
+      ExpDesc::Call(pc) | ExpDesc::VarArgs(pc) => [self.multi_reg(*pc); 2],
+      _ => return,
+    };
+    let nlocals = self.fs.locals.len();
+    if let Some(reg) = regs.into_iter().filter(|r| *r >= nlocals).min() {
+      self.fs.sp = self.fs.sp.min(reg);
+    }
+  }
+
+  /// Generate bytecode to put the value of `desc` into register `dst`.
+  pub(super) fn discharge(&mut self, dst: usize, desc: ExpDesc) {
+    self.free_desc(&desc);
+    let d = dst as u8;
+    let code = match desc {
+      ExpDesc::Nil => Some(ByteCode::LoadNil(d)),
+      ExpDesc::Boolean(b) => Some(ByteCode::LoadBool(d, b)),
+      ExpDesc::Integer(i) => match i16::try_from(i) {
+        // do not need to add current integer into the const table,
+        // just move it into the bytecode itself.
+        Ok(i_16) => Some(ByteCode::LoadInt(d, i_16)),
+        Err(_) => Some(self.load_const(dst, Value::Integer(i))),
+      },
+      ExpDesc::Float(f) => Some(self.load_const(dst, Value::Float(f))),
+      ExpDesc::String(s) => Some(self.load_const(dst, s.into())),
+      ExpDesc::Local(src) => (src != dst).then_some(ByteCode::Move(d, src as u8)),
+      ExpDesc::Upval(index) => Some(ByteCode::GetUpval(d, index as u8)),
+      ExpDesc::Index(t, k) => Some(ByteCode::GetTable(d, t as u8, k as u8)),
+      ExpDesc::IndexField(t, k) => Some(ByteCode::GetField(d, t as u8, k as u8)),
+      ExpDesc::IndexInt(t, i) => Some(ByteCode::GetInt(d, t as u8, i)),
+      ExpDesc::IndexUpval(t, k) => Some(ByteCode::GetTabUp(d, t as u8, k as u8)),
+      ExpDesc::Call(pc) | ExpDesc::VarArgs(pc) => {
+        self.set_nresults(pc, Some(1));
+        let src = self.multi_reg(pc);
+        (src != dst).then_some(ByteCode::Move(d, src as u8))
+      }
+      ExpDesc::Function(index) => Some(ByteCode::Closure(d, index as u16)),
+    };
+    if let Some(code) = code {
+      self.code(code);
+    }
+    if dst >= self.fs.sp {
+      self.set_sp(dst + 1);
+    }
+  }
+
+  /// Put the value of `desc` into any register, return the register.
+  ///
+  /// Local variables are used in place, without copying.
+  pub(super) fn discharge_any(&mut self, desc: ExpDesc) -> usize {
+    match desc {
+      ExpDesc::Local(reg) => reg,
+      desc => self.discharge_top(desc),
+    }
+  }
+
+  /// Put the value of `desc` into a temporary register on the top.
+  pub(super) fn discharge_top(&mut self, desc: ExpDesc) -> usize {
+    self.free_desc(&desc);
+    let dst = self.fs.sp.max(self.fs.locals.len());
+    self.discharge(dst, desc);
+    dst
+  }
+
+  /// Adjust `n` expressions, which has been put into registers from `sp0`
+  /// except the `last` one, into `want` values.
+  pub(super) fn adjust_explist(&mut self, sp0: usize, n: usize, last: ExpDesc, want: usize) {
+    match last {
+      ExpDesc::Call(pc) | ExpDesc::VarArgs(pc) if want >= n => {
+        self.set_nresults(pc, Some(want - n + 1));
+        self.set_sp(sp0 + want);
+      }
+      last => {
+        self.discharge(sp0 + n - 1, last);
+        for reg in n..want {
+          self.code(ByteCode::LoadNil((sp0 + reg) as u8));
+        }
+        self.set_sp(sp0 + want.max(n));
+      }
+    }
+  }
+
+  /// Generate bytecode to assign the value in register `src` to `target`.
+  pub(super) fn store(&mut self, target: &ExpDesc, src: usize) {
+    let s = src as u8;
+    let code = match *target {
+      ExpDesc::Local(reg) => ByteCode::Move(reg as u8, s),
+      ExpDesc::Upval(index) => ByteCode::SetUpval(index as u8, s),
+      ExpDesc::Index(t, k) => ByteCode::SetTable(t as u8, k as u8, s),
+      ExpDesc::IndexField(t, k) => ByteCode::SetField(t as u8, k as u8, s),
+      ExpDesc::IndexInt(t, i) => ByteCode::SetInt(t as u8, i, s),
+      ExpDesc::IndexUpval(t, k) => ByteCode::SetTabUp(t as u8, k as u8, s),
+      _ => self.syntax_error("syntax error: cannot assign"),
+    };
+    self.code(code);
+  }
 }
@@ -0,0 +1,506 @@
+//! Expression parsing.
+//!
+//! exp ::= nil | false | true | Numeral | LiteralString | `...` | functiondef |
+//!         prefixexp | tableconstructor | exp binop exp | unop exp
+
+use super::{exp_desc::ExpDesc, *};
+use crate::vm::arith;
+
+/// Priority of unary operators.
+const UNARY_PRI: i32 = 12;
+
+/// Max count of array items in the stack before `SetList` flushes them.
+const FIELDS_PER_FLUSH: usize = 50;
+
+/// (left priority, right priority) of binary operators, `(-1, -1)` for non-operators.
+fn binop_pri(token: &Token) -> (i32, i32) {
+  match token {
+    Token::Pow => (14, 13), // right associative
+    Token::Mul | Token::Mod | Token::Div | Token::Idiv => (11, 11),
+    Token::Add | Token::Sub => (10, 10),
+    Token::Concat => (9, 8), // right associative
+    Token::ShiftL | Token::ShiftR => (7, 7),
+    Token::BitAnd => (6, 6),
+    Token::BitXor => (5, 5),
+    Token::BitOr => (4, 4),
+    Token::Equal | Token::NotEq | Token::Less | Token::LesEq | Token::Greater | Token::GreEq => {
+      (3, 3)
+    }
+    Token::And => (2, 2),
+    Token::Or => (1, 1),
+    _ => (-1, -1),
+  }
+}
+
+impl<R: Read> ParseProto<R> {
+  pub(super) fn exp(&mut self) -> ExpDesc {
+    self.exp_limit(0)
+  }
+
+  fn exp_limit(&mut self, limit: i32) -> ExpDesc {
+    self.enter_level();
+    let desc = match self.lexer.peek() {
+      Token::Not | Token::Sub | Token::Len | Token::BitXor => {
+        let op = self.lexer.next();
+        let operand = self.exp_limit(UNARY_PRI);
+        self.unop(op, operand)
+      }
+      _ => self.simple_exp(),
+    };
+    let desc = self.exp_binop(desc, limit);
+    self.leave_level();
+    desc
+  }
+
+  /// Continue parsing binary operators after the left operand `desc`.
+  pub(super) fn exp_binop(&mut self, mut desc: ExpDesc, limit: i32) -> ExpDesc {
+    loop {
+      let (left_pri, right_pri) = binop_pri(self.lexer.peek());
+      if left_pri <= limit {
+        return desc;
+      }
+      let op = self.lexer.next();
+      desc = match op {
+        Token::And | Token::Or => {
+          let dst = self.discharge_top(desc);
+          let jump = self.code(match op {
+            Token::And => ByteCode::JumpIfFalse(dst as u8, 0),
+            _ => ByteCode::JumpIfTrue(dst as u8, 0),
+          });
+          let right = self.exp_limit(right_pri);
+          self.discharge(dst, right);
+          self.patch_jump(jump, self.pc());
+          ExpDesc::Local(dst)
+        }
+        op => {
+          let left = match desc {
+            // keep constants for folding
+            ExpDesc::Integer(_) | ExpDesc::Float(_) | ExpDesc::String(_) => desc,
+            desc => ExpDesc::Local(self.discharge_any(desc)),
+          };
+          let right = self.exp_limit(right_pri);
+          self.binop(op, left, right)
+        }
+      };
+    }
+  }
+
+  fn simple_exp(&mut self) -> ExpDesc {
+    match self.lexer.peek() {
+      Token::Nil => self.const_exp(ExpDesc::Nil),
+      Token::True => self.const_exp(ExpDesc::Boolean(true)),
+      Token::False => self.const_exp(ExpDesc::Boolean(false)),
+      Token::Integer(i) => {
+        let i = *i;
+        self.const_exp(ExpDesc::Integer(i))
+      }
+      Token::Float(f) => {
+        let f = *f;
+        self.const_exp(ExpDesc::Float(f))
+      }
+      Token::String(_) => match self.lexer.next() {
+        Token::String(s) => ExpDesc::String(s),
+        _ => unreachable!(),
+      },
+      Token::Dots => {
+        self.lexer.next();
+        if !self.fs.proto.is_vararg {
+          self.syntax_error("cannot use '...' outside a vararg function");
+        }
+        let dst = self.alloc_reg();
+        ExpDesc::VarArgs(self.code(ByteCode::VarArgs(dst as u8, 2)))
+      }
+      Token::Function => {
+        self.lexer.next();
+        let line = self.lexer.token_line();
+        self.funcbody(false, line)
+      }
+      Token::CurlyL => {
+        self.lexer.next();
+        self.table_constructor()
+      }
+      _ => self.prefixexp(),
+    }
+  }
+
+  fn const_exp(&mut self, desc: ExpDesc) -> ExpDesc {
+    self.lexer.next();
+    desc
+  }
+
+  /// Name => local variable, upvalue, or global variable as `_ENV.Name`
+  pub(super) fn simple_name(&mut self, name: String) -> ExpDesc {
+    match self.get_var(&name) {
+      Some(Var::Local(reg)) => ExpDesc::Local(reg),
+      Some(Var::Upval(index)) => ExpDesc::Upval(index),
+      Some(Var::Const(value)) => ExpDesc::from_const(value),
+      None => {
+        let env = match self.get_var("_ENV") {
+          Some(Var::Local(reg)) => ExpDesc::Local(reg),
+          Some(Var::Upval(index)) => ExpDesc::Upval(index),
+          Some(Var::Const(value)) => ExpDesc::from_const(value),
+          None => unreachable!("no _ENV"),
+        };
+        match (env, self.add_const_u8(name.clone())) {
+          (ExpDesc::Upval(env), Some(k)) => ExpDesc::IndexUpval(env, k as usize),
+          (env, _) => {
+            let table = self.discharge_any(env);
+            self.index_desc(table, ExpDesc::String(name.into_bytes()))
+          }
+        }
+      }
+    }
+  }
+
+  /// prefixexp ::= Name | `(` exp `)` | prefixexp `[` exp `]` | prefixexp `.` Name |
+  ///               prefixexp [`:` Name] args
+  pub(super) fn prefixexp(&mut self) -> ExpDesc {
+    let desc = match self.lexer.next() {
+      Token::Name(name) => self.simple_name(name),
+      Token::ParL => {
+        let desc = self.exp();
+        self.expect(Token::ParR, ")");
+        // `(f())` is adjusted to 1 value
+        match desc {
+          desc if desc.is_multi() => ExpDesc::Local(self.discharge_top(desc)),
+          desc => desc,
+        }
+      }
+      t => self.syntax_error(&format!("unexpected symbol near {t}")),
+    };
+    self.suffixedexp(desc)
+  }
+
+  /// Continue parsing `.Name`, `[exp]`, `:Name args` and `args` after `desc`.
+  pub(super) fn suffixedexp(&mut self, mut desc: ExpDesc) -> ExpDesc {
+    loop {
+      desc = match self.lexer.peek() {
+        Token::SqurL => {
+          self.lexer.next();
+          let table = self.discharge_any(desc);
+          let key = self.exp();
+          self.expect(Token::SqurR, "]");
+          self.index_desc(table, key)
+        }
+        Token::Dot => {
+          self.lexer.next();
+          let table = self.discharge_any(desc);
+          let key = self.expect_name();
+          self.index_desc(table, ExpDesc::String(key.into_bytes()))
+        }
+        Token::Colon => {
+          self.lexer.next();
+          let key = self.expect_name();
+          let key = self
+            .add_const_u8(key)
+            .unwrap_or_else(|| self.syntax_error("too many constants"));
+          let table = self.discharge_any(desc);
+          self.free_desc(&ExpDesc::Local(table));
+          let func = self.fs.sp;
+          self.code(ByteCode::Method(func as u8, table as u8, key));
+          self.set_sp(func + 2);
+          self.call_args(func, 1)
+        }
+        Token::ParL | Token::String(_) | Token::CurlyL => {
+          let func = self.discharge_top(desc);
+          self.call_args(func, 0)
+        }
+        _ => return desc,
+      };
+    }
+  }
+
+  /// `table[key]`
+  fn index_desc(&mut self, table: usize, key: ExpDesc) -> ExpDesc {
+    match key {
+      ExpDesc::String(s) => match self.add_const_u8(s.clone()) {
+        Some(k) => ExpDesc::IndexField(table, k as usize),
+        None => ExpDesc::Index(table, self.discharge_any(ExpDesc::String(s))),
+      },
+      ExpDesc::Integer(i) if (0..=255).contains(&i) => ExpDesc::IndexInt(table, i as u8),
+      key => ExpDesc::Index(table, self.discharge_any(key)),
+    }
+  }
+
+  /// explist ::= exp {`,` exp}
+  ///
+  /// Put the values into registers from the current `sp` on except the last one,
+  /// return the count of expressions and the last one.
+  pub(super) fn explist(&mut self) -> (usize, ExpDesc) {
+    let sp0 = self.fs.sp;
+    let mut n = 1;
+    let mut desc = self.exp();
+    while self.lexer.peek() == &Token::Comma {
+      self.lexer.next();
+      self.discharge(sp0 + n - 1, desc);
+      n += 1;
+      desc = self.exp();
+    }
+    (n, desc)
+  }
+
+  /// args ::= `(` [explist] `)` | tableconstructor | LiteralString
+  ///
+  /// `nfixed` arguments have been put after `func`.
+  fn call_args(&mut self, func: usize, nfixed: usize) -> ExpDesc {
+    let nargs = match self.lexer.next() {
+      // '('
+      Token::ParL => {
+        if self.lexer.peek() == &Token::ParR {
+          self.lexer.next();
+          Some(nfixed)
+        } else {
+          let sp0 = self.fs.sp;
+          let (n, last) = self.explist();
+          self.expect(Token::ParR, ")");
+          if let ExpDesc::Call(pc) | ExpDesc::VarArgs(pc) = last {
+            self.set_nresults(pc, None);
+            None
+          } else {
+            self.discharge(sp0 + n - 1, last);
+            Some(nfixed + n)
+          }
+        }
+      }
+      // "literal_string"
+      Token::String(s) => {
+        self.discharge(func + 1 + nfixed, ExpDesc::String(s));
+        Some(nfixed + 1)
+      }
+      // {table}
+      Token::CurlyL => {
+        let table = self.table_constructor();
+        self.discharge(func + 1 + nfixed, table);
+        Some(nfixed + 1)
+      }
+      t => self.syntax_error(&format!("function arguments expected near {t}")),
+    };
+    let nargs = nargs.map_or(0, |n| n + 1) as u8;
+    let pc = self.code(ByteCode::Call(func as u8, nargs, 2));
+    self.fs.sp = func + 1;
+    ExpDesc::Call(pc)
+  }
+
+  /// tableconstructor ::= `{` [fieldlist] `}`, and `{` has been read.
+  fn table_constructor(&mut self) -> ExpDesc {
+    let table = self.alloc_reg();
+    let new_table = self.code(ByteCode::NewTable(table as u8, 0, 0));
+
+    let mut narray = 0;
+    let mut nmap = 0;
+    // count of array items waiting for `SetList`
+    let mut pending = 0;
+    // the last array item, not discharged yet for it may be multiple values
+    let mut last_item: Option<ExpDesc> = None;
+    loop {
+      if self.lexer.peek() == &Token::CurlyR {
+        self.lexer.next();
+        break;
+      }
+      // put the previous array item in place
+      if let Some(item) = last_item.take() {
+        self.discharge(table + 1 + pending, item);
+        pending += 1;
+        if pending == FIELDS_PER_FLUSH {
+          self.code(ByteCode::SetList(table as u8, pending as u8));
+          pending = 0;
+          self.fs.sp = table + 1;
+        }
+      }
+
+      let entry = match self.lexer.peek() {
+        // `[exp] = exp`
+        Token::SqurL => {
+          self.lexer.next();
+          let key = self.exp();
+          self.expect(Token::SqurR, "]");
+          self.expect(Token::Assign, "=");
+          Some(key)
+        }
+        // `Name = exp` or an expression beginning with Name
+        Token::Name(_) => {
+          let name = self.expect_name();
+          if self.lexer.peek() == &Token::Assign {
+            self.lexer.next();
+            Some(ExpDesc::String(name.into_bytes()))
+          } else {
+            let desc = self.simple_name(name);
+            let desc = self.suffixedexp(desc);
+            last_item = Some(self.exp_binop(desc, 0));
+            narray += 1;
+            None
+          }
+        }
+        _ => {
+          last_item = Some(self.exp());
+          narray += 1;
+          None
+        }
+      };
+      if let Some(key) = entry {
+        nmap += 1;
+        let key = self.index_desc(table, key);
+        let value = self.exp();
+        let value = self.discharge_any(value);
+        self.store(&key, value);
+        self.fs.sp = table + 1 + pending;
+      }
+
+      match self.lexer.next() {
+        Token::SemiColon | Token::Comma => (),
+        Token::CurlyR => break,
+        t => self.syntax_error(&format!("'}}' expected near {t}")),
+      }
+    }
+
+    match last_item {
+      Some(ExpDesc::Call(pc)) | Some(ExpDesc::VarArgs(pc)) => {
+        self.set_nresults(pc, None);
+        self.code(ByteCode::SetList(table as u8, 0));
+      }
+      item => {
+        if let Some(item) = item {
+          self.discharge(table + 1 + pending, item);
+          pending += 1;
+        }
+        if pending > 0 {
+          self.code(ByteCode::SetList(table as u8, pending as u8));
+        }
+      }
+    }
+    self.fs.sp = table + 1;
+
+    self.fs.proto.bytecodes[new_table] =
+      ByteCode::NewTable(table as u8, narray.min(255) as u8, nmap.min(255) as u8);
+    ExpDesc::Local(table)
+  }
+
+  /// funcbody ::= `(` [parlist] `)` block end
+  pub(super) fn funcbody(&mut self, has_self: bool, line: usize) -> ExpDesc {
+    self.open_function(line);
+    if has_self {
+      self.add_local("self".to_owned());
+    }
+    self.expect(Token::ParL, "(");
+    loop {
+      match self.lexer.next() {
+        Token::Name(name) => self.add_local(name),
+        Token::Dots => {
+          self.fs.proto.is_vararg = true;
+          self.expect(Token::ParR, ")");
+          break;
+        }
+        Token::ParR if self.fs.locals.len() == has_self as usize => break,
+        t => self.syntax_error(&format!("<name> expected near {t}")),
+      }
+      match self.lexer.next() {
+        Token::Comma => (),
+        Token::ParR => break,
+        t => self.syntax_error(&format!("')' expected near {t}")),
+      }
+    }
+    self.fs.proto.nparam = self.fs.locals.len();
+
+    if self.block() != Token::End {
+      self.syntax_error(&format!(
+        "'end' expected (to close 'function' at line {line})"
+      ));
+    }
+    ExpDesc::Function(self.close_function())
+  }
+
+  fn unop(&mut self, op: Token, desc: ExpDesc) -> ExpDesc {
+    // constant folding
+    match (&op, &desc) {
+      (Token::Sub, ExpDesc::Integer(i)) => return ExpDesc::Integer(i.wrapping_neg()),
+      (Token::Sub, ExpDesc::Float(f)) => return ExpDesc::Float(-f),
+      (Token::BitXor, ExpDesc::Integer(i)) => return ExpDesc::Integer(!i),
+      (Token::Not, ExpDesc::Nil | ExpDesc::Boolean(false)) => return ExpDesc::Boolean(true),
+      (
+        Token::Not,
+        ExpDesc::Boolean(true) | ExpDesc::Integer(_) | ExpDesc::Float(_) | ExpDesc::String(_),
+      ) => return ExpDesc::Boolean(false),
+      _ => (),
+    }
+    let src = self.discharge_any(desc);
+    self.free_desc(&ExpDesc::Local(src));
+    let dst = self.alloc_reg();
+    let (d, s) = (dst as u8, src as u8);
+    self.code(match op {
+      Token::Sub => ByteCode::Neg(d, s),
+      Token::Not => ByteCode::Not(d, s),
+      Token::Len => ByteCode::Len(d, s),
+      Token::BitXor => ByteCode::BitNot(d, s),
+      _ => unreachable!("not an unary operator"),
+    });
+    ExpDesc::Local(dst)
+  }
+
+  fn binop(&mut self, op: Token, left: ExpDesc, right: ExpDesc) -> ExpDesc {
+    if let Some(folded) = fold_binop(&op, &left, &right) {
+      return folded;
+    }
+    let right = self.discharge_any(right);
+    let left = self.discharge_any(left);
+    self.free_desc(&ExpDesc::Local(left));
+    self.free_desc(&ExpDesc::Local(right));
+    let dst = self.alloc_reg();
+    let (d, l, r) = (dst as u8, left as u8, right as u8);
+    self.code(match op {
+      Token::Add => ByteCode::Add(d, l, r),
+      Token::Sub => ByteCode::Sub(d, l, r),
+      Token::Mul => ByteCode::Mul(d, l, r),
+      Token::Div => ByteCode::Div(d, l, r),
+      Token::Idiv => ByteCode::Idiv(d, l, r),
+      Token::Mod => ByteCode::Mod(d, l, r),
+      Token::Pow => ByteCode::Pow(d, l, r),
+      Token::BitAnd => ByteCode::BitAnd(d, l, r),
+      Token::BitOr => ByteCode::BitOr(d, l, r),
+      Token::BitXor => ByteCode::BitXor(d, l, r),
+      Token::ShiftL => ByteCode::ShiftL(d, l, r),
+      Token::ShiftR => ByteCode::ShiftR(d, l, r),
+      Token::Concat => ByteCode::Concat(d, l, r),
+      Token::Equal => ByteCode::Equal(d, l, r),
+      Token::NotEq => ByteCode::NotEq(d, l, r),
+      Token::Less => ByteCode::Less(d, l, r),
+      Token::LesEq => ByteCode::LesEq(d, l, r),
+      // `a > b` <=> `b < a`
+      Token::Greater => ByteCode::Less(d, r, l),
+      Token::GreEq => ByteCode::LesEq(d, r, l),
+      _ => unreachable!("not a binary operator"),
+    });
+    ExpDesc::Local(dst)
+  }
+}
+
+/// Fold arithmetic on numeric constants, just as `lcode.c` does: never
+/// fold the operations which raise errors, or produce NaN or zero floats.
+fn fold_binop(op: &Token, left: &ExpDesc, right: &ExpDesc) -> Option<ExpDesc> {
+  let to_value = |desc: &ExpDesc| match desc {
+    ExpDesc::Integer(i) => Some(Value::Integer(*i)),
+    ExpDesc::Float(f) => Some(Value::Float(*f)),
+    _ => None,
+  };
+  let (l, r) = (to_value(left)?, to_value(right)?);
+  let result = match op {
+    Token::Add => arith::add(&l, &r),
+    Token::Sub => arith::sub(&l, &r),
+    Token::Mul => arith::mul(&l, &r),
+    Token::Div => arith::div(&l, &r),
+    Token::Pow => arith::pow(&l, &r),
+    Token::Idiv => arith::idiv(&l, &r),
+    Token::Mod => arith::modulo(&l, &r),
+    Token::BitAnd => arith::bit_and(&l, &r),
+    Token::BitOr => arith::bit_or(&l, &r),
+    Token::BitXor => arith::bit_xor(&l, &r),
+    Token::ShiftL => arith::shift_left(&l, &r),
+    Token::ShiftR => arith::shift_right(&l, &r),
+    _ => return None,
+  }
+  .ok()?;
+  match result {
+    Value::Integer(i) => Some(ExpDesc::Integer(i)),
+    Value::Float(f) if !f.is_nan() && f != 0.0 => Some(ExpDesc::Float(f)),
+    _ => None,
+  }
+}
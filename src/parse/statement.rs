@@ -0,0 +1,693 @@
+//! Statement parsing.
+//!
+//! block ::= {stat} [retstat]
+
+use super::{exp_desc::ExpDesc, *};
+
+impl<R: Read> ParseProto<R> {
+  pub(super) fn chunk(&mut self) {
+    self.fs.proto.is_vararg = true;
+    let t = self.block();
+    if t != Token::Eos {
+      self.syntax_error(&format!("'<eof>' expected near {t}"));
+    }
+    self.code(ByteCode::Return(0, 1));
+    // upvalues and to-be-closed variables are closed by `Return`
+    self.fs.needs_close.fill(false);
+    self.close_scope(0);
+  }
+
+  /// Parse a block in a new scope, return the token which ends the block.
+  pub(super) fn block(&mut self) -> Token {
+    self.enter_level();
+    let nlocals = self.fs.locals.len();
+    self.open_block();
+    let end = self.statements();
+    self.close_scope(nlocals);
+    self.close_block();
+    self.leave_level();
+    end
+  }
+
+  fn open_block(&mut self) {
+    self.fs.blocks.push(BlockState {
+      nlocals: self.fs.locals.len(),
+      first_label: self.fs.labels.len(),
+      first_goto: self.fs.gotos.len(),
+    });
+  }
+
+  /// Drop the labels of the innermost block. The `goto`s still pending at
+  /// the end of the function have no visible label.
+  fn close_block(&mut self) {
+    let block = self.fs.blocks.pop().expect("not in block");
+    self.fs.labels.truncate(block.first_label);
+    if self.fs.blocks.is_empty() {
+      if let Some(goto) = self.fs.gotos.first() {
+        self.syntax_error(&format!(
+          "no visible label '{}' for <goto> at line {}",
+          goto.name, goto.line
+        ));
+      }
+    }
+  }
+
+  /// Parse statements until the end of a block, return the ending token.
+  fn statements(&mut self) -> Token {
+    loop {
+      self.fs.sp = self.fs.locals.len();
+      if matches!(self.lexer.peek(), Token::Name(_) | Token::ParL) {
+        self.exp_statement();
+        continue;
+      }
+      match self.lexer.next() {
+        Token::SemiColon => (),
+        Token::Local => {
+          if self.lexer.peek() == &Token::Function {
+            self.lexer.next();
+            self.local_function();
+          } else {
+            self.local_bind();
+          }
+        }
+        Token::Function => self.function_stat(),
+        Token::If => self.if_stat(),
+        Token::While => self.while_stat(),
+        Token::For => self.for_stat(),
+        Token::Repeat => self.repeat_stat(),
+        Token::Do => {
+          let t = self.block();
+          if t != Token::End {
+            self.syntax_error(&format!("'end' expected near {t}"));
+          }
+        }
+        Token::Break => self.break_stat(),
+        Token::Goto => self.goto_stat(),
+        Token::DoubColon => self.label_stat(),
+        Token::Return => {
+          self.return_stat();
+          // `return` must be the last statement of a block
+          return match self.lexer.next() {
+            t @ (Token::End | Token::Else | Token::Elseif | Token::Until | Token::Eos) => t,
+            t => self.syntax_error(&format!("'<eof>' expected near {t}")),
+          };
+        }
+        t @ (Token::End | Token::Else | Token::Elseif | Token::Until | Token::Eos) => return t,
+        t => self.syntax_error(&format!("unexpected symbol near {t}")),
+      }
+    }
+  }
+
+  /// FuncName(expression) / var {`,` var} = explist
+  fn exp_statement(&mut self) {
+    let desc = self.assign_target();
+    if let ExpDesc::Call(pc) = desc {
+      if !matches!(self.lexer.peek(), Token::Assign | Token::Comma) {
+        // function call statement, drop all results
+        self.set_nresults(pc, Some(0));
+        return;
+      }
+    }
+
+    let mut targets = vec![desc];
+    while self.lexer.peek() == &Token::Comma {
+      self.lexer.next();
+      targets.push(self.assign_target());
+    }
+    self.expect(Token::Assign, "=");
+    self.assignment(targets);
+  }
+
+  /// A prefixexp which may be assigned, and must not be read-only.
+  fn assign_target(&mut self) -> ExpDesc {
+    let name = match self.lexer.peek() {
+      Token::Name(name) => Some(name.clone()),
+      _ => None,
+    };
+    let desc = self.prefixexp();
+    if !matches!(desc, ExpDesc::Call(_)) {
+      self.check_readonly(&desc, name.as_deref());
+    }
+    desc
+  }
+
+  /// var {`,` var} = explist
+  fn assignment(&mut self, mut targets: Vec<ExpDesc>) {
+    let nlocals = self.fs.locals.len();
+    for target in targets.iter() {
+      match target {
+        ExpDesc::Local(reg) if *reg < nlocals => (),
+        ExpDesc::Upval(_)
+        | ExpDesc::Index(..)
+        | ExpDesc::IndexField(..)
+        | ExpDesc::IndexInt(..)
+        | ExpDesc::IndexUpval(..) => (),
+        _ => self.syntax_error("syntax error near '='"),
+      }
+    }
+    if targets.len() > 1 {
+      self.check_conflict(&mut targets);
+    }
+
+    let sp0 = self.fs.sp;
+    let (n, last) = self.explist();
+
+    if targets.len() == 1 && n == 1 {
+      match targets[0] {
+        // l_var := var
+        ExpDesc::Local(dst) => self.discharge(dst, last),
+        _ => {
+          let src = self.discharge_any(last);
+          self.store(&targets[0], src);
+        }
+      }
+      return;
+    }
+
+    self.adjust_explist(sp0, n, last, targets.len());
+    for (i, target) in targets.iter().enumerate().rev() {
+      self.store(target, sp0 + i);
+    }
+  }
+
+  /// In a multiple assignment, if a local variable or upvalue assigned is
+  /// used as the table or key of a previous target, copy it before it gets
+  /// changed.
+  fn check_conflict(&mut self, targets: &mut [ExpDesc]) {
+    for i in 1..targets.len() {
+      let mut copy = None;
+      for j in 0..i {
+        let conflict = match (&targets[i], &targets[j]) {
+          (ExpDesc::Local(reg), ExpDesc::Index(t, k)) => t == reg || k == reg,
+          (ExpDesc::Local(reg), ExpDesc::IndexField(t, _) | ExpDesc::IndexInt(t, _)) => t == reg,
+          (ExpDesc::Upval(up), ExpDesc::IndexUpval(t, _)) => t == up,
+          _ => false,
+        };
+        if !conflict {
+          continue;
+        }
+        let tmp = *copy.get_or_insert_with(|| {
+          let tmp = self.alloc_reg();
+          match targets[i] {
+            ExpDesc::Local(reg) => self.code(ByteCode::Move(tmp as u8, reg as u8)),
+            ExpDesc::Upval(up) => self.code(ByteCode::GetUpval(tmp as u8, up as u8)),
+            _ => unreachable!(),
+          };
+          tmp
+        });
+        let reg = match targets[i] {
+          ExpDesc::Local(reg) => reg,
+          _ => usize::MAX,
+        };
+        match &mut targets[j] {
+          ExpDesc::Index(t, k) => {
+            if *t == reg {
+              *t = tmp;
+            }
+            if *k == reg {
+              *k = tmp;
+            }
+          }
+          ExpDesc::IndexField(t, _) | ExpDesc::IndexInt(t, _) => *t = tmp,
+          ExpDesc::IndexUpval(_, k) => targets[j] = ExpDesc::IndexField(tmp, *k),
+          _ => (),
+        }
+      }
+    }
+  }
+
+  /// local attnamelist [= explist]
+  ///
+  /// attnamelist ::= Name attrib {`,` Name attrib}
+  fn local_bind(&mut self) {
+    let mut vars = vec![];
+    loop {
+      let name = self.expect_name();
+      let kind = self.attrib();
+      if kind == VarKind::ToClose && vars.iter().any(|(_, k)| *k == VarKind::ToClose) {
+        self.syntax_error("multiple to-be-closed variables in local list");
+      }
+      vars.push((name, kind));
+      if self.lexer.peek() != &Token::Comma {
+        break;
+      }
+      self.lexer.next();
+    }
+
+    let sp0 = self.fs.sp;
+    if self.lexer.peek() == &Token::Assign {
+      self.lexer.next();
+      let (n, last) = self.explist();
+      // the last `<const>` variable is folded if its value is a constant
+      if n == vars.len() {
+        if let (Some(value), Some((_, kind))) = (last.to_const(), vars.last_mut()) {
+          if *kind == VarKind::Const {
+            *kind = VarKind::CompileTimeConst(value);
+          }
+        }
+      }
+      self.adjust_explist(sp0, n, last, vars.len());
+    } else {
+      for i in 0..vars.len() {
+        self.code(ByteCode::LoadNil((sp0 + i) as u8));
+      }
+    }
+
+    // add to locals after load_expression
+    for (name, kind) in vars {
+      let to_close = kind == VarKind::ToClose;
+      self.add_local_kind(name, kind);
+      if to_close {
+        let reg = self.fs.locals.len() - 1;
+        self.code(ByteCode::Tbc(reg as u8));
+      }
+    }
+  }
+
+  /// attrib ::= [`<` Name `>`]
+  fn attrib(&mut self) -> VarKind {
+    if self.lexer.peek() != &Token::Less {
+      return VarKind::Regular;
+    }
+    self.lexer.next();
+    let attr = self.expect_name();
+    self.expect(Token::Greater, ">");
+    match attr.as_str() {
+      "const" => VarKind::Const,
+      "close" => VarKind::ToClose,
+      _ => self.syntax_error(&format!("unknown attribute '{attr}'")),
+    }
+  }
+
+  /// Raise an error if `target`, which begins with name `name`, is a
+  /// read-only variable.
+  fn check_readonly(&self, target: &ExpDesc, name: Option<&str>) {
+    let name = match target {
+      ExpDesc::Local(reg) if self.fs.kinds[*reg] != VarKind::Regular => &self.fs.locals[*reg],
+      ExpDesc::Upval(index) if self.upval_readonly(self.enclosing.len(), *index) => {
+        &self.fs.proto.upvalues[*index].name
+      }
+      // a name resolved to a constant value
+      desc if desc.to_const().is_some() && name.is_some() => name.unwrap(),
+      _ => return,
+    };
+    self.syntax_error(&format!("attempt to assign to const variable '{name}'"));
+  }
+
+  /// local function Name funcbody
+  fn local_function(&mut self) {
+    let line = self.lexer.token_line();
+    let name = self.expect_name();
+    // the local is visible inside the function body for recursion
+    self.add_local(name);
+    let func = self.funcbody(false, line);
+    self.discharge(self.fs.locals.len() - 1, func);
+  }
+
+  /// function funcname funcbody
+  ///
+  /// funcname ::= Name {`.` Name} [`:` Name]
+  fn function_stat(&mut self) {
+    let line = self.lexer.token_line();
+    let name = self.expect_name();
+    let mut target = self.simple_name(name.clone());
+    if !matches!(self.lexer.peek(), Token::Dot | Token::Colon) {
+      self.check_readonly(&target, Some(&name));
+    }
+    let mut has_self = false;
+    while let Token::Dot | Token::Colon = self.lexer.peek() {
+      has_self = self.lexer.next() == Token::Colon;
+      let table = self.discharge_any(target);
+      let key = self.expect_name();
+      target = match self.add_const_u8(key) {
+        Some(key) => ExpDesc::IndexField(table, key as usize),
+        None => self.syntax_error("too many constants"),
+      };
+      if has_self {
+        break;
+      }
+    }
+    let func = self.funcbody(has_self, line);
+    let src = self.discharge_any(func);
+    self.store(&target, src);
+  }
+
+  /// if exp then block {elseif exp then block} [else block] end
+  fn if_stat(&mut self) {
+    let mut end_jumps = vec![];
+    let mut cond_jump = self.cond_then();
+    let mut end = self.block();
+    loop {
+      match end {
+        Token::Elseif => {
+          end_jumps.push(self.code(ByteCode::Jump(0)));
+          self.patch_jump(cond_jump, self.pc());
+          cond_jump = self.cond_then();
+          end = self.block();
+        }
+        Token::Else => {
+          end_jumps.push(self.code(ByteCode::Jump(0)));
+          self.patch_jump(cond_jump, self.pc());
+          let t = self.block();
+          if t != Token::End {
+            self.syntax_error(&format!("'end' expected near {t}"));
+          }
+          break;
+        }
+        Token::End => {
+          self.patch_jump(cond_jump, self.pc());
+          break;
+        }
+        t => self.syntax_error(&format!("'end' expected near {t}")),
+      }
+    }
+    let pc = self.pc();
+    for jump in end_jumps {
+      self.patch_jump(jump, pc);
+    }
+  }
+
+  /// exp then, return the pc of the jump which skips the block if false.
+  fn cond_then(&mut self) -> usize {
+    let cond = self.exp();
+    self.expect(Token::Then, "then");
+    self.cond_jump(cond)
+  }
+
+  fn cond_jump(&mut self, cond: ExpDesc) -> usize {
+    let cond = self.discharge_any(cond);
+    self.fs.sp = self.fs.locals.len();
+    self.code(ByteCode::JumpIfFalse(cond as u8, 0))
+  }
+
+  /// while exp do block end
+  fn while_stat(&mut self) {
+    let start = self.pc();
+    let cond = self.exp();
+    self.expect(Token::Do, "do");
+    let cond_jump = self.cond_jump(cond);
+
+    self.open_loop();
+    let t = self.block();
+    if t != Token::End {
+      self.syntax_error(&format!("'end' expected near {t}"));
+    }
+    let jump = self.code(ByteCode::Jump(0));
+    self.patch_jump(jump, start);
+    self.patch_jump(cond_jump, self.pc());
+    self.close_loop();
+  }
+
+  /// repeat block until exp
+  fn repeat_stat(&mut self) {
+    let start = self.pc();
+    self.open_loop();
+    let nlocals = self.fs.locals.len();
+    self.open_block();
+    let t = self.statements();
+    if t != Token::Until {
+      self.syntax_error(&format!("'until' expected near {t}"));
+    }
+    // the condition can see the locals of the block
+    let cond = self.exp();
+    if self.needs_close(nlocals) {
+      // repetition must close upvalues and to-be-closed variables
+      let cond = self.discharge_any(cond);
+      self.fs.sp = self.fs.locals.len();
+      let exit = self.code(ByteCode::JumpIfTrue(cond as u8, 0));
+      self.code(ByteCode::Close(nlocals as u8));
+      let jump = self.code(ByteCode::Jump(0));
+      self.patch_jump(jump, start);
+      self.patch_jump(exit, self.pc());
+    } else {
+      let cond_jump = self.cond_jump(cond);
+      self.patch_jump(cond_jump, start);
+    }
+    self.close_scope(nlocals);
+    self.close_block();
+    self.close_loop();
+  }
+
+  fn open_loop(&mut self) {
+    self.fs.loops.push(LoopState {
+      nlocals: self.fs.locals.len(),
+      ..Default::default()
+    });
+  }
+
+  /// Point the `break`s of the innermost loop to the current pc, where the
+  /// upvalues and to-be-closed variables of the loop are closed if any.
+  fn close_loop(&mut self) {
+    let lp = self.fs.loops.pop().expect("not in loop");
+    let pc = self.pc();
+    if lp.needs_close {
+      self.code(ByteCode::Close(lp.nlocals as u8));
+    }
+    for jump in lp.breaks {
+      self.patch_jump(jump, pc);
+    }
+  }
+
+  fn break_stat(&mut self) {
+    if self.fs.loops.is_empty() {
+      self.syntax_error(&format!(
+        "break outside a loop at line {}",
+        self.lexer.token_line()
+      ));
+    }
+    let jump = self.code(ByteCode::Jump(0));
+    self.fs.loops.last_mut().unwrap().breaks.push(jump);
+  }
+
+  /// goto Name
+  fn goto_stat(&mut self) {
+    let line = self.lexer.token_line();
+    let name = self.expect_name();
+    let nlocals = self.fs.locals.len();
+    match self.fs.labels.iter().rfind(|label| label.name == name) {
+      // backward jump, closing the variables declared after the label,
+      // which may be captured later in the block
+      Some(label) => {
+        let (target, level) = (label.pc, label.nlocals);
+        if nlocals > level {
+          self.code(ByteCode::Close(level as u8));
+        }
+        let jump = self.code(ByteCode::Jump(0));
+        self.patch_jump(jump, target);
+      }
+      // forward jump, resolved at the label
+      None => {
+        let pc = self.code(ByteCode::Jump(0));
+        self.fs.gotos.push(LabelDesc {
+          name,
+          pc,
+          line,
+          nlocals,
+          needs_close: false,
+        });
+      }
+    }
+  }
+
+  /// `::` Name `::`
+  fn label_stat(&mut self) {
+    let line = self.lexer.token_line();
+    let name = self.expect_name();
+    self.expect(Token::DoubColon, "::");
+    // skip other no-op statements
+    loop {
+      match self.lexer.peek() {
+        Token::SemiColon => {
+          self.lexer.next();
+        }
+        Token::DoubColon => {
+          self.lexer.next();
+          self.label_stat();
+        }
+        _ => break,
+      }
+    }
+    if let Some(label) = self.fs.labels.iter().find(|label| label.name == name) {
+      self.syntax_error(&format!(
+        "label '{name}' already defined on line {}",
+        label.line
+      ));
+    }
+    let block = self.fs.blocks.last().expect("not in block");
+    let first_goto = block.first_goto;
+    // a label at the end of the block is out of the scope of its locals,
+    // so it can be jumped to from before them
+    let nlocals = match self.lexer.peek() {
+      Token::End | Token::Else | Token::Elseif | Token::Eos => block.nlocals,
+      _ => self.fs.locals.len(),
+    };
+    let pc = self.pc();
+
+    // resolve the pending `goto`s of the block
+    let mut needs_close = false;
+    let mut i = first_goto;
+    while i < self.fs.gotos.len() {
+      if self.fs.gotos[i].name != name {
+        i += 1;
+        continue;
+      }
+      let goto = self.fs.gotos.remove(i);
+      if goto.nlocals < nlocals {
+        self.syntax_error(&format!(
+          "<goto {name}> at line {} jumps into the scope of local '{}'",
+          goto.line, self.fs.locals[goto.nlocals]
+        ));
+      }
+      needs_close |= goto.needs_close;
+      self.patch_jump(goto.pc, pc);
+    }
+    if needs_close {
+      self.code(ByteCode::Close(nlocals as u8));
+    }
+    self.fs.labels.push(LabelDesc {
+      name,
+      pc,
+      line,
+      nlocals,
+      needs_close: false,
+    });
+  }
+
+  /// for Name `=` exp `,` exp [`,` exp] do block end |
+  /// for namelist in explist do block end
+  fn for_stat(&mut self) {
+    let name = self.expect_name();
+    match self.lexer.peek() {
+      Token::Assign => {
+        self.lexer.next();
+        self.numeric_for(name);
+      }
+      Token::Comma | Token::In => self.generic_for(name),
+      t => {
+        let msg = format!("'=' or 'in' expected near {t}");
+        self.syntax_error(&msg)
+      }
+    }
+  }
+
+  fn numeric_for(&mut self, name: String) {
+    let base = self.fs.sp;
+    let init = self.exp();
+    self.discharge(base, init);
+    self.expect(Token::Comma, ",");
+    let limit = self.exp();
+    self.discharge(base + 1, limit);
+    if self.lexer.peek() == &Token::Comma {
+      self.lexer.next();
+      let step = self.exp();
+      self.discharge(base + 2, step);
+    } else {
+      self.discharge(base + 2, ExpDesc::Integer(1));
+    }
+    self.expect(Token::Do, "do");
+
+    // internal states of the loop
+    let nlocals = self.fs.locals.len();
+    for _ in 0..3 {
+      self.add_local("(for state)".to_owned());
+    }
+    let prepare = self.code(ByteCode::ForPrepare(base as u8, 0));
+
+    self.open_loop();
+    self.add_local(name);
+    let t = self.block();
+    if t != Token::End {
+      self.syntax_error(&format!("'end' expected near {t}"));
+    }
+    self.close_scope(nlocals + 3);
+
+    let lp = self.code(ByteCode::ForLoop(base as u8, 0));
+    let offset = u16::try_from(lp - prepare)
+      .unwrap_or_else(|_| self.syntax_error("control structure too long"));
+    self.fs.proto.bytecodes[prepare] = ByteCode::ForPrepare(base as u8, offset);
+    self.fs.proto.bytecodes[lp] = ByteCode::ForLoop(base as u8, offset);
+    self.close_loop();
+    self.close_scope(nlocals);
+  }
+
+  fn generic_for(&mut self, name: String) {
+    let mut names = vec![name];
+    while self.lexer.peek() == &Token::Comma {
+      self.lexer.next();
+      names.push(self.expect_name());
+    }
+    self.expect(Token::In, "in");
+    let base = self.fs.sp;
+    let (n, last) = self.explist();
+    self.adjust_explist(base, n, last, 4);
+    self.expect(Token::Do, "do");
+
+    // internal states of the loop: iterator, state, control and the
+    // closing value, which is closed when the loop ends
+    let nlocals = self.fs.locals.len();
+    for _ in 0..3 {
+      self.add_local("(for state)".to_owned());
+    }
+    self.add_local_kind("(for state)".to_owned(), VarKind::ToClose);
+    self.code(ByteCode::Tbc((base + 3) as u8));
+    let prepare = self.code(ByteCode::Jump(0));
+
+    self.open_loop();
+    let nvars = names.len();
+    for name in names {
+      self.add_local(name);
+    }
+    let t = self.block();
+    if t != Token::End {
+      self.syntax_error(&format!("'end' expected near {t}"));
+    }
+    self.close_scope(nlocals + 4);
+
+    self.patch_jump(prepare, self.pc());
+    self.code(ByteCode::ForCall(base as u8, nvars as u8));
+    let lp = self.code(ByteCode::ForGenericLoop(base as u8, 0));
+    let offset = u16::try_from(lp - prepare)
+      .unwrap_or_else(|_| self.syntax_error("control structure too long"));
+    self.fs.proto.bytecodes[lp] = ByteCode::ForGenericLoop(base as u8, offset);
+    self.close_loop();
+    self.close_scope(nlocals);
+  }
+
+  /// return [explist] [`;`]
+  fn return_stat(&mut self) {
+    let sp0 = self.fs.sp;
+    match self.lexer.peek() {
+      Token::SemiColon | Token::End | Token::Else | Token::Elseif | Token::Until | Token::Eos => {
+        self.code(ByteCode::Return(sp0 as u8, 1));
+      }
+      _ => {
+        let (n, last) = self.explist();
+        match last {
+          // no tail call, for the to-be-closed variables are closed
+          // after the call
+          ExpDesc::Call(pc) if n == 1 && !self.fs.kinds.contains(&VarKind::ToClose) => {
+            // tail call
+            if let ByteCode::Call(func, nargs, _) = self.fs.proto.bytecodes[pc] {
+              self.fs.proto.bytecodes[pc] = ByteCode::TailCall(func, nargs);
+            }
+          }
+          ExpDesc::Call(pc) | ExpDesc::VarArgs(pc) => {
+            self.set_nresults(pc, None);
+            self.code(ByteCode::Return(sp0 as u8, 0));
+          }
+          last if n == 1 => {
+            let src = self.discharge_any(last);
+            self.code(ByteCode::Return(src as u8, 2));
+          }
+          last => {
+            self.discharge(sp0 + n - 1, last);
+            self.code(ByteCode::Return(sp0 as u8, (n + 1) as u8));
+          }
+        }
+      }
+    }
+    if self.lexer.peek() == &Token::SemiColon {
+      self.lexer.next();
+    }
+  }
+}
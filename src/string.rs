@@ -0,0 +1,271 @@
+//! # String
+//!
+//! String objects of rua.
+//!
+//! Short strings are interned: there is only one object for each content,
+//! so comparing them is comparing pointers, and their hashes are computed
+//! once at creation. Long strings are created without lookup, and hashed
+//! lazily when used as table keys.
+//!
+//! Each state has its string table, in its heap. Strings created out of a
+//! state, as the constants of chunks, are interned only when the state
+//! takes them, so two short strings of different pointers are compared by
+//! their hashes, and then by their contents if equal.
+
+use crate::gc::{Allocated, Charge};
+use std::{
+  cell::Cell,
+  collections::HashMap,
+  hash::{BuildHasherDefault, Hasher},
+  mem,
+  ops::Deref,
+  rc::{Rc, Weak},
+};
+
+/// Max length of short strings, as `LUAI_MAXSHORTLEN`.
+pub const SHORT_STR_MAX: usize = 40;
+
+/// Seed of string hashes.
+const SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// Hash of `bytes`, as `luaS_hash`.
+fn hash_bytes(bytes: &[u8]) -> u64 {
+  let mut h = SEED ^ bytes.len() as u64;
+  for &b in bytes.iter().rev() {
+    h ^= (h << 5).wrapping_add(h >> 2).wrapping_add(b as u64);
+  }
+  h
+}
+
+/// Hasher of keys which are hashes already, multiplied to spread their
+/// entropy into the high bits, which the hash map matches first.
+#[derive(Default)]
+struct MixHasher(u64);
+
+impl Hasher for MixHasher {
+  fn finish(&self) -> u64 {
+    self.0
+  }
+  fn write(&mut self, _: &[u8]) {
+    unreachable!("only u64 keys")
+  }
+  fn write_u64(&mut self, n: u64) {
+    self.0 = n.wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+  }
+}
+
+/// Interned strings of a hash, which are mostly one.
+enum Bucket {
+  One(Weak<ShortString>),
+  Many(Vec<Weak<ShortString>>),
+}
+
+impl Bucket {
+  fn find(&self, bytes: &[u8]) -> Option<Rc<ShortString>> {
+    let found = |s: &Weak<ShortString>| s.upgrade().filter(|s| s[..] == *bytes);
+    match self {
+      Bucket::One(s) => found(s),
+      Bucket::Many(v) => v.iter().find_map(found),
+    }
+  }
+
+  /// Add `s`, dropping the dead ones.
+  fn push(&mut self, s: Weak<ShortString>) {
+    *self = match mem::replace(self, Bucket::Many(vec![])) {
+      Bucket::One(old) if old.strong_count() == 0 => Bucket::One(s),
+      Bucket::One(old) => Bucket::Many(vec![old, s]),
+      Bucket::Many(mut v) => {
+        v.retain(|s| s.strong_count() > 0);
+        v.push(s);
+        Bucket::Many(v)
+      }
+    };
+  }
+
+  /// Drop the dead ones, return whether it's empty then.
+  fn clean(&mut self) -> bool {
+    match self {
+      Bucket::One(s) => s.strong_count() == 0,
+      Bucket::Many(v) => {
+        v.retain(|s| s.strong_count() > 0);
+        v.is_empty()
+      }
+    }
+  }
+}
+
+/// Min count of hashes before dropping the dead strings of a table.
+const MIN_CLEAN: usize = 1024;
+
+/// ## StringTable
+///
+/// The interned short strings of a state, keyed by hash. The dead ones are
+/// dropped when the table doubles, and by full collections.
+#[derive(Default)]
+pub struct StringTable {
+  strings: HashMap<u64, Bucket, BuildHasherDefault<MixHasher>>,
+  /// Drop the dead strings when the count of hashes reaches it
+  clean_at: usize,
+}
+
+impl StringTable {
+  /// The only string object of content `bytes`, created and charged to
+  /// `allocated` if absent.
+  pub fn intern(&mut self, bytes: &[u8], allocated: &Allocated) -> Rc<ShortString> {
+    let hash = hash_bytes(bytes);
+    if let Some(s) = self.strings.get(&hash).and_then(|b| b.find(bytes)) {
+      return s;
+    }
+    let s = Rc::new(ShortString::with_hash(bytes, hash));
+    self.insert(&s, allocated);
+    s
+  }
+
+  /// The interned string of the content of `s`, which is `s` itself if
+  /// absent. Strings taken by a state already are returned as they are.
+  pub fn intern_string(&mut self, s: Rc<ShortString>, allocated: &Allocated) -> Rc<ShortString> {
+    if s.charge.is_set() {
+      return s;
+    }
+    if let Some(found) = self.strings.get(&s.hash).and_then(|b| b.find(&s)) {
+      return found;
+    }
+    self.insert(&s, allocated);
+    s
+  }
+
+  fn insert(&mut self, s: &Rc<ShortString>, allocated: &Allocated) {
+    s.charge.set(allocated, mem::size_of::<ShortString>());
+    if self.strings.len() >= self.clean_at {
+      self.clean();
+      self.clean_at = (self.strings.len() * 2).max(MIN_CLEAN);
+    }
+    match self.strings.get_mut(&s.hash) {
+      Some(bucket) => bucket.push(Rc::downgrade(s)),
+      None => {
+        self.strings.insert(s.hash, Bucket::One(Rc::downgrade(s)));
+      }
+    }
+  }
+
+  /// Drop the dead strings.
+  pub fn clean(&mut self) {
+    self.strings.retain(|_, bucket| !bucket.clean());
+  }
+}
+
+/// ## ShortString
+///
+/// A string of at most `SHORT_STR_MAX` bytes, whose hash is computed at
+/// creation, and which is interned when a state takes it.
+pub struct ShortString {
+  hash: u64,
+  len: u8,
+  buf: [u8; SHORT_STR_MAX],
+  charge: Charge,
+}
+
+impl ShortString {
+  /// A string not interned, as the constants of chunks.
+  pub fn new(bytes: &[u8]) -> Self {
+    Self::with_hash(bytes, hash_bytes(bytes))
+  }
+
+  fn with_hash(bytes: &[u8], hash: u64) -> Self {
+    debug_assert!(bytes.len() <= SHORT_STR_MAX);
+    let mut buf = [0; SHORT_STR_MAX];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    ShortString {
+      hash,
+      len: bytes.len() as u8,
+      buf,
+      charge: Charge::default(),
+    }
+  }
+
+  pub fn hash(&self) -> u64 {
+    self.hash
+  }
+}
+
+impl PartialEq for ShortString {
+  fn eq(&self, other: &Self) -> bool {
+    self.hash == other.hash && self[..] == other[..]
+  }
+}
+
+impl Drop for ShortString {
+  fn drop(&mut self) {
+    self.charge.free(mem::size_of::<Self>());
+  }
+}
+
+impl Deref for ShortString {
+  type Target = [u8];
+  fn deref(&self) -> &[u8] {
+    &self.buf[..self.len as usize]
+  }
+}
+
+/// ## LongString
+///
+/// A string not interned, which is hashed at the first use.
+pub struct LongString {
+  hash: Cell<Option<u64>>,
+  bytes: Vec<u8>,
+  charge: Charge,
+}
+
+impl LongString {
+  pub fn new(bytes: Vec<u8>) -> Self {
+    Self {
+      hash: Cell::new(None),
+      bytes,
+      charge: Charge::default(),
+    }
+  }
+
+  /// Charge it to `allocated`, when a state takes it.
+  pub(crate) fn charge_to(&self, allocated: &Allocated) {
+    self.charge.set(allocated, self.mem_size());
+  }
+
+  pub fn hash(&self) -> u64 {
+    match self.hash.get() {
+      Some(hash) => hash,
+      None => {
+        let hash = hash_bytes(&self.bytes);
+        self.hash.set(Some(hash));
+        hash
+      }
+    }
+  }
+
+  fn mem_size(&self) -> usize {
+    mem::size_of::<Self>() + self.bytes.capacity()
+  }
+}
+
+impl PartialEq for LongString {
+  fn eq(&self, other: &Self) -> bool {
+    if let (Some(h0), Some(h1)) = (self.hash.get(), other.hash.get()) {
+      if h0 != h1 {
+        return false;
+      }
+    }
+    self.bytes == other.bytes
+  }
+}
+
+impl Drop for LongString {
+  fn drop(&mut self) {
+    self.charge.free(self.mem_size());
+  }
+}
+
+impl Deref for LongString {
+  type Target = [u8];
+  fn deref(&self) -> &[u8] {
+    &self.bytes
+  }
+}
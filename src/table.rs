@@ -3,29 +3,469 @@
 //! Implementation of `Table` type in lua.
 
 use std::{
+  cell::RefCell,
   collections::HashMap,
   fmt::{Debug, Display},
+  hash::{BuildHasherDefault, Hasher},
+  rc::Rc,
 };
 
-use crate::value::Value;
+use crate::{
+  gc::{self, Allocated, Charge, DropRef},
+  value::Value,
+  vm::arith::float_to_integer,
+};
+
+/// Hasher of table keys, as `FxHasher`.
+///
+/// Keys feed it small integers, pointers or the hashes cached in strings,
+/// which do not need a strong hasher.
+#[derive(Default)]
+pub struct KeyHasher(u64);
+
+impl Hasher for KeyHasher {
+  fn finish(&self) -> u64 {
+    self.0
+  }
+  fn write(&mut self, bytes: &[u8]) {
+    for &b in bytes {
+      self.write_u64(b as u64);
+    }
+  }
+  fn write_u64(&mut self, n: u64) {
+    self.0 = (self.0.rotate_left(5) ^ n).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+  }
+  fn write_u8(&mut self, n: u8) {
+    self.write_u64(n as u64);
+  }
+  fn write_i64(&mut self, n: i64) {
+    self.write_u64(n as u64);
+  }
+  fn write_usize(&mut self, n: usize) {
+    self.write_u64(n as u64);
+  }
+}
+
+/// ## KeyMap
+///
+/// The map part of tables, whose entries are kept in order of insertion,
+/// so that a traversal can resume from any key.
+///
+/// Removing a key only clears its value: the dead entry keeps its place,
+/// so fields can be cleared during traversal, until the map is compacted
+/// at rehash.
+#[derive(Debug, Default)]
+pub struct KeyMap {
+  /// Positions of the entries, keyed by their keys
+  index: HashMap<Value, usize, BuildHasherDefault<KeyHasher>>,
+  entries: Vec<(Value, Value)>,
+}
+
+impl KeyMap {
+  pub fn with_capacity(capacity: usize) -> Self {
+    Self {
+      index: HashMap::with_capacity_and_hasher(capacity, Default::default()),
+      entries: Vec::with_capacity(capacity),
+    }
+  }
+
+  /// Count of the entries, the dead ones included.
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  /// Count of the entries it can hold without growing.
+  pub fn capacity(&self) -> usize {
+    self.entries.capacity()
+  }
+
+  fn mem_size(&self) -> usize {
+    self.entries.capacity() * std::mem::size_of::<(Value, Value)>()
+      + self.index.capacity() * std::mem::size_of::<(Value, usize, u64)>()
+  }
+
+  /// The value of `key`, which is `Nil` for a dead entry.
+  pub fn get(&self, key: &Value) -> Option<&Value> {
+    self.index.get(key).map(|&i| &self.entries[i].1)
+  }
+
+  /// The value slot of `key`, assigning `Nil` to which removes the entry.
+  pub fn get_mut(&mut self, key: &Value) -> Option<&mut Value> {
+    self.index.get(key).map(|&i| &mut self.entries[i].1)
+  }
+
+  pub fn insert(&mut self, key: Value, value: Value) {
+    match self.index.get(&key) {
+      Some(&i) => self.entries[i].1 = value,
+      None => {
+        self.index.insert(key.clone(), self.entries.len());
+        self.entries.push((key, value));
+      }
+    }
+  }
+
+  /// Remove the value of `key`, leaving a dead entry in place.
+  pub fn remove(&mut self, key: &Value) -> Option<Value> {
+    let slot = self.get_mut(key)?;
+    Some(std::mem::replace(slot, Value::Nil)).filter(|v| !v.is_nil())
+  }
+
+  /// Remove the entry of `key` entirely, for the collector to drop keys.
+  ///
+  /// Its place is left empty, so the traversal can go on after it.
+  pub fn purge(&mut self, key: &Value) -> Option<(Value, Value)> {
+    let i = self.index.remove(key)?;
+    Some(std::mem::replace(
+      &mut self.entries[i],
+      (Value::Nil, Value::Nil),
+    ))
+  }
+
+  /// Remove all entries, return their keys and values.
+  fn drain(&mut self) -> impl Iterator<Item = Value> + '_ {
+    self.index.clear();
+    self.entries.drain(..).flat_map(|(k, v)| [k, v])
+  }
+
+  /// Drop the dead entries.
+  pub fn compact(&mut self) {
+    if self.entries.iter().all(|(_, v)| !v.is_nil()) {
+      return;
+    }
+    self.entries.retain(|(_, v)| !v.is_nil());
+    self.index.clear();
+    for (i, (k, _)) in self.entries.iter().enumerate() {
+      self.index.insert(k.clone(), i);
+    }
+  }
+
+  /// The live entries, in order.
+  pub fn iter(&self) -> impl Iterator<Item = (&Value, &Value)> {
+    self.entries().filter(|(_, v)| !v.is_nil())
+  }
+
+  pub fn keys(&self) -> impl Iterator<Item = &Value> {
+    self.iter().map(|(k, _)| k)
+  }
+
+  /// All the entries, dead or empty ones included, which the collector
+  /// inspects.
+  pub fn entries(&self) -> impl Iterator<Item = (&Value, &Value)> {
+    self.entries.iter().map(|(k, v)| (k, v))
+  }
+
+  /// The first live entry from position `start` on.
+  fn next_from(&self, start: usize) -> Option<(Value, Value)> {
+    let rest = self.entries.get(start..)?;
+    let (k, v) = rest.iter().find(|(_, v)| !v.is_nil())?;
+    Some((k.clone(), v.clone()))
+  }
+}
 
 #[derive(Debug)]
 pub struct Table {
   pub array: Vec<Value>,
-  pub map: HashMap<Value, Value>,
+  pub map: KeyMap,
+  pub metatable: Option<Rc<RefCell<Table>>>,
+  /// Memory size accounted for the collector
+  mem_size: usize,
+  charge: Charge,
 }
 
 impl Table {
   pub fn new(array_size: usize, map_size: usize) -> Self {
-    Self {
+    let mut table = Self {
       array: Vec::with_capacity(array_size),
-      map: HashMap::with_capacity(map_size),
+      map: KeyMap::with_capacity(map_size),
+      metatable: None,
+      mem_size: 0,
+      charge: Charge::default(),
+    };
+    table.update_mem_size();
+    table
+  }
+
+  /// Update the memory size accounted, after the array or map is resized.
+  pub fn update_mem_size(&mut self) {
+    let size = std::mem::size_of::<Self>()
+      + self.array.capacity() * std::mem::size_of::<Value>()
+      + self.map.mem_size();
+    let old = std::mem::replace(&mut self.mem_size, size);
+    self.charge.resize(old, size);
+  }
+
+  /// Move out the references it holds, to be dropped by `gc::drop_refs`.
+  pub(crate) fn take_refs(&mut self, refs: &mut Vec<DropRef>) {
+    refs.extend(self.array.drain(..).map(DropRef::Value));
+    refs.extend(self.map.drain().map(DropRef::Value));
+    refs.extend(
+      self
+        .metatable
+        .take()
+        .map(|mt| DropRef::Value(Value::Table(mt))),
+    );
+  }
+
+  /// Charge it to `allocated`, when a state takes it.
+  pub(crate) fn charge_to(&self, allocated: &Allocated) {
+    self.charge.set(allocated, self.mem_size);
+  }
+
+  /// Error message if `key` can not be a key of table.
+  pub fn key_error(key: &Value) -> Option<&'static str> {
+    match key {
+      Value::Nil => Some("index is nil"),
+      Value::Float(f) if f.is_nan() => Some("index is NaN"),
+      _ => None,
+    }
+  }
+
+  /// `t[key]`, `Nil` if absent
+  pub fn get(&self, key: &Value) -> Value {
+    if let Value::Float(f) = key {
+      if let Some(i) = float_to_integer(*f) {
+        return self.get(&Value::Integer(i));
+      }
+    }
+    if let Value::Integer(i) = key {
+      if let Some(v) = (*i as usize).checked_sub(1).and_then(|i| self.array.get(i)) {
+        return v.clone();
+      }
+    }
+    self.map.get(key).cloned().unwrap_or(Value::Nil)
+  }
+
+  /// `t[i]`, `Nil` if absent
+  pub fn get_int(&self, i: i64) -> Value {
+    match (i as usize).checked_sub(1).and_then(|i| self.array.get(i)) {
+      Some(v) => v.clone(),
+      None => self
+        .map
+        .get(&Value::Integer(i))
+        .cloned()
+        .unwrap_or(Value::Nil),
+    }
+  }
+
+  /// `t[key] = value`, assigning `nil` removes the entry
+  ///
+  /// Floats with integer values are converted into integers. Invalid keys,
+  /// see [`Table::key_error`], are ignored.
+  pub fn set(&mut self, key: Value, value: Value) {
+    if Self::key_error(&key).is_some() {
+      return;
+    }
+    let key = match key {
+      Value::Float(f) => float_to_integer(f).map_or(key, Value::Integer),
+      key => key,
+    };
+    if let Value::Integer(i) = key {
+      if let Some(slot) = (i as usize)
+        .checked_sub(1)
+        .and_then(|i| self.array.get_mut(i))
+      {
+        *slot = value;
+        return;
+      }
+      if i >= 1 && i as usize == self.array.len() + 1 && !value.is_nil() {
+        let capacity = self.array.capacity();
+        self.array.push(value);
+        // move the following entries from map to array
+        let mut next = self.array.len() as i64 + 1;
+        while let Some(v) = self.map.remove(&Value::Integer(next)) {
+          self.array.push(v);
+          next += 1;
+        }
+        if self.array.capacity() != capacity {
+          self.update_mem_size();
+        }
+        return;
+      }
+    }
+    if value.is_nil() {
+      self.map.remove(&key);
+      return;
+    }
+    if let Some(slot) = self.map.get_mut(&key) {
+      *slot = value;
+      return;
+    }
+    // a new key, resize the parts before the map grows
+    if self.map.len() == self.map.capacity() {
+      self.rehash(&key);
+      if let Value::Integer(i) = key {
+        if let Some(slot) = (i as usize)
+          .checked_sub(1)
+          .and_then(|i| self.array.get_mut(i))
+        {
+          *slot = value;
+          return;
+        }
+      }
+    }
+    self.map.insert(key, value);
+    self.update_mem_size();
+  }
+
+  /// Resize the array part to the largest power of 2, `n`, such that more
+  /// than half of the slots `1..=n` would be used, counting the new key
+  /// `extra`, as `computesizes` of Lua. Integer keys are moved between the
+  /// array part and the map part accordingly.
+  fn rehash(&mut self, extra: &Value) {
+    // nums[i]: number of keys `k` with `2^(i-1) < k <= 2^i`
+    let mut nums = [0usize; 65];
+    let mut count = |k: i64| {
+      if k >= 1 {
+        nums[64 - (k as u64 - 1).leading_zeros() as usize] += 1;
+      }
+    };
+    for (i, v) in self.array.iter().enumerate() {
+      if !v.is_nil() {
+        count(i as i64 + 1);
+      }
+    }
+    for k in self.map.keys() {
+      if let Value::Integer(k) = k {
+        count(*k);
+      }
+    }
+    if let Value::Integer(k) = extra {
+      count(*k);
     }
+
+    let total: usize = nums.iter().sum();
+    let (mut a, mut optimal, mut twotoi) = (0, 0, 1usize);
+    for n in nums {
+      if total <= twotoi / 2 {
+        break;
+      }
+      a += n;
+      if a > twotoi / 2 {
+        optimal = twotoi;
+      }
+      twotoi *= 2;
+    }
+
+    let old = self.array.len();
+    if optimal < old {
+      for (i, v) in self.array.drain(optimal..).enumerate() {
+        if !v.is_nil() {
+          self.map.insert(Value::Integer((optimal + i + 1) as i64), v);
+        }
+      }
+      self.array.shrink_to_fit();
+    } else if optimal > old {
+      self.array.resize(optimal, Value::Nil);
+      for i in old..optimal {
+        if let Some(v) = self.map.remove(&Value::Integer(i as i64 + 1)) {
+          self.array[i] = v;
+        }
+      }
+    }
+    self.map.compact();
+    self.update_mem_size();
+  }
+
+  /// The entry after `key` in traversal order, which is the array part and
+  /// then the map part, or the first entry if `key` is `Nil`.
+  ///
+  /// `Ok(None)` at the end, and an error if `key` is not in the table.
+  pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, &'static str> {
+    let key = match key {
+      Value::Float(f) => float_to_integer(*f).map_or(key.clone(), Value::Integer),
+      key => key.clone(),
+    };
+    let start = match key {
+      Value::Nil => 0,
+      Value::Integer(i) if i >= 1 && i as usize <= self.array.len() => i as usize,
+      key => match self.map.index.get(&key) {
+        Some(pos) => self.array.len() + pos + 1,
+        None => return Err("invalid key to 'next'"),
+      },
+    };
+    if let Some(rest) = self.array.get(start..) {
+      if let Some(i) = rest.iter().position(|v| !v.is_nil()) {
+        let i = start + i;
+        return Ok(Some((Value::Integer(i as i64 + 1), self.array[i].clone())));
+      }
+    }
+    Ok(self.map.next_from(start.saturating_sub(self.array.len())))
+  }
+
+  /// Field `event` of the metatable, `Nil` if absent
+  pub fn metafield(&self, event: &str) -> Value {
+    match &self.metatable {
+      Some(mt) => mt.borrow().get(&event.into()),
+      None => Value::Nil,
+    }
+  }
+
+  /// A border of the table, as the `#` operator returns, which is
+  /// searched as `luaH_getn`.
+  pub fn len(&self) -> usize {
+    let n = self.array.len();
+    if n > 0 && self.array[n - 1].is_nil() {
+      // binary search in the array part, with `t[i]` non-nil (or `i` is 0)
+      // and `t[j]` nil
+      let (mut i, mut j) = (0, n);
+      while j - i > 1 {
+        let m = (i + j) / 2;
+        if self.array[m - 1].is_nil() {
+          j = m;
+        } else {
+          i = m;
+        }
+      }
+      return i;
+    }
+    if self.map.is_empty() || self.get_int(n as i64 + 1).is_nil() {
+      return n;
+    }
+    // unbound search in the map part, doubling `j` until `t[j]` is nil
+    let (mut i, mut j) = (n + 1, (n + 1) * 2);
+    while !self.get_int(j as i64).is_nil() {
+      i = j;
+      if j > i64::MAX as usize / 2 {
+        // a pathological table, resort to linear search
+        let mut k = 1;
+        while !self.get_int(k).is_nil() {
+          k += 1;
+        }
+        return k as usize - 1;
+      }
+      j *= 2;
+    }
+    while j - i > 1 {
+      let m = (i + j) / 2;
+      if self.get_int(m as i64).is_nil() {
+        j = m;
+      } else {
+        i = m;
+      }
+    }
+    i
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.array.is_empty() && self.map.is_empty()
+  }
+}
+
+impl Drop for Table {
+  fn drop(&mut self) {
+    self.charge.free(self.mem_size);
+    let mut refs = vec![];
+    self.take_refs(&mut refs);
+    gc::drop_refs(refs);
   }
 }
 
 impl Display for Table {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "{{table}}: {:p}", self)
+    write!(f, "table: {:p}", self)
   }
 }
@@ -2,10 +2,19 @@
 //!
 //! Definition of `Value` type of rua.
 
-use crate::{table::Table, vm::ExeState};
+use crate::{
+  gc::{self, Allocated, Charge, DropRef},
+  parse::FuncProto,
+  string::{LongString, ShortString, SHORT_STR_MAX},
+  table::Table,
+  vm::{
+    arith::{float_to_integer, float_to_string},
+    RustFunction,
+  },
+};
 use core::fmt;
-use gc::Gc;
 use std::{
+  any::Any,
   cell::RefCell,
   fmt::{Debug, Display},
   hash::Hash,
@@ -13,39 +22,148 @@ use std::{
   str,
 };
 
-/// sizeof(Value) - 1(tag) - 1(len)
-const SHORT_STR_MAX: usize = 16 - 1 - 1;
-/// 64 - sizeof(value)
-const MID_STR_MAX: usize = 48 - 1;
-
-fn slice_to_short_mid_str(v: &[u8]) -> Option<Value> {
-  let len = v.len();
-  match len {
-    l if l <= SHORT_STR_MAX => {
-      let mut buf = [0; SHORT_STR_MAX];
-      buf[..len].copy_from_slice(v);
-      Some(Value::ShortStr(l as u8, buf))
+/// ## Upvalue
+///
+/// A variable captured by closures: open while it's alive on the stack,
+/// closed (moved into here) after it goes out of scope.
+#[derive(Debug)]
+pub enum Upvalue {
+  /// Index of the variable in stack
+  Open(usize),
+  Closed(Value),
+}
+
+impl Upvalue {
+  /// Move out the closed value, to be dropped by `gc::drop_refs`.
+  pub(crate) fn take_refs(&mut self, refs: &mut Vec<DropRef>) {
+    if let Upvalue::Closed(v) = self {
+      refs.push(DropRef::Value(std::mem::replace(v, Value::Nil)));
+    }
+  }
+}
+
+impl Drop for Upvalue {
+  fn drop(&mut self) {
+    let mut refs = vec![];
+    self.take_refs(&mut refs);
+    gc::drop_refs(refs);
+  }
+}
+
+/// ## LuaClosure
+///
+/// A Lua function with its upvalues.
+pub struct LuaClosure {
+  pub proto: Rc<FuncProto>,
+  /// Upvalues, which `debug.upvaluejoin` may replace
+  pub upvalues: RefCell<Vec<Rc<RefCell<Upvalue>>>>,
+  charge: Charge,
+}
+
+impl LuaClosure {
+  pub fn new(proto: Rc<FuncProto>, upvalues: Vec<Rc<RefCell<Upvalue>>>) -> Self {
+    Self {
+      proto,
+      upvalues: RefCell::new(upvalues),
+      charge: Charge::default(),
     }
-    l if l <= MID_STR_MAX => {
-      let mut buf = [0; MID_STR_MAX];
-      buf[..len].copy_from_slice(v);
-      Some(Value::MidStr(Gc::new((l as u8, buf))))
+  }
+
+  /// Charge it to `allocated`, when a state takes it.
+  pub(crate) fn charge_to(&self, allocated: &Allocated) {
+    self.charge.set(allocated, self.mem_size());
+  }
+
+  fn mem_size(&self) -> usize {
+    std::mem::size_of::<Self>()
+      + self.proto.upvalues.len() * std::mem::size_of::<RefCell<Upvalue>>()
+  }
+
+  /// Move out the upvalues, to be dropped by `gc::drop_refs`.
+  pub(crate) fn take_refs(&self, refs: &mut Vec<DropRef>) {
+    if let Ok(mut upvalues) = self.upvalues.try_borrow_mut() {
+      refs.extend(upvalues.drain(..).map(DropRef::Upvalue));
     }
-    _ => None,
   }
 }
 
+impl Drop for LuaClosure {
+  fn drop(&mut self) {
+    self.charge.free(self.mem_size());
+    let mut refs = vec![];
+    self.take_refs(&mut refs);
+    gc::drop_refs(refs);
+  }
+}
+
+/// ## NativeClosure
+///
+/// A native function with upvalues, which it accesses by
+/// [`ExeState::native_upvalue`](crate::vm::ExeState::native_upvalue).
+pub struct NativeClosure {
+  pub func: RustFunction,
+  pub upvalues: RefCell<Vec<Value>>,
+}
+
+/// ## UserData
+///
+/// An object of the host, as a file handle, with its metatable. It's freed,
+/// and so its data dropped, when the last reference goes.
+pub struct UserData {
+  pub data: RefCell<Box<dyn Any>>,
+  pub metatable: RefCell<Option<Rc<RefCell<Table>>>>,
+}
+
 #[derive(Clone)]
 pub enum Value {
   Nil,
   Boolean(bool),
   Integer(i64),
   Float(f64),
-  ShortStr(u8, [u8; SHORT_STR_MAX]),
-  MidStr(Gc<(u8, [u8; MID_STR_MAX])>),
-  LongStr(Gc<Vec<u8>>),
-  Function(fn(&mut ExeState) -> i32),
+  ShortStr(Rc<ShortString>),
+  LongStr(Rc<LongString>),
+  Function(RustFunction),
+  LuaFunction(Rc<LuaClosure>),
+  NativeClosure(Rc<NativeClosure>),
   Table(Rc<RefCell<Table>>),
+  UserData(Rc<UserData>),
+  /// A bare pointer as identity, as `debug.upvalueid` gives
+  LightUserData(*const ()),
+}
+
+impl Value {
+  /// Name of the type, as `type()` returns.
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Value::Nil => "nil",
+      Value::Boolean(_) => "boolean",
+      Value::Integer(_) | Value::Float(_) => "number",
+      Value::ShortStr(_) | Value::LongStr(_) => "string",
+      Value::Function(_) | Value::LuaFunction(_) | Value::NativeClosure(_) => "function",
+      Value::Table(_) => "table",
+      Value::UserData(_) | Value::LightUserData(_) => "userdata",
+    }
+  }
+
+  pub fn is_nil(&self) -> bool {
+    matches!(self, Value::Nil)
+  }
+
+  /// Only `nil` and `false` are falsy in Lua.
+  pub fn is_falsy(&self) -> bool {
+    matches!(self, Value::Nil | Value::Boolean(false))
+  }
+
+  pub fn is_str(&self) -> bool {
+    matches!(self, Value::ShortStr(_) | Value::LongStr(_))
+  }
+
+  pub fn is_function(&self) -> bool {
+    matches!(
+      self,
+      Value::Function(_) | Value::LuaFunction(_) | Value::NativeClosure(_)
+    )
+  }
 }
 
 impl Hash for Value {
@@ -54,22 +172,29 @@ impl Hash for Value {
       Value::Nil => (),
       Value::Boolean(b) => b.hash(state),
       Value::Integer(i) => i.hash(state),
-      Value::Float(f) => {
-        // TODO: try to convert to integer
-        (f).to_bits().hash(state)
-      }
-      Value::ShortStr(len, buf) => buf[..*len as usize].hash(state),
-      Value::MidStr(s) => s.1[..s.0 as usize].hash(state),
-      Value::LongStr(s) => s.hash(state),
+      // equal to the integer
+      Value::Float(f) => match float_to_integer(*f) {
+        Some(i) => i.hash(state),
+        None => f.to_bits().hash(state),
+      },
+      Value::ShortStr(s) => state.write_u64(s.hash()),
+      Value::LongStr(s) => state.write_u64(s.hash()),
       Value::Function(f) => (*f as *const usize).hash(state),
+      Value::LuaFunction(f) => Rc::as_ptr(f).hash(state),
+      Value::NativeClosure(f) => Rc::as_ptr(f).hash(state),
       Value::Table(t) => Rc::as_ptr(t).hash(state),
+      Value::UserData(u) => Rc::as_ptr(u).hash(state),
+      Value::LightUserData(p) => p.hash(state),
     }
   }
 }
 
 impl From<&[u8]> for Value {
   fn from(v: &[u8]) -> Self {
-    slice_to_short_mid_str(v).unwrap_or(Value::LongStr(Gc::new(v.to_vec())))
+    match v.len() {
+      l if l <= SHORT_STR_MAX => Value::ShortStr(Rc::new(ShortString::new(v))),
+      _ => Value::LongStr(Rc::new(LongString::new(v.to_vec()))),
+    }
   }
 }
 impl From<&str> for Value {
@@ -79,7 +204,10 @@ impl From<&str> for Value {
 }
 impl From<Vec<u8>> for Value {
   fn from(v: Vec<u8>) -> Self {
-    slice_to_short_mid_str(&v).unwrap_or(Value::LongStr(Gc::new(v.to_vec())))
+    match v.len() {
+      l if l <= SHORT_STR_MAX => Value::ShortStr(Rc::new(ShortString::new(&v))),
+      _ => Value::LongStr(Rc::new(LongString::new(v))),
+    }
   }
 }
 impl From<String> for Value {
@@ -91,18 +219,12 @@ impl From<String> for Value {
 impl<'a> From<&'a Value> for &'a [u8] {
   fn from(v: &'a Value) -> Self {
     match v {
-      Value::ShortStr(len, buf) => &buf[..*len as usize],
-      Value::MidStr(s) => &s.1[..s.0 as usize],
+      Value::ShortStr(s) => s,
       Value::LongStr(s) => s,
       _ => panic!("invalid string Value"),
     }
   }
 }
-impl<'a> From<&'a Value> for &'a str {
-  fn from(v: &'a Value) -> Self {
-    str::from_utf8(v.into()).unwrap()
-  }
-}
 impl From<&Value> for String {
   fn from(v: &Value) -> Self {
     String::from_utf8_lossy(v.into()).to_string()
@@ -129,8 +251,8 @@ impl From<f64> for Value {
     Self::Float(f)
   }
 }
-impl From<fn(&mut ExeState) -> i32> for Value {
-  fn from(func: fn(&mut ExeState) -> i32) -> Self {
+impl From<RustFunction> for Value {
+  fn from(func: RustFunction) -> Self {
     Self::Function(func)
   }
 }
@@ -142,14 +264,19 @@ impl PartialEq for Value {
       (Self::Boolean(l0), Self::Boolean(r0)) => *l0 == *r0,
       (Self::Integer(l0), Self::Integer(r0)) => *l0 == *r0,
       (Self::Float(l0), Self::Float(r0)) => *l0 == *r0,
-      (Self::ShortStr(len0, s0), Self::ShortStr(len1, s1)) => {
-        s0[..*len0 as usize] == s1[..*len1 as usize]
+      (Self::Integer(i), Self::Float(f)) | (Self::Float(f), Self::Integer(i)) => {
+        float_to_integer(*f) == Some(*i)
       }
-      (Self::MidStr(s0), Self::MidStr(s1)) => s0.1[..s0.0 as usize] == s1.1[..s1.0 as usize],
-      (Self::LongStr(s0), Self::LongStr(s1)) => *s0 == *s1,
-      (Self::Function(l0), Self::Function(r0)) => std::ptr::eq(l0, r0),
-      // TODO: detailed logic of comparing two `Table` objects
-      (Self::Table(l0), Self::Table(r0)) => std::ptr::eq(l0, r0),
+      // interned, unless created out of the state
+      (Self::ShortStr(s0), Self::ShortStr(s1)) => Rc::ptr_eq(s0, s1) || s0 == s1,
+      (Self::LongStr(s0), Self::LongStr(s1)) => Rc::ptr_eq(s0, s1) || s0 == s1,
+      // identity of the objects
+      (Self::Function(l0), Self::Function(r0)) => std::ptr::fn_addr_eq(*l0, *r0),
+      (Self::LuaFunction(l0), Self::LuaFunction(r0)) => Rc::ptr_eq(l0, r0),
+      (Self::NativeClosure(l0), Self::NativeClosure(r0)) => Rc::ptr_eq(l0, r0),
+      (Self::Table(l0), Self::Table(r0)) => Rc::ptr_eq(l0, r0),
+      (Self::UserData(l0), Self::UserData(r0)) => Rc::ptr_eq(l0, r0),
+      (Self::LightUserData(l0), Self::LightUserData(r0)) => l0 == r0,
       _ => false,
     }
   }
@@ -164,33 +291,36 @@ impl Debug for Value {
       Value::Boolean(b) => write!(f, "{b}"),
       Value::Integer(i) => write!(f, "{i}"),
       Value::Float(n) => write!(f, "{:?}", n),
-      Value::ShortStr(len, buf) => {
-        write!(
-          f,
-          "'{s}'",
-          s = String::from_utf8_lossy(&buf[..*len as usize])
-        )
-      }
-      Value::MidStr(s) => write!(
-        f,
-        "'{s}'",
-        s = String::from_utf8_lossy(&s.1[..s.0 as usize])
-      ),
-      Value::LongStr(s) => write!(f, "'{s}'", s = String::from_utf8_lossy(&s[..])),
-      Value::Function(_) => write!(f, "<function>"),
+      Value::ShortStr(s) => write!(f, "'{s}'", s = String::from_utf8_lossy(s)),
+      Value::LongStr(s) => write!(f, "'{s}'", s = String::from_utf8_lossy(s)),
+      Value::Function(_) | Value::NativeClosure(_) => write!(f, "<function>"),
+      Value::LuaFunction(closure) => write!(f, "function: {:p}", Rc::as_ptr(closure)),
       Value::Table(t) => {
         let t = t.borrow();
         write!(f, "{}", t)
       }
+      Value::UserData(u) => write!(f, "userdata: {:p}", Rc::as_ptr(u)),
+      Value::LightUserData(p) => write!(f, "userdata: {p:p}"),
     }
   }
 }
 
+/// The string form of values without metamethods, as `tostring` shows.
 impl Display for Value {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
-      Value::Table(t) => write!(f, "{}", t.borrow()),
-      _ => write!(f, "{:?}", self),
+      Value::Nil => write!(f, "nil"),
+      Value::Boolean(b) => write!(f, "{b}"),
+      Value::Integer(i) => write!(f, "{i}"),
+      Value::Float(n) => write!(f, "{}", float_to_string(*n)),
+      Value::ShortStr(s) => write!(f, "{}", String::from_utf8_lossy(s)),
+      Value::LongStr(s) => write!(f, "{}", String::from_utf8_lossy(s)),
+      Value::Function(func) => write!(f, "function: builtin: {:p}", *func as *const ()),
+      Value::LuaFunction(closure) => write!(f, "function: {:p}", Rc::as_ptr(closure)),
+      Value::NativeClosure(closure) => write!(f, "function: builtin: {:p}", Rc::as_ptr(closure)),
+      Value::Table(t) => write!(f, "table: {:p}", Rc::as_ptr(t)),
+      Value::UserData(u) => write!(f, "userdata: {:p}", Rc::as_ptr(u)),
+      Value::LightUserData(p) => write!(f, "userdata: {p:p}"),
     }
   }
 }
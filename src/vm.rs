@@ -7,130 +7,1485 @@
 //! The virtual machine do store values on a `stack-liked` vector, but
 //! it's bytecode is register-based.
 
-use crate::{bytecode::ByteCode, parse::ParseProto, table::Table, utils::New, value::Value};
-use std::{cell::RefCell, collections::HashMap, io::Read, rc::Rc};
+use crate::{
+  bytecode::ByteCode,
+  error::LuaError,
+  gc::Heap,
+  parse::FuncProto,
+  table::Table,
+  utils::New,
+  value::{LuaClosure, NativeClosure, Upvalue, Value},
+};
+use std::{
+  cell::RefCell,
+  collections::{BTreeMap, HashMap},
+  mem,
+  rc::Rc,
+};
 
+pub mod api;
+pub mod arith;
+mod call_info;
 pub mod lib;
 
-use self::lib::io::lib_print;
+use self::{
+  arith::ArithError,
+  call_info::{local_name, upval_info, var_info, CallInfo},
+  lib::debug::{MASK_CALL, MASK_COUNT, MASK_LINE, MASK_RET},
+};
+pub use self::{call_info::chunk_id, lib::package::ModuleBuilder};
+
+/// Signature of native functions.
+///
+/// Arguments are on the stack after `func_index`, and the returned count of
+/// values on the top of the stack are the results.
+pub type RustFunction = fn(&mut ExeState) -> Result<i32, LuaError>;
+
+/// Index of the global table in the registry, as `LUA_RIDX_GLOBALS`.
+const RIDX_GLOBALS: i64 = 2;
+
+/// Max depth of nested calls of the vm by Rust code, i.e. native functions
+/// and metamethods, as `LUAI_MAXCCALLS`.
+const MAX_CALLS: usize = 200;
+
+/// Max size of the stack, which limits the depth of the calls of Lua
+/// functions, as `LUAI_MAXSTACK`.
+const MAX_STACK: usize = 1_000_000;
+
+/// Max length of `__index`/`__newindex` chains, as `MAXTAGLOOP`.
+const MAX_TAG_LOOP: usize = 2000;
 
 pub struct ExeState {
-  /// A hashtable of global variables
-  globals: HashMap<String, Value>,
+  /// The table of global variables, `_G`, which is the default `_ENV`
+  globals: Rc<RefCell<Table>>,
+  /// Table of the host and libraries, as `debug.getregistry` gives
+  registry: Rc<RefCell<Table>>,
   /// Stack of values => core component of vm
   stack: Vec<Value>,
   /// The index of called func (in stack)
   func_index: usize,
+  /// Frames of the running functions, the innermost last
+  frames: Vec<CallInfo>,
+  /// Depth of the nested calls of the vm by Rust code
+  rust_calls: usize,
+  /// Registered names of native functions, keyed by the function address
+  native_names: HashMap<usize, String>,
+  /// Message handlers of `xpcall`s in progress, `None` for `pcall`s
+  handlers: Vec<Option<Value>>,
+  /// Upvalues still pointing to the stack, keyed by the stack index
+  open_upvalues: BTreeMap<usize, Rc<RefCell<Upvalue>>>,
+  /// Stack indexes of the pending to-be-closed variables, the innermost last
+  tbc_slots: Vec<usize>,
+  /// Collectable objects and the collector
+  heap: Heap,
+  /// Whether finalizers are being called
+  finalizing: bool,
+  /// Whether `warn` emits warnings
+  warnings: bool,
+  /// Metatable shared by all strings
+  string_meta: Option<Rc<RefCell<Table>>>,
+  /// Metatables shared by the values of the other types without their own
+  /// metatables, by type name, as `debug.setmetatable` sets
+  type_metas: HashMap<&'static str, Rc<RefCell<Table>>>,
+  /// Generator of `math.random`
+  random: lib::math::Random,
+  /// State of the `io` library
+  io: lib::io::IoState,
+  /// State of the `package` library
+  package: lib::package::PackageState,
+  /// Hook set by `debug.sethook`
+  hook: Option<lib::debug::Hook>,
+  /// Whether hooks are called, i.e. not running a hook
+  allow_hook: bool,
 }
 
 impl ExeState {
-  fn set_stack(&mut self, dst: u8, value: Value) {
-    let dst = dst as usize;
+  fn set_stack(&mut self, dst: usize, value: Value) {
     match dst.cmp(&self.stack.len()) {
       std::cmp::Ordering::Less => self.stack[dst] = value,
       std::cmp::Ordering::Equal => self.stack.push(value),
-      std::cmp::Ordering::Greater => panic!("fail in set_stack, for stack is full"),
+      std::cmp::Ordering::Greater => {
+        self.stack.resize(dst, Value::Nil);
+        self.stack.push(value);
+      }
+    }
+  }
+
+  /// Set global variable `name` as native function `f`, and remember its name.
+  pub fn register(&mut self, name: &str, f: RustFunction) {
+    self.native_names.insert(f as usize, name.to_owned());
+    self
+      .globals
+      .borrow_mut()
+      .set(name.into(), Value::Function(f));
+  }
+
+  /// Set global variable `libname` as a table of native functions.
+  pub fn register_lib(&mut self, libname: &str, funcs: &[(&str, RustFunction)]) {
+    let mut table = Table::new(0, funcs.len());
+    for (name, f) in funcs {
+      self
+        .native_names
+        .insert(*f as usize, format!("{libname}.{name}"));
+      table.map.insert((*name).into(), Value::Function(*f));
+    }
+    let table = self.new_table(table);
+    self.globals.borrow_mut().set(libname.into(), table);
+  }
+
+  /// Create a table value managed by the collector.
+  pub fn new_table(&mut self, table: Table) -> Value {
+    let table = Rc::new(RefCell::new(table));
+    self.heap.register_table(&table);
+    Value::Table(table)
+  }
+
+  /// Create a closure value managed by the collector.
+  fn new_closure(&mut self, closure: LuaClosure) -> Value {
+    let closure = Rc::new(closure);
+    self.heap.register_closure(&closure);
+    Value::LuaFunction(closure)
+  }
+
+  /// Run a step of the collector if it's in debt.
+  pub fn check_gc(&mut self) {
+    if self.heap.should_step() {
+      self.heap.step();
+      self.call_finalizers();
+    }
+  }
+
+  /// Close the state, as `lua_close`: close the pending to-be-closed
+  /// variables, call the finalizers of all objects marked, and free all
+  /// objects, so that the files left open are closed.
+  pub fn close(&mut self) {
+    self.hook = None;
+    let _ = self.close_tbc(0, None);
+    self.heap.finalize_all();
+    self.call_finalizers();
+    self.close_upvalues(0);
+    self.stack.clear();
+    *self.globals.borrow_mut() = Table::new(0, 0);
+    *self.registry.borrow_mut() = Table::new(0, 0);
+    self.string_meta = None;
+    self.type_metas.clear();
+    self.io = lib::io::IoState::default();
+    self.package = lib::package::PackageState::default();
+    self.heap.full_collect();
+  }
+
+  /// Call the `__gc` metamethods of the objects collected, in order.
+  ///
+  /// Errors in finalizers are ignored, and they are not called again by
+  /// the collections they trigger.
+  pub(crate) fn call_finalizers(&mut self) {
+    if self.finalizing {
+      return;
+    }
+    self.finalizing = true;
+    // not handled by the enclosing `xpcall`
+    self.handlers.push(None);
+    while let Some(obj) = self.heap.next_finalizer() {
+      let gc = self.get_metafield(&obj, "__gc");
+      if gc.is_function() {
+        let _ = self.call(gc, vec![obj]);
+      }
+    }
+    self.handlers.pop();
+    self.finalizing = false;
+  }
+
+  /// Field `event` of the metatable of `value`, `Nil` if absent.
+  pub(crate) fn get_metafield(&self, value: &Value, event: &str) -> Value {
+    match self.get_metatable(value) {
+      Some(mt) => mt.borrow().get(&event.into()),
+      None => Value::Nil,
+    }
+  }
+
+  /// Metatable of `value`: its own for tables and userdata, the shared one
+  /// of its type for the others.
+  pub(crate) fn get_metatable(&self, value: &Value) -> Option<Rc<RefCell<Table>>> {
+    match value {
+      Value::Table(t) => t.borrow().metatable.clone(),
+      Value::ShortStr(_) | Value::LongStr(_) => self.string_meta.clone(),
+      Value::UserData(u) => u.metatable.borrow().clone(),
+      v => self.type_metas.get(v.type_name()).cloned(),
+    }
+  }
+
+  /// Set the metatable shared by the values of the type of `value`, which
+  /// is not a table or userdata.
+  pub(crate) fn set_type_metatable(&mut self, value: &Value, mt: Option<Rc<RefCell<Table>>>) {
+    match (value, mt) {
+      (Value::ShortStr(_) | Value::LongStr(_), mt) => self.string_meta = mt,
+      (v, Some(mt)) => {
+        self.type_metas.insert(v.type_name(), mt);
+      }
+      (v, None) => {
+        self.type_metas.remove(v.type_name());
+      }
+    }
+  }
+
+  /// The table of global variables.
+  pub fn globals(&self) -> Value {
+    Value::Table(self.globals.clone())
+  }
+
+  /// The registry, where the host and libraries keep their values.
+  pub fn registry(&self) -> Value {
+    Value::Table(self.registry.clone())
+  }
+
+  /// Create a closure of main function `proto`, whose first upvalue, the
+  /// `_ENV` of text chunks, is `env`. The others, which only functions
+  /// loaded from binary chunks may have, are fresh ones of `nil`.
+  pub fn main_closure(&mut self, proto: &Rc<FuncProto>, env: Value) -> Value {
+    let mut env = Some(env);
+    let upvalues = proto
+      .upvalues
+      .iter()
+      .map(|_| {
+        let value = env.take().unwrap_or(Value::Nil);
+        let upvalue = Rc::new(RefCell::new(Upvalue::Closed(value)));
+        self.heap.register_upvalue(&upvalue);
+        upvalue
+      })
+      .collect();
+    self.new_closure(LuaClosure::new(proto.clone(), upvalues))
+  }
+
+  /// Get the upvalue which refers to stack slot `index`, create it if absent.
+  fn find_upvalue(&mut self, index: usize) -> Rc<RefCell<Upvalue>> {
+    let heap = &mut self.heap;
+    self
+      .open_upvalues
+      .entry(index)
+      .or_insert_with(|| {
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(index)));
+        heap.register_upvalue(&upvalue);
+        upvalue
+      })
+      .clone()
+  }
+
+  /// Close the upvalues of stack slots from `level` on, i.e. move the values
+  /// from stack into them.
+  fn close_upvalues(&mut self, level: usize) {
+    for (index, upvalue) in self.open_upvalues.split_off(&level) {
+      let value = self.stack.get(index).cloned().unwrap_or(Value::Nil);
+      *upvalue.borrow_mut() = Upvalue::Closed(value);
+    }
+  }
+
+  /// Call the `__close` metamethods of the to-be-closed variables from stack
+  /// slot `level` on, in reverse order, with the error being propagated.
+  ///
+  /// An error raised by a metamethod replaces the previous one, and is
+  /// passed to the remaining ones.
+  fn close_tbc(&mut self, level: usize, mut error: Option<LuaError>) -> Result<(), LuaError> {
+    while let Some(slot) = self.tbc_slots.pop_if(|slot| *slot >= level) {
+      let value = self.stack.get(slot).cloned().unwrap_or(Value::Nil);
+      let close = self.get_metafield(&value, "__close");
+      let err = error.as_ref().map_or(Value::Nil, |e| e.value.clone());
+      if let Err(e) = self.call(close, vec![value, err]) {
+        error = Some(e);
+      }
+    }
+    error.map_or(Ok(()), Err)
+  }
+
+  fn get_upvalue(&self, upvalue: &RefCell<Upvalue>) -> Value {
+    match &*upvalue.borrow() {
+      Upvalue::Open(index) => self.stack[*index].clone(),
+      Upvalue::Closed(value) => value.clone(),
+    }
+  }
+
+  fn set_upvalue(&mut self, upvalue: &RefCell<Upvalue>, value: Value) {
+    match &mut *upvalue.borrow_mut() {
+      Upvalue::Open(index) => self.stack[*index] = value,
+      Upvalue::Closed(v) => *v = value,
     }
   }
 }
 
 impl ExeState {
-  pub fn execute<R: Read>(&mut self, proto: &ParseProto<R>) {
-    for code in proto.bytecodes.iter() {
-      match *code {
-        ByteCode::GetGlobal(dst, name) => {
-          let name: &str = (&proto.constants[name as usize]).into();
-          let value = self.globals.get(name).unwrap_or(&Value::Nil).to_owned();
-          self.set_stack(dst, value);
-        }
-        ByteCode::SetGlobal(name, src) => {
-          let name = &proto.constants[name as usize];
-          let value = self.stack[src as usize].to_owned();
-          self.globals.insert(name.into(), value);
-        }
-        ByteCode::SetGlobalConst(name, src) => {
-          let name = &proto.constants[name as usize];
-          let value = proto.constants[src as usize].to_owned();
-          self.globals.insert(name.into(), value);
+  /// Execute the main function of a chunk.
+  ///
+  /// The error thrown, if any, is returned with its traceback.
+  pub fn execute(&mut self, proto: &Rc<FuncProto>) -> Result<(), LuaError> {
+    let func_index = self.stack.len();
+    let globals = self.globals();
+    let main = self.main_closure(proto, globals);
+    self.stack.push(main);
+    let result = self.call_function(func_index);
+    self.stack.truncate(func_index);
+    result.map(|_| ())
+  }
+
+  /// Call the function at `func_index` with the values after it as arguments.
+  ///
+  /// The results are moved to `func_index` on, return the count of them.
+  pub(crate) fn call_function(&mut self, func_index: usize) -> Result<usize, LuaError> {
+    if self.rust_calls >= MAX_CALLS {
+      return Err(self.rt_error("C stack overflow".to_owned()));
+    }
+    if !self.resolve_call(func_index) {
+      let msg = format!(
+        "attempt to call a {} value",
+        self.stack[func_index].type_name()
+      );
+      return Err(self.rt_error(msg));
+    }
+    let func = self.stack[func_index].clone();
+    self.check_stack(func_index)?;
+    self.rust_calls += 1;
+    let result = match func {
+      Value::Function(f) => self.call_native(f, func, func_index),
+      Value::NativeClosure(ref closure) => {
+        let f = closure.func;
+        self.call_native(f, func, func_index)
+      }
+      _ => {
+        self.frames.push(CallInfo::new(func, func_index));
+        let result = self.execute_lua();
+        match result {
+          Err(err) => {
+            self.close_upvalues(func_index + 1);
+            self.close_tbc(func_index + 1, Some(err)).map(|_| 0)
+          }
+          result => result,
         }
-        ByteCode::SetGlobalGlobal(name, src) => {
-          let name = &proto.constants[name as usize];
-          let src: &str = (&proto.constants[src as usize]).into();
-          let value = self.globals.get(src).unwrap_or(&Value::Nil).to_owned();
-          self.globals.insert(name.into(), value);
+      }
+    };
+    self.rust_calls -= 1;
+    self.frames.pop();
+    result
+  }
+
+  /// Make the value at `func_index` callable: a value that is not a function
+  /// is replaced by its `__call` metamethod, and becomes the first argument,
+  /// as `luaD_tryfuncTM`. Return `false` if there is no such metamethod.
+  fn resolve_call(&mut self, func_index: usize) -> bool {
+    for _ in 0..MAX_TAG_LOOP {
+      let func = &self.stack[func_index];
+      if func.is_function() {
+        return true;
+      }
+      match self.get_metafield(func, "__call") {
+        Value::Nil => return false,
+        handler => self.stack.insert(func_index, handler),
+      }
+    }
+    false
+  }
+
+  /// Raise error if the Lua function at `func_index` would make the stack
+  /// larger than `MAX_STACK`, as `luaD_growstack`.
+  fn check_stack(&mut self, func_index: usize) -> Result<(), LuaError> {
+    match &self.stack[func_index] {
+      Value::LuaFunction(closure) if func_index + 1 + closure.proto.max_stack > MAX_STACK => {
+        Err(self.rt_error("stack overflow".to_owned()))
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /// Call the native function `f`, which is `func` at `func_index`.
+  fn call_native(
+    &mut self,
+    f: RustFunction,
+    func: Value,
+    func_index: usize,
+  ) -> Result<usize, LuaError> {
+    self.frames.push(CallInfo::new(func, func_index));
+    self.call_hook(MASK_CALL, "call", None)?;
+    let saved = mem::replace(&mut self.func_index, func_index);
+    let result = f(self);
+    self.func_index = saved;
+    let n = result? as usize;
+    self.call_hook(MASK_RET, "return", None)?;
+    // move the results to the function's place
+    let start = self.stack.len() - n;
+    self.stack.drain(func_index..start);
+    Ok(n)
+  }
+
+  /// Call the hook for `event` if it's in the hook mask, with the `line` of
+  /// line events. Hooks are not called while running a hook.
+  fn call_hook(
+    &mut self,
+    mask: u8,
+    event: &'static str,
+    line: Option<usize>,
+  ) -> Result<(), LuaError> {
+    let func = match &self.hook {
+      Some(hook) if self.allow_hook && hook.mask & mask != 0 => hook.func.clone(),
+      _ => return Ok(()),
+    };
+    let line = line.map_or(Value::Nil, |line| Value::Integer(line as i64));
+    self.allow_hook = false;
+    let result = self.call(func, vec![event.into(), line]);
+    self.allow_hook = true;
+    result.map(|_| ())
+  }
+
+  /// Call the count and line hooks before running the bytecode at `pc`, as
+  /// `luaG_traceexec`. A line event is for a new line, or a jump back (a
+  /// loop) from `old_pc`, the pc of the last line event.
+  fn trace_exec(
+    &mut self,
+    proto: &FuncProto,
+    pc: usize,
+    old_pc: &mut usize,
+  ) -> Result<(), LuaError> {
+    let Some(hook) = &mut self.hook else {
+      return Ok(());
+    };
+    if !self.allow_hook {
+      return Ok(());
+    }
+    if hook.mask & MASK_COUNT != 0 {
+      hook.remaining -= 1;
+      if hook.remaining == 0 {
+        hook.remaining = hook.count;
+        self.call_hook(MASK_COUNT, "count", None)?;
+      }
+    }
+    if self
+      .hook
+      .as_ref()
+      .is_some_and(|hook| hook.mask & MASK_LINE != 0)
+    {
+      // stripped functions have no lines
+      if let Some(&line) = proto.lineinfo.get(pc) {
+        if pc <= *old_pc || Some(&line) != proto.lineinfo.get(*old_pc) {
+          self.call_hook(MASK_LINE, "line", Some(line))?;
         }
-        ByteCode::Call(func, _) => {
-          self.func_index = func as usize;
-          let func = &self.stack[self.func_index];
-          if let Value::Function(func) = func {
-            func(self);
-          } else {
-            panic!("invalid function: {:?}", func);
+      }
+      *old_pc = pc;
+    }
+    Ok(())
+  }
+
+  /// Create an error object with the traceback of current frames.
+  ///
+  /// If it's raised inside `xpcall`, the message handler is called here,
+  /// before the stack is unwound.
+  pub(crate) fn throw(&mut self, value: Value) -> LuaError {
+    let value = match self.handlers.last() {
+      Some(Some(handler)) => {
+        let handler = handler.clone();
+        // errors in the handler are not handled again
+        self.handlers.push(None);
+        let func_index = self.stack.len();
+        self.stack.push(handler);
+        self.stack.push(value);
+        let result = self.call_function(func_index);
+        self.handlers.pop();
+        let value = match result {
+          Ok(n) if n > 0 => self.stack[func_index].clone(),
+          Ok(_) => Value::Nil,
+          Err(_) => "error in error handling".into(),
+        };
+        self.stack.truncate(func_index);
+        value
+      }
+      _ => value,
+    };
+    LuaError::new(value, self.traceback(0))
+  }
+
+  /// Raise a runtime error in the running Lua function.
+  fn rt_error(&mut self, msg: String) -> LuaError {
+    let msg = self.where_(0) + &msg;
+    self.throw(msg.into())
+  }
+}
+
+impl ExeState {
+  /// Run the Lua function of the top frame.
+  ///
+  /// The Lua functions it calls are run in this loop too, with their frames
+  /// pushed and popped here, so the depth of Lua calls is limited by the
+  /// stack size only. If an error is raised, the frames above the first one
+  /// are unwound here, and the first one is left to the caller.
+  fn execute_lua(&mut self) -> Result<usize, LuaError> {
+    let entry = self.frames.len() - 1;
+    let mut result = self.run_lua(entry);
+    if result.is_err() {
+      while self.frames.len() > entry + 1 {
+        let level = self.frames[self.frames.len() - 1].func_index + 1;
+        self.close_upvalues(level);
+        result = self.close_tbc(level, result.err()).map(|_| 0);
+        self.frames.pop();
+      }
+    }
+    result
+  }
+
+  /// Run the Lua functions from frame `entry` on, as `luaV_execute`.
+  fn run_lua(&mut self, entry: usize) -> Result<usize, LuaError> {
+    let mut frame = entry;
+    // loop again for calls and tail calls of Lua functions
+    'enter: loop {
+      let mut func_index = self.frames[frame].func_index;
+      let mut closure = self.lua_closure(func_index);
+      let mut proto = closure.proto.clone();
+      let mut base = func_index + 1;
+      let nargs = self.stack.len() - base;
+      if proto.is_vararg && nargs > proto.nparam {
+        self.frames[frame].varargs = self.stack.split_off(base + proto.nparam);
+      }
+      let mut frame_top = base + proto.max_stack;
+      self.stack.resize(frame_top, Value::Nil);
+      if self.hook.is_some() {
+        let event = match self.frames[frame].is_tail {
+          true => "tail call",
+          false => "call",
+        };
+        self.call_hook(MASK_CALL, event, None)?;
+      }
+
+      let mut pc = 0;
+      let mut old_pc = 0;
+      // loop again for returns to the calling Lua functions
+      loop {
+        let n = 'run: loop {
+          let code = proto.bytecodes[pc];
+          self.frames[frame].pc = pc;
+          if self.hook.is_some() {
+            self.trace_exec(&proto, pc, &mut old_pc)?;
+          }
+          pc += 1;
+          match code {
+            ByteCode::GetUpval(dst, index) => {
+              let value = self.get_upvalue(&closure.upvalues.borrow()[index as usize]);
+              self.set_stack(base + dst as usize, value);
+            }
+            ByteCode::SetUpval(index, src) => {
+              let value = self.stack[base + src as usize].clone();
+              self.set_upvalue(&closure.upvalues.borrow()[index as usize], value);
+            }
+            ByteCode::GetTabUp(dst, table, k) => {
+              let t = self.get_upvalue(&closure.upvalues.borrow()[table as usize]);
+              let info = || upval_info(&proto, table as usize);
+              let value = self.get_index(&t, &proto.constants[k as usize], info)?;
+              self.set_stack(base + dst as usize, value);
+            }
+            ByteCode::SetTabUp(table, k, v) => {
+              let t = self.get_upvalue(&closure.upvalues.borrow()[table as usize]);
+              let key = proto.constants[k as usize].clone();
+              let value = self.stack[base + v as usize].clone();
+              self.set_index(&t, key, value, || upval_info(&proto, table as usize))?;
+            }
+            ByteCode::Close(a) => {
+              self.close_upvalues(base + a as usize);
+              self.close_tbc(base + a as usize, None)?;
+            }
+            ByteCode::Tbc(a) => {
+              // `nil` and `false` are ignored
+              let value = &self.stack[base + a as usize];
+              if !value.is_falsy() {
+                if self.get_metafield(value, "__close").is_nil() {
+                  let name = local_name(&proto, a as usize, pc - 1).unwrap_or("?");
+                  let msg = format!("variable '{name}' got a non-closable value");
+                  return Err(self.rt_error(msg));
+                }
+                self.tbc_slots.push(base + a as usize);
+              }
+            }
+            ByteCode::Call(func, nargs, nresults) => {
+              let func = func as usize;
+              let callee_index = base + func;
+              if nargs != 0 {
+                self.stack.truncate(callee_index + nargs as usize);
+              }
+              self.check_callable(&proto, frame, func)?;
+              if let Value::LuaFunction(_) = self.stack[callee_index] {
+                self.check_stack(callee_index)?;
+                let callee = self.stack[callee_index].clone();
+                self.frames.push(CallInfo::new(callee, callee_index));
+                frame += 1;
+                continue 'enter;
+              }
+              let n = self.call_function(callee_index)?;
+              self.adjust_results(callee_index, n, nresults, frame_top);
+            }
+            ByteCode::TailCall(func, nargs) => {
+              let func = func as usize;
+              let callee_index = base + func;
+              if nargs != 0 {
+                self.stack.truncate(callee_index + nargs as usize);
+              }
+              self.check_callable(&proto, frame, func)?;
+              self.close_upvalues(base);
+              if let Value::LuaFunction(_) = self.stack[callee_index] {
+                self.check_stack(callee_index)?;
+                // reuse the frame
+                self.stack.drain(func_index..callee_index);
+                let callee = self.stack[func_index].clone();
+                self.frames[frame] = CallInfo {
+                  is_tail: true,
+                  ..CallInfo::new(callee, func_index)
+                };
+                continue 'enter;
+              }
+              let n = self.call_function(callee_index)?;
+              self.call_hook(MASK_RET, "return", None)?;
+              self.stack.drain(func_index..callee_index);
+              break 'run n;
+            }
+            ByteCode::Return(first, nresults) => {
+              let start = base + first as usize;
+              let end = match nresults {
+                0 => self.stack.len(),
+                n => start + n as usize - 1,
+              };
+              self.close_upvalues(base);
+              // keep the results from the calls of `__close`
+              self.stack.resize(end, Value::Nil);
+              self.close_tbc(base, None)?;
+              self.stack.truncate(end);
+              self.call_hook(MASK_RET, "return", None)?;
+              self.stack.drain(func_index..start);
+              break 'run end - start;
+            }
+            ByteCode::Move(dst, src) => {
+              let value = self.stack[base + src as usize].to_owned();
+              self.set_stack(base + dst as usize, value);
+            }
+            ByteCode::LoadConst(dst, index) => {
+              let value = proto.constants[index as usize].to_owned();
+              self.set_stack(base + dst as usize, value);
+            }
+            ByteCode::LoadNil(dst) => self.set_stack(base + dst as usize, Value::Nil),
+            ByteCode::LoadBool(dst, b) => self.set_stack(base + dst as usize, Value::Boolean(b)),
+            ByteCode::LoadInt(dst, i_16) => {
+              self.set_stack(base + dst as usize, Value::Integer(i_16 as i64))
+            }
+            ByteCode::NewTable(dst, array_size, map_size) => {
+              let table = Table::new(array_size as usize, map_size as usize);
+              let table = self.new_table(table);
+              self.set_stack(base + dst as usize, table);
+              self.check_gc();
+            }
+            ByteCode::SetTable(table, k, v) => {
+              // key is a variable
+              let k = self.stack[base + k as usize].clone();
+              self.set_reg_index(&proto, frame, table, k, v)?;
+            }
+            ByteCode::SetField(table, k, v) => {
+              // key is a constant
+              let k = proto.constants[k as usize].clone();
+              self.set_reg_index(&proto, frame, table, k, v)?;
+            }
+            ByteCode::SetInt(table, i, v) => {
+              self.set_reg_index(&proto, frame, table, Value::Integer(i as i64), v)?;
+            }
+            ByteCode::SetList(table, n) => {
+              let value_index = base + table as usize + 1;
+              let end = match n {
+                0 => self.stack.len(),
+                n => value_index + n as usize,
+              };
+              if let Value::Table(table) = self.stack[base + table as usize].to_owned() {
+                // `drain + extend` could be much better than `for + push`
+                let values = self.stack.drain(value_index..end);
+                let mut table = table.borrow_mut();
+                let old = table.array.len();
+                table.array.extend(values);
+                // positional fields override the keyed ones
+                if !table.map.is_empty() {
+                  for i in old..table.array.len() {
+                    table.map.remove(&Value::Integer(i as i64 + 1));
+                  }
+                }
+                table.update_mem_size();
+              } else {
+                panic!("not table");
+              }
+              self
+                .stack
+                .resize(frame_top.max(self.stack.len()), Value::Nil);
+            }
+            ByteCode::GetTable(dst, table, k) => {
+              let k = self.stack[base + k as usize].clone();
+              let value = self.get_reg_index(&proto, frame, table, &k)?;
+              self.set_stack(base + dst as usize, value);
+            }
+            ByteCode::GetField(dst, table, k) => {
+              let value = self.get_reg_index(&proto, frame, table, &proto.constants[k as usize])?;
+              self.set_stack(base + dst as usize, value);
+            }
+            ByteCode::GetInt(dst, table, i) => {
+              let value = self.get_reg_index(&proto, frame, table, &Value::Integer(i as i64))?;
+              self.set_stack(base + dst as usize, value);
+            }
+            ByteCode::Method(dst, table, k) => {
+              let value = self.get_reg_index(&proto, frame, table, &proto.constants[k as usize])?;
+              let table = self.stack[base + table as usize].clone();
+              self.set_stack(base + dst as usize + 1, table);
+              self.set_stack(base + dst as usize, value);
+            }
+            ByteCode::Closure(dst, index) => {
+              let proto = proto.protos[index as usize].clone();
+              let upvalues = proto
+                .upvalues
+                .iter()
+                .map(|desc| match desc.in_stack {
+                  true => self.find_upvalue(base + desc.index),
+                  false => closure.upvalues.borrow()[desc.index].clone(),
+                })
+                .collect();
+              let closure = self.new_closure(LuaClosure::new(proto, upvalues));
+              self.set_stack(base + dst as usize, closure);
+              self.check_gc();
+            }
+            ByteCode::VarArgs(dst, n) => {
+              let dst = base + dst as usize;
+              if n == 0 {
+                self.stack.truncate(dst);
+                self
+                  .stack
+                  .extend(self.frames[frame].varargs.iter().cloned());
+              } else {
+                for i in 0..n as usize - 1 {
+                  let value = self.frames[frame].varargs.get(i).cloned();
+                  let value = value.unwrap_or(Value::Nil);
+                  self.set_stack(dst + i, value);
+                }
+              }
+            }
+            ByteCode::Jump(offset) => pc = (pc as isize + offset as isize) as usize,
+            ByteCode::JumpIfTrue(cond, offset) => {
+              if !self.stack[base + cond as usize].is_falsy() {
+                pc = (pc as isize + offset as isize) as usize;
+              }
+            }
+            ByteCode::JumpIfFalse(cond, offset) => {
+              if self.stack[base + cond as usize].is_falsy() {
+                pc = (pc as isize + offset as isize) as usize;
+              }
+            }
+            ByteCode::ForPrepare(a, offset) => {
+              if !self.for_prepare(base + a as usize)? {
+                pc += offset as usize;
+              }
+            }
+            ByteCode::ForLoop(a, offset) => {
+              if self.for_loop(base + a as usize) {
+                pc -= offset as usize;
+              }
+            }
+            ByteCode::ForCall(a, n) => {
+              let a = base + a as usize;
+              // call on copies of the iterator and states, after them
+              let func_index = a + 4;
+              self.stack.truncate(func_index);
+              self.stack.extend_from_within(a..a + 3);
+              if !self.resolve_call(func_index) {
+                let msg = format!(
+                  "attempt to call a {} value (for iterator 'for iterator')",
+                  self.stack[a].type_name()
+                );
+                return Err(self.rt_error(msg));
+              }
+              self.call_function(func_index)?;
+              self.stack.resize(func_index + n as usize, Value::Nil);
+              self
+                .stack
+                .resize(frame_top.max(self.stack.len()), Value::Nil);
+            }
+            ByteCode::ForGenericLoop(a, offset) => {
+              let a = base + a as usize;
+              if !self.stack[a + 4].is_nil() {
+                self.stack[a + 2] = self.stack[a + 4].clone();
+                pc -= offset as usize;
+              }
+            }
+            ByteCode::Neg(dst, src) => {
+              let value = self.arith_unop(&proto, frame, base, src, arith::neg)?;
+              self.set_stack(base + dst as usize, value);
+            }
+            ByteCode::BitNot(dst, src) => {
+              let value = self.arith_unop(&proto, frame, base, src, arith::bit_not)?;
+              self.set_stack(base + dst as usize, value);
+            }
+            ByteCode::Not(dst, src) => {
+              let value = Value::Boolean(self.stack[base + src as usize].is_falsy());
+              self.set_stack(base + dst as usize, value);
+            }
+            ByteCode::Len(dst, src) => {
+              let v = self.stack[base + src as usize].clone();
+              let pc = self.frames[frame].pc;
+              let value = self.len_of(&v, || var_info(&proto, pc, src as usize))?;
+              self.set_stack(base + dst as usize, value);
+            }
+            ByteCode::Add(dst, l, r) => {
+              self.arith_binop(&proto, frame, base, (dst, l, r), arith::add)?
+            }
+            ByteCode::Sub(dst, l, r) => {
+              self.arith_binop(&proto, frame, base, (dst, l, r), arith::sub)?
+            }
+            ByteCode::Mul(dst, l, r) => {
+              self.arith_binop(&proto, frame, base, (dst, l, r), arith::mul)?
+            }
+            ByteCode::Div(dst, l, r) => {
+              self.arith_binop(&proto, frame, base, (dst, l, r), arith::div)?
+            }
+            ByteCode::Idiv(dst, l, r) => {
+              self.arith_binop(&proto, frame, base, (dst, l, r), arith::idiv)?
+            }
+            ByteCode::Mod(dst, l, r) => {
+              self.arith_binop(&proto, frame, base, (dst, l, r), arith::modulo)?
+            }
+            ByteCode::Pow(dst, l, r) => {
+              self.arith_binop(&proto, frame, base, (dst, l, r), arith::pow)?
+            }
+            ByteCode::BitAnd(dst, l, r) => {
+              self.arith_binop(&proto, frame, base, (dst, l, r), arith::bit_and)?
+            }
+            ByteCode::BitOr(dst, l, r) => {
+              self.arith_binop(&proto, frame, base, (dst, l, r), arith::bit_or)?
+            }
+            ByteCode::BitXor(dst, l, r) => {
+              self.arith_binop(&proto, frame, base, (dst, l, r), arith::bit_xor)?
+            }
+            ByteCode::ShiftL(dst, l, r) => {
+              self.arith_binop(&proto, frame, base, (dst, l, r), arith::shift_left)?
+            }
+            ByteCode::ShiftR(dst, l, r) => {
+              self.arith_binop(&proto, frame, base, (dst, l, r), arith::shift_right)?
+            }
+            ByteCode::Concat(dst, l, r) => {
+              let (lv, rv) = (
+                &self.stack[base + l as usize],
+                &self.stack[base + r as usize],
+              );
+              let value = match arith::concat(lv, rv) {
+                Some(bytes) => self.heap.new_string(bytes),
+                None => {
+                  // blame the operand which is not a string nor a number
+                  let culprit = if arith::concat(lv, &"".into()).is_some() {
+                    r
+                  } else {
+                    l
+                  };
+                  let (lv, rv) = (lv.clone(), rv.clone());
+                  match self.bin_metamethod("__concat", &lv, &rv)? {
+                    Some(value) => value,
+                    None => {
+                      let msg = format!(
+                        "attempt to concatenate a {} value{}",
+                        self.stack[base + culprit as usize].type_name(),
+                        var_info(&proto, self.frames[frame].pc, culprit as usize)
+                      );
+                      return Err(self.rt_error(msg));
+                    }
+                  }
+                }
+              };
+              self.set_stack(base + dst as usize, value);
+              self.check_gc();
+            }
+            ByteCode::Equal(dst, l, r) => {
+              let (lv, rv) = (
+                self.stack[base + l as usize].clone(),
+                self.stack[base + r as usize].clone(),
+              );
+              let value = self.equals(&lv, &rv)?;
+              self.set_stack(base + dst as usize, Value::Boolean(value));
+            }
+            ByteCode::NotEq(dst, l, r) => {
+              let (lv, rv) = (
+                self.stack[base + l as usize].clone(),
+                self.stack[base + r as usize].clone(),
+              );
+              let value = !self.equals(&lv, &rv)?;
+              self.set_stack(base + dst as usize, Value::Boolean(value));
+            }
+            ByteCode::Less(dst, l, r) => self.compare(base, (dst, l, r), Self::less_than)?,
+            ByteCode::LesEq(dst, l, r) => self.compare(base, (dst, l, r), Self::less_equal)?,
           }
+        };
+
+        // the `n` results are at `func_index` on
+        if frame == entry {
+          return Ok(n);
         }
-        ByteCode::Move(dst, src) => {
-          let value = self.stack[src as usize].to_owned();
-          self.set_stack(dst, value);
+        self.frames.pop();
+        frame -= 1;
+        func_index = self.frames[frame].func_index;
+        closure = self.lua_closure(func_index);
+        proto = closure.proto.clone();
+        base = func_index + 1;
+        frame_top = base + proto.max_stack;
+        old_pc = self.frames[frame].pc;
+        pc = old_pc + 1;
+        if let ByteCode::Call(func, _, nresults) = proto.bytecodes[old_pc] {
+          self.adjust_results(base + func as usize, n, nresults, frame_top);
         }
-        ByteCode::LoadConst(dst, index) => {
-          let value = proto.constants[index as usize].to_owned();
-          self.set_stack(dst, value);
+      }
+    }
+  }
+
+  /// The closure of the Lua function at `func_index`.
+  fn lua_closure(&self, func_index: usize) -> Rc<LuaClosure> {
+    match &self.stack[func_index] {
+      Value::LuaFunction(closure) => closure.clone(),
+      _ => unreachable!("not a Lua function"),
+    }
+  }
+
+  /// Adjust the `n` results of a call at `func_index` to `nresults - 1`
+  /// values, or keep all of them if `nresults` is `0`, as `Call` requires.
+  fn adjust_results(&mut self, func_index: usize, n: usize, nresults: u8, frame_top: usize) {
+    if nresults != 0 {
+      self
+        .stack
+        .resize(func_index + nresults as usize - 1, Value::Nil);
+      self
+        .stack
+        .resize(frame_top.max(self.stack.len()), Value::Nil);
+    } else {
+      self.stack.truncate(func_index + n);
+    }
+  }
+
+  fn check_callable(
+    &mut self,
+    proto: &FuncProto,
+    frame: usize,
+    func: usize,
+  ) -> Result<(), LuaError> {
+    let base = self.frames[frame].func_index + 1;
+    if self.resolve_call(base + func) {
+      return Ok(());
+    }
+    let msg = format!(
+      "attempt to call a {} value{}",
+      self.stack[base + func].type_name(),
+      var_info(proto, self.frames[frame].pc, func)
+    );
+    Err(self.rt_error(msg))
+  }
+
+  /// `t[key]`, with the `__index` metamethods, `info` describes `t` for
+  /// the error message
+  pub(crate) fn get_index(
+    &mut self,
+    t: &Value,
+    key: &Value,
+    info: impl FnOnce() -> String,
+  ) -> Result<Value, LuaError> {
+    // fast path for tables without metatables
+    if let Value::Table(table) = t {
+      let table = table.borrow();
+      let value = table.get(key);
+      if !value.is_nil() || table.metatable.is_none() {
+        return Ok(value);
+      }
+    }
+    let mut t = t.clone();
+    let mut info = Some(info);
+    for _ in 0..MAX_TAG_LOOP {
+      let handler = match &t {
+        Value::Table(table) => {
+          let table = table.borrow();
+          let value = table.get(key);
+          if !value.is_nil() {
+            return Ok(value);
+          }
+          match table.metafield("__index") {
+            Value::Nil => return Ok(Value::Nil),
+            handler => handler,
+          }
         }
-        ByteCode::LoadNil(dst) => self.set_stack(dst, Value::Nil),
-        ByteCode::LoadBool(dst, b) => self.set_stack(dst, Value::Boolean(b)),
-        ByteCode::LoadInt(dst, i_16) => self.set_stack(dst, Value::Integer(i_16 as i64)),
-        ByteCode::NewTable(dst, array_size, map_size) => {
-          let table = Table::new(array_size as usize, map_size as usize);
-          self.set_stack(dst, Value::Table(Rc::new(RefCell::new(table))));
+        v => match self.get_metafield(v, "__index") {
+          Value::Nil => {
+            let info = info.take().map_or(String::new(), |info| info());
+            let msg = format!("attempt to index a {} value{info}", v.type_name());
+            return Err(self.rt_error(msg));
+          }
+          handler => handler,
+        },
+      };
+      if handler.is_function() {
+        let results = self.call(handler, vec![t, key.clone()])?;
+        return Ok(results.into_iter().next().unwrap_or(Value::Nil));
+      }
+      // only the indexed value itself is described
+      info = None;
+      t = handler;
+    }
+    Err(self.rt_error("'__index' chain too long; possibly a loop".to_owned()))
+  }
+
+  /// `t[key] = value`, with the `__newindex` metamethods, `info` describes
+  /// `t` for the error message
+  pub(crate) fn set_index(
+    &mut self,
+    t: &Value,
+    key: Value,
+    value: Value,
+    info: impl FnOnce() -> String,
+  ) -> Result<(), LuaError> {
+    let mut t = t.clone();
+    let mut info = Some(info);
+    for _ in 0..MAX_TAG_LOOP {
+      let handler = match &t {
+        Value::Table(table) => {
+          let handler = {
+            let table = table.borrow();
+            match table.metatable.is_some() && table.get(&key).is_nil() {
+              true => table.metafield("__newindex"),
+              false => Value::Nil,
+            }
+          };
+          if handler.is_nil() {
+            if let Some(msg) = Table::key_error(&key) {
+              return Err(self.rt_error(msg.to_owned()));
+            }
+            table.borrow_mut().set(key, value);
+            return Ok(());
+          }
+          handler
         }
-        ByteCode::SetTable(table, k, v) => {
-          // key is a variable
-          let k = self.stack[k as usize].clone();
-          let v = self.stack[v as usize].clone();
-          if let Value::Table(table) = &self.stack[table as usize] {
-            table.borrow_mut().map.insert(k, v);
-          } else {
-            panic!("not table");
+        v => match self.get_metafield(v, "__newindex") {
+          Value::Nil => {
+            let info = info.take().map_or(String::new(), |info| info());
+            let msg = format!("attempt to index a {} value{info}", v.type_name());
+            return Err(self.rt_error(msg));
+          }
+          handler => handler,
+        },
+      };
+      if handler.is_function() {
+        self.call(handler, vec![t, key, value])?;
+        return Ok(());
+      }
+      info = None;
+      t = handler;
+    }
+    Err(self.rt_error("'__newindex' chain too long; possibly a loop".to_owned()))
+  }
+
+  /// `#v`, with the `__len` metamethod.
+  pub(crate) fn len_of(
+    &mut self,
+    v: &Value,
+    info: impl FnOnce() -> String,
+  ) -> Result<Value, LuaError> {
+    let handler = self.get_metafield(v, "__len");
+    if !handler.is_nil() {
+      let results = self.call(handler, vec![v.clone()])?;
+      return Ok(results.into_iter().next().unwrap_or(Value::Nil));
+    }
+    match v {
+      v if v.is_str() => Ok(Value::Integer(<&[u8]>::from(v).len() as i64)),
+      Value::Table(t) => Ok(Value::Integer(t.borrow().len() as i64)),
+      v => {
+        let msg = format!(
+          "attempt to get length of a {} value{}",
+          v.type_name(),
+          info()
+        );
+        Err(self.rt_error(msg))
+      }
+    }
+  }
+
+  /// `table[key]`, the table is in register `table`
+  fn get_reg_index(
+    &mut self,
+    proto: &FuncProto,
+    frame: usize,
+    table: u8,
+    key: &Value,
+  ) -> Result<Value, LuaError> {
+    let (base, pc) = (self.frames[frame].func_index + 1, self.frames[frame].pc);
+    let t = self.stack[base + table as usize].clone();
+    self.get_index(&t, key, || var_info(proto, pc, table as usize))
+  }
+
+  /// `table[key] = value`, the table and value are in registers
+  fn set_reg_index(
+    &mut self,
+    proto: &FuncProto,
+    frame: usize,
+    table: u8,
+    key: Value,
+    value: u8,
+  ) -> Result<(), LuaError> {
+    let (base, pc) = (self.frames[frame].func_index + 1, self.frames[frame].pc);
+    let t = self.stack[base + table as usize].clone();
+    let value = self.stack[base + value as usize].clone();
+    self.set_index(&t, key, value, || var_info(proto, pc, table as usize))
+  }
+
+  fn arith_binop(
+    &mut self,
+    proto: &FuncProto,
+    frame: usize,
+    base: usize,
+    (dst, l, r): (u8, u8, u8),
+    op: fn(&Value, &Value) -> Result<Value, ArithError>,
+  ) -> Result<(), LuaError> {
+    let (lv, rv) = (
+      &self.stack[base + l as usize],
+      &self.stack[base + r as usize],
+    );
+    let value = match op(lv, rv) {
+      Ok(value) => value,
+      Err(err) => {
+        let (lv, rv) = (lv.clone(), rv.clone());
+        let event = arith_event(proto.bytecodes[self.frames[frame].pc]);
+        match self.bin_metamethod(event, &lv, &rv)? {
+          Some(value) => value,
+          None => {
+            let msg = self.arith_error_msg(proto, frame, base, err, l, r);
+            return Err(self.rt_error(msg));
           }
         }
-        ByteCode::SetField(table, k, v) => {
-          // key is a constant
-          let k = proto.constants[k as usize].clone();
-          let v = self.stack[v as usize].clone();
-          if let Value::Table(table) = &self.stack[table as usize] {
-            table.borrow_mut().map.insert(k, v);
-          } else {
-            panic!("not table");
+      }
+    };
+    self.set_stack(base + dst as usize, value);
+    Ok(())
+  }
+
+  fn arith_unop(
+    &mut self,
+    proto: &FuncProto,
+    frame: usize,
+    base: usize,
+    src: u8,
+    op: fn(&Value) -> Result<Value, ArithError>,
+  ) -> Result<Value, LuaError> {
+    let v = &self.stack[base + src as usize];
+    match op(v) {
+      Ok(value) => Ok(value),
+      Err(err) => {
+        // the operand is repeated as the second one
+        let v = v.clone();
+        let event = arith_event(proto.bytecodes[self.frames[frame].pc]);
+        match self.bin_metamethod(event, &v, &v)? {
+          Some(value) => Ok(value),
+          None => {
+            let msg = self.arith_error_msg(proto, frame, base, err, src, src);
+            Err(self.rt_error(msg))
           }
         }
-        ByteCode::SetList(table, n) => {
-          let value_index = table as usize + 1;
-          if let Value::Table(table) = self.stack[table as usize].to_owned() {
-            // `drain + extend` could be much better than `for + push`
-            let values = self.stack.drain(value_index..value_index + n as usize);
-            table.borrow_mut().array.extend(values);
+      }
+    }
+  }
+
+  /// Call the metamethod `event` of `a`, or of `b` if `a` has none, with
+  /// both of them, as `luaT_trybinTM`. Return its first result, or `None`
+  /// if neither has it.
+  fn bin_metamethod(
+    &mut self,
+    event: &str,
+    a: &Value,
+    b: &Value,
+  ) -> Result<Option<Value>, LuaError> {
+    let handler = match self.get_metafield(a, event) {
+      Value::Nil => self.get_metafield(b, event),
+      handler => handler,
+    };
+    if handler.is_nil() {
+      return Ok(None);
+    }
+    let results = self.call(handler, vec![a.clone(), b.clone()])?;
+    Ok(Some(results.into_iter().next().unwrap_or(Value::Nil)))
+  }
+
+  /// `a == b`, with the `__eq` metamethod for tables and userdata, as
+  /// `luaV_equalobj`.
+  pub(crate) fn equals(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+    if a == b {
+      return Ok(true);
+    }
+    match (a, b) {
+      (Value::Table(_), Value::Table(_)) | (Value::UserData(_), Value::UserData(_)) => {
+        let result = self.bin_metamethod("__eq", a, b)?;
+        Ok(result.is_some_and(|v| !v.is_falsy()))
+      }
+      _ => Ok(false),
+    }
+  }
+
+  /// `a < b`, with the `__lt` metamethod, as `luaV_lessthan`.
+  pub(crate) fn less_than(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+    self.order(a, b, arith::less_than, "__lt")
+  }
+
+  /// `a <= b`, with the `__le` metamethod, as `luaV_lessequal`.
+  pub(crate) fn less_equal(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+    self.order(a, b, arith::less_equal, "__le")
+  }
+
+  fn order(
+    &mut self,
+    a: &Value,
+    b: &Value,
+    op: fn(&Value, &Value) -> Option<bool>,
+    event: &str,
+  ) -> Result<bool, LuaError> {
+    if let Some(result) = op(a, b) {
+      return Ok(result);
+    }
+    match self.bin_metamethod(event, a, b)? {
+      Some(result) => Ok(!result.is_falsy()),
+      None => Err(self.rt_error(compare_error_msg(a, b))),
+    }
+  }
+
+  /// Message of a failed arithmetic, blaming the right operand, as `luaG_opinterror`.
+  fn arith_error_msg(
+    &self,
+    proto: &FuncProto,
+    frame: usize,
+    base: usize,
+    err: ArithError,
+    l: u8,
+    r: u8,
+  ) -> String {
+    let pc = self.frames[frame].pc;
+    let lv = &self.stack[base + l as usize];
+    let bitwise = matches!(
+      proto.bytecodes[pc],
+      ByteCode::BitAnd(..)
+        | ByteCode::BitOr(..)
+        | ByteCode::BitXor(..)
+        | ByteCode::ShiftL(..)
+        | ByteCode::ShiftR(..)
+        | ByteCode::BitNot(..)
+    );
+    match err {
+      ArithError::NotNumber => {
+        let culprit = if arith::to_number(lv).is_some() { r } else { l };
+        let v = &self.stack[base + culprit as usize];
+        let operation = match bitwise {
+          true => "perform bitwise operation on",
+          false => "perform arithmetic on",
+        };
+        format!(
+          "attempt to {} a {} value{}",
+          operation,
+          v.type_name(),
+          var_info(proto, pc, culprit as usize)
+        )
+      }
+      ArithError::NoInteger => {
+        let culprit = if arith::to_integer(lv).is_some() {
+          r
+        } else {
+          l
+        };
+        format!(
+          "number{} has no integer representation",
+          var_info(proto, pc, culprit as usize)
+        )
+      }
+      err => err.message().to_owned(),
+    }
+  }
+
+  fn compare(
+    &mut self,
+    base: usize,
+    (dst, l, r): (u8, u8, u8),
+    op: fn(&mut Self, &Value, &Value) -> Result<bool, LuaError>,
+  ) -> Result<(), LuaError> {
+    let (lv, rv) = (
+      self.stack[base + l as usize].clone(),
+      self.stack[base + r as usize].clone(),
+    );
+    let value = op(self, &lv, &rv)?;
+    self.set_stack(base + dst as usize, Value::Boolean(value));
+    Ok(())
+  }
+
+  /// Prepare a numeric for loop at `a`, return whether the loop runs.
+  ///
+  /// The registers are `(init, limit, step, var)`. For integer loops the
+  /// limit is replaced by the count of iterations, as Lua 5.4 does.
+  fn for_prepare(&mut self, a: usize) -> Result<bool, LuaError> {
+    let (init, limit, step) = (
+      self.stack[a].clone(),
+      self.stack[a + 1].clone(),
+      self.stack[a + 2].clone(),
+    );
+    if let (Value::Integer(init), Value::Integer(step)) = (&init, &step) {
+      let (init, step) = (*init, *step);
+      if step == 0 {
+        return Err(self.rt_error("'for' step is zero".to_owned()));
+      }
+      let limit = match arith::to_number(&limit) {
+        Some(Value::Integer(limit)) => limit,
+        Some(Value::Float(limit)) => {
+          let rounded = if step < 0 {
+            limit.ceil()
           } else {
-            panic!("not table");
+            limit.floor()
+          };
+          match arith::float_to_integer(rounded) {
+            Some(limit) => limit,
+            // NaN, or out of the range of integers
+            None if limit.is_nan() => return Ok(false),
+            None if limit > 0.0 => match step < 0 {
+              true => return Ok(false),
+              false => i64::MAX,
+            },
+            None => match step > 0 {
+              true => return Ok(false),
+              false => i64::MIN,
+            },
           }
         }
+        _ => return Err(self.rt_error("'for' limit must be a number".to_owned())),
+      };
+      if (step > 0 && init > limit) || (step < 0 && init < limit) {
+        return Ok(false);
+      }
+      let count = if step > 0 {
+        (limit as u64).wrapping_sub(init as u64) / step as u64
+      } else {
+        (init as u64).wrapping_sub(limit as u64) / ((-(step + 1)) as u64 + 1)
+      };
+      self.stack[a + 1] = Value::Integer(count as i64);
+      self.stack[a + 3] = Value::Integer(init);
+      Ok(true)
+    } else {
+      let Some(f_limit) = arith::to_float(&limit) else {
+        return Err(self.rt_error("'for' limit must be a number".to_owned()));
+      };
+      let Some(f_step) = arith::to_float(&step) else {
+        return Err(self.rt_error("'for' step must be a number".to_owned()));
+      };
+      let Some(f_init) = arith::to_float(&init) else {
+        return Err(self.rt_error("'for' initial value must be a number".to_owned()));
+      };
+      if f_step == 0.0 {
+        return Err(self.rt_error("'for' step is zero".to_owned()));
+      }
+      if (f_step > 0.0 && f_limit < f_init) || (f_step < 0.0 && f_init < f_limit) {
+        return Ok(false);
+      }
+      self.stack[a] = Value::Float(f_init);
+      self.stack[a + 1] = Value::Float(f_limit);
+      self.stack[a + 2] = Value::Float(f_step);
+      self.stack[a + 3] = Value::Float(f_init);
+      Ok(true)
+    }
+  }
+
+  /// Step the numeric for loop at `a`, return whether to continue.
+  fn for_loop(&mut self, a: usize) -> bool {
+    match (&self.stack[a], &self.stack[a + 1], &self.stack[a + 2]) {
+      (Value::Integer(i), Value::Integer(count), Value::Integer(step)) => {
+        let count = *count as u64;
+        if count == 0 {
+          return false;
+        }
+        let i = i.wrapping_add(*step);
+        self.stack[a] = Value::Integer(i);
+        self.stack[a + 1] = Value::Integer(count as i64 - 1);
+        self.stack[a + 3] = Value::Integer(i);
+        true
+      }
+      (Value::Float(i), Value::Float(limit), Value::Float(step)) => {
+        let i = i + step;
+        let runs = if *step > 0.0 {
+          i <= *limit
+        } else {
+          *limit <= i
+        };
+        if runs {
+          self.stack[a] = Value::Float(i);
+          self.stack[a + 3] = Value::Float(i);
+        }
+        runs
       }
+      _ => unreachable!("invalid for loop state"),
     }
   }
 }
 
+/// Metamethod event of the arithmetic or bitwise bytecode `code`.
+fn arith_event(code: ByteCode) -> &'static str {
+  match code {
+    ByteCode::Add(..) => "__add",
+    ByteCode::Sub(..) => "__sub",
+    ByteCode::Mul(..) => "__mul",
+    ByteCode::Div(..) => "__div",
+    ByteCode::Mod(..) => "__mod",
+    ByteCode::Pow(..) => "__pow",
+    ByteCode::Idiv(..) => "__idiv",
+    ByteCode::BitAnd(..) => "__band",
+    ByteCode::BitOr(..) => "__bor",
+    ByteCode::BitXor(..) => "__bxor",
+    ByteCode::ShiftL(..) => "__shl",
+    ByteCode::ShiftR(..) => "__shr",
+    ByteCode::Neg(..) => "__unm",
+    ByteCode::BitNot(..) => "__bnot",
+    _ => unreachable!("not an arithmetic bytecode"),
+  }
+}
+
+/// Error message of comparing `a` and `b` which are not comparable.
+fn compare_error_msg(a: &Value, b: &Value) -> String {
+  let (t1, t2) = (a.type_name(), b.type_name());
+  if t1 == t2 {
+    format!("attempt to compare two {t1} values")
+  } else {
+    format!("attempt to compare {t1} with {t2}")
+  }
+}
+
 impl New for ExeState {
   type Output = Self;
   fn new() -> Self::Output {
-    let globals_vec = vec![("print", Value::Function(lib_print))];
-    let mut globals = HashMap::new();
-    for (k, v) in globals_vec {
-      globals.insert(k.to_owned(), v.to_owned());
-    }
-    Self {
+    let globals = Rc::new(RefCell::new(Table::new(0, 0)));
+    let mut registry = Table::new(2, 0);
+    registry.set(Value::Integer(RIDX_GLOBALS), Value::Table(globals.clone()));
+    let registry = Rc::new(RefCell::new(registry));
+    let mut heap = Heap::default();
+    heap.register_table(&globals);
+    heap.register_table(&registry);
+    let mut state = Self {
       globals,
+      registry,
       stack: Vec::new(),
       func_index: 0,
-    }
+      frames: Vec::new(),
+      rust_calls: 0,
+      native_names: HashMap::new(),
+      handlers: Vec::new(),
+      open_upvalues: BTreeMap::new(),
+      tbc_slots: Vec::new(),
+      heap,
+      finalizing: false,
+      warnings: false,
+      string_meta: None,
+      type_metas: HashMap::new(),
+      random: lib::math::Random::new().0,
+      io: lib::io::IoState::default(),
+      package: lib::package::PackageState::default(),
+      hook: None,
+      allow_hook: true,
+    };
+    lib::open_libs(&mut state);
+    state
+  }
+}
+
+impl Drop for ExeState {
+  fn drop(&mut self) {
+    self.close();
   }
 }
 
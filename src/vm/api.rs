@@ -0,0 +1,275 @@
+//! # API
+//!
+//! Helpers for native functions to access their arguments and raise
+//! errors, in the spirit of `lauxlib`.
+
+use super::*;
+use crate::value::UserData;
+use std::any::Any;
+
+impl ExeState {
+  /// Count of the arguments of the running native function.
+  pub fn get_top(&self) -> usize {
+    self.stack.len() - self.func_index - 1
+  }
+
+  /// The `i`-th argument (1-based), `Nil` if absent.
+  pub fn arg(&self, i: usize) -> Value {
+    self
+      .stack
+      .get(self.func_index + i)
+      .filter(|_| i >= 1)
+      .cloned()
+      .unwrap_or(Value::Nil)
+  }
+
+  /// The `i`-th upvalue (1-based) of the running native closure, `Nil` if
+  /// absent.
+  pub fn native_upvalue(&self, i: usize) -> Value {
+    match &self.stack[self.func_index] {
+      Value::NativeClosure(closure) => closure
+        .upvalues
+        .borrow()
+        .get(i.wrapping_sub(1))
+        .cloned()
+        .unwrap_or(Value::Nil),
+      _ => Value::Nil,
+    }
+  }
+
+  /// Set the `i`-th upvalue (1-based) of the running native closure.
+  pub fn set_native_upvalue(&self, i: usize, value: Value) {
+    if let Value::NativeClosure(closure) = &self.stack[self.func_index] {
+      if let Some(upvalue) = closure.upvalues.borrow_mut().get_mut(i.wrapping_sub(1)) {
+        *upvalue = value;
+      }
+    }
+  }
+
+  /// Create a native closure of `func` with `upvalues`, as `lua_pushcclosure`.
+  pub fn new_native_closure(&mut self, func: RustFunction, upvalues: Vec<Value>) -> Value {
+    let closure = Rc::new(NativeClosure {
+      func,
+      upvalues: RefCell::new(upvalues),
+    });
+    self.heap.register_native_closure(&closure);
+    Value::NativeClosure(closure)
+  }
+
+  /// Push a result onto the stack, which the state takes if a string.
+  pub fn push(&mut self, value: impl Into<Value>) {
+    let value = self.heap.own(value.into());
+    self.stack.push(value);
+  }
+
+  /// Raise error `msg`, prefixed by the position of the calling Lua code.
+  pub fn error(&mut self, msg: impl AsRef<str>) -> LuaError {
+    let msg = self.where_(1) + msg.as_ref();
+    self.throw(msg.into())
+  }
+
+  /// Raise error about the `i`-th argument, as `luaL_argerror`.
+  pub fn arg_error(&mut self, i: usize, msg: &str) -> LuaError {
+    let mut i = i;
+    let name = match self.call_site_name(0) {
+      Some(("method", name)) => {
+        i -= 1;
+        if i == 0 {
+          return self.error(format!("calling '{name}' on bad self ({msg})"));
+        }
+        name
+      }
+      Some((_, name)) => name,
+      None => match self.frame_func_name(0) {
+        Some((_, name)) => name,
+        None => "?".to_owned(),
+      },
+    };
+    self.error(format!("bad argument #{i} to '{name}' ({msg})"))
+  }
+
+  /// Raise error about the type of the `i`-th argument, as `luaL_typeerror`.
+  pub fn type_error(&mut self, i: usize, expected: &str) -> LuaError {
+    let got = match i <= self.get_top() {
+      true => self.arg(i).type_name(),
+      false => "no value",
+    };
+    self.arg_error(i, &format!("{expected} expected, got {got}"))
+  }
+
+  /// Check the `i`-th argument is present.
+  pub fn check_any(&mut self, i: usize) -> Result<Value, LuaError> {
+    match i <= self.get_top() {
+      true => Ok(self.arg(i)),
+      false => Err(self.arg_error(i, "value expected")),
+    }
+  }
+
+  /// Check the `i`-th argument is an integer, or convertible to it.
+  pub fn check_integer(&mut self, i: usize) -> Result<i64, LuaError> {
+    let v = self.arg(i);
+    match arith::to_integer(&v) {
+      Some(n) => Ok(n),
+      None if arith::to_number(&v).is_some() => {
+        Err(self.arg_error(i, "number has no integer representation"))
+      }
+      None => Err(self.type_error(i, "number")),
+    }
+  }
+
+  /// Like [`ExeState::check_integer`], but `default` for absent/`nil` argument.
+  pub fn opt_integer(&mut self, i: usize, default: i64) -> Result<i64, LuaError> {
+    match self.arg(i) {
+      Value::Nil => Ok(default),
+      _ => self.check_integer(i),
+    }
+  }
+
+  /// Check the `i`-th argument is a number, or convertible to it.
+  pub fn check_number(&mut self, i: usize) -> Result<Value, LuaError> {
+    match arith::to_number(&self.arg(i)) {
+      Some(n) => Ok(n),
+      None => Err(self.type_error(i, "number")),
+    }
+  }
+
+  /// Check the `i`-th argument is a number, and convert it to a float.
+  pub fn check_float(&mut self, i: usize) -> Result<f64, LuaError> {
+    match arith::to_float(&self.arg(i)) {
+      Some(f) => Ok(f),
+      None => Err(self.type_error(i, "number")),
+    }
+  }
+
+  /// Check the `i`-th argument is a userdata of `T`, `tname` names the type
+  /// in the error, as `luaL_checkudata`.
+  pub fn check_userdata<T: Any>(
+    &mut self,
+    i: usize,
+    tname: &str,
+  ) -> Result<Rc<UserData>, LuaError> {
+    match self.arg(i) {
+      Value::UserData(u) if u.data.borrow().is::<T>() => Ok(u),
+      _ => Err(self.type_error(i, tname)),
+    }
+  }
+
+  /// Create a userdata of `data` with `metatable`.
+  pub fn new_userdata(&mut self, data: impl Any, metatable: Option<Rc<RefCell<Table>>>) -> Value {
+    let userdata = Rc::new(UserData {
+      data: RefCell::new(Box::new(data)),
+      metatable: RefCell::new(metatable.clone()),
+    });
+    self.heap.register_userdata(&userdata);
+    let userdata = Value::UserData(userdata);
+    self.heap.check_finalizer(&userdata, metatable.as_ref());
+    userdata
+  }
+
+  /// Check the `i`-th argument is a string, or a number converted to it.
+  pub fn check_string(&mut self, i: usize) -> Result<Vec<u8>, LuaError> {
+    let v = self.arg(i);
+    if v.is_str() {
+      return Ok(<&[u8]>::from(&v).to_vec());
+    }
+    match arith::number_to_bytes(&v) {
+      Some(bytes) => Ok(bytes),
+      None => Err(self.type_error(i, "string")),
+    }
+  }
+
+  /// Like [`ExeState::check_string`], but `default` for absent/`nil` argument.
+  pub fn opt_string(&mut self, i: usize, default: &str) -> Result<Vec<u8>, LuaError> {
+    match self.arg(i) {
+      Value::Nil => Ok(default.as_bytes().to_vec()),
+      _ => self.check_string(i),
+    }
+  }
+
+  /// Check the `i`-th argument is a string in `options`, return its index.
+  ///
+  /// `default` is used for absent/`nil` argument.
+  pub fn check_option(
+    &mut self,
+    i: usize,
+    default: Option<&str>,
+    options: &[&str],
+  ) -> Result<usize, LuaError> {
+    let arg = self.arg(i);
+    let name = match (&arg, default) {
+      (Value::Nil, Some(default)) => default.to_owned(),
+      (v, _) if v.is_str() => String::from(v),
+      _ => return Err(self.type_error(i, "string")),
+    };
+    match options.iter().position(|o| *o == name) {
+      Some(index) => Ok(index),
+      None => Err(self.arg_error(i, &format!("invalid option '{name}'"))),
+    }
+  }
+
+  /// Check the `i`-th argument is a table.
+  pub fn check_table(&mut self, i: usize) -> Result<Rc<RefCell<Table>>, LuaError> {
+    match self.arg(i) {
+      Value::Table(t) => Ok(t),
+      _ => Err(self.type_error(i, "table")),
+    }
+  }
+
+  /// `t[key]`, with metamethods, as `lua_gettable`.
+  pub fn get_table(&mut self, t: &Value, key: &Value) -> Result<Value, LuaError> {
+    self.get_index(t, key, String::new)
+  }
+
+  /// `t[key] = value`, with metamethods, as `lua_settable`.
+  pub fn set_table(&mut self, t: &Value, key: Value, value: Value) -> Result<(), LuaError> {
+    self.set_index(t, key, value, String::new)
+  }
+
+  /// Length of `v`, with metamethods, which must be an integer, as
+  /// `luaL_len`.
+  pub fn obj_len(&mut self, v: &Value) -> Result<i64, LuaError> {
+    match self.len_of(v, String::new)? {
+      Value::Integer(n) => Ok(n),
+      _ => Err(self.error("object length is not an integer")),
+    }
+  }
+
+  /// Convert `v` into a string as `tostring` does, with the `__tostring`
+  /// and `__name` metafields, as `luaL_tolstring`.
+  pub fn to_lua_string(&mut self, v: &Value) -> Result<Vec<u8>, LuaError> {
+    let handler = self.get_metafield(v, "__tostring");
+    if !handler.is_nil() {
+      let result = self.call(handler, vec![v.clone()])?;
+      return match result.first() {
+        Some(s) if s.is_str() => Ok(<&[u8]>::from(s).to_vec()),
+        Some(n @ (Value::Integer(_) | Value::Float(_))) => Ok(n.to_string().into_bytes()),
+        _ => Err(self.error("'__tostring' must return a string")),
+      };
+    }
+    match v {
+      v if v.is_str() => Ok(<&[u8]>::from(v).to_vec()),
+      Value::Table(_) | Value::UserData(_) => match self.get_metafield(v, "__name") {
+        name if name.is_str() => {
+          let addr = match v {
+            Value::Table(t) => Rc::as_ptr(t) as *const (),
+            Value::UserData(u) => Rc::as_ptr(u) as *const (),
+            _ => unreachable!(),
+          };
+          Ok(format!("{}: {addr:p}", String::from(&name)).into_bytes())
+        }
+        _ => Ok(v.to_string().into_bytes()),
+      },
+      v => Ok(v.to_string().into_bytes()),
+    }
+  }
+
+  /// Call `func` with `args`, and return all its results.
+  pub fn call(&mut self, func: Value, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let func_index = self.stack.len();
+    self.stack.push(func);
+    self.stack.extend(args);
+    let result = self.call_function(func_index);
+    let results = self.stack.split_off(func_index);
+    result.map(|_| results)
+  }
+}
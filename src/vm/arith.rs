@@ -0,0 +1,347 @@
+//! # Arith
+//!
+//! Arithmetic, bitwise and comparison operations on `Value`, following the
+//! semantics of Lua 5.4 (integer/float subtypes, string coercion).
+
+use crate::{
+  lex::lexing_methods::{str_to_number, Number},
+  value::Value,
+};
+
+/// 2^63, the first float out of the range of `i64`.
+const TWO_POW_63: f64 = 9223372036854775808.0;
+
+/// Reason of a failed arithmetic operation.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ArithError {
+  /// Some operand is not a number (nor a string convertible to a number).
+  NotNumber,
+  /// Some operand of a bitwise operation has no integer representation.
+  NoInteger,
+  /// Integer division by zero.
+  DivByZero,
+  /// Integer modulo by zero.
+  ModByZero,
+}
+
+impl ArithError {
+  /// Error message, for the errors not depending on the operand types.
+  pub fn message(&self) -> &'static str {
+    match self {
+      ArithError::NotNumber => "attempt to perform arithmetic on a non-number value",
+      ArithError::NoInteger => "number has no integer representation",
+      ArithError::DivByZero => "attempt to perform 'n//0'",
+      ArithError::ModByZero => "attempt to perform 'n%0'",
+    }
+  }
+}
+
+type ArithResult = Result<Value, ArithError>;
+
+/// Convert a number or a numeric string into a number value.
+pub fn to_number(v: &Value) -> Option<Value> {
+  match v {
+    Value::Integer(_) | Value::Float(_) => Some(v.clone()),
+    v if v.is_str() => match str_to_number(v.into())? {
+      Number::Integer(i) => Some(Value::Integer(i)),
+      Number::Float(f) => Some(Value::Float(f)),
+    },
+    _ => None,
+  }
+}
+
+/// Convert a number or a numeric string into a float.
+pub fn to_float(v: &Value) -> Option<f64> {
+  match to_number(v)? {
+    Value::Integer(i) => Some(i as f64),
+    Value::Float(f) => Some(f),
+    _ => None,
+  }
+}
+
+/// Convert a float into an integer only if it has an exact integer value.
+pub fn float_to_integer(f: f64) -> Option<i64> {
+  (f.floor() == f && (-TWO_POW_63..TWO_POW_63).contains(&f)).then_some(f as i64)
+}
+
+/// Convert a number or a numeric string into an integer, without rounding.
+pub fn to_integer(v: &Value) -> Option<i64> {
+  match to_number(v)? {
+    Value::Integer(i) => Some(i),
+    Value::Float(f) => float_to_integer(f),
+    _ => None,
+  }
+}
+
+fn arith_op(a: &Value, b: &Value, fi: fn(i64, i64) -> i64, ff: fn(f64, f64) -> f64) -> ArithResult {
+  match (to_number(a), to_number(b)) {
+    (Some(Value::Integer(x)), Some(Value::Integer(y))) => Ok(Value::Integer(fi(x, y))),
+    (Some(x), Some(y)) => Ok(Value::Float(ff(
+      to_float(&x).unwrap(),
+      to_float(&y).unwrap(),
+    ))),
+    _ => Err(ArithError::NotNumber),
+  }
+}
+
+fn float_op(a: &Value, b: &Value, ff: fn(f64, f64) -> f64) -> ArithResult {
+  match (to_float(a), to_float(b)) {
+    (Some(x), Some(y)) => Ok(Value::Float(ff(x, y))),
+    _ => Err(ArithError::NotNumber),
+  }
+}
+
+fn bitwise_op(a: &Value, b: &Value, fi: fn(i64, i64) -> i64) -> ArithResult {
+  match (to_integer(a), to_integer(b)) {
+    (Some(x), Some(y)) => Ok(Value::Integer(fi(x, y))),
+    _ if to_number(a).is_some() && to_number(b).is_some() => Err(ArithError::NoInteger),
+    _ => Err(ArithError::NotNumber),
+  }
+}
+
+pub fn add(a: &Value, b: &Value) -> ArithResult {
+  arith_op(a, b, i64::wrapping_add, |x, y| x + y)
+}
+
+pub fn sub(a: &Value, b: &Value) -> ArithResult {
+  arith_op(a, b, i64::wrapping_sub, |x, y| x - y)
+}
+
+pub fn mul(a: &Value, b: &Value) -> ArithResult {
+  arith_op(a, b, i64::wrapping_mul, |x, y| x * y)
+}
+
+pub fn div(a: &Value, b: &Value) -> ArithResult {
+  float_op(a, b, |x, y| x / y)
+}
+
+pub fn pow(a: &Value, b: &Value) -> ArithResult {
+  float_op(a, b, |x, y| if y == 2.0 { x * x } else { x.powf(y) })
+}
+
+/// Floor division `//`
+pub fn idiv(a: &Value, b: &Value) -> ArithResult {
+  match (to_number(a), to_number(b)) {
+    (Some(Value::Integer(_)), Some(Value::Integer(0))) => Err(ArithError::DivByZero),
+    (Some(Value::Integer(x)), Some(Value::Integer(y))) => {
+      let q = x.wrapping_div(y);
+      // round towards minus infinity
+      if x.wrapping_rem(y) != 0 && (x ^ y) < 0 {
+        Ok(Value::Integer(q - 1))
+      } else {
+        Ok(Value::Integer(q))
+      }
+    }
+    (Some(x), Some(y)) => Ok(Value::Float(
+      (to_float(&x).unwrap() / to_float(&y).unwrap()).floor(),
+    )),
+    _ => Err(ArithError::NotNumber),
+  }
+}
+
+/// Modulo `%`, the result has the same sign as the divisor.
+pub fn modulo(a: &Value, b: &Value) -> ArithResult {
+  match (to_number(a), to_number(b)) {
+    (Some(Value::Integer(_)), Some(Value::Integer(0))) => Err(ArithError::ModByZero),
+    (Some(Value::Integer(x)), Some(Value::Integer(y))) => {
+      let r = x.wrapping_rem(y);
+      if r != 0 && (r ^ y) < 0 {
+        Ok(Value::Integer(r + y))
+      } else {
+        Ok(Value::Integer(r))
+      }
+    }
+    (Some(x), Some(y)) => Ok(Value::Float(float_mod(
+      to_float(&x).unwrap(),
+      to_float(&y).unwrap(),
+    ))),
+    _ => Err(ArithError::NotNumber),
+  }
+}
+
+/// `luai_nummod`
+pub fn float_mod(a: f64, b: f64) -> f64 {
+  let m = a % b;
+  if (m > 0.0 && b < 0.0) || (m < 0.0 && b > 0.0) {
+    m + b
+  } else {
+    m
+  }
+}
+
+pub fn bit_and(a: &Value, b: &Value) -> ArithResult {
+  bitwise_op(a, b, |x, y| x & y)
+}
+
+pub fn bit_or(a: &Value, b: &Value) -> ArithResult {
+  bitwise_op(a, b, |x, y| x | y)
+}
+
+pub fn bit_xor(a: &Value, b: &Value) -> ArithResult {
+  bitwise_op(a, b, |x, y| x ^ y)
+}
+
+/// Logical shift, shifting to the other direction for negative `n`.
+pub fn shift_int(x: i64, n: i64) -> i64 {
+  match n {
+    n if n <= -64 || n >= 64 => 0,
+    n if n >= 0 => ((x as u64) << n) as i64,
+    n => ((x as u64) >> -n) as i64,
+  }
+}
+
+pub fn shift_left(a: &Value, b: &Value) -> ArithResult {
+  bitwise_op(a, b, shift_int)
+}
+
+pub fn shift_right(a: &Value, b: &Value) -> ArithResult {
+  bitwise_op(a, b, |x, n| shift_int(x, n.wrapping_neg()))
+}
+
+/// Unary minus `-`
+pub fn neg(a: &Value) -> ArithResult {
+  match to_number(a) {
+    Some(Value::Integer(i)) => Ok(Value::Integer(i.wrapping_neg())),
+    Some(Value::Float(f)) => Ok(Value::Float(-f)),
+    _ => Err(ArithError::NotNumber),
+  }
+}
+
+/// Bitwise not `~`
+pub fn bit_not(a: &Value) -> ArithResult {
+  match (to_integer(a), to_number(a)) {
+    (Some(i), _) => Ok(Value::Integer(!i)),
+    (None, Some(_)) => Err(ArithError::NoInteger),
+    _ => Err(ArithError::NotNumber),
+  }
+}
+
+/// Significant digits of floats converted to strings, as `LUAI_NUMFFORMAT`.
+const FLOAT_DIGITS: usize = 14;
+
+/// Convert a float into a string as `%.14g` does, with `.0` appended if it
+/// looks like an integer, as `tostringbuff`.
+pub fn float_to_string(f: f64) -> String {
+  if f.is_nan() {
+    return if f.is_sign_negative() { "-nan" } else { "nan" }.to_owned();
+  }
+  if f.is_infinite() {
+    return if f < 0.0 { "-inf" } else { "inf" }.to_owned();
+  }
+  // the exponent after rounding to the significant digits
+  let sci = format!("{:.*e}", FLOAT_DIGITS - 1, f);
+  let (mantissa, exp) = sci.split_once('e').unwrap();
+  let exp: i32 = exp.parse().unwrap();
+  let mut s = if exp < -4 || exp >= FLOAT_DIGITS as i32 {
+    let mantissa = trim_fraction(mantissa);
+    let sign = if exp < 0 { '-' } else { '+' };
+    format!("{mantissa}e{sign}{:02}", exp.abs())
+  } else {
+    let fixed = format!("{:.*}", (FLOAT_DIGITS as i32 - 1 - exp) as usize, f);
+    trim_fraction(&fixed).to_owned()
+  };
+  if s.bytes().all(|b| b == b'-' || b.is_ascii_digit()) {
+    s.push_str(".0");
+  }
+  s
+}
+
+/// Remove the trailing zeros of the fraction, and the point if no fraction.
+fn trim_fraction(s: &str) -> &str {
+  match s.contains('.') {
+    true => s.trim_end_matches('0').trim_end_matches('.'),
+    false => s,
+  }
+}
+
+/// Convert a number into its string form, for concatenation and `tostring`.
+pub fn number_to_bytes(v: &Value) -> Option<Vec<u8>> {
+  match v {
+    Value::Integer(i) => Some(i.to_string().into_bytes()),
+    Value::Float(f) => Some(float_to_string(*f).into_bytes()),
+    _ => None,
+  }
+}
+
+/// Bytes of `a .. b`, for strings and numbers only.
+pub fn concat(a: &Value, b: &Value) -> Option<Vec<u8>> {
+  let bytes = |v: &Value| match v {
+    v if v.is_str() => Some(<&[u8]>::from(v).to_vec()),
+    v => number_to_bytes(v),
+  };
+  let mut s = bytes(a)?;
+  s.extend(bytes(b)?);
+  Some(s)
+}
+
+/// Exact comparison `i < f`
+fn lt_int_float(i: i64, f: f64) -> bool {
+  if f.is_nan() {
+    false
+  } else if f >= TWO_POW_63 {
+    true
+  } else if f.ceil() < -TWO_POW_63 {
+    false
+  } else {
+    i < f.ceil() as i64
+  }
+}
+
+/// Exact comparison `i <= f`
+fn le_int_float(i: i64, f: f64) -> bool {
+  if f.is_nan() {
+    false
+  } else if f >= TWO_POW_63 {
+    true
+  } else if f.floor() < -TWO_POW_63 {
+    false
+  } else {
+    i <= f.floor() as i64
+  }
+}
+
+/// Exact comparison `f < i`
+fn lt_float_int(f: f64, i: i64) -> bool {
+  if f.is_nan() || f >= TWO_POW_63 {
+    false
+  } else if f.floor() < -TWO_POW_63 {
+    true
+  } else {
+    (f.floor() as i64) < i
+  }
+}
+
+/// Exact comparison `f <= i`
+fn le_float_int(f: f64, i: i64) -> bool {
+  if f.is_nan() || f >= TWO_POW_63 {
+    false
+  } else if f.ceil() < -TWO_POW_63 {
+    true
+  } else {
+    (f.ceil() as i64) <= i
+  }
+}
+
+/// `a < b`, `None` if they are not comparable.
+pub fn less_than(a: &Value, b: &Value) -> Option<bool> {
+  match (a, b) {
+    (Value::Integer(x), Value::Integer(y)) => Some(x < y),
+    (Value::Float(x), Value::Float(y)) => Some(x < y),
+    (Value::Integer(x), Value::Float(y)) => Some(lt_int_float(*x, *y)),
+    (Value::Float(x), Value::Integer(y)) => Some(lt_float_int(*x, *y)),
+    (a, b) if a.is_str() && b.is_str() => Some(<&[u8]>::from(a) < <&[u8]>::from(b)),
+    _ => None,
+  }
+}
+
+/// `a <= b`, `None` if they are not comparable.
+pub fn less_equal(a: &Value, b: &Value) -> Option<bool> {
+  match (a, b) {
+    (Value::Integer(x), Value::Integer(y)) => Some(x <= y),
+    (Value::Float(x), Value::Float(y)) => Some(x <= y),
+    (Value::Integer(x), Value::Float(y)) => Some(le_int_float(*x, *y)),
+    (Value::Float(x), Value::Integer(y)) => Some(le_float_int(*x, *y)),
+    (a, b) if a.is_str() && b.is_str() => Some(<&[u8]>::from(a) <= <&[u8]>::from(b)),
+    _ => None,
+  }
+}
@@ -0,0 +1,323 @@
+//! # Call Info
+//!
+//! Call frames of the running functions, and the debug information derived
+//! from them: positions, names of variables/functions and tracebacks.
+
+use super::*;
+use crate::parse::FuncProto;
+
+/// Max length of a chunk id, as `LUA_IDSIZE`.
+const ID_SIZE: usize = 60;
+
+/// Levels shown at the top/bottom of a long traceback.
+const TRACEBACK_LEVELS_TOP: usize = 10;
+const TRACEBACK_LEVELS_BOTTOM: usize = 11;
+
+/// ## CallInfo
+///
+/// A call frame: which function is running and where it is.
+#[derive(Debug, Clone)]
+pub(crate) struct CallInfo {
+  /// The running function
+  pub func: Value,
+  /// Index of the function in stack, arguments are after it
+  pub func_index: usize,
+  /// pc of the running bytecode, for Lua functions only
+  pub pc: usize,
+  /// Whether it was entered by a tail call
+  pub is_tail: bool,
+  /// Extra arguments of vararg Lua functions
+  pub varargs: Vec<Value>,
+}
+
+impl CallInfo {
+  pub fn new(func: Value, func_index: usize) -> Self {
+    Self {
+      func,
+      func_index,
+      pc: 0,
+      is_tail: false,
+      varargs: Vec::new(),
+    }
+  }
+
+  pub fn proto(&self) -> Option<&Rc<FuncProto>> {
+    match &self.func {
+      Value::LuaFunction(closure) => Some(&closure.proto),
+      _ => None,
+    }
+  }
+
+  /// Source line of the running bytecode, for Lua functions only.
+  pub fn current_line(&self) -> Option<usize> {
+    self
+      .proto()
+      .and_then(|proto| proto.lineinfo.get(self.pc).copied())
+  }
+}
+
+/// Chunk name for messages, as `luaO_chunkid`:
+///
+/// - `=name` => `name`
+/// - `@path` => `path` (keep the tail if too long)
+/// - `source` => `[string "source"]` (first line only)
+pub fn chunk_id(source: &str) -> String {
+  if let Some(name) = source.strip_prefix('=') {
+    name.chars().take(ID_SIZE - 1).collect()
+  } else if let Some(path) = source.strip_prefix('@') {
+    let count = path.chars().count();
+    if count < ID_SIZE {
+      path.to_owned()
+    } else {
+      let tail = path.chars().skip(count - (ID_SIZE - 4)).collect::<String>();
+      format!("...{tail}")
+    }
+  } else {
+    const PRE: &str = "[string \"";
+    const POS: &str = "\"]";
+    let max = ID_SIZE - PRE.len() - POS.len() - 4;
+    let first_line = source.split('\n').next().unwrap_or_default();
+    if first_line.len() < source.len() || first_line.chars().count() > max {
+      let line = first_line.chars().take(max).collect::<String>();
+      format!("{PRE}{line}...{POS}")
+    } else {
+      format!("{PRE}{first_line}{POS}")
+    }
+  }
+}
+
+/// Name of the local variable in register `reg` at `pc`, as `luaF_getlocalname`.
+pub(crate) fn local_name(proto: &FuncProto, reg: usize, pc: usize) -> Option<&str> {
+  proto
+    .locvars
+    .iter()
+    .filter(|var| var.start_pc <= pc && pc < var.end_pc)
+    .nth(reg)
+    .map(|var| var.name.as_str())
+}
+
+/// Registers written by `code`, and the jump target if it's a jump.
+fn written_regs(code: &ByteCode, pc: usize) -> (Option<(usize, usize)>, Option<usize>) {
+  let one = |a: &u8| Some((*a as usize, *a as usize + 1));
+  match code {
+    ByteCode::GetUpval(a, _)
+    | ByteCode::GetTabUp(a, _, _)
+    | ByteCode::LoadConst(a, _)
+    | ByteCode::LoadNil(a)
+    | ByteCode::LoadBool(a, _)
+    | ByteCode::LoadInt(a, _)
+    | ByteCode::Move(a, _)
+    | ByteCode::NewTable(a, _, _)
+    | ByteCode::GetTable(a, _, _)
+    | ByteCode::GetField(a, _, _)
+    | ByteCode::GetInt(a, _, _)
+    | ByteCode::Closure(a, _)
+    | ByteCode::Neg(a, _)
+    | ByteCode::Not(a, _)
+    | ByteCode::BitNot(a, _)
+    | ByteCode::Len(a, _)
+    | ByteCode::Add(a, _, _)
+    | ByteCode::Sub(a, _, _)
+    | ByteCode::Mul(a, _, _)
+    | ByteCode::Div(a, _, _)
+    | ByteCode::Idiv(a, _, _)
+    | ByteCode::Mod(a, _, _)
+    | ByteCode::Pow(a, _, _)
+    | ByteCode::BitAnd(a, _, _)
+    | ByteCode::BitOr(a, _, _)
+    | ByteCode::BitXor(a, _, _)
+    | ByteCode::ShiftL(a, _, _)
+    | ByteCode::ShiftR(a, _, _)
+    | ByteCode::Concat(a, _, _)
+    | ByteCode::Equal(a, _, _)
+    | ByteCode::NotEq(a, _, _)
+    | ByteCode::Less(a, _, _)
+    | ByteCode::LesEq(a, _, _) => (one(a), None),
+    ByteCode::Method(a, _, _) => (Some((*a as usize, *a as usize + 2)), None),
+    ByteCode::ForPrepare(a, _) | ByteCode::ForLoop(a, _) => {
+      (Some((*a as usize, *a as usize + 4)), None)
+    }
+    ByteCode::ForCall(a, n) => (Some((*a as usize + 4, *a as usize + 4 + *n as usize)), None),
+    ByteCode::ForGenericLoop(a, _) => (Some((*a as usize + 2, *a as usize + 3)), None),
+    // all registers from `a` on
+    ByteCode::Call(a, _, _) | ByteCode::TailCall(a, _) | ByteCode::VarArgs(a, _) => {
+      (Some((*a as usize, usize::MAX)), None)
+    }
+    ByteCode::Jump(offset) => (None, Some((pc as isize + 1 + *offset as isize) as usize)),
+    _ => (None, None),
+  }
+}
+
+/// Find the last bytecode before `last_pc` which sets register `reg`,
+/// ignoring the ones which may be skipped by a jump, as `findsetreg`.
+fn find_set_reg(proto: &FuncProto, last_pc: usize, reg: usize) -> Option<usize> {
+  let mut set_pc = None;
+  let mut jump_target = 0;
+  for (pc, code) in proto.bytecodes[..last_pc].iter().enumerate() {
+    let (written, target) = written_regs(code, pc);
+    if let Some(target) = target {
+      if pc < target && target <= last_pc && target > jump_target {
+        jump_target = target;
+      }
+    }
+    if let Some((from, to)) = written {
+      if from <= reg && reg < to {
+        set_pc = (pc >= jump_target).then_some(pc);
+      }
+    }
+  }
+  set_pc
+}
+
+/// Describe the value in register `reg` at `pc`, like `("global", "print")`,
+/// as `getobjname`.
+pub(crate) fn object_name(
+  proto: &FuncProto,
+  pc: usize,
+  reg: usize,
+) -> Option<(&'static str, String)> {
+  if let Some(name) = local_name(proto, reg, pc) {
+    return Some(("local", name.to_owned()));
+  }
+  let constant = |k: usize| -> Option<String> {
+    let k = proto.constants.get(k)?;
+    k.is_str().then(|| String::from(k))
+  };
+  let set_pc = find_set_reg(proto, pc, reg)?;
+  // a field of `_ENV` is a global variable
+  let field_kind = |is_env: bool| if is_env { "global" } else { "field" };
+  match proto.bytecodes[set_pc] {
+    ByteCode::Move(a, b) if (b as usize) < (a as usize) => object_name(proto, pc, b as usize),
+    ByteCode::GetUpval(_, up) => Some(("upvalue", proto.upvalues.get(up as usize)?.name.clone())),
+    ByteCode::GetTabUp(_, up, k) => {
+      let is_env = proto.upvalues.get(up as usize)?.name == "_ENV";
+      Some((field_kind(is_env), constant(k as usize)?))
+    }
+    ByteCode::GetField(_, t, k) => {
+      let is_env = local_name(proto, t as usize, set_pc) == Some("_ENV");
+      Some((field_kind(is_env), constant(k as usize)?))
+    }
+    ByteCode::Method(_, _, k) => Some(("method", constant(k as usize)?)),
+    ByteCode::LoadConst(_, k) => Some(("constant", constant(k as usize)?)),
+    _ => None,
+  }
+}
+
+/// ` (upvalue 'foo')` for error messages about the value in upvalue `index`.
+pub(crate) fn upval_info(proto: &FuncProto, index: usize) -> String {
+  match proto.upvalues.get(index) {
+    Some(upvalue) => format!(" (upvalue '{}')", upvalue.name),
+    None => String::new(),
+  }
+}
+
+/// ` (global 'foo')` for error messages about the value in register `reg`.
+pub(crate) fn var_info(proto: &FuncProto, pc: usize, reg: usize) -> String {
+  match object_name(proto, pc, reg) {
+    Some((kind, name)) => format!(" ({kind} '{name}')"),
+    None => String::new(),
+  }
+}
+
+impl ExeState {
+  /// Name of the function running at frame `level` (the top is `0`): the
+  /// registered name for native functions, the global variable holding it,
+  /// or the one derived from how its caller calls it.
+  pub(crate) fn frame_func_name(&self, level: usize) -> Option<(&'static str, String)> {
+    let index = self.frames.len().checked_sub(level + 1)?;
+    let func = &self.frames[index].func;
+    let native = match func {
+      Value::Function(f) => Some(*f),
+      Value::NativeClosure(closure) => Some(closure.func),
+      _ => None,
+    };
+    if let Some(name) = native.and_then(|f| self.native_names.get(&(f as usize))) {
+      return Some(("function", name.clone()));
+    }
+    let globals = self.globals.borrow();
+    let global = globals.map.iter().find(|(k, v)| k.is_str() && *v == func);
+    if let Some((name, _)) = global {
+      return Some(("function", String::from(name)));
+    }
+    self.call_site_name(level)
+  }
+
+  /// Name of the function running at frame `level`, derived from how its
+  /// caller calls it, as `funcnamefromcall`.
+  pub(crate) fn call_site_name(&self, level: usize) -> Option<(&'static str, String)> {
+    let index = self.frames.len().checked_sub(level + 1)?;
+    if self.frames[index].is_tail || index == 0 {
+      return None;
+    }
+    let caller = &self.frames[index - 1];
+    let proto = caller.proto()?;
+    match proto.bytecodes[caller.pc] {
+      ByteCode::Call(func, _, _) | ByteCode::TailCall(func, _) => {
+        match object_name(proto, caller.pc, func as usize)? {
+          ("global", name) => Some(("function", name)),
+          name => Some(name),
+        }
+      }
+      ByteCode::ForCall(..) => Some(("for iterator", "for iterator".to_owned())),
+      _ => None,
+    }
+  }
+
+  /// `chunk:line:` of the function at frame `level`, empty for native functions.
+  pub fn where_(&self, level: usize) -> String {
+    let Some(index) = self.frames.len().checked_sub(level + 1) else {
+      return String::new();
+    };
+    let frame = &self.frames[index];
+    match (frame.proto(), frame.current_line()) {
+      (Some(proto), Some(line)) => format!("{}:{}: ", chunk_id(&proto.source), line),
+      _ => String::new(),
+    }
+  }
+
+  /// Describe the frame at `index` for traceback.
+  fn frame_description(&self, index: usize) -> String {
+    let frame = &self.frames[index];
+    let level = self.frames.len() - 1 - index;
+    let location = match (frame.proto(), frame.current_line()) {
+      (Some(proto), Some(line)) => format!("{}:{}:", chunk_id(&proto.source), line),
+      (Some(proto), None) => format!("{}:", chunk_id(&proto.source)),
+      (None, _) => "[C]:".to_owned(),
+    };
+    let what = match (self.frame_func_name(level), frame.proto()) {
+      (Some(("function", name)), _) => format!("function '{name}'"),
+      (Some((kind, name)), _) => format!("{kind} '{name}'"),
+      (None, Some(proto)) if proto.line_defined == 0 => "main chunk".to_owned(),
+      (None, Some(proto)) => format!(
+        "function <{}:{}>",
+        chunk_id(&proto.source),
+        proto.line_defined
+      ),
+      (None, None) => "?".to_owned(),
+    };
+    let mut description = format!("\n\t{location} in {what}");
+    if frame.is_tail {
+      description.push_str("\n\t(...tail calls...)");
+    }
+    description
+  }
+
+  /// Lua-style traceback from frame `level` (the top is `0`) to the bottom.
+  pub fn traceback(&self, level: usize) -> String {
+    let mut traceback = String::from("stack traceback:");
+    let count = self.frames.len().saturating_sub(level);
+    for (i, index) in (0..count).rev().enumerate() {
+      if count > TRACEBACK_LEVELS_TOP + TRACEBACK_LEVELS_BOTTOM {
+        if i == TRACEBACK_LEVELS_TOP {
+          let skipped = count - TRACEBACK_LEVELS_TOP - TRACEBACK_LEVELS_BOTTOM;
+          traceback.push_str(&format!("\n\t...\t(skipping {skipped} levels)"));
+        }
+        if (TRACEBACK_LEVELS_TOP..count - TRACEBACK_LEVELS_BOTTOM).contains(&i) {
+          continue;
+        }
+      }
+      traceback.push_str(&self.frame_description(index));
+    }
+    traceback
+  }
+}
@@ -0,0 +1,582 @@
+//! # Base Library
+//!
+//! Basic functions of Lua: errors, protected calls, metatables, chunk
+//! loading and the collector.
+
+use super::*;
+use crate::{
+  dump,
+  gc::GcMode,
+  lex::lexing_methods::str_to_int_base,
+  parse::{FuncProto, ParseProto},
+};
+use std::{fs, io::Read};
+
+/// `error(message [, level])`
+///
+/// A string message is prefixed by the position of the function at `level`.
+pub(crate) fn lib_error(state: &mut ExeState) -> Result<i32, LuaError> {
+  let level = state.opt_integer(2, 1)?;
+  let value = match state.arg(1) {
+    v if v.is_str() && level > 0 => {
+      let msg = state.where_(level as usize) + &String::from(&v);
+      msg.into()
+    }
+    v => v,
+  };
+  Err(state.throw(value))
+}
+
+/// Call the function at `func_index` in protected mode, with the message
+/// handler `handler`. Return `true` and its results, or `false` and the error.
+fn protected_call(
+  state: &mut ExeState,
+  func_index: usize,
+  handler: Option<Value>,
+) -> Result<i32, LuaError> {
+  state.handlers.push(handler);
+  let result = state.call_function(func_index);
+  state.handlers.pop();
+  match result {
+    Ok(n) => {
+      state.stack.insert(func_index, Value::Boolean(true));
+      Ok(n as i32 + 1)
+    }
+    Err(err) => {
+      state.stack.truncate(func_index);
+      state.push(false);
+      state.push(err.value);
+      Ok(2)
+    }
+  }
+}
+
+/// `pcall(f, ...)`
+pub(crate) fn lib_pcall(state: &mut ExeState) -> Result<i32, LuaError> {
+  state.check_any(1)?;
+  protected_call(state, state.func_index + 1, None)
+}
+
+/// `xpcall(f, msgh, ...)`
+pub(crate) fn lib_xpcall(state: &mut ExeState) -> Result<i32, LuaError> {
+  state.check_any(2)?;
+  let handler = state.stack.remove(state.func_index + 2);
+  protected_call(state, state.func_index + 1, Some(handler))
+}
+
+/// Compile `chunk`, which is restricted by `mode` to be text (`t`),
+/// binary (`b`) or both.
+pub(crate) fn compile_chunk(
+  chunk: &[u8],
+  chunkname: &str,
+  mode: &[u8],
+) -> Result<Rc<FuncProto>, String> {
+  let (kind, allowed) = match chunk.first() {
+    Some(&c) if c == dump::SIGNATURE[0] => ("binary", mode.contains(&b'b')),
+    _ => ("text", mode.contains(&b't')),
+  };
+  if !allowed {
+    return Err(format!(
+      "attempt to load a {kind} chunk (mode is '{}')",
+      String::from_utf8_lossy(mode)
+    ));
+  }
+  match kind {
+    "binary" => dump::undump(chunk, chunkname),
+    _ => ParseProto::try_load(chunk, chunkname),
+  }
+}
+
+/// Compile `chunk` and push the resulting function, or `nil` and the
+/// message on error. The first upvalue of the function is set to `env` if
+/// given, or the global table.
+fn load_chunk(
+  state: &mut ExeState,
+  chunk: &[u8],
+  chunkname: &str,
+  mode: &[u8],
+  env: Option<Value>,
+) -> Result<i32, LuaError> {
+  match compile_chunk(chunk, chunkname, mode) {
+    Ok(proto) => {
+      let env = env.unwrap_or_else(|| state.globals());
+      let f = state.main_closure(&proto, env);
+      state.push(f);
+      Ok(1)
+    }
+    Err(msg) => {
+      state.push(Value::Nil);
+      state.push(msg);
+      Ok(2)
+    }
+  }
+}
+
+/// `load(chunk [, chunkname [, mode [, env]]])`
+///
+/// `chunk` is a string, or a function returning the pieces of it, ended
+/// by `nil` or an empty string.
+pub(crate) fn lib_load(state: &mut ExeState) -> Result<i32, LuaError> {
+  let mode = state.opt_string(3, "bt")?;
+  let env = match state.get_top() >= 4 {
+    true => Some(state.arg(4)),
+    false => None,
+  };
+  let (chunk, chunkname) = match state.arg(1) {
+    reader if reader.is_function() => {
+      let chunkname = state.opt_string(2, "=(load)")?;
+      let mut chunk = vec![];
+      loop {
+        // errors of the reader are returned as the loading error
+        let piece = match state.call(reader.clone(), vec![]) {
+          Ok(results) => results.into_iter().next().unwrap_or(Value::Nil),
+          Err(err) => {
+            state.push(Value::Nil);
+            state.push(err.value);
+            return Ok(2);
+          }
+        };
+        match piece {
+          Value::Nil => break,
+          s if s.is_str() => match <&[u8]>::from(&s) {
+            [] => break,
+            s => chunk.extend_from_slice(s),
+          },
+          _ => {
+            state.push(Value::Nil);
+            state.push("reader function must return a string");
+            return Ok(2);
+          }
+        }
+      }
+      (chunk, chunkname)
+    }
+    Value::Nil | Value::Boolean(_) | Value::Table(_) => {
+      return Err(state.type_error(1, "function"));
+    }
+    _ => {
+      let chunk = state.check_string(1)?;
+      let chunkname = match state.arg(2) {
+        Value::Nil => chunk.clone(),
+        _ => state.check_string(2)?,
+      };
+      (chunk, chunkname)
+    }
+  };
+  let chunkname = String::from_utf8_lossy(&chunkname).into_owned();
+  load_chunk(state, &chunk, &chunkname, &mode, env)
+}
+
+/// Read the file `filename`, or stdin if `None`, with the leading `#`
+/// line (as `#!/usr/bin/lua`) blanked out. Return the content and the
+/// chunk name, or the error message.
+pub(crate) fn read_chunk_file(filename: Option<&str>) -> Result<(Vec<u8>, String), String> {
+  let (content, chunkname) = match filename {
+    Some(filename) => match fs::read(filename) {
+      Ok(content) => (content, format!("@{filename}")),
+      Err(err) => return Err(format!("cannot open {filename}: {}", os_error_msg(&err))),
+    },
+    None => {
+      let mut content = vec![];
+      if let Err(err) = std::io::stdin().read_to_end(&mut content) {
+        return Err(format!("cannot read stdin: {}", os_error_msg(&err)));
+      }
+      (content, "=stdin".to_owned())
+    }
+  };
+  let mut content = content;
+  if content.starts_with(b"\xEF\xBB\xBF") {
+    content.drain(..3);
+  }
+  if content.first() == Some(&b'#') {
+    // keep the newline to not shift the line numbers
+    let end = content
+      .iter()
+      .position(|&c| c == b'\n')
+      .unwrap_or(content.len());
+    content.drain(..end);
+  }
+  Ok((content, chunkname))
+}
+
+/// `loadfile([filename [, mode [, env]]])`
+///
+/// Load the chunk from the file `filename`, or stdin if absent.
+pub(crate) fn lib_loadfile(state: &mut ExeState) -> Result<i32, LuaError> {
+  let filename = match state.arg(1) {
+    Value::Nil => None,
+    _ => Some(String::from_utf8_lossy(&state.check_string(1)?).into_owned()),
+  };
+  let mode = state.opt_string(2, "bt")?;
+  let env = match state.get_top() >= 3 {
+    true => Some(state.arg(3)),
+    false => None,
+  };
+  match read_chunk_file(filename.as_deref()) {
+    Ok((chunk, chunkname)) => load_chunk(state, &chunk, &chunkname, &mode, env),
+    Err(msg) => {
+      state.push(Value::Nil);
+      state.push(msg);
+      Ok(2)
+    }
+  }
+}
+
+/// `dofile([filename])`
+///
+/// Run the chunk from the file `filename`, or stdin if absent, and return
+/// all its results. Errors are propagated to the caller.
+pub(crate) fn lib_dofile(state: &mut ExeState) -> Result<i32, LuaError> {
+  let filename = match state.arg(1) {
+    Value::Nil => None,
+    _ => Some(String::from_utf8_lossy(&state.check_string(1)?).into_owned()),
+  };
+  let func_index = state.stack.len();
+  let proto = read_chunk_file(filename.as_deref())
+    .and_then(|(chunk, chunkname)| compile_chunk(&chunk, &chunkname, b"bt"));
+  match proto {
+    Ok(proto) => {
+      let globals = state.globals();
+      let f = state.main_closure(&proto, globals);
+      state.push(f);
+      let n = state.call_function(func_index)?;
+      Ok(n as i32)
+    }
+    Err(msg) => Err(state.throw(msg.into())),
+  }
+}
+
+/// `setmetatable(table, metatable)`
+pub(crate) fn lib_setmetatable(state: &mut ExeState) -> Result<i32, LuaError> {
+  let t = state.check_table(1)?;
+  let mt = match state.arg(2) {
+    Value::Nil => None,
+    Value::Table(mt) => Some(mt),
+    _ => return Err(state.type_error(2, "nil or table")),
+  };
+  if !t.borrow().metafield("__metatable").is_nil() {
+    return Err(state.error("cannot change a protected metatable"));
+  }
+  state
+    .heap
+    .check_finalizer(&Value::Table(t.clone()), mt.as_ref());
+  t.borrow_mut().metatable = mt;
+  state.stack.truncate(state.func_index + 2);
+  Ok(1)
+}
+
+/// `getmetatable(object)`
+///
+/// The `__metatable` field of the metatable is returned instead if present.
+pub(crate) fn lib_getmetatable(state: &mut ExeState) -> Result<i32, LuaError> {
+  let v = state.check_any(1)?;
+  let mt = state.get_metatable(&v);
+  let result = match mt {
+    Some(mt) => match mt.borrow().get(&"__metatable".into()) {
+      Value::Nil => Value::Table(mt.clone()),
+      protected => protected,
+    },
+    None => Value::Nil,
+  };
+  state.push(result);
+  Ok(1)
+}
+
+/// `type(v)`
+pub(crate) fn lib_type(state: &mut ExeState) -> Result<i32, LuaError> {
+  let v = state.check_any(1)?;
+  state.push(v.type_name());
+  Ok(1)
+}
+
+/// `tonumber(e [, base])`
+///
+/// Without `base`, numbers and numerals as the lexer accepts them are
+/// converted. With `base`, `e` must be a string of an integer in it.
+pub(crate) fn lib_tonumber(state: &mut ExeState) -> Result<i32, LuaError> {
+  let result = match state.arg(2) {
+    Value::Nil => {
+      let v = state.check_any(1)?;
+      arith::to_number(&v).unwrap_or(Value::Nil)
+    }
+    _ => {
+      let base = state.check_integer(2)?;
+      let v = state.arg(1);
+      if !v.is_str() {
+        return Err(state.type_error(1, "string"));
+      }
+      if !(2..=36).contains(&base) {
+        return Err(state.arg_error(2, "base out of range"));
+      }
+      match str_to_int_base((&v).into(), base as u32) {
+        Some(n) => Value::Integer(n),
+        None => Value::Nil,
+      }
+    }
+  };
+  state.push(result);
+  Ok(1)
+}
+
+/// `assert(v [, message])`
+///
+/// Return all arguments if `v` is true, otherwise raise `message`, which
+/// is not prefixed by the position.
+pub(crate) fn lib_assert(state: &mut ExeState) -> Result<i32, LuaError> {
+  let v = state.check_any(1)?;
+  if !v.is_falsy() {
+    return Ok(state.get_top() as i32);
+  }
+  match state.get_top() {
+    1 => Err(state.error("assertion failed!")),
+    _ => {
+      let msg = state.arg(2);
+      Err(state.throw(msg))
+    }
+  }
+}
+
+/// `select(n, ...)`
+///
+/// Return the arguments after the `n`-th one, counted from the end if
+/// negative, or the count of them if `n` is `"#"`.
+pub(crate) fn lib_select(state: &mut ExeState) -> Result<i32, LuaError> {
+  let n = state.get_top() as i64;
+  let selector = state.arg(1);
+  if selector.is_str() && <&[u8]>::from(&selector).first() == Some(&b'#') {
+    state.push(Value::Integer(n - 1));
+    return Ok(1);
+  }
+  let i = match state.check_integer(1)? {
+    i if i < 0 => n + i,
+    i => i.min(n),
+  };
+  if i < 1 {
+    return Err(state.arg_error(1, "index out of range"));
+  }
+  Ok((n - i) as i32)
+}
+
+/// `rawget(table, index)`
+pub(crate) fn lib_rawget(state: &mut ExeState) -> Result<i32, LuaError> {
+  let t = state.check_table(1)?;
+  let key = state.check_any(2)?;
+  let value = t.borrow().get(&key);
+  state.push(value);
+  Ok(1)
+}
+
+/// `rawset(table, index, value)`
+pub(crate) fn lib_rawset(state: &mut ExeState) -> Result<i32, LuaError> {
+  let t = state.check_table(1)?;
+  let key = state.check_any(2)?;
+  let value = state.check_any(3)?;
+  if let Some(msg) = Table::key_error(&key) {
+    return Err(state.error(msg));
+  }
+  t.borrow_mut().set(key, value);
+  state.push(Value::Table(t));
+  Ok(1)
+}
+
+/// `rawequal(v1, v2)`
+pub(crate) fn lib_rawequal(state: &mut ExeState) -> Result<i32, LuaError> {
+  let a = state.check_any(1)?;
+  let b = state.check_any(2)?;
+  state.push(a == b);
+  Ok(1)
+}
+
+/// `rawlen(v)`
+pub(crate) fn lib_rawlen(state: &mut ExeState) -> Result<i32, LuaError> {
+  let len = match state.arg(1) {
+    Value::Table(t) => t.borrow().len(),
+    v if v.is_str() => <&[u8]>::from(&v).len(),
+    _ => return Err(state.arg_error(1, "table or string expected")),
+  };
+  state.push(Value::Integer(len as i64));
+  Ok(1)
+}
+
+/// `warn(msg1, ...)`
+///
+/// Emit a warning to stderr, which is off by default. A single message
+/// starting with `@` is a control message: `@on` and `@off` turn warnings
+/// on and off, and the others are ignored.
+pub(crate) fn lib_warn(state: &mut ExeState) -> Result<i32, LuaError> {
+  let n = state.get_top();
+  state.check_string(1)?;
+  let mut msg = vec![];
+  for i in 1..=n {
+    msg.extend(state.check_string(i)?);
+  }
+  if n == 1 && msg.first() == Some(&b'@') {
+    match &msg[..] {
+      b"@on" => state.warnings = true,
+      b"@off" => state.warnings = false,
+      _ => (),
+    }
+  } else if state.warnings {
+    eprintln!("Lua warning: {}", String::from_utf8_lossy(&msg));
+  }
+  Ok(0)
+}
+
+/// `tostring(v)`
+pub(crate) fn lib_tostring(state: &mut ExeState) -> Result<i32, LuaError> {
+  let v = state.check_any(1)?;
+  let s = state.to_lua_string(&v)?;
+  state.push(s);
+  Ok(1)
+}
+
+/// `next(table [, key])`
+pub(crate) fn lib_next(state: &mut ExeState) -> Result<i32, LuaError> {
+  let t = state.check_table(1)?;
+  let next = t.borrow().next(&state.arg(2));
+  match next {
+    Ok(Some((k, v))) => {
+      state.push(k);
+      state.push(v);
+      Ok(2)
+    }
+    Ok(None) => {
+      state.push(Value::Nil);
+      Ok(1)
+    }
+    Err(msg) => Err(state.error(msg)),
+  }
+}
+
+/// `pairs(t)`
+///
+/// The `__pairs` metamethod, if present, is called with `t` and its first
+/// 3 results are returned.
+pub(crate) fn lib_pairs(state: &mut ExeState) -> Result<i32, LuaError> {
+  let t = state.check_any(1)?;
+  let pairs = state.get_metafield(&t, "__pairs");
+  if pairs.is_nil() {
+    state.push(Value::Function(lib_next));
+    state.push(t);
+    state.push(Value::Nil);
+  } else {
+    let mut results = state.call(pairs, vec![t])?;
+    results.resize(3, Value::Nil);
+    state.stack.extend(results);
+  }
+  Ok(3)
+}
+
+/// Iterator of `ipairs`, return `i + 1` and `t[i + 1]` until it's `nil`,
+/// indexing with metamethods.
+fn ipairs_aux(state: &mut ExeState) -> Result<i32, LuaError> {
+  let i = state.check_integer(2)?.wrapping_add(1);
+  let t = state.arg(1);
+  let v = state.get_table(&t, &Value::Integer(i))?;
+  if v.is_nil() {
+    state.push(Value::Nil);
+    return Ok(1);
+  }
+  state.push(i);
+  state.push(v);
+  Ok(2)
+}
+
+/// `ipairs(t)`
+pub(crate) fn lib_ipairs(state: &mut ExeState) -> Result<i32, LuaError> {
+  let t = state.check_any(1)?;
+  state.push(Value::Function(ipairs_aux));
+  state.push(t);
+  state.push(Value::Integer(0));
+  Ok(3)
+}
+
+/// `collectgarbage([opt [, arg]])`
+pub(crate) fn lib_collectgarbage(state: &mut ExeState) -> Result<i32, LuaError> {
+  const OPTIONS: [&str; 10] = [
+    "collect",
+    "stop",
+    "restart",
+    "count",
+    "step",
+    "isrunning",
+    "setpause",
+    "setstepmul",
+    "incremental",
+    "generational",
+  ];
+  let option = state.check_option(1, Some("collect"), &OPTIONS)?;
+  let result: Value = match OPTIONS[option] {
+    "collect" => {
+      state.heap.full_collect();
+      Value::Integer(0)
+    }
+    "stop" => {
+      state.heap.running = false;
+      Value::Integer(0)
+    }
+    "restart" => {
+      state.heap.running = true;
+      Value::Integer(0)
+    }
+    "count" => Value::Float(state.heap.allocated() as f64 / 1024.0),
+    "step" => {
+      // `n` KB to be collected, as if they have been allocated
+      let n = state.opt_integer(2, 0)?.max(0) as usize;
+      let steps = ((n * 1024) >> state.heap.stepsize).max(1);
+      let mut finished = false;
+      for _ in 0..steps {
+        finished = state.heap.step();
+        if finished {
+          break;
+        }
+      }
+      Value::Boolean(finished)
+    }
+    "isrunning" => Value::Boolean(state.heap.running),
+    "setpause" => {
+      let pause = state.opt_integer(2, 0)?.max(0) as usize;
+      Value::Integer(mem::replace(&mut state.heap.pause, pause) as i64)
+    }
+    "setstepmul" => {
+      let stepmul = state.opt_integer(2, 0)?.max(0) as usize;
+      Value::Integer(mem::replace(&mut state.heap.stepmul, stepmul) as i64)
+    }
+    "incremental" => {
+      let (pause, stepmul, stepsize) = (
+        state.opt_integer(2, 0)?,
+        state.opt_integer(3, 0)?,
+        state.opt_integer(4, 0)?,
+      );
+      let heap = &mut state.heap;
+      // zero means not changing it
+      for (param, v) in [
+        (&mut heap.pause, pause),
+        (&mut heap.stepmul, stepmul),
+        (&mut heap.stepsize, stepsize.min(40)),
+      ] {
+        if v > 0 {
+          *param = v as usize;
+        }
+      }
+      heap.set_mode(GcMode::Incremental).name().into()
+    }
+    "generational" => {
+      let (minormul, majormul) = (state.opt_integer(2, 0)?, state.opt_integer(3, 0)?);
+      let heap = &mut state.heap;
+      for (param, v) in [
+        (&mut heap.minormul, minormul),
+        (&mut heap.majormul, majormul),
+      ] {
+        if v > 0 {
+          *param = v as usize;
+        }
+      }
+      heap.set_mode(GcMode::Generational).name().into()
+    }
+    _ => unreachable!(),
+  };
+  state.call_finalizers();
+  state.push(result);
+  Ok(1)
+}
@@ -0,0 +1,569 @@
+//! # Debug Library
+//!
+//! Debug library for Lua VM, over the call frames of [`ExeState`] and the
+//! prototypes of the running functions. Levels count the frames from the
+//! top, where `0` is the running function of the library itself.
+//!
+//! rua has no coroutines, so no function takes a thread argument.
+
+use super::*;
+use crate::vm::call_info::{chunk_id, local_name};
+use std::io::{BufRead, Write};
+
+/// Events of hooks, as `LUA_MASKCALL`, `LUA_MASKRET`, `LUA_MASKLINE` and
+/// `LUA_MASKCOUNT`.
+pub(crate) const MASK_CALL: u8 = 1;
+pub(crate) const MASK_RET: u8 = 2;
+pub(crate) const MASK_LINE: u8 = 4;
+pub(crate) const MASK_COUNT: u8 = 8;
+
+/// ## Hook
+///
+/// The function set by `debug.sethook`, called with the event name, and the
+/// line for line events.
+pub(crate) struct Hook {
+  pub func: Value,
+  pub mask: u8,
+  /// Count of instructions between count events
+  pub count: usize,
+  /// Instructions left until the next count event
+  pub remaining: usize,
+}
+
+/// Where a local variable is: a stack slot, or an extra argument.
+enum LocalSlot {
+  Stack(usize),
+  Vararg(usize),
+}
+
+/// Index of the frame at `level`, if there is.
+fn frame_index(state: &ExeState, level: i64) -> Option<usize> {
+  let level = usize::try_from(level).ok()?;
+  state.frames.len().checked_sub(level + 1)
+}
+
+/// Name and place of the `n`-th local variable of the frame at `index`, as
+/// `luaG_findlocal`. Negative `n` are the extra arguments, and the slots
+/// without variables in the frame are temporaries.
+fn find_local(state: &ExeState, index: usize, n: i64) -> Option<(String, LocalSlot)> {
+  let frame = &state.frames[index];
+  let base = frame.func_index + 1;
+  let mut name = None;
+  if let Some(proto) = frame.proto() {
+    if n < 0 {
+      let i = (-(n + 1)) as usize;
+      return (proto.is_vararg && i < frame.varargs.len())
+        .then(|| ("(vararg)".to_owned(), LocalSlot::Vararg(i)));
+    }
+    if n > 0 {
+      name = local_name(proto, n as usize - 1, frame.pc).map(str::to_owned);
+    }
+  }
+  if name.is_none() {
+    let limit = match state.frames.get(index + 1) {
+      Some(next) => next.func_index,
+      None => state.stack.len(),
+    };
+    if n <= 0 || limit.saturating_sub(base) < n as usize {
+      return None;
+    }
+    name = Some(match frame.proto() {
+      Some(_) => "(temporary)".to_owned(),
+      None => "(C temporary)".to_owned(),
+    });
+  }
+  name.map(|name| (name, LocalSlot::Stack(base + n as usize - 1)))
+}
+
+/// Name and value of the `n`-th upvalue of `f`, as `lua_getupvalue`. Upvalues
+/// of native functions have empty names.
+fn get_upvalue(state: &ExeState, f: &Value, n: i64) -> Option<(String, Value)> {
+  let i = usize::try_from(n.checked_sub(1)?).ok()?;
+  match f {
+    Value::LuaFunction(c) => {
+      let upvalue = c.upvalues.borrow().get(i)?.clone();
+      let name = match c.proto.upvalues.get(i) {
+        // stripped away
+        Some(u) if !u.name.is_empty() => &u.name,
+        _ => "(no name)",
+      };
+      Some((name.to_owned(), state.get_upvalue(&upvalue)))
+    }
+    Value::NativeClosure(c) => Some((String::new(), c.upvalues.borrow().get(i)?.clone())),
+    _ => None,
+  }
+}
+
+/// Identity of the `n`-th upvalue of `f`, as `lua_upvalueid`.
+fn upvalue_id(f: &Value, n: i64) -> Option<*const ()> {
+  let i = usize::try_from(n.checked_sub(1)?).ok()?;
+  match f {
+    Value::LuaFunction(c) => c
+      .upvalues
+      .borrow()
+      .get(i)
+      .map(|u| Rc::as_ptr(u) as *const ()),
+    Value::NativeClosure(c) => c
+      .upvalues
+      .borrow()
+      .get(i)
+      .map(|v| v as *const Value as *const ()),
+    _ => None,
+  }
+}
+
+/// Check the `i`-th argument is a function.
+fn check_function(state: &mut ExeState, i: usize) -> Result<Value, LuaError> {
+  match state.arg(i) {
+    f if f.is_function() => Ok(f),
+    _ => Err(state.type_error(i, "function")),
+  }
+}
+
+/// Check the upvalue index of the `argn`-th argument for the function of the
+/// `argf`-th, and return it 0-based, as `checkupval`.
+fn check_upvalue(state: &mut ExeState, argf: usize, argn: usize) -> Result<usize, LuaError> {
+  let n = state.check_integer(argn)?;
+  let f = check_function(state, argf)?;
+  match upvalue_id(&f, n) {
+    Some(_) => Ok(n as usize - 1),
+    None => Err(state.arg_error(argn, "invalid upvalue index")),
+  }
+}
+
+/// `debug.debug()`
+///
+/// Run the lines from stdin as commands, until a line `cont`.
+pub(crate) fn lib_debug(state: &mut ExeState) -> Result<i32, LuaError> {
+  loop {
+    eprint!("lua_debug> ");
+    let _ = std::io::stderr().flush();
+    let mut line = String::new();
+    match std::io::stdin().lock().read_line(&mut line) {
+      Ok(0) | Err(_) => return Ok(0),
+      Ok(_) if line == "cont\n" => return Ok(0),
+      Ok(_) => (),
+    }
+    let result = match base::compile_chunk(line.as_bytes(), "=(debug command)", b"t") {
+      Ok(proto) => {
+        let globals = state.globals();
+        let f = state.main_closure(&proto, globals);
+        state.call(f, vec![]).map(|_| ())
+      }
+      Err(msg) => Err(LuaError::new(msg.into(), String::new())),
+    };
+    if let Err(err) = result {
+      let msg = state.to_lua_string(&err.value)?;
+      eprintln!("{}", String::from_utf8_lossy(&msg));
+    }
+  }
+}
+
+/// `debug.gethook()`
+///
+/// The hook function, mask and count, or `fail` if no hook is set.
+pub(crate) fn lib_gethook(state: &mut ExeState) -> Result<i32, LuaError> {
+  let Some(hook) = &state.hook else {
+    state.push(Value::Nil);
+    return Ok(1);
+  };
+  let mut mask = String::new();
+  for (bit, c) in [(MASK_CALL, 'c'), (MASK_RET, 'r'), (MASK_LINE, 'l')] {
+    if hook.mask & bit != 0 {
+      mask.push(c);
+    }
+  }
+  let (func, count) = (hook.func.clone(), hook.count as i64);
+  state.push(func);
+  state.push(mask);
+  state.push(Value::Integer(count));
+  Ok(3)
+}
+
+/// `debug.getinfo(f|level [, what])`
+///
+/// A table of information about function `f`, or the one running at
+/// `level`, `fail` if there is no such level. `what` selects the fields:
+///
+/// - `S`: `source`, `short_src`, `linedefined`, `lastlinedefined`, `what`
+/// - `l`: `currentline`
+/// - `u`: `nups`, `nparams`, `isvararg`
+/// - `n`: `name`, `namewhat`
+/// - `r`: `ftransfer`, `ntransfer`
+/// - `t`: `istailcall`
+/// - `L`: `activelines`
+/// - `f`: `func`
+pub(crate) fn lib_getinfo(state: &mut ExeState) -> Result<i32, LuaError> {
+  let options = state.opt_string(2, "flnSrtu")?;
+  if options.first() == Some(&b'>') {
+    return Err(state.arg_error(2, "invalid option '>'"));
+  }
+  let (func, index) = match state.arg(1) {
+    f if f.is_function() => (f, None),
+    _ => {
+      let level = state.check_integer(1)?;
+      match frame_index(state, level) {
+        Some(index) => (state.frames[index].func.clone(), Some(index)),
+        None => {
+          state.push(Value::Nil);
+          return Ok(1);
+        }
+      }
+    }
+  };
+  let proto = match &func {
+    Value::LuaFunction(c) => Some(c.proto.clone()),
+    _ => None,
+  };
+  let mut info = Table::new(0, 16);
+  let mut set = |key: &str, value: Value| info.set(key.into(), value);
+  for option in options {
+    match option {
+      b'S' => match &proto {
+        Some(proto) => {
+          let what = if proto.line_defined == 0 {
+            "main"
+          } else {
+            "Lua"
+          };
+          set("source", proto.source.as_str().into());
+          set("short_src", chunk_id(&proto.source).into());
+          set("linedefined", Value::Integer(proto.line_defined as i64));
+          set(
+            "lastlinedefined",
+            Value::Integer(proto.last_line_defined as i64),
+          );
+          set("what", what.into());
+        }
+        None => {
+          set("source", "=[C]".into());
+          set("short_src", "[C]".into());
+          set("linedefined", Value::Integer(-1));
+          set("lastlinedefined", Value::Integer(-1));
+          set("what", "C".into());
+        }
+      },
+      b'l' => {
+        let line = index.and_then(|index| state.frames[index].current_line());
+        set("currentline", Value::Integer(line.map_or(-1, |l| l as i64)));
+      }
+      b'u' => {
+        let nups = match &func {
+          Value::LuaFunction(c) => c.upvalues.borrow().len(),
+          Value::NativeClosure(c) => c.upvalues.borrow().len(),
+          _ => 0,
+        };
+        set("nups", Value::Integer(nups as i64));
+        match &proto {
+          Some(proto) => {
+            set("nparams", Value::Integer(proto.nparam as i64));
+            set("isvararg", Value::Boolean(proto.is_vararg));
+          }
+          None => {
+            set("nparams", Value::Integer(0));
+            set("isvararg", Value::Boolean(true));
+          }
+        }
+      }
+      b'n' => {
+        let level = index.map(|index| state.frames.len() - 1 - index);
+        match level.and_then(|level| state.call_site_name(level)) {
+          Some((namewhat, name)) => {
+            // the call site names the global functions as `function`
+            let namewhat = if namewhat == "function" {
+              "global"
+            } else {
+              namewhat
+            };
+            set("name", name.into());
+            set("namewhat", namewhat.into());
+          }
+          None => set("namewhat", "".into()),
+        }
+      }
+      b'r' => {
+        set("ftransfer", Value::Integer(0));
+        set("ntransfer", Value::Integer(0));
+      }
+      b't' => {
+        let is_tail = index.is_some_and(|index| state.frames[index].is_tail);
+        set("istailcall", Value::Boolean(is_tail));
+      }
+      b'L' => {
+        if let Some(proto) = &proto {
+          let mut lines = Table::new(0, proto.lineinfo.len());
+          for &line in &proto.lineinfo {
+            lines.set(Value::Integer(line as i64), Value::Boolean(true));
+          }
+          set("activelines", state.new_table(lines));
+        }
+      }
+      b'f' => set("func", func.clone()),
+      _ => return Err(state.arg_error(2, "invalid option")),
+    }
+  }
+  let info = state.new_table(info);
+  state.push(info);
+  Ok(1)
+}
+
+/// `debug.getlocal(f|level, n)`
+///
+/// Name and value of the `n`-th local variable of the function running at
+/// `level`, or `fail`. For function `f`, only the name of its `n`-th
+/// parameter.
+pub(crate) fn lib_getlocal(state: &mut ExeState) -> Result<i32, LuaError> {
+  let n = state.check_integer(2)?;
+  match state.arg(1) {
+    Value::LuaFunction(c) => {
+      let name = usize::try_from(n - 1)
+        .ok()
+        .and_then(|i| local_name(&c.proto, i, 0));
+      state.push(name.map_or(Value::Nil, Value::from));
+      Ok(1)
+    }
+    f if f.is_function() => {
+      state.push(Value::Nil);
+      Ok(1)
+    }
+    _ => {
+      let level = state.check_integer(1)?;
+      let Some(index) = frame_index(state, level) else {
+        return Err(state.arg_error(1, "level out of range"));
+      };
+      let Some((name, slot)) = find_local(state, index, n) else {
+        state.push(Value::Nil);
+        return Ok(1);
+      };
+      let value = match slot {
+        LocalSlot::Stack(i) => state.stack[i].clone(),
+        LocalSlot::Vararg(i) => state.frames[index].varargs[i].clone(),
+      };
+      state.push(name);
+      state.push(value);
+      Ok(2)
+    }
+  }
+}
+
+/// `debug.getmetatable(value)`
+///
+/// The metatable of `value`, without the `__metatable` field.
+pub(crate) fn lib_getmetatable(state: &mut ExeState) -> Result<i32, LuaError> {
+  let v = state.check_any(1)?;
+  let mt = state.get_metatable(&v);
+  state.push(mt.map_or(Value::Nil, Value::Table));
+  Ok(1)
+}
+
+/// `debug.getregistry()`
+pub(crate) fn lib_getregistry(state: &mut ExeState) -> Result<i32, LuaError> {
+  let registry = state.registry();
+  state.push(registry);
+  Ok(1)
+}
+
+/// `debug.getupvalue(f, up)`
+///
+/// Name and value of the `up`-th upvalue of `f`, or nothing.
+pub(crate) fn lib_getupvalue(state: &mut ExeState) -> Result<i32, LuaError> {
+  let n = state.check_integer(2)?;
+  let f = check_function(state, 1)?;
+  match get_upvalue(state, &f, n) {
+    Some((name, value)) => {
+      state.push(name);
+      state.push(value);
+      Ok(2)
+    }
+    None => Ok(0),
+  }
+}
+
+/// `debug.getuservalue(u [, n])`
+///
+/// Userdata of rua have no user values, so it's always `fail`.
+pub(crate) fn lib_getuservalue(state: &mut ExeState) -> Result<i32, LuaError> {
+  state.opt_integer(2, 1)?;
+  state.push(Value::Nil);
+  Ok(1)
+}
+
+/// `debug.setcstacklimit(limit)`
+///
+/// Deprecated, it does nothing and returns `0`.
+pub(crate) fn lib_setcstacklimit(state: &mut ExeState) -> Result<i32, LuaError> {
+  state.check_integer(1)?;
+  state.push(Value::Integer(0));
+  Ok(1)
+}
+
+/// `debug.sethook([f, mask [, count]])`
+///
+/// Set `f` as the hook, called on the events in `mask`: `c` for calls, `r`
+/// for returns, `l` for new lines, and every `count` instructions. Without
+/// arguments, the hook is turned off.
+pub(crate) fn lib_sethook(state: &mut ExeState) -> Result<i32, LuaError> {
+  if state.arg(1).is_nil() {
+    state.hook = None;
+    return Ok(0);
+  }
+  let mask_str = state.check_string(2)?;
+  let func = check_function(state, 1)?;
+  let count = state.opt_integer(3, 0)?.max(0) as usize;
+  let mut mask = 0;
+  for (bit, c) in [(MASK_CALL, b'c'), (MASK_RET, b'r'), (MASK_LINE, b'l')] {
+    if mask_str.contains(&c) {
+      mask |= bit;
+    }
+  }
+  if count > 0 {
+    mask |= MASK_COUNT;
+  }
+  state.hook = (mask != 0).then_some(Hook {
+    func,
+    mask,
+    count,
+    remaining: count,
+  });
+  Ok(0)
+}
+
+/// `debug.setlocal(level, n, value)`
+///
+/// Set the `n`-th local variable of the function running at `level`, and
+/// return its name, or `fail` if there is no such variable.
+pub(crate) fn lib_setlocal(state: &mut ExeState) -> Result<i32, LuaError> {
+  let level = state.check_integer(1)?;
+  let n = state.check_integer(2)?;
+  let Some(index) = frame_index(state, level) else {
+    return Err(state.arg_error(1, "level out of range"));
+  };
+  let value = state.check_any(3)?;
+  match find_local(state, index, n) {
+    Some((name, slot)) => {
+      match slot {
+        LocalSlot::Stack(i) => state.stack[i] = value,
+        LocalSlot::Vararg(i) => state.frames[index].varargs[i] = value,
+      }
+      state.push(name);
+    }
+    None => state.push(Value::Nil),
+  }
+  Ok(1)
+}
+
+/// `debug.setmetatable(value, table)`
+///
+/// Set the metatable of `value` even if it's protected, and return `value`.
+/// The values of the types other than tables and userdata share a
+/// metatable per type.
+pub(crate) fn lib_setmetatable(state: &mut ExeState) -> Result<i32, LuaError> {
+  let mt = match state.arg(2) {
+    Value::Nil => None,
+    Value::Table(mt) => Some(mt),
+    _ => return Err(state.type_error(2, "nil or table")),
+  };
+  let v = state.arg(1);
+  match &v {
+    Value::Table(t) => {
+      state.heap.check_finalizer(&v, mt.as_ref());
+      t.borrow_mut().metatable = mt;
+    }
+    Value::UserData(u) => {
+      state.heap.check_finalizer(&v, mt.as_ref());
+      *u.metatable.borrow_mut() = mt;
+    }
+    v => state.set_type_metatable(v, mt),
+  }
+  state.push(v);
+  Ok(1)
+}
+
+/// `debug.setupvalue(f, up, value)`
+///
+/// Set the `up`-th upvalue of `f`, and return its name, or nothing.
+pub(crate) fn lib_setupvalue(state: &mut ExeState) -> Result<i32, LuaError> {
+  let value = state.check_any(3)?;
+  let n = state.check_integer(2)?;
+  let f = check_function(state, 1)?;
+  let Some((name, _)) = get_upvalue(state, &f, n) else {
+    return Ok(0);
+  };
+  let i = n as usize - 1;
+  match &f {
+    Value::LuaFunction(c) => {
+      let upvalue = c.upvalues.borrow()[i].clone();
+      state.set_upvalue(&upvalue, value);
+    }
+    Value::NativeClosure(c) => c.upvalues.borrow_mut()[i] = value,
+    _ => unreachable!(),
+  }
+  state.push(name);
+  Ok(1)
+}
+
+/// `debug.setuservalue(u, value [, n])`
+///
+/// Userdata of rua have no user values, so it's always `fail`.
+pub(crate) fn lib_setuservalue(state: &mut ExeState) -> Result<i32, LuaError> {
+  state.opt_integer(3, 1)?;
+  if !matches!(state.arg(1), Value::UserData(_)) {
+    return Err(state.type_error(1, "userdata"));
+  }
+  state.check_any(2)?;
+  state.push(Value::Nil);
+  Ok(1)
+}
+
+/// `debug.traceback([message [, level]])`
+///
+/// A `message` that is neither a string nor a number is returned untouched.
+pub(crate) fn lib_traceback(state: &mut ExeState) -> Result<i32, LuaError> {
+  let msg = state.arg(1);
+  // numbers are messages too, as strings
+  let is_msg = msg.is_str() || matches!(msg, Value::Integer(_) | Value::Float(_));
+  if !is_msg && !msg.is_nil() {
+    state.push(msg);
+    return Ok(1);
+  }
+  let level = state.opt_integer(2, 1)?;
+  let mut traceback = match msg.is_nil() {
+    true => String::new(),
+    false => msg.to_string() + "\n",
+  };
+  traceback.push_str(&state.traceback(level.max(0) as usize));
+  state.push(traceback);
+  Ok(1)
+}
+
+/// `debug.upvalueid(f, n)`
+///
+/// A light userdata identifying the `n`-th upvalue of `f`, equal for the
+/// closures sharing it, or `fail`.
+pub(crate) fn lib_upvalueid(state: &mut ExeState) -> Result<i32, LuaError> {
+  let n = state.check_integer(2)?;
+  let f = check_function(state, 1)?;
+  match upvalue_id(&f, n) {
+    Some(id) => state.push(Value::LightUserData(id)),
+    None => state.push(Value::Nil),
+  }
+  Ok(1)
+}
+
+/// `debug.upvaluejoin(f1, n1, f2, n2)`
+///
+/// Make the `n1`-th upvalue of Lua closure `f1` refer to the `n2`-th
+/// upvalue of Lua closure `f2`.
+pub(crate) fn lib_upvaluejoin(state: &mut ExeState) -> Result<i32, LuaError> {
+  let n1 = check_upvalue(state, 1, 2)?;
+  let n2 = check_upvalue(state, 3, 4)?;
+  let Value::LuaFunction(f1) = state.arg(1) else {
+    return Err(state.arg_error(1, "Lua function expected"));
+  };
+  let Value::LuaFunction(f2) = state.arg(3) else {
+    return Err(state.arg_error(3, "Lua function expected"));
+  };
+  let upvalue = f2.upvalues.borrow()[n2].clone();
+  f1.upvalues.borrow_mut()[n1] = upvalue;
+  Ok(0)
+}
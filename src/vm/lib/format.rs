@@ -0,0 +1,301 @@
+//! # Format
+//!
+//! Conversions of `string.format`, which follow C `printf`: the flags,
+//! width and precision of a conversion specification, and the integer,
+//! float, character and string conversions.
+
+/// Flags allowed by the float conversions, as `L_FMTFLAGSF`.
+pub(super) const FLAGS_FLOAT: &[u8] = b"-+ #0";
+/// Flags allowed by `%x`, `%X` and `%o`, as `L_FMTFLAGSX`.
+pub(super) const FLAGS_HEX: &[u8] = b"-#0";
+/// Flags allowed by `%d` and `%i`, as `L_FMTFLAGSI`.
+pub(super) const FLAGS_INT: &[u8] = b"-+ 0";
+/// Flags allowed by `%u`, as `L_FMTFLAGSU`.
+pub(super) const FLAGS_UNSIGNED: &[u8] = b"-0";
+/// Flags allowed by `%c`, `%p` and `%s`, as `L_FMTFLAGSC`.
+pub(super) const FLAGS_CHAR: &[u8] = b"-";
+
+/// ## Spec
+///
+/// A conversion specification: `%[flags][width][.precision]conversion`.
+#[derive(Default)]
+pub(super) struct Spec {
+  /// `-`: left-justify within the width
+  left: bool,
+  /// `+`: sign even positive numbers
+  plus: bool,
+  /// ` `: a space for the sign of positive numbers
+  space: bool,
+  /// `#`: the alternate form
+  alt: bool,
+  /// `0`: pad numbers with leading zeros
+  zero: bool,
+  width: usize,
+  precision: Option<usize>,
+  conversion: u8,
+}
+
+impl Spec {
+  /// Parse `form`, the specification after `%`, which allows `flags` and a
+  /// precision if `precision`, as `checkformat`. Width and precision have
+  /// 2 digits at most.
+  pub fn parse(form: &[u8], flags: &[u8], precision: bool) -> Option<Spec> {
+    let mut spec = Spec::default();
+    let mut i = 0;
+    while let Some(&c) = form.get(i).filter(|c| flags.contains(c)) {
+      match c {
+        b'-' => spec.left = true,
+        b'+' => spec.plus = true,
+        b' ' => spec.space = true,
+        b'#' => spec.alt = true,
+        _ => spec.zero = true,
+      }
+      i += 1;
+    }
+    let two_digits = |i: &mut usize| {
+      let mut n = 0;
+      for _ in 0..2 {
+        match form.get(*i) {
+          Some(c) if c.is_ascii_digit() => {
+            n = n * 10 + (c - b'0') as usize;
+            *i += 1;
+          }
+          _ => break,
+        }
+      }
+      n
+    };
+    // a width can not start with `0`
+    if form.get(i) != Some(&b'0') {
+      spec.width = two_digits(&mut i);
+      if form.get(i) == Some(&b'.') && precision {
+        i += 1;
+        spec.precision = Some(two_digits(&mut i));
+      }
+    }
+    match form.get(i) {
+      Some(c) if c.is_ascii_alphabetic() && i == form.len() - 1 => {
+        spec.conversion = *c;
+        Some(spec)
+      }
+      _ => None,
+    }
+  }
+
+  /// The specification of `%a`, without flags.
+  pub fn hex_float() -> Spec {
+    Spec {
+      conversion: b'a',
+      ..Spec::default()
+    }
+  }
+
+  /// Pad `prefix` (sign and base) and `body` to the width, with zeros
+  /// between them if `zero_pad`.
+  fn pad(&self, prefix: &str, body: &str, zero_pad: bool) -> Vec<u8> {
+    let len = prefix.len() + body.len();
+    let fill = self.width.saturating_sub(len);
+    let mut s = Vec::with_capacity(len + fill);
+    if self.left {
+      s.extend_from_slice(prefix.as_bytes());
+      s.extend_from_slice(body.as_bytes());
+      s.resize(len + fill, b' ');
+    } else if zero_pad && self.zero {
+      s.extend_from_slice(prefix.as_bytes());
+      s.resize(prefix.len() + fill, b'0');
+      s.extend_from_slice(body.as_bytes());
+    } else {
+      s.resize(fill, b' ');
+      s.extend_from_slice(prefix.as_bytes());
+      s.extend_from_slice(body.as_bytes());
+    }
+    s
+  }
+
+  /// Sign of a number: `-` if negative, or as the flags `+` and ` ` say.
+  fn sign(&self, negative: bool) -> &'static str {
+    match (negative, self.plus, self.space) {
+      (true, _, _) => "-",
+      (false, true, _) => "+",
+      (false, false, true) => " ",
+      _ => "",
+    }
+  }
+
+  /// `%d`, `%i`, `%u`, `%o`, `%x` and `%X`.
+  pub fn format_integer(&self, n: i64) -> Vec<u8> {
+    let (prefix, mut digits) = match self.conversion {
+      b'd' | b'i' => (self.sign(n < 0), n.unsigned_abs().to_string()),
+      b'u' => ("", (n as u64).to_string()),
+      b'o' => ("", format!("{:o}", n as u64)),
+      b'x' => (
+        if self.alt && n != 0 { "0x" } else { "" },
+        format!("{:x}", n as u64),
+      ),
+      _ => (
+        if self.alt && n != 0 { "0X" } else { "" },
+        format!("{:X}", n as u64),
+      ),
+    };
+    if let Some(precision) = self.precision {
+      if precision == 0 && n == 0 {
+        digits.clear();
+      } else if digits.len() < precision {
+        digits.insert_str(0, &"0".repeat(precision - digits.len()));
+      }
+    }
+    // the alternate form of octal starts with `0`
+    if self.conversion == b'o' && self.alt && !digits.starts_with('0') {
+      digits.insert(0, '0');
+    }
+    self.pad(prefix, &digits, self.precision.is_none())
+  }
+
+  /// `%c`
+  pub fn format_char(&self, c: u8) -> Vec<u8> {
+    let mut s = self.pad("", " ", false);
+    // replace the placeholder of the character
+    let i = if self.left { 0 } else { s.len() - 1 };
+    s[i] = c;
+    s
+  }
+
+  /// `%s`, truncated to the precision.
+  pub fn format_string(&self, s: &[u8]) -> Vec<u8> {
+    let s = match self.precision {
+      Some(precision) if precision < s.len() => &s[..precision],
+      _ => s,
+    };
+    let fill = self.width.saturating_sub(s.len());
+    let mut result = Vec::with_capacity(s.len() + fill);
+    if !self.left {
+      result.resize(fill, b' ');
+    }
+    result.extend_from_slice(s);
+    if self.left {
+      result.resize(s.len() + fill, b' ');
+    }
+    result
+  }
+
+  /// `%a`, `%A`, `%e`, `%E`, `%f`, `%F`, `%g` and `%G`.
+  pub fn format_float(&self, f: f64) -> Vec<u8> {
+    let upper = self.conversion.is_ascii_uppercase();
+    let sign = self.sign(f.is_sign_negative());
+    let abs = f.abs();
+    if !abs.is_finite() {
+      let body = match (abs.is_nan(), upper) {
+        (true, false) => "nan",
+        (true, true) => "NAN",
+        (false, false) => "inf",
+        (false, true) => "INF",
+      };
+      return self.pad(sign, body, false);
+    }
+    let (base, body) = match self.conversion.to_ascii_lowercase() {
+      b'f' => ("", fixed(abs, self.precision.unwrap_or(6), self.alt)),
+      b'e' => ("", exponential(abs, self.precision.unwrap_or(6), self.alt)),
+      b'g' => ("", general(abs, self.precision.unwrap_or(6), self.alt)),
+      _ => ("0x", hexadecimal(abs, self.precision, self.alt)),
+    };
+    let (prefix, body) = match upper {
+      true => ((sign.to_owned() + base).to_uppercase(), body.to_uppercase()),
+      false => (sign.to_owned() + base, body),
+    };
+    self.pad(&prefix, &body, true)
+  }
+}
+
+/// `%.{precision}f` of non-negative `f`.
+fn fixed(f: f64, precision: usize, alt: bool) -> String {
+  let mut s = format!("{f:.precision$}");
+  if alt && precision == 0 {
+    s.push('.');
+  }
+  s
+}
+
+/// `%.{precision}e` of non-negative `f`.
+fn exponential(f: f64, precision: usize, alt: bool) -> String {
+  let s = format!("{f:.precision$e}");
+  let (mantissa, exp) = s.split_once('e').unwrap();
+  let exp: i32 = exp.parse().unwrap();
+  let point = if alt && precision == 0 { "." } else { "" };
+  let sign = if exp < 0 { '-' } else { '+' };
+  format!("{mantissa}{point}e{sign}{:02}", exp.abs())
+}
+
+/// `%.{precision}g` of non-negative `f`: the shorter of `%e` and `%f` with
+/// `precision` significant digits, without trailing zeros unless `alt`.
+fn general(f: f64, precision: usize, alt: bool) -> String {
+  let precision = precision.max(1);
+  // the exponent after rounding to the significant digits
+  let sci = format!("{:.*e}", precision - 1, f);
+  let exp: i32 = sci.split_once('e').unwrap().1.parse().unwrap();
+  let s = if exp < -4 || exp >= precision as i32 {
+    exponential(f, precision - 1, alt)
+  } else {
+    fixed(f, (precision as i32 - 1 - exp) as usize, alt)
+  };
+  if alt {
+    return match s.contains('.') {
+      true => s,
+      false => s + ".",
+    };
+  }
+  // remove the trailing zeros of the fraction
+  let (mantissa, exp) = match s.find('e') {
+    Some(i) => s.split_at(i),
+    None => (&s[..], ""),
+  };
+  let mantissa = match mantissa.contains('.') {
+    true => mantissa.trim_end_matches('0').trim_end_matches('.'),
+    false => mantissa,
+  };
+  mantissa.to_owned() + exp
+}
+
+/// `%a` of non-negative `f` without the `0x`: the hexadecimal mantissa
+/// rounded to `precision` digits (all significant ones by default) and the
+/// binary exponent.
+fn hexadecimal(f: f64, precision: Option<usize>, alt: bool) -> String {
+  const MANTISSA_BITS: u32 = 52;
+  const MANTISSA_DIGITS: usize = 13;
+  let bits = f.to_bits();
+  let biased_exp = (bits >> MANTISSA_BITS) as i64 & 0x7ff;
+  let mantissa = bits & ((1 << MANTISSA_BITS) - 1);
+  let (lead, exp) = match (biased_exp, mantissa) {
+    (0, 0) => (0, 0),
+    // subnormal
+    (0, _) => (0, -1022),
+    (e, _) => (1, e - 1023),
+  };
+  let (lead, digits) = match precision {
+    Some(p) if p < MANTISSA_DIGITS => {
+      // round to nearest even
+      let shift = (MANTISSA_DIGITS - p) * 4;
+      let full = (lead << MANTISSA_BITS) | mantissa;
+      let (rem, half) = (full & ((1 << shift) - 1), 1 << (shift - 1));
+      let mut q = full >> shift;
+      if rem > half || (rem == half && q & 1 == 1) {
+        q += 1;
+      }
+      let digits = match p {
+        0 => String::new(),
+        p => format!("{:0p$x}", q & ((1 << (p * 4)) - 1)),
+      };
+      (q >> (p * 4), digits)
+    }
+    Some(p) => (
+      lead,
+      format!("{mantissa:013x}{}", "0".repeat(p - MANTISSA_DIGITS)),
+    ),
+    None => (
+      lead,
+      format!("{mantissa:013x}").trim_end_matches('0').to_owned(),
+    ),
+  };
+  let point = if !digits.is_empty() || alt { "." } else { "" };
+  let sign = if exp < 0 { '-' } else { '+' };
+  format!("{lead:x}{point}{digits}p{sign}{}", exp.abs())
+}
@@ -1,14 +1,1033 @@
 //! # IO Library
 //!
-//! IO library for Lua VM.
+//! IO library for Lua VM. Files are userdata of [`LuaFile`], whose methods
+//! are in the shared metatable of files. A file is closed by `close`, or
+//! else when it's collected.
 
 use super::*;
+use crate::{
+  lex::lexing_methods::{str_to_number, Number},
+  value::UserData,
+};
+use std::{
+  cell::RefMut,
+  fs::{self, File, OpenOptions},
+  io::{self, Read, Seek, SeekFrom, Write},
+  mem,
+  process::{self, Child, Command, ExitStatus, Stdio},
+  rc::Weak,
+  time::{SystemTime, UNIX_EPOCH},
+};
 
-/// "print" function in Lua's std-lib.
+/// Size of the buffers of files, as `LUAL_BUFFERSIZE`.
+const BUFFER_SIZE: usize = 8192;
+/// Max length of a numeral read by `read("n")`, as `L_MAXLENNUM`.
+const MAX_NUMERAL_LEN: usize = 200;
+/// Max count of formats of `lines`, as `MAXARGLINE`.
+const MAX_ARG_LINE: usize = 250;
+/// Name of the type of files, as `LUA_FILEHANDLE`.
+const FILE_HANDLE: &str = "FILE*";
+/// `EBADF`, for the operations a stream doesn't support.
+const EBADF: i32 = 9;
+/// `ESPIPE`, for seeking a stream which is not a file.
+const ESPIPE: i32 = 29;
+
+/// Write `bytes` to the standard output.
+///
+/// Valid UTF-8 is written by `print!`, which the test harness captures.
+fn write_stdout(bytes: &[u8]) {
+  match std::str::from_utf8(bytes) {
+    Ok(s) => print!("{s}"),
+    Err(_) => {
+      let _ = io::stdout().write_all(bytes);
+    }
+  }
+}
+
+/// `print(...)`
+///
+/// Print the arguments converted by `tostring`, separated by tabs.
+pub(crate) fn lib_print(state: &mut ExeState) -> Result<i32, LuaError> {
+  let mut line = vec![];
+  for i in 1..=state.get_top() {
+    if i > 1 {
+      line.push(b'\t');
+    }
+    let s = state.to_lua_string(&state.arg(i))?;
+    line.extend(s);
+  }
+  line.push(b'\n');
+  write_stdout(&line);
+  Ok(0)
+}
+
+/// ## Stream
+///
+/// What a file reads from and writes to.
+enum Stream {
+  File(File),
+  Stdin,
+  Stdout,
+  Stderr,
+  /// A command of `io.popen`, whose output is read or input is written
+  Pipe(Child),
+}
+
+impl Stream {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      Stream::File(f) => f.read(buf),
+      Stream::Stdin => io::stdin().read(buf),
+      Stream::Pipe(Child {
+        stdout: Some(out), ..
+      }) => out.read(buf),
+      _ => Err(io::Error::from_raw_os_error(EBADF)),
+    }
+  }
+
+  fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+    match self {
+      Stream::File(f) => f.write_all(buf),
+      Stream::Stdout => {
+        write_stdout(buf);
+        Ok(())
+      }
+      Stream::Stderr => io::stderr().write_all(buf),
+      Stream::Pipe(Child {
+        stdin: Some(input), ..
+      }) => input.write_all(buf),
+      _ => Err(io::Error::from_raw_os_error(EBADF)),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match self {
+      Stream::File(f) => f.flush(),
+      Stream::Stdout => io::stdout().flush(),
+      Stream::Pipe(Child {
+        stdin: Some(input), ..
+      }) => input.flush(),
+      _ => Ok(()),
+    }
+  }
+
+  fn is_standard(&self) -> bool {
+    matches!(self, Stream::Stdin | Stream::Stdout | Stream::Stderr)
+  }
+}
+
+/// Buffering modes of output, as `setvbuf` sets.
+#[derive(Clone, Copy, PartialEq)]
+enum Buffering {
+  No,
+  Full,
+  Line,
+}
+
+/// ## LuaFile
+///
+/// A file handle of Lua, with its own buffers as a C `FILE`: input is read
+/// ahead by blocks, and output is kept until the buffer is full (or at the
+/// end of a line, in line buffering mode).
+pub(crate) struct LuaFile {
+  /// `None` once closed
+  stream: Option<Stream>,
+  /// Bytes read ahead, consumed up to `read_pos`
+  read_buf: Vec<u8>,
+  read_pos: usize,
+  /// Bytes written but not sent to the stream yet
+  write_buf: Vec<u8>,
+  buffering: Buffering,
+  buffer_size: usize,
+}
+
+impl LuaFile {
+  fn new(stream: Stream) -> Self {
+    // Rust buffers the standard output itself
+    let buffering = match stream {
+      Stream::File(_) | Stream::Pipe(_) => Buffering::Full,
+      _ => Buffering::No,
+    };
+    Self {
+      stream: Some(stream),
+      read_buf: Vec::new(),
+      read_pos: 0,
+      write_buf: Vec::new(),
+      buffering,
+      buffer_size: BUFFER_SIZE,
+    }
+  }
+
+  fn is_closed(&self) -> bool {
+    self.stream.is_none()
+  }
+
+  fn stream(&mut self) -> io::Result<&mut Stream> {
+    self
+      .stream
+      .as_mut()
+      .ok_or_else(|| io::Error::from_raw_os_error(EBADF))
+  }
+
+  /// Send the bytes written to the stream.
+  fn flush_write(&mut self) -> io::Result<()> {
+    if self.write_buf.is_empty() {
+      return Ok(());
+    }
+    let buf = mem::take(&mut self.write_buf);
+    self.stream()?.write(&buf)
+  }
+
+  /// Drop the bytes read ahead, and move the position of the stream back
+  /// to the first of them.
+  fn drop_read_ahead(&mut self) -> io::Result<()> {
+    let unread = self.read_buf.len() - self.read_pos;
+    self.read_buf.clear();
+    self.read_pos = 0;
+    match &mut self.stream {
+      Some(Stream::File(f)) if unread > 0 => {
+        f.seek(SeekFrom::Current(-(unread as i64))).map(|_| ())
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /// Read ahead if all bytes read are consumed, return `false` at the end
+  /// of file.
+  fn fill(&mut self) -> io::Result<bool> {
+    if self.read_pos < self.read_buf.len() {
+      return Ok(true);
+    }
+    self.flush_write()?;
+    self.read_buf.resize(BUFFER_SIZE, 0);
+    self.read_pos = 0;
+    let mut buf = mem::take(&mut self.read_buf);
+    let result = loop {
+      match self.stream()?.read(&mut buf) {
+        Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+        result => break result,
+      }
+    };
+    buf.truncate(*result.as_ref().unwrap_or(&0));
+    self.read_buf = buf;
+    Ok(result? > 0)
+  }
+
+  fn peek(&mut self) -> io::Result<Option<u8>> {
+    Ok(self.fill()?.then(|| self.read_buf[self.read_pos]))
+  }
+
+  /// Read a line, with the newline if `keep_newline`, `None` at the end of
+  /// file, as `read_line`.
+  fn read_line(&mut self, keep_newline: bool) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    while self.fill()? {
+      let buf = &self.read_buf[self.read_pos..];
+      match buf.iter().position(|&c| c == b'\n') {
+        Some(i) => {
+          let end = if keep_newline { i + 1 } else { i };
+          line.extend_from_slice(&buf[..end]);
+          self.read_pos += i + 1;
+          return Ok(Some(line));
+        }
+        None => {
+          line.extend_from_slice(buf);
+          self.read_pos = self.read_buf.len();
+        }
+      }
+    }
+    Ok((!line.is_empty()).then_some(line))
+  }
+
+  /// Read the rest of the file, as `read_all`.
+  fn read_all(&mut self) -> io::Result<Vec<u8>> {
+    let mut s = Vec::new();
+    while self.fill()? {
+      s.extend_from_slice(&self.read_buf[self.read_pos..]);
+      self.read_pos = self.read_buf.len();
+    }
+    Ok(s)
+  }
+
+  /// Read `n` bytes at most, `None` at the end of file, as `read_chars`.
+  fn read_chars(&mut self, n: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut s = Vec::new();
+    while s.len() < n && self.fill()? {
+      let buf = &self.read_buf[self.read_pos..];
+      let count = buf.len().min(n - s.len());
+      s.extend_from_slice(&buf[..count]);
+      self.read_pos += count;
+    }
+    Ok((!s.is_empty()).then_some(s))
+  }
+
+  /// Read a numeral, `None` if it's not a valid one, as `l_getn`.
+  fn read_number(&mut self) -> io::Result<Option<Value>> {
+    while self
+      .peek()?
+      .is_some_and(|c| matches!(c, b' ' | b'\t'..=b'\r'))
+    {
+      self.read_pos += 1;
+    }
+    let mut numeral = Numeral {
+      file: self,
+      buf: Vec::new(),
+      too_long: false,
+    };
+    let mut count = 0;
+    let mut hex = false;
+    numeral.accept(b"-+")?;
+    if numeral.accept(b"0")? {
+      match numeral.accept(b"xX")? {
+        true => hex = true,
+        // count the initial `0` as a valid digit
+        false => count = 1,
+      }
+    }
+    count += numeral.digits(hex)?;
+    if numeral.accept(b".")? {
+      count += numeral.digits(hex)?;
+    }
+    if count > 0 && numeral.accept(if hex { b"pP" } else { b"eE" })? {
+      numeral.accept(b"-+")?;
+      numeral.digits(false)?;
+    }
+    if numeral.too_long {
+      return Ok(None);
+    }
+    Ok(match str_to_number(&numeral.buf) {
+      Some(Number::Integer(i)) => Some(Value::Integer(i)),
+      Some(Number::Float(f)) => Some(Value::Float(f)),
+      None => None,
+    })
+  }
+
+  fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+    self.drop_read_ahead()?;
+    if self.buffering == Buffering::No {
+      self.flush_write()?;
+      return self.stream()?.write(bytes);
+    }
+    self.write_buf.extend_from_slice(bytes);
+    if self.write_buf.len() >= self.buffer_size
+      || (self.buffering == Buffering::Line && bytes.contains(&b'\n'))
+    {
+      self.flush_write()?;
+    }
+    Ok(())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.flush_write()?;
+    self.stream()?.flush()
+  }
+
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    self.flush_write()?;
+    self.drop_read_ahead()?;
+    match self.stream()? {
+      Stream::File(f) => f.seek(pos),
+      _ => Err(io::Error::from_raw_os_error(ESPIPE)),
+    }
+  }
+
+  fn set_buffering(&mut self, buffering: Buffering, size: usize) -> io::Result<()> {
+    self.flush_write()?;
+    self.buffering = buffering;
+    self.buffer_size = size;
+    Ok(())
+  }
+
+  /// Close the stream, return the exit status for a command of `io.popen`.
+  fn close(&mut self) -> io::Result<Option<ExitStatus>> {
+    let flushed = self.flush_write();
+    self.read_buf.clear();
+    self.read_pos = 0;
+    match self.stream.take() {
+      Some(Stream::Pipe(mut child)) => {
+        // the command sees the end of its input
+        drop(child.stdin.take());
+        drop(child.stdout.take());
+        let status = child.wait()?;
+        flushed.map(|_| Some(status))
+      }
+      _ => flushed.map(|_| None),
+    }
+  }
+}
+
+/// A file is closed when it's collected.
+impl Drop for LuaFile {
+  fn drop(&mut self) {
+    let _ = self.close();
+  }
+}
+
+/// ## Numeral
+///
+/// A numeral being read by `read("n")`, as `RN`.
+struct Numeral<'a> {
+  file: &'a mut LuaFile,
+  buf: Vec<u8>,
+  /// Whether it's too long to be valid
+  too_long: bool,
+}
+
+impl Numeral<'_> {
+  /// Accept the next byte if it's in `set`, as `test2`.
+  fn accept(&mut self, set: &[u8]) -> io::Result<bool> {
+    match self.file.peek()? {
+      Some(c) if set.contains(&c) => Ok(self.next(c)),
+      _ => Ok(false),
+    }
+  }
+
+  /// Accept the next byte `c`, as `nextc`.
+  fn next(&mut self, c: u8) -> bool {
+    if self.buf.len() >= MAX_NUMERAL_LEN {
+      self.too_long = true;
+      return false;
+    }
+    self.buf.push(c);
+    self.file.read_pos += 1;
+    true
+  }
+
+  /// Accept a sequence of digits, return the count, as `readdigits`.
+  fn digits(&mut self, hex: bool) -> io::Result<usize> {
+    let mut count = 0;
+    while let Some(c) = self.file.peek()? {
+      let digit = match hex {
+        true => c.is_ascii_hexdigit(),
+        false => c.is_ascii_digit(),
+      };
+      if !digit || !self.next(c) {
+        break;
+      }
+      count += 1;
+    }
+    Ok(count)
+  }
+}
+
+/// ## IoState
+///
+/// State of the library: the metatable of files, and the default input and
+/// output files.
+pub(crate) struct IoState {
+  file_meta: Option<Rc<RefCell<Table>>>,
+  input: Value,
+  output: Value,
+  /// Files created, to be flushed at exit
+  files: Vec<Weak<UserData>>,
+}
+
+impl Default for IoState {
+  fn default() -> Self {
+    Self {
+      file_meta: None,
+      input: Value::Nil,
+      output: Value::Nil,
+      files: Vec::new(),
+    }
+  }
+}
+
+/// Create a file of `stream`.
+fn new_file(state: &mut ExeState, stream: Stream) -> Value {
+  let file = state.new_userdata(LuaFile::new(stream), state.io.file_meta.clone());
+  if let Value::UserData(u) = &file {
+    state.io.files.retain(|f| f.strong_count() > 0);
+    state.io.files.push(Rc::downgrade(u));
+  }
+  file
+}
+
+/// Flush all files alive, as C does at exit.
+pub(crate) fn flush_files(state: &mut ExeState) {
+  for file in state.io.files.iter().filter_map(Weak::upgrade) {
+    if let Ok(mut data) = file.data.try_borrow_mut() {
+      if let Some(file) = data.downcast_mut::<LuaFile>() {
+        let _ = file.flush();
+      }
+    }
+  }
+}
+
+/// The file of userdata `u`.
+fn file_mut(u: &UserData) -> RefMut<'_, LuaFile> {
+  RefMut::map(u.data.borrow_mut(), |data| data.downcast_mut().unwrap())
+}
+
+/// Check the `i`-th argument is a file, not closed, as `tofile`.
+fn to_file(state: &mut ExeState, i: usize) -> Result<Rc<UserData>, LuaError> {
+  let u = state.check_userdata::<LuaFile>(i, FILE_HANDLE)?;
+  if file_mut(&u).is_closed() {
+    return Err(state.error("attempt to use a closed file"));
+  }
+  Ok(u)
+}
+
+/// Options of `io.open` for `mode`, as `l_checkmode` accepts it.
+fn open_options(mode: &[u8]) -> Option<OpenOptions> {
+  let (kind, rest) = mode.split_first()?;
+  let (update, rest) = match rest.split_first() {
+    Some((b'+', rest)) => (true, rest),
+    _ => (false, rest),
+  };
+  if rest.iter().any(|&c| c != b'b') {
+    return None;
+  }
+  let mut options = OpenOptions::new();
+  match kind {
+    b'r' => options.read(true).write(update),
+    b'w' => options.write(true).create(true).truncate(true).read(update),
+    b'a' => options.append(true).create(true).read(update),
+    _ => return None,
+  };
+  Some(options)
+}
+
+/// Open `filename` in `mode`, raise the error if it fails, as
+/// `opencheckfile`.
+fn open_check_file(state: &mut ExeState, filename: &str, mode: &[u8]) -> Result<Value, LuaError> {
+  match open_options(mode).unwrap().open(filename) {
+    Ok(f) => Ok(new_file(state, Stream::File(f))),
+    Err(err) => Err(state.error(format!(
+      "cannot open file '{filename}' ({})",
+      os_error_msg(&err)
+    ))),
+  }
+}
+
+/// The default input file if `input`, or else the default output file, as
+/// `getiofile`.
+fn io_file(state: &mut ExeState, input: bool) -> Result<Rc<UserData>, LuaError> {
+  let file = match input {
+    true => state.io.input.clone(),
+    false => state.io.output.clone(),
+  };
+  match file {
+    Value::UserData(u) if !file_mut(&u).is_closed() => Ok(u),
+    _ => {
+      let kind = if input { "input" } else { "output" };
+      Err(state.error(format!("default {kind} file is closed")))
+    }
+  }
+}
+
+/// Close file `u` and push the results, as `aux_close`.
+fn close_file(state: &mut ExeState, u: &UserData) -> Result<i32, LuaError> {
+  let mut file = file_mut(u);
+  if file.stream.as_ref().is_some_and(Stream::is_standard) {
+    state.push(Value::Nil);
+    state.push("cannot close standard file");
+    return Ok(2);
+  }
+  let is_pipe = matches!(file.stream, Some(Stream::Pipe(_)));
+  match file.close() {
+    Ok(Some(status)) => Ok(exec_result(state, Ok(status))),
+    Ok(None) => {
+      state.push(Value::Boolean(true));
+      Ok(1)
+    }
+    Err(err) if is_pipe => Ok(exec_result(state, Err(err))),
+    Err(err) => Ok(file_error(state, &err, None)),
+  }
+}
+
+/// Formats of `read`.
+enum ReadFormat {
+  /// Bytes, at most the count; `0` tests the end of file
+  Count(usize),
+  Number,
+  /// A line, with the newline if `true`
+  Line(bool),
+  All,
+}
+
+/// Check `format`, the argument at `i`, as `read` accepts it.
+fn check_read_format(
+  state: &mut ExeState,
+  format: &Value,
+  i: usize,
+) -> Result<ReadFormat, LuaError> {
+  match format {
+    Value::Integer(_) | Value::Float(_) => match arith::to_integer(format) {
+      // a negative count reads all, as a huge `size_t`
+      Some(n) => Ok(ReadFormat::Count(n as usize)),
+      None => Err(state.arg_error(i, "number has no integer representation")),
+    },
+    v if v.is_str() => {
+      let format = <&[u8]>::from(v);
+      // the `*` of Lua 5.2 is optional
+      let format = format.strip_prefix(b"*").unwrap_or(format);
+      match format.first() {
+        Some(b'n') => Ok(ReadFormat::Number),
+        Some(b'l') => Ok(ReadFormat::Line(false)),
+        Some(b'L') => Ok(ReadFormat::Line(true)),
+        Some(b'a') => Ok(ReadFormat::All),
+        _ => Err(state.arg_error(i, "invalid format")),
+      }
+    }
+    _ => Err(state.type_error(i, "string")),
+  }
+}
+
+/// Read `formats` from file `u` and push the results, the first failed one
+/// as `nil`, as `g_read`. `first` is the argument index of the first format.
+fn read_formats(
+  state: &mut ExeState,
+  u: &UserData,
+  formats: &[Value],
+  first: usize,
+) -> Result<i32, LuaError> {
+  let formats = match formats.is_empty() {
+    true => vec![ReadFormat::Line(false)],
+    false => (formats.iter().enumerate())
+      .map(|(i, format)| check_read_format(state, format, first + i))
+      .collect::<Result<_, _>>()?,
+  };
+  let top = state.stack.len();
+  let mut file = file_mut(u);
+  for format in formats {
+    let result = match format {
+      ReadFormat::Count(0) => file.fill().map(|more| more.then(|| Value::from(""))),
+      ReadFormat::Count(n) => file.read_chars(n).map(|s| s.map(Value::from)),
+      ReadFormat::Number => file.read_number(),
+      ReadFormat::Line(keep_newline) => file.read_line(keep_newline).map(|s| s.map(Value::from)),
+      ReadFormat::All => file.read_all().map(|s| Some(Value::from(s))),
+    };
+    match result {
+      Ok(Some(v)) => state.push(v),
+      Ok(None) => {
+        state.push(Value::Nil);
+        break;
+      }
+      Err(err) => {
+        state.stack.truncate(top);
+        return Ok(file_error(state, &err, None));
+      }
+    }
+  }
+  Ok((state.stack.len() - top) as i32)
+}
+
+/// Write the arguments from the `first`-th to file `u`, and push the file,
+/// as `g_write`.
+fn write_args(state: &mut ExeState, u: Rc<UserData>, first: usize) -> Result<i32, LuaError> {
+  let mut result = Ok(());
+  for i in first..=state.get_top() {
+    let bytes = match state.arg(i) {
+      Value::Integer(n) => n.to_string().into_bytes(),
+      Value::Float(f) => format::Spec::parse(b".14g", format::FLAGS_FLOAT, true)
+        .unwrap()
+        .format_float(f),
+      _ => state.check_string(i)?,
+    };
+    // write all arguments even after an error, as C does
+    let written = file_mut(&u).write(&bytes);
+    result = result.and(written);
+  }
+  match result {
+    Ok(()) => {
+      state.push(Value::UserData(u));
+      Ok(1)
+    }
+    Err(err) => Ok(file_error(state, &err, None)),
+  }
+}
+
+/// Push the iterator of `lines` over file `file`, reading the arguments
+/// from the `first`-th as formats, and closing the file at the end if
+/// `close`, as `aux_lines`.
+fn push_lines(
+  state: &mut ExeState,
+  file: Value,
+  first: usize,
+  close: bool,
+) -> Result<(), LuaError> {
+  let count = state.get_top().saturating_sub(first - 1);
+  if count > MAX_ARG_LINE {
+    return Err(state.arg_error(MAX_ARG_LINE + first, "too many arguments"));
+  }
+  let mut upvalues = vec![file, Value::Integer(count as i64), Value::Boolean(close)];
+  upvalues.extend((first..first + count).map(|i| state.arg(i)));
+  let iter = state.new_native_closure(lines_aux, upvalues);
+  state.push(iter);
+  Ok(())
+}
+
+/// Iterator of `lines`, with upvalues: the file, the count of formats,
+/// whether to close the file at the end, and the formats, as `io_readline`.
+fn lines_aux(state: &mut ExeState) -> Result<i32, LuaError> {
+  let Value::UserData(u) = state.native_upvalue(1) else {
+    unreachable!()
+  };
+  if file_mut(&u).is_closed() {
+    return Err(state.error("file is already closed"));
+  }
+  let count = arith::to_integer(&state.native_upvalue(2)).unwrap() as usize;
+  let formats: Vec<_> = (4..4 + count).map(|i| state.native_upvalue(i)).collect();
+  let top = state.stack.len();
+  let n = read_formats(state, &u, &formats, 2)?;
+  if state.stack[top].is_falsy() {
+    // the error message, if any
+    if n > 1 {
+      let msg = state.stack[top + 1].clone();
+      return Err(state.error(String::from(&msg)));
+    }
+    if !state.native_upvalue(3).is_falsy() {
+      close_file(state, &u)?;
+    }
+    state.stack.truncate(top);
+    return Ok(0);
+  }
+  Ok(n)
+}
+
+/// `io.close([file])`
+///
+/// Close `file`, or the default output file.
+pub(crate) fn lib_close(state: &mut ExeState) -> Result<i32, LuaError> {
+  let u = match state.arg(1) {
+    Value::Nil => io_file(state, false)?,
+    _ => to_file(state, 1)?,
+  };
+  close_file(state, &u)
+}
+
+/// `io.flush()`
+///
+/// Flush the default output file.
+pub(crate) fn lib_flush(state: &mut ExeState) -> Result<i32, LuaError> {
+  let u = io_file(state, false)?;
+  let result = file_mut(&u).flush();
+  match result {
+    Ok(()) => {
+      state.push(Value::Boolean(true));
+      Ok(1)
+    }
+    Err(err) => Ok(file_error(state, &err, None)),
+  }
+}
+
+/// `io.input([file])` and `io.output([file])`, as `g_iofile`.
+fn set_io_file(state: &mut ExeState, input: bool) -> Result<i32, LuaError> {
+  let file = match state.arg(1) {
+    Value::Nil => None,
+    v if v.is_str() => {
+      let filename = String::from(&v);
+      let mode: &[u8] = if input { b"r" } else { b"w" };
+      Some(open_check_file(state, &filename, mode)?)
+    }
+    v => {
+      to_file(state, 1)?;
+      Some(v)
+    }
+  };
+  if let Some(file) = file {
+    match input {
+      true => state.io.input = file,
+      false => state.io.output = file,
+    }
+  }
+  let current = match input {
+    true => state.io.input.clone(),
+    false => state.io.output.clone(),
+  };
+  state.push(current);
+  Ok(1)
+}
+
+/// `io.input([file])`
+///
+/// Set the default input file to `file`, or the file named `file` opened,
+/// and return the current one.
+pub(crate) fn lib_input(state: &mut ExeState) -> Result<i32, LuaError> {
+  set_io_file(state, true)
+}
+
+/// `io.output([file])`
+///
+/// Set the default output file to `file`, or the file named `file` opened,
+/// and return the current one.
+pub(crate) fn lib_output(state: &mut ExeState) -> Result<i32, LuaError> {
+  set_io_file(state, false)
+}
+
+/// `io.lines([filename, ...])`
+///
+/// Iterate over the default input file, or the file named `filename`
+/// which is closed at the end.
+pub(crate) fn lib_lines(state: &mut ExeState) -> Result<i32, LuaError> {
+  let (file, close) = match state.arg(1) {
+    Value::Nil => {
+      let u = io_file(state, true)?;
+      (Value::UserData(u), false)
+    }
+    _ => {
+      let filename = String::from_utf8_lossy(&state.check_string(1)?).into_owned();
+      (open_check_file(state, &filename, b"r")?, true)
+    }
+  };
+  push_lines(state, file.clone(), 2, close)?;
+  if !close {
+    return Ok(1);
+  }
+  // the file is the to-be-closed value of the generic `for`
+  state.push(Value::Nil);
+  state.push(Value::Nil);
+  state.push(file);
+  Ok(4)
+}
+
+/// `io.open(filename [, mode])`
+pub(crate) fn lib_open(state: &mut ExeState) -> Result<i32, LuaError> {
+  let filename = String::from_utf8_lossy(&state.check_string(1)?).into_owned();
+  let mode = state.opt_string(2, "r")?;
+  let Some(options) = open_options(&mode) else {
+    return Err(state.arg_error(2, "invalid mode"));
+  };
+  match options.open(&filename) {
+    Ok(f) => {
+      let file = new_file(state, Stream::File(f));
+      state.push(file);
+      Ok(1)
+    }
+    Err(err) => Ok(file_error(state, &err, Some(&filename))),
+  }
+}
+
+/// `io.popen(prog [, mode])`
+///
+/// Run `prog` by the shell, and return a file to read its output (mode
+/// `"r"`) or to write its input (mode `"w"`).
+pub(crate) fn lib_popen(state: &mut ExeState) -> Result<i32, LuaError> {
+  let prog = String::from_utf8_lossy(&state.check_string(1)?).into_owned();
+  let mode = state.opt_string(2, "r")?;
+  let mut command = Command::new("/bin/sh");
+  command.arg("-c").arg(&prog);
+  match &mode[..] {
+    b"r" => command.stdout(Stdio::piped()),
+    b"w" => command.stdin(Stdio::piped()),
+    _ => return Err(state.arg_error(2, "invalid mode")),
+  };
+  match command.spawn() {
+    Ok(child) => {
+      let file = new_file(state, Stream::Pipe(child));
+      state.push(file);
+      Ok(1)
+    }
+    Err(err) => Ok(file_error(state, &err, Some(&prog))),
+  }
+}
+
+/// `io.read(...)`
+///
+/// Read the default input file by the formats.
+pub(crate) fn lib_read(state: &mut ExeState) -> Result<i32, LuaError> {
+  let u = io_file(state, true)?;
+  let formats: Vec<_> = (1..=state.get_top()).map(|i| state.arg(i)).collect();
+  read_formats(state, &u, &formats, 1)
+}
+
+/// `io.tmpfile()`
 ///
-/// It supports only 1 argument and assumes the argument is at `index: 1` on stack.
-pub(crate) fn lib_print(state: &mut ExeState) -> i32 {
-  let expression_index = state.func_index + 1;
-  println!("{:?}", state.stack[expression_index]);
-  0
+/// A new temporary file in update mode, removed when closed.
+pub(crate) fn lib_tmpfile(state: &mut ExeState) -> Result<i32, LuaError> {
+  let nanos = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |d| d.subsec_nanos());
+  let path = std::env::temp_dir().join(format!("lua_{}_{nanos:09}", process::id()));
+  let result = OpenOptions::new()
+    .read(true)
+    .write(true)
+    .create_new(true)
+    .open(&path);
+  match result {
+    Ok(f) => {
+      // the open file stays usable without its name
+      let _ = fs::remove_file(&path);
+      let file = new_file(state, Stream::File(f));
+      state.push(file);
+      Ok(1)
+    }
+    Err(err) => Ok(file_error(state, &err, None)),
+  }
+}
+
+/// `io.type(obj)`
+///
+/// `"file"`, `"closed file"`, or `fail` if `obj` is not a file.
+pub(crate) fn lib_type(state: &mut ExeState) -> Result<i32, LuaError> {
+  match state.check_any(1)? {
+    Value::UserData(u) if u.data.borrow().is::<LuaFile>() => match file_mut(&u).is_closed() {
+      true => state.push("closed file"),
+      false => state.push("file"),
+    },
+    _ => state.push(Value::Nil),
+  }
+  Ok(1)
+}
+
+/// `io.write(...)`
+///
+/// Write the arguments to the default output file.
+pub(crate) fn lib_write(state: &mut ExeState) -> Result<i32, LuaError> {
+  let u = io_file(state, false)?;
+  write_args(state, u, 1)
+}
+
+/// `file:close()`
+fn file_close(state: &mut ExeState) -> Result<i32, LuaError> {
+  let u = to_file(state, 1)?;
+  close_file(state, &u)
+}
+
+/// `file:flush()`
+fn file_flush(state: &mut ExeState) -> Result<i32, LuaError> {
+  let u = to_file(state, 1)?;
+  let result = file_mut(&u).flush();
+  match result {
+    Ok(()) => {
+      state.push(Value::Boolean(true));
+      Ok(1)
+    }
+    Err(err) => Ok(file_error(state, &err, None)),
+  }
+}
+
+/// `file:lines(...)`
+fn file_lines(state: &mut ExeState) -> Result<i32, LuaError> {
+  to_file(state, 1)?;
+  push_lines(state, state.arg(1), 2, false)?;
+  Ok(1)
+}
+
+/// `file:read(...)`
+fn file_read(state: &mut ExeState) -> Result<i32, LuaError> {
+  let u = to_file(state, 1)?;
+  let formats: Vec<_> = (2..=state.get_top()).map(|i| state.arg(i)).collect();
+  read_formats(state, &u, &formats, 2)
+}
+
+/// `file:seek([whence [, offset]])`
+///
+/// Set the position to `offset` from the start (`"set"`), the current
+/// position (`"cur"`) or the end (`"end"`), and return the new position.
+fn file_seek(state: &mut ExeState) -> Result<i32, LuaError> {
+  let u = to_file(state, 1)?;
+  let whence = state.check_option(2, Some("cur"), &["set", "cur", "end"])?;
+  let offset = state.opt_integer(3, 0)?;
+  let pos = match whence {
+    // a negative offset fails as an invalid argument
+    0 => SeekFrom::Start(offset as u64),
+    1 => SeekFrom::Current(offset),
+    _ => SeekFrom::End(offset),
+  };
+  let result = file_mut(&u).seek(pos);
+  match result {
+    Ok(pos) => {
+      state.push(Value::Integer(pos as i64));
+      Ok(1)
+    }
+    Err(err) => Ok(file_error(state, &err, None)),
+  }
+}
+
+/// `file:setvbuf(mode [, size])`
+fn file_setvbuf(state: &mut ExeState) -> Result<i32, LuaError> {
+  let u = to_file(state, 1)?;
+  let buffering = match state.check_option(2, None, &["no", "full", "line"])? {
+    0 => Buffering::No,
+    1 => Buffering::Full,
+    _ => Buffering::Line,
+  };
+  let size = state.opt_integer(3, BUFFER_SIZE as i64)?;
+  let result = file_mut(&u).set_buffering(buffering, size.max(1) as usize);
+  match result {
+    Ok(()) => {
+      state.push(Value::Boolean(true));
+      Ok(1)
+    }
+    Err(err) => Ok(file_error(state, &err, None)),
+  }
+}
+
+/// `file:write(...)`
+fn file_write(state: &mut ExeState) -> Result<i32, LuaError> {
+  let u = to_file(state, 1)?;
+  write_args(state, u, 2)
+}
+
+/// `__gc` and `__close` of files: close the file if it's open.
+fn file_gc(state: &mut ExeState) -> Result<i32, LuaError> {
+  let u = state.check_userdata::<LuaFile>(1, FILE_HANDLE)?;
+  let standard = match &file_mut(&u).stream {
+    Some(stream) => stream.is_standard(),
+    None => true,
+  };
+  if !standard {
+    let _ = file_mut(&u).close();
+  }
+  Ok(0)
+}
+
+/// `__tostring` of files.
+fn file_tostring(state: &mut ExeState) -> Result<i32, LuaError> {
+  let u = state.check_userdata::<LuaFile>(1, FILE_HANDLE)?;
+  match file_mut(&u).is_closed() {
+    true => state.push("file (closed)"),
+    false => state.push(format!("file ({:p})", Rc::as_ptr(&u))),
+  }
+  Ok(1)
+}
+
+/// Create the metatable of files, and the standard files in table `io`,
+/// as `createmeta` and `createstdfile`.
+pub(crate) fn open_std_files(state: &mut ExeState) {
+  let methods: [(&str, RustFunction); 7] = [
+    ("close", file_close),
+    ("flush", file_flush),
+    ("lines", file_lines),
+    ("read", file_read),
+    ("seek", file_seek),
+    ("setvbuf", file_setvbuf),
+    ("write", file_write),
+  ];
+  let mut method_table = Table::new(0, methods.len());
+  for (name, f) in methods {
+    method_table.map.insert(name.into(), Value::Function(f));
+  }
+  let method_table = state.new_table(method_table);
+  let mut meta = Table::new(0, 5);
+  meta.map.insert("__index".into(), method_table);
+  meta.map.insert("__name".into(), FILE_HANDLE.into());
+  meta.map.insert("__gc".into(), Value::Function(file_gc));
+  meta.map.insert("__close".into(), Value::Function(file_gc));
+  meta
+    .map
+    .insert("__tostring".into(), Value::Function(file_tostring));
+  let meta = Rc::new(RefCell::new(meta));
+  state.heap.register_table(&meta);
+  // registered as `luaL_newmetatable` does
+  state
+    .registry
+    .borrow_mut()
+    .set(FILE_HANDLE.into(), Value::Table(meta.clone()));
+  state.io.file_meta = Some(meta);
+
+  let Value::Table(io) = state.globals.borrow().get(&"io".into()) else {
+    unreachable!()
+  };
+  let stdin = new_file(state, Stream::Stdin);
+  let stdout = new_file(state, Stream::Stdout);
+  let stderr = new_file(state, Stream::Stderr);
+  let mut io = io.borrow_mut();
+  io.map.insert("stdin".into(), stdin.clone());
+  io.map.insert("stdout".into(), stdout.clone());
+  io.map.insert("stderr".into(), stderr);
+  state.io.input = stdin;
+  state.io.output = stdout;
 }
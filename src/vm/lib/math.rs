@@ -0,0 +1,337 @@
+//! # Math Library
+//!
+//! Mathematical functions of Lua. Functions keep integers as integers when
+//! they can, as `floor` and `ceil`, and `random` is the xoshiro256**
+//! generator of Lua 5.4, so the sequence of a seed is the same everywhere.
+
+use super::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// ## Random
+///
+/// State of the xoshiro256** generator.
+pub(crate) struct Random {
+  state: [u64; 4],
+}
+
+impl Random {
+  /// A generator seeded by the current time and an address, as
+  /// `randseed`.
+  pub fn new() -> (Self, i64, i64) {
+    let time = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |d| d.as_nanos() as i64);
+    let local = 0u8;
+    let addr = &local as *const u8 as i64;
+    (Self::with_seed(time, addr), time, addr)
+  }
+
+  /// A generator seeded by `n1` and `n2`, as `setseed`.
+  pub fn with_seed(n1: i64, n2: i64) -> Self {
+    let mut random = Self {
+      state: [n1 as u64, 0xff, n2 as u64, 0],
+    };
+    // discard the initial values to "spread" the seed
+    for _ in 0..16 {
+      random.next();
+    }
+    random
+  }
+
+  /// Next random 64 bits, as `nextrand`.
+  fn next(&mut self) -> u64 {
+    let s = &mut self.state;
+    let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+    let t = s[1] << 17;
+    s[2] ^= s[0];
+    s[3] ^= s[1];
+    s[1] ^= s[2];
+    s[0] ^= s[3];
+    s[2] ^= t;
+    s[3] = s[3].rotate_left(45);
+    result
+  }
+
+  /// Project a random `ran` into `[0, n]`, drawing again as needed for a
+  /// uniform result, as `project`.
+  fn project(&mut self, mut ran: u64, n: u64) -> u64 {
+    // is `n + 1` a power of 2?
+    if n & n.wrapping_add(1) == 0 {
+      return ran & n;
+    }
+    // the smallest `2^b - 1` not smaller than `n`
+    let lim = u64::MAX >> n.leading_zeros();
+    loop {
+      ran &= lim;
+      if ran <= n {
+        return ran;
+      }
+      ran = self.next();
+    }
+  }
+}
+
+/// Push `f` as an integer if it fits, or else as a float, as `pushnumint`.
+fn push_float_integer(state: &mut ExeState, f: f64) {
+  match arith::float_to_integer(f) {
+    Some(i) => state.push(Value::Integer(i)),
+    None => state.push(Value::Float(f)),
+  }
+}
+
+/// Apply `f` to the float of the 1st argument.
+fn float_fn(state: &mut ExeState, f: fn(f64) -> f64) -> Result<i32, LuaError> {
+  let x = state.check_float(1)?;
+  state.push(Value::Float(f(x)));
+  Ok(1)
+}
+
+/// `math.abs(x)`
+pub(crate) fn lib_abs(state: &mut ExeState) -> Result<i32, LuaError> {
+  match state.check_number(1)? {
+    Value::Integer(i) => state.push(Value::Integer(i.wrapping_abs())),
+    n => state.push(Value::Float(arith::to_float(&n).unwrap().abs())),
+  }
+  Ok(1)
+}
+
+/// `math.ceil(x)`
+pub(crate) fn lib_ceil(state: &mut ExeState) -> Result<i32, LuaError> {
+  match state.check_number(1)? {
+    Value::Integer(i) => state.push(Value::Integer(i)),
+    n => push_float_integer(state, arith::to_float(&n).unwrap().ceil()),
+  }
+  Ok(1)
+}
+
+/// `math.floor(x)`
+pub(crate) fn lib_floor(state: &mut ExeState) -> Result<i32, LuaError> {
+  match state.check_number(1)? {
+    Value::Integer(i) => state.push(Value::Integer(i)),
+    n => push_float_integer(state, arith::to_float(&n).unwrap().floor()),
+  }
+  Ok(1)
+}
+
+/// `math.fmod(x, y)`
+///
+/// Remainder of `x / y` rounded towards zero, unlike `%`.
+pub(crate) fn lib_fmod(state: &mut ExeState) -> Result<i32, LuaError> {
+  match (state.check_number(1)?, state.check_number(2)?) {
+    (Value::Integer(m), Value::Integer(d)) => match d {
+      0 => return Err(state.arg_error(2, "zero")),
+      // avoid the overflow of `mininteger % -1`
+      -1 => state.push(Value::Integer(0)),
+      d => state.push(Value::Integer(m % d)),
+    },
+    (m, d) => {
+      let (m, d) = (arith::to_float(&m).unwrap(), arith::to_float(&d).unwrap());
+      state.push(Value::Float(m % d));
+    }
+  }
+  Ok(1)
+}
+
+/// `math.modf(x)`
+///
+/// Integral part and fractional part of `x`.
+pub(crate) fn lib_modf(state: &mut ExeState) -> Result<i32, LuaError> {
+  match state.check_number(1)? {
+    Value::Integer(i) => {
+      state.push(Value::Integer(i));
+      state.push(Value::Float(0.0));
+    }
+    n => {
+      let f = arith::to_float(&n).unwrap();
+      let ip = f.trunc();
+      state.push(Value::Float(ip));
+      // `inf - inf` would be NaN
+      state.push(Value::Float(if f == ip { 0.0 } else { f - ip }));
+    }
+  }
+  Ok(2)
+}
+
+/// `math.sqrt(x)`
+pub(crate) fn lib_sqrt(state: &mut ExeState) -> Result<i32, LuaError> {
+  float_fn(state, f64::sqrt)
+}
+
+/// `math.exp(x)`
+pub(crate) fn lib_exp(state: &mut ExeState) -> Result<i32, LuaError> {
+  float_fn(state, f64::exp)
+}
+
+/// `math.log(x [, base])`
+pub(crate) fn lib_log(state: &mut ExeState) -> Result<i32, LuaError> {
+  let x = state.check_float(1)?;
+  let result = match state.arg(2) {
+    Value::Nil => x.ln(),
+    _ => match state.check_float(2)? {
+      2.0 => x.log2(),
+      10.0 => x.log10(),
+      base => x.ln() / base.ln(),
+    },
+  };
+  state.push(Value::Float(result));
+  Ok(1)
+}
+
+/// `math.sin(x)`
+pub(crate) fn lib_sin(state: &mut ExeState) -> Result<i32, LuaError> {
+  float_fn(state, f64::sin)
+}
+
+/// `math.cos(x)`
+pub(crate) fn lib_cos(state: &mut ExeState) -> Result<i32, LuaError> {
+  float_fn(state, f64::cos)
+}
+
+/// `math.tan(x)`
+pub(crate) fn lib_tan(state: &mut ExeState) -> Result<i32, LuaError> {
+  float_fn(state, f64::tan)
+}
+
+/// `math.asin(x)`
+pub(crate) fn lib_asin(state: &mut ExeState) -> Result<i32, LuaError> {
+  float_fn(state, f64::asin)
+}
+
+/// `math.acos(x)`
+pub(crate) fn lib_acos(state: &mut ExeState) -> Result<i32, LuaError> {
+  float_fn(state, f64::acos)
+}
+
+/// `math.atan(y [, x])`
+pub(crate) fn lib_atan(state: &mut ExeState) -> Result<i32, LuaError> {
+  let y = state.check_float(1)?;
+  let x = match state.arg(2) {
+    Value::Nil => 1.0,
+    _ => state.check_float(2)?,
+  };
+  state.push(Value::Float(y.atan2(x)));
+  Ok(1)
+}
+
+/// `math.deg(x)`
+pub(crate) fn lib_deg(state: &mut ExeState) -> Result<i32, LuaError> {
+  float_fn(state, f64::to_degrees)
+}
+
+/// `math.rad(x)`
+pub(crate) fn lib_rad(state: &mut ExeState) -> Result<i32, LuaError> {
+  float_fn(state, f64::to_radians)
+}
+
+/// `math.min(x, ...)` and `math.max(x, ...)`, the first of the arguments
+/// for which `better(arg, current)` never holds.
+fn min_max(state: &mut ExeState, better: fn(&Value, &Value) -> bool) -> Result<i32, LuaError> {
+  let n = state.get_top();
+  if n < 1 {
+    return Err(state.arg_error(1, "number expected"));
+  }
+  let mut result = state.check_number(1)?;
+  for i in 2..=n {
+    let v = state.check_number(i)?;
+    if better(&v, &result) {
+      result = v;
+    }
+  }
+  state.push(result);
+  Ok(1)
+}
+
+/// `math.min(x, ...)`
+pub(crate) fn lib_min(state: &mut ExeState) -> Result<i32, LuaError> {
+  min_max(state, |v, min| arith::less_than(v, min).unwrap())
+}
+
+/// `math.max(x, ...)`
+pub(crate) fn lib_max(state: &mut ExeState) -> Result<i32, LuaError> {
+  min_max(state, |v, max| arith::less_than(max, v).unwrap())
+}
+
+/// `math.tointeger(x)`
+///
+/// `x` converted to an integer if it has an exact integer value, or else
+/// `fail`.
+pub(crate) fn lib_tointeger(state: &mut ExeState) -> Result<i32, LuaError> {
+  let v = state.check_any(1)?;
+  match arith::to_integer(&v) {
+    Some(i) => state.push(Value::Integer(i)),
+    None => state.push(Value::Nil),
+  }
+  Ok(1)
+}
+
+/// `math.type(x)`
+///
+/// `"integer"`, `"float"`, or `fail` if `x` is not a number.
+pub(crate) fn lib_type(state: &mut ExeState) -> Result<i32, LuaError> {
+  match state.check_any(1)? {
+    Value::Integer(_) => state.push("integer"),
+    Value::Float(_) => state.push("float"),
+    _ => state.push(Value::Nil),
+  }
+  Ok(1)
+}
+
+/// `math.ult(m, n)`
+///
+/// Whether `m < n` when compared as unsigned integers.
+pub(crate) fn lib_ult(state: &mut ExeState) -> Result<i32, LuaError> {
+  let m = state.check_integer(1)?;
+  let n = state.check_integer(2)?;
+  state.push(Value::Boolean((m as u64) < (n as u64)));
+  Ok(1)
+}
+
+/// `math.random([m [, n]])`
+///
+/// A float in `[0, 1)` without arguments, an integer in `[1, m]` or
+/// `[m, n]`, or any integer for `math.random(0)`.
+pub(crate) fn lib_random(state: &mut ExeState) -> Result<i32, LuaError> {
+  let rv = state.random.next();
+  let (low, up) = match state.get_top() {
+    0 => {
+      // the 53 higher bits as the fraction of a float
+      state.push(Value::Float((rv >> 11) as f64 * 0.5f64.powi(53)));
+      return Ok(1);
+    }
+    1 => {
+      let up = state.check_integer(1)?;
+      if up == 0 {
+        state.push(Value::Integer(rv as i64));
+        return Ok(1);
+      }
+      (1, up)
+    }
+    2 => (state.check_integer(1)?, state.check_integer(2)?),
+    _ => return Err(state.error("wrong number of arguments")),
+  };
+  if low > up {
+    return Err(state.arg_error(1, "interval is empty"));
+  }
+  let n = state.random.project(rv, up.wrapping_sub(low) as u64);
+  state.push(Value::Integer((n as i64).wrapping_add(low)));
+  Ok(1)
+}
+
+/// `math.randomseed([x [, y]])`
+///
+/// Seed the generator, randomly without arguments, and return the two seed
+/// components.
+pub(crate) fn lib_randomseed(state: &mut ExeState) -> Result<i32, LuaError> {
+  let (random, n1, n2) = match state.get_top() {
+    0 => Random::new(),
+    _ => {
+      let n1 = state.check_integer(1)?;
+      let n2 = state.opt_integer(2, 0)?;
+      (Random::with_seed(n1, n2), n1, n2)
+    }
+  };
+  state.random = random;
+  state.push(Value::Integer(n1));
+  state.push(Value::Integer(n2));
+  Ok(2)
+}
@@ -3,5 +3,261 @@
 //! Runtime library for rua VM.
 
 use super::*;
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
 
+pub mod base;
+pub mod debug;
+mod format;
 pub mod io;
+pub mod math;
+pub mod os;
+mod pack;
+pub mod package;
+mod pattern;
+pub mod string;
+pub mod table;
+pub mod utf8;
+
+/// Message of an OS error without the ` (os error N)` suffix, as
+/// `strerror` gives.
+pub(crate) fn os_error_msg(err: &std::io::Error) -> String {
+  let msg = err.to_string();
+  match msg.find(" (os error ") {
+    Some(i) => msg[..i].to_owned(),
+    None => msg,
+  }
+}
+
+/// Push the results of a failed file operation, `nil`, the message (prefixed
+/// by `filename` if any) and the error number, as `luaL_fileresult`.
+pub(crate) fn file_error(
+  state: &mut ExeState,
+  err: &std::io::Error,
+  filename: Option<&str>,
+) -> i32 {
+  let msg = match filename {
+    Some(filename) => format!("{filename}: {}", os_error_msg(err)),
+    None => os_error_msg(err),
+  };
+  state.push(Value::Nil);
+  state.push(msg);
+  state.push(Value::Integer(err.raw_os_error().unwrap_or(0) as i64));
+  3
+}
+
+/// Push the results of running a command: `true` (or `nil` if it failed),
+/// `"exit"` and the exit status, or `"signal"` and the signal that killed
+/// it, as `luaL_execresult`.
+pub(crate) fn exec_result(state: &mut ExeState, status: std::io::Result<ExitStatus>) -> i32 {
+  let status = match status {
+    Ok(status) => status,
+    Err(err) => return file_error(state, &err, None),
+  };
+  let (what, code) = match status.code() {
+    Some(code) => ("exit", code),
+    #[cfg(unix)]
+    None => ("signal", status.signal().unwrap_or(0)),
+    #[cfg(not(unix))]
+    None => ("signal", 0),
+  };
+  match what == "exit" && code == 0 {
+    true => state.push(Value::Boolean(true)),
+    false => state.push(Value::Nil),
+  }
+  state.push(what);
+  state.push(Value::Integer(code as i64));
+  3
+}
+
+/// Register the standard libraries into `state`.
+pub(crate) fn open_libs(state: &mut ExeState) {
+  let globals = state.globals();
+  state.globals.borrow_mut().set("_G".into(), globals);
+  state
+    .globals
+    .borrow_mut()
+    .set("_VERSION".into(), "Lua 5.4".into());
+  state.register("print", io::lib_print);
+  state.register("error", base::lib_error);
+  state.register("pcall", base::lib_pcall);
+  state.register("xpcall", base::lib_xpcall);
+  state.register("setmetatable", base::lib_setmetatable);
+  state.register("getmetatable", base::lib_getmetatable);
+  state.register("type", base::lib_type);
+  state.register("tostring", base::lib_tostring);
+  state.register("tonumber", base::lib_tonumber);
+  state.register("assert", base::lib_assert);
+  state.register("select", base::lib_select);
+  state.register("rawget", base::lib_rawget);
+  state.register("rawset", base::lib_rawset);
+  state.register("rawequal", base::lib_rawequal);
+  state.register("rawlen", base::lib_rawlen);
+  state.register("warn", base::lib_warn);
+  state.register("next", base::lib_next);
+  state.register("pairs", base::lib_pairs);
+  state.register("ipairs", base::lib_ipairs);
+  state.register("load", base::lib_load);
+  state.register("loadfile", base::lib_loadfile);
+  state.register("dofile", base::lib_dofile);
+  state.register("collectgarbage", base::lib_collectgarbage);
+  state.register("require", package::lib_require);
+  state.register_lib("package", &[("searchpath", package::lib_searchpath)]);
+  state.register_lib(
+    "table",
+    &[
+      ("insert", table::lib_insert),
+      ("remove", table::lib_remove),
+      ("move", table::lib_move),
+      ("concat", table::lib_concat),
+      ("pack", table::lib_pack),
+      ("unpack", table::lib_unpack),
+      ("sort", table::lib_sort),
+    ],
+  );
+  state.register_lib(
+    "string",
+    &[
+      ("len", string::lib_len),
+      ("sub", string::lib_sub),
+      ("upper", string::lib_upper),
+      ("lower", string::lib_lower),
+      ("rep", string::lib_rep),
+      ("reverse", string::lib_reverse),
+      ("byte", string::lib_byte),
+      ("char", string::lib_char),
+      ("dump", string::lib_dump),
+      ("find", string::lib_find),
+      ("match", string::lib_match),
+      ("gmatch", string::lib_gmatch),
+      ("gsub", string::lib_gsub),
+      ("format", string::lib_format),
+      ("pack", string::lib_pack),
+      ("packsize", string::lib_packsize),
+      ("unpack", string::lib_unpack),
+    ],
+  );
+  // strings share the metatable `{ __index = string }`
+  let mut string_meta = Table::new(0, 1);
+  let string = state.globals.borrow().get(&"string".into());
+  string_meta.map.insert("__index".into(), string);
+  let string_meta = Rc::new(RefCell::new(string_meta));
+  state.heap.register_table(&string_meta);
+  state.string_meta = Some(string_meta);
+  state.register_lib(
+    "io",
+    &[
+      ("close", io::lib_close),
+      ("flush", io::lib_flush),
+      ("input", io::lib_input),
+      ("lines", io::lib_lines),
+      ("open", io::lib_open),
+      ("output", io::lib_output),
+      ("popen", io::lib_popen),
+      ("read", io::lib_read),
+      ("tmpfile", io::lib_tmpfile),
+      ("type", io::lib_type),
+      ("write", io::lib_write),
+    ],
+  );
+  io::open_std_files(state);
+  state.register_lib(
+    "math",
+    &[
+      ("abs", math::lib_abs),
+      ("ceil", math::lib_ceil),
+      ("floor", math::lib_floor),
+      ("fmod", math::lib_fmod),
+      ("modf", math::lib_modf),
+      ("sqrt", math::lib_sqrt),
+      ("exp", math::lib_exp),
+      ("log", math::lib_log),
+      ("sin", math::lib_sin),
+      ("cos", math::lib_cos),
+      ("tan", math::lib_tan),
+      ("asin", math::lib_asin),
+      ("acos", math::lib_acos),
+      ("atan", math::lib_atan),
+      ("deg", math::lib_deg),
+      ("rad", math::lib_rad),
+      ("min", math::lib_min),
+      ("max", math::lib_max),
+      ("tointeger", math::lib_tointeger),
+      ("type", math::lib_type),
+      ("ult", math::lib_ult),
+      ("random", math::lib_random),
+      ("randomseed", math::lib_randomseed),
+    ],
+  );
+  let math = state.globals.borrow().get(&"math".into());
+  if let Value::Table(math) = math {
+    let mut math = math.borrow_mut();
+    math
+      .map
+      .insert("pi".into(), Value::Float(std::f64::consts::PI));
+    math.map.insert("huge".into(), Value::Float(f64::INFINITY));
+    math
+      .map
+      .insert("maxinteger".into(), Value::Integer(i64::MAX));
+    math
+      .map
+      .insert("mininteger".into(), Value::Integer(i64::MIN));
+  }
+  state.register_lib(
+    "os",
+    &[
+      ("clock", os::lib_clock),
+      ("date", os::lib_date),
+      ("difftime", os::lib_difftime),
+      ("execute", os::lib_execute),
+      ("exit", os::lib_exit),
+      ("getenv", os::lib_getenv),
+      ("remove", os::lib_remove),
+      ("rename", os::lib_rename),
+      ("setlocale", os::lib_setlocale),
+      ("time", os::lib_time),
+      ("tmpname", os::lib_tmpname),
+    ],
+  );
+  state.register_lib(
+    "utf8",
+    &[
+      ("char", utf8::lib_char),
+      ("codepoint", utf8::lib_codepoint),
+      ("codes", utf8::lib_codes),
+      ("len", utf8::lib_len),
+      ("offset", utf8::lib_offset),
+    ],
+  );
+  let utf8 = state.globals.borrow().get(&"utf8".into());
+  if let Value::Table(utf8) = utf8 {
+    utf8
+      .borrow_mut()
+      .map
+      .insert("charpattern".into(), utf8::CHAR_PATTERN.to_vec().into());
+  }
+  state.register_lib(
+    "debug",
+    &[
+      ("debug", debug::lib_debug),
+      ("gethook", debug::lib_gethook),
+      ("getinfo", debug::lib_getinfo),
+      ("getlocal", debug::lib_getlocal),
+      ("getmetatable", debug::lib_getmetatable),
+      ("getregistry", debug::lib_getregistry),
+      ("getupvalue", debug::lib_getupvalue),
+      ("getuservalue", debug::lib_getuservalue),
+      ("setcstacklimit", debug::lib_setcstacklimit),
+      ("sethook", debug::lib_sethook),
+      ("setlocal", debug::lib_setlocal),
+      ("setmetatable", debug::lib_setmetatable),
+      ("setupvalue", debug::lib_setupvalue),
+      ("setuservalue", debug::lib_setuservalue),
+      ("traceback", debug::lib_traceback),
+      ("upvalueid", debug::lib_upvalueid),
+      ("upvaluejoin", debug::lib_upvaluejoin),
+    ],
+  );
+  package::open_package(state);
+}
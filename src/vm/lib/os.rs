@@ -0,0 +1,333 @@
+//! # OS Library
+//!
+//! Operating system facilities of Lua, on the C library as `loslib.c`:
+//! dates and times, environment variables, files and commands.
+
+use super::*;
+use std::{
+  ffi::{CStr, CString},
+  fs,
+  io::Write,
+  mem,
+  os::unix::ffi::OsStrExt,
+  process::{self, Command},
+};
+
+/// Conversions allowed by `os.date`, as `LUA_STRFTIMEOPTIONS` of C99: the
+/// ones of 1 byte, and after `||` the ones of 2 bytes.
+const STRFTIME_OPTIONS: &[u8] =
+  b"aAbBcCdDeFgGhHIjmMnprRStTuUVwWxXyYzZ%||EcECExEXEyEYOdOeOHOIOmOMOSOuOUOVOwOWOy";
+/// Size of the result of a conversion of `os.date`, as `SIZETIMEFMT`.
+const SIZE_TIME_FMT: usize = 250;
+
+/// Length of the valid conversion at the start of `conv`, as
+/// `checkoption`.
+fn check_time_option(conv: &[u8]) -> Option<usize> {
+  let (mut i, mut len) = (0, 1);
+  while i < STRFTIME_OPTIONS.len() && len <= conv.len() {
+    if STRFTIME_OPTIONS[i] == b'|' {
+      // options of the next length
+      len += 1;
+    } else if conv[..len] == STRFTIME_OPTIONS[i..i + len] {
+      return Some(len);
+    }
+    i += len;
+  }
+  None
+}
+
+/// Check the `i`-th argument is a time, as `l_checktime`.
+fn check_time(state: &mut ExeState, i: usize) -> Result<libc::time_t, LuaError> {
+  let t = state.check_integer(i)?;
+  libc::time_t::try_from(t).map_err(|_| state.arg_error(i, "time out-of-bounds"))
+}
+
+/// Set the fields of date table `t` from `tm`, as `setallfields`.
+fn set_all_fields(state: &mut ExeState, t: &Value, tm: &libc::tm) -> Result<(), LuaError> {
+  let fields = [
+    ("year", tm.tm_year as i64 + 1900),
+    ("month", tm.tm_mon as i64 + 1),
+    ("day", tm.tm_mday as i64),
+    ("hour", tm.tm_hour as i64),
+    ("min", tm.tm_min as i64),
+    ("sec", tm.tm_sec as i64),
+    ("yday", tm.tm_yday as i64 + 1),
+    ("wday", tm.tm_wday as i64 + 1),
+  ];
+  for (key, value) in fields {
+    state.set_table(t, key.into(), Value::Integer(value))?;
+  }
+  // undefined if negative
+  if tm.tm_isdst >= 0 {
+    state.set_table(t, "isdst".into(), Value::Boolean(tm.tm_isdst != 0))?;
+  }
+  Ok(())
+}
+
+/// Get field `key` of date table `t`, minus `delta`, `default` if absent
+/// (and required if `None`), as `getfield`.
+fn get_field(
+  state: &mut ExeState,
+  t: &Value,
+  key: &str,
+  default: Option<i32>,
+  delta: i64,
+) -> Result<i32, LuaError> {
+  let value = state.get_table(t, &key.into())?;
+  match arith::to_integer(&value) {
+    Some(n) => {
+      let in_bounds = match n >= 0 {
+        true => n - delta <= i32::MAX as i64,
+        false => i32::MIN as i64 + delta <= n,
+      };
+      match in_bounds {
+        true => Ok((n - delta) as i32),
+        false => Err(state.error(format!("field '{key}' is out-of-bound"))),
+      }
+    }
+    None if !value.is_nil() => Err(state.error(format!("field '{key}' is not an integer"))),
+    None => match default {
+      Some(default) => Ok(default),
+      None => Err(state.error(format!("field '{key}' missing in date table"))),
+    },
+  }
+}
+
+/// `os.clock()`
+///
+/// CPU time used by the program, in seconds.
+pub(crate) fn lib_clock(state: &mut ExeState) -> Result<i32, LuaError> {
+  let mut ts: libc::timespec = unsafe { mem::zeroed() };
+  unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut ts) };
+  state.push(Value::Float(ts.tv_sec as f64 + ts.tv_nsec as f64 / 1e9));
+  Ok(1)
+}
+
+/// `os.date([format [, time]])`
+///
+/// Format `time` (the current time by default) by the `strftime`
+/// conversions of `format`, in UTC if it starts with `!`. Format `"*t"`
+/// gives a table of the fields of the date instead.
+pub(crate) fn lib_date(state: &mut ExeState) -> Result<i32, LuaError> {
+  let format = state.opt_string(1, "%c")?;
+  let t = match state.arg(2) {
+    Value::Nil => unsafe { libc::time(std::ptr::null_mut()) },
+    _ => check_time(state, 2)?,
+  };
+  let mut tm: libc::tm = unsafe { mem::zeroed() };
+  let (utc, format) = match format.strip_prefix(b"!") {
+    Some(format) => (true, format),
+    None => (false, &format[..]),
+  };
+  let result = match utc {
+    true => unsafe { libc::gmtime_r(&t, &mut tm) },
+    false => unsafe { libc::localtime_r(&t, &mut tm) },
+  };
+  if result.is_null() {
+    return Err(state.error("date result cannot be represented in this installation"));
+  }
+  if format == b"*t" {
+    let table = state.new_table(Table::new(0, 9));
+    set_all_fields(state, &table, &tm)?;
+    state.push(table);
+    return Ok(1);
+  }
+  let mut s = Vec::new();
+  let mut i = 0;
+  while i < format.len() {
+    if format[i] != b'%' {
+      s.push(format[i]);
+      i += 1;
+      continue;
+    }
+    let conv = &format[i + 1..];
+    let Some(len) = check_time_option(conv) else {
+      let conv = String::from_utf8_lossy(conv);
+      return Err(state.arg_error(1, &format!("invalid conversion specifier '%{conv}'")));
+    };
+    let spec = CString::new([b"%", &conv[..len]].concat()).unwrap();
+    let mut buf = [0u8; SIZE_TIME_FMT];
+    let n = unsafe {
+      libc::strftime(
+        buf.as_mut_ptr() as *mut libc::c_char,
+        SIZE_TIME_FMT,
+        spec.as_ptr(),
+        &tm,
+      )
+    };
+    s.extend_from_slice(&buf[..n]);
+    i += 1 + len;
+  }
+  state.push(s);
+  Ok(1)
+}
+
+/// `os.difftime(t2, t1)`
+///
+/// Difference `t2 - t1` in seconds.
+pub(crate) fn lib_difftime(state: &mut ExeState) -> Result<i32, LuaError> {
+  let t2 = check_time(state, 1)?;
+  let t1 = check_time(state, 2)?;
+  state.push(Value::Float((t2 - t1) as f64));
+  Ok(1)
+}
+
+/// `os.execute([command])`
+///
+/// Run `command` by the shell, and return how it ended, as `io.popen`'s
+/// `close`. Without `command`, whether a shell is available.
+pub(crate) fn lib_execute(state: &mut ExeState) -> Result<i32, LuaError> {
+  if state.arg(1).is_nil() {
+    state.push(Value::Boolean(true));
+    return Ok(1);
+  }
+  let command = String::from_utf8_lossy(&state.check_string(1)?).into_owned();
+  let status = Command::new("/bin/sh").arg("-c").arg(command).status();
+  Ok(exec_result(state, status))
+}
+
+/// `os.exit([code [, close]])`
+///
+/// Exit the program with `code`: `true` for success, `false` for failure,
+/// or the exit status. The state is closed first if `close`.
+pub(crate) fn lib_exit(state: &mut ExeState) -> Result<i32, LuaError> {
+  let code = match state.arg(1) {
+    Value::Boolean(success) => i32::from(!success),
+    _ => state.opt_integer(1, 0)? as i32,
+  };
+  if !state.arg(2).is_falsy() {
+    state.close();
+  }
+  // C flushes all streams at exit
+  io::flush_files(state);
+  let _ = std::io::stdout().flush();
+  process::exit(code)
+}
+
+/// `os.getenv(varname)`
+///
+/// Value of environment variable `varname`, or `fail` if it's not defined.
+pub(crate) fn lib_getenv(state: &mut ExeState) -> Result<i32, LuaError> {
+  let name = state.check_string(1)?;
+  // names which can not be defined
+  if name.is_empty() || name.contains(&b'=') || name.contains(&0) {
+    state.push(Value::Nil);
+    return Ok(1);
+  }
+  match std::env::var_os(std::ffi::OsStr::from_bytes(&name)) {
+    Some(value) => state.push(value.as_bytes()),
+    None => state.push(Value::Nil),
+  }
+  Ok(1)
+}
+
+/// `os.remove(filename)`
+///
+/// Remove the file, or the empty directory, `filename`.
+pub(crate) fn lib_remove(state: &mut ExeState) -> Result<i32, LuaError> {
+  let filename = String::from_utf8_lossy(&state.check_string(1)?).into_owned();
+  let result = match fs::symlink_metadata(&filename) {
+    Ok(metadata) if metadata.is_dir() => fs::remove_dir(&filename),
+    _ => fs::remove_file(&filename),
+  };
+  match result {
+    Ok(()) => {
+      state.push(Value::Boolean(true));
+      Ok(1)
+    }
+    Err(err) => Ok(file_error(state, &err, Some(&filename))),
+  }
+}
+
+/// `os.rename(oldname, newname)`
+pub(crate) fn lib_rename(state: &mut ExeState) -> Result<i32, LuaError> {
+  let from = String::from_utf8_lossy(&state.check_string(1)?).into_owned();
+  let to = String::from_utf8_lossy(&state.check_string(2)?).into_owned();
+  match fs::rename(&from, to) {
+    Ok(()) => {
+      state.push(Value::Boolean(true));
+      Ok(1)
+    }
+    Err(err) => Ok(file_error(state, &err, Some(&from))),
+  }
+}
+
+/// `os.setlocale([locale [, category]])`
+///
+/// Set the locale of `category` (`"all"` by default), return the name of
+/// the new locale, or `fail` if it can not be set. Without `locale`, query
+/// the current one.
+pub(crate) fn lib_setlocale(state: &mut ExeState) -> Result<i32, LuaError> {
+  const CATEGORIES: [libc::c_int; 6] = [
+    libc::LC_ALL,
+    libc::LC_COLLATE,
+    libc::LC_CTYPE,
+    libc::LC_MONETARY,
+    libc::LC_NUMERIC,
+    libc::LC_TIME,
+  ];
+  let locale = match state.arg(1) {
+    Value::Nil => None,
+    _ => Some(state.check_string(1)?),
+  };
+  let category = state.check_option(
+    2,
+    Some("all"),
+    &["all", "collate", "ctype", "monetary", "numeric", "time"],
+  )?;
+  let locale = locale.map(|l| CString::new(l).unwrap_or_default());
+  let locale_ptr = locale.as_ref().map_or(std::ptr::null(), |l| l.as_ptr());
+  let result = unsafe { libc::setlocale(CATEGORIES[category], locale_ptr) };
+  match result.is_null() {
+    true => state.push(Value::Nil),
+    false => state.push(unsafe { CStr::from_ptr(result) }.to_bytes()),
+  }
+  Ok(1)
+}
+
+/// `os.time([t])`
+///
+/// The current time, or the time of date table `t`, whose fields are
+/// normalized, as `mktime` does: `{month = 14}` becomes February of the
+/// next year.
+pub(crate) fn lib_time(state: &mut ExeState) -> Result<i32, LuaError> {
+  let t = match state.arg(1) {
+    Value::Nil => unsafe { libc::time(std::ptr::null_mut()) },
+    table => {
+      state.check_table(1)?;
+      let mut tm: libc::tm = unsafe { mem::zeroed() };
+      tm.tm_year = get_field(state, &table, "year", None, 1900)?;
+      tm.tm_mon = get_field(state, &table, "month", None, 1)?;
+      tm.tm_mday = get_field(state, &table, "day", None, 0)?;
+      tm.tm_hour = get_field(state, &table, "hour", Some(12), 0)?;
+      tm.tm_min = get_field(state, &table, "min", Some(0), 0)?;
+      tm.tm_sec = get_field(state, &table, "sec", Some(0), 0)?;
+      tm.tm_isdst = match state.get_table(&table, &"isdst".into())? {
+        Value::Nil => -1,
+        isdst => i32::from(!isdst.is_falsy()),
+      };
+      let t = unsafe { libc::mktime(&mut tm) };
+      set_all_fields(state, &table, &tm)?;
+      t
+    }
+  };
+  if t == -1 {
+    return Err(state.error("time result cannot be represented in this installation"));
+  }
+  state.push(Value::Integer(t as i64));
+  Ok(1)
+}
+
+/// `os.tmpname()`
+///
+/// Name of a new file which can be used as a temporary file.
+pub(crate) fn lib_tmpname(state: &mut ExeState) -> Result<i32, LuaError> {
+  let mut template = *b"/tmp/lua_XXXXXX\0";
+  let fd = unsafe { libc::mkstemp(template.as_mut_ptr() as *mut libc::c_char) };
+  if fd == -1 {
+    return Err(state.error("unable to generate a unique filename"));
+  }
+  unsafe { libc::close(fd) };
+  state.push(&template[..template.len() - 1]);
+  Ok(1)
+}
@@ -0,0 +1,232 @@
+//! # Pack
+//!
+//! The format language of `string.pack` and `string.unpack`, ported from
+//! `lstrlib.c`: the options with their sizes and alignments, and the
+//! integers of any size from 1 to 16 bytes.
+
+use super::*;
+
+/// Max size of integers, as `MAXINTSIZE`.
+const MAX_INT_SIZE: usize = 16;
+/// Size of `lua_Integer`, as `SZINT`.
+const SIZE_INT: usize = 8;
+/// Default max alignment for `!`, as the alignment of `double`.
+const MAX_ALIGN: usize = 8;
+/// Max size of the counts in formats, as `MAXSIZE`.
+pub(super) const MAX_SIZE: usize = i32::MAX as usize;
+
+/// ## KOption
+///
+/// Kinds of the options of formats.
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum KOption {
+  /// signed integers
+  Int,
+  /// unsigned integers
+  Uint,
+  /// `float`
+  Float,
+  /// Lua float
+  Number,
+  /// `double`
+  Double,
+  /// fixed-size strings
+  Char,
+  /// strings with the length before
+  String,
+  /// zero-terminated strings
+  Zstr,
+  /// padding
+  Padding,
+  /// padding for alignment
+  PaddAlign,
+  /// no-op, as the endianness and alignment settings
+  Nop,
+}
+
+/// ## Header
+///
+/// The format being read, with the endianness and max alignment set by
+/// its options.
+pub(super) struct Header<'a> {
+  fmt: &'a [u8],
+  /// Position of the next option in `fmt`
+  pos: usize,
+  pub little: bool,
+  max_align: usize,
+}
+
+impl<'a> Header<'a> {
+  pub fn new(fmt: &'a [u8]) -> Self {
+    Self {
+      fmt,
+      pos: 0,
+      little: cfg!(target_endian = "little"),
+      max_align: 1,
+    }
+  }
+
+  pub fn is_end(&self) -> bool {
+    self.pos >= self.fmt.len()
+  }
+
+  /// Read an optional count, as `getnum`.
+  fn get_num(&mut self, default: usize) -> usize {
+    if !self.fmt.get(self.pos).is_some_and(u8::is_ascii_digit) {
+      return default;
+    }
+    let mut a = 0;
+    while let Some(c) = self.fmt.get(self.pos).filter(|c| c.is_ascii_digit()) {
+      a = a * 10 + (c - b'0') as usize;
+      self.pos += 1;
+      if a > (MAX_SIZE - 9) / 10 {
+        break;
+      }
+    }
+    a
+  }
+
+  /// Read an optional size of integers, as `getnumlimit`.
+  fn get_num_limit(&mut self, state: &mut ExeState, default: usize) -> Result<usize, LuaError> {
+    match self.get_num(default) {
+      size if size > MAX_INT_SIZE || size == 0 => Err(state.error(format!(
+        "integral size ({size}) out of limits [1,{MAX_INT_SIZE}]"
+      ))),
+      size => Ok(size),
+    }
+  }
+
+  /// Read an option and its size, as `getoption`.
+  fn get_option(&mut self, state: &mut ExeState) -> Result<(KOption, usize), LuaError> {
+    let opt = self.fmt[self.pos];
+    self.pos += 1;
+    let option = match opt {
+      b'b' => (KOption::Int, 1),
+      b'B' => (KOption::Uint, 1),
+      b'h' => (KOption::Int, 2),
+      b'H' => (KOption::Uint, 2),
+      b'l' | b'j' => (KOption::Int, 8),
+      b'L' | b'J' | b'T' => (KOption::Uint, 8),
+      b'f' => (KOption::Float, 4),
+      b'n' => (KOption::Number, 8),
+      b'd' => (KOption::Double, 8),
+      b'i' => (KOption::Int, self.get_num_limit(state, 4)?),
+      b'I' => (KOption::Uint, self.get_num_limit(state, 4)?),
+      b's' => (KOption::String, self.get_num_limit(state, 8)?),
+      b'c' => match self.get_num(usize::MAX) {
+        usize::MAX => return Err(state.error("missing size for format option 'c'")),
+        size => (KOption::Char, size),
+      },
+      b'z' => (KOption::Zstr, 0),
+      b'x' => (KOption::Padding, 1),
+      b'X' => (KOption::PaddAlign, 0),
+      b' ' => (KOption::Nop, 0),
+      b'<' => {
+        self.little = true;
+        (KOption::Nop, 0)
+      }
+      b'>' => {
+        self.little = false;
+        (KOption::Nop, 0)
+      }
+      b'=' => {
+        self.little = cfg!(target_endian = "little");
+        (KOption::Nop, 0)
+      }
+      b'!' => {
+        self.max_align = self.get_num_limit(state, MAX_ALIGN)?;
+        (KOption::Nop, 0)
+      }
+      c => {
+        return Err(state.error(format!("invalid format option '{}'", c as char)));
+      }
+    };
+    Ok(option)
+  }
+
+  /// Read the next option, return its kind, size and the padding to align
+  /// it after `total` bytes, as `getdetails`.
+  pub fn next_option(
+    &mut self,
+    state: &mut ExeState,
+    total: usize,
+  ) -> Result<(KOption, usize, usize), LuaError> {
+    let (opt, size) = self.get_option(state)?;
+    // usually, alignment follows size
+    let mut align = size;
+    if opt == KOption::PaddAlign {
+      // `X` gets alignment from the following option
+      let next = match self.is_end() {
+        true => None,
+        false => Some(self.get_option(state)?),
+      };
+      match next {
+        Some((next, next_align)) if next != KOption::Char && next_align != 0 => {
+          align = next_align;
+        }
+        _ => return Err(state.arg_error(1, "invalid next option for option 'X'")),
+      }
+    }
+    if align <= 1 || opt == KOption::Char {
+      return Ok((opt, size, 0));
+    }
+    let align = align.min(self.max_align);
+    if !align.is_power_of_two() {
+      return Err(state.arg_error(1, "format asks for alignment not power of 2"));
+    }
+    let to_align = (align - (total & (align - 1))) & (align - 1);
+    Ok((opt, size, to_align))
+  }
+}
+
+/// Append integer `n` of `size` bytes, sign extended if `negative`, as
+/// `packint`.
+pub(super) fn pack_int(b: &mut Vec<u8>, n: u64, little: bool, size: usize, negative: bool) {
+  let mut bytes = [0u8; MAX_INT_SIZE];
+  bytes[..SIZE_INT].copy_from_slice(&n.to_le_bytes());
+  if negative {
+    bytes[SIZE_INT..].fill(0xff);
+  }
+  let bytes = &mut bytes[..size];
+  if !little {
+    bytes.reverse();
+  }
+  b.extend_from_slice(bytes);
+}
+
+/// Read integer of `bytes`, sign extended if `signed`, as `unpackint`.
+pub(super) fn unpack_int(
+  state: &mut ExeState,
+  bytes: &[u8],
+  little: bool,
+  signed: bool,
+) -> Result<i64, LuaError> {
+  let size = bytes.len();
+  // least significant byte first
+  let byte = |i: usize| match little {
+    true => bytes[i],
+    false => bytes[size - 1 - i],
+  };
+  let limit = size.min(SIZE_INT);
+  let mut res = 0u64;
+  for i in (0..limit).rev() {
+    res = (res << 8) | byte(i) as u64;
+  }
+  if size < SIZE_INT {
+    if signed {
+      // sign extension
+      let mask = 1u64 << (size * 8 - 1);
+      res = (res ^ mask).wrapping_sub(mask);
+    }
+  } else if size > SIZE_INT {
+    // the bytes not read must be the sign extension
+    let mask = match !signed || (res as i64) >= 0 {
+      true => 0,
+      false => 0xff,
+    };
+    if (limit..size).any(|i| byte(i) != mask) {
+      return Err(state.error(format!("{size}-byte integer does not fit into Lua Integer")));
+    }
+  }
+  Ok(res as i64)
+}
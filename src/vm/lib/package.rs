@@ -0,0 +1,433 @@
+//! # Package Library
+//!
+//! Modules of Lua, as `loadlib.c`: `require` finds a loader of a module by
+//! the searchers in `package.searchers`, runs it once and caches the
+//! module in `package.loaded`. rua can't load C libraries, so there are no
+//! `cpath` and C searchers; instead, the host registers native modules,
+//! built by [`ModuleBuilder`], which the native searcher finds.
+
+use super::*;
+use std::{collections::HashMap, env, fs::File};
+
+/// Default of `package.path`, as `LUA_PATH_DEFAULT`.
+const PATH_DEFAULT: &str = "/usr/local/share/lua/5.4/?.lua;/usr/local/share/lua/5.4/?/init.lua;\
+  /usr/local/lib/lua/5.4/?.lua;/usr/local/lib/lua/5.4/?/init.lua;./?.lua;./?/init.lua";
+/// Environment variable of `package.path`, tried after the one suffixed by
+/// the version.
+const PATH_VAR: &str = "LUA_PATH";
+/// Suffix of the environment variables of the version, as `LUA_VERSUFFIX`.
+const VERSION_SUFFIX: &str = "_5_4";
+/// Separator of templates in a path, as `LUA_PATH_SEP`.
+const PATH_SEP: &str = ";";
+/// Mark replaced by the module name in a template, as `LUA_PATH_MARK`.
+const PATH_MARK: &str = "?";
+/// Separator of directories, as `LUA_DIRSEP`.
+const DIR_SEP: &str = "/";
+/// Names of the standard libraries, loaded from the start.
+const STD_LIBS: [&str; 9] = [
+  "_G", "package", "table", "io", "os", "string", "math", "utf8", "debug",
+];
+
+/// ## ModuleBuilder
+///
+/// Native module of the host, a table of functions and constants built
+/// when it's required first, registered by
+/// [`ExeState::register_module`].
+#[derive(Clone, Default)]
+pub struct ModuleBuilder {
+  functions: Vec<(String, RustFunction)>,
+  constants: Vec<(String, Value)>,
+}
+
+impl ModuleBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Add function `f` as field `name`.
+  pub fn function(mut self, name: &str, f: RustFunction) -> Self {
+    self.functions.push((name.to_owned(), f));
+    self
+  }
+
+  /// Add functions of name/function pairs.
+  pub fn functions(mut self, functions: &[(&str, RustFunction)]) -> Self {
+    for (name, f) in functions {
+      self.functions.push(((*name).to_owned(), *f));
+    }
+    self
+  }
+
+  /// Add `value` as field `name`.
+  pub fn constant(mut self, name: &str, value: impl Into<Value>) -> Self {
+    self.constants.push((name.to_owned(), value.into()));
+    self
+  }
+
+  /// Create the table of the module.
+  fn build(&self, state: &mut ExeState) -> Value {
+    let mut table = Table::new(0, self.functions.len() + self.constants.len());
+    for (name, f) in &self.functions {
+      table.set(name.as_str().into(), Value::Function(*f));
+    }
+    for (name, value) in &self.constants {
+      table.set(name.as_str().into(), value.clone());
+    }
+    state.new_table(table)
+  }
+}
+
+/// ## PackageState
+///
+/// State of the library: the `package` table, the tables of loaded modules
+/// and preloaders which `require` uses even if they are replaced in
+/// `package`, the modules being loaded, and the native modules registered.
+pub(crate) struct PackageState {
+  package: Value,
+  loaded: Option<Rc<RefCell<Table>>>,
+  preload: Option<Rc<RefCell<Table>>>,
+  /// Names of the modules being loaded, to detect the loops
+  loading: Vec<Vec<u8>>,
+  modules: HashMap<Vec<u8>, ModuleBuilder>,
+}
+
+impl Default for PackageState {
+  fn default() -> Self {
+    Self {
+      package: Value::Nil,
+      loaded: None,
+      preload: None,
+      loading: Vec::new(),
+      modules: HashMap::new(),
+    }
+  }
+}
+
+impl ExeState {
+  /// Register the native module `name`, which `require(name)` loads by
+  /// building it on the first call, and caches in `package.loaded`.
+  ///
+  /// A module registered again replaces the previous one, but not the one
+  /// already loaded.
+  pub fn register_module(&mut self, name: &str, module: ModuleBuilder) {
+    for (fname, f) in &module.functions {
+      self
+        .native_names
+        .insert(*f as usize, format!("{name}.{fname}"));
+    }
+    self
+      .package
+      .modules
+      .insert(name.as_bytes().to_vec(), module);
+  }
+}
+
+/// Create a table managed by the collector, and return it as the `Rc` too.
+fn new_table(state: &mut ExeState, table: Table) -> (Value, Rc<RefCell<Table>>) {
+  match state.new_table(table) {
+    Value::Table(t) => (Value::Table(t.clone()), t),
+    _ => unreachable!(),
+  }
+}
+
+/// Value of `package.path` from the environment variable `var` (suffixed
+/// by the version, or not), with `;;` replaced by the default path, or the
+/// default path if not set, as `setpath`.
+fn env_path(var: &str, default: &str) -> String {
+  let path = env::var(format!("{var}{VERSION_SUFFIX}")).or_else(|_| env::var(var));
+  let Ok(path) = path else {
+    return default.to_owned();
+  };
+  let double_sep = PATH_SEP.repeat(2);
+  match path.find(&double_sep) {
+    None => path,
+    Some(i) => {
+      let (prefix, suffix) = (&path[..i], &path[i + 2..]);
+      let mut result = String::new();
+      if !prefix.is_empty() {
+        result.push_str(prefix);
+        result.push_str(PATH_SEP);
+      }
+      result.push_str(default);
+      if !suffix.is_empty() {
+        result.push_str(PATH_SEP);
+        result.push_str(suffix);
+      }
+      result
+    }
+  }
+}
+
+/// Search `name` in `path`, a list of templates separated by `;`, whose
+/// `?` is replaced by `name` with `sep` replaced by `dir_sep`. Return the
+/// first readable file, or the message listing the files tried.
+fn search_path(name: &[u8], path: &[u8], sep: &[u8], dir_sep: &[u8]) -> Result<Vec<u8>, Vec<u8>> {
+  let name = match sep {
+    [] => name.to_vec(),
+    sep => replace(name, sep, dir_sep),
+  };
+  let path = replace(path, PATH_MARK.as_bytes(), &name);
+  let found = path
+    .split(|&c| c == PATH_SEP.as_bytes()[0])
+    .filter(|filename| !filename.is_empty())
+    .find(|filename| File::open(&*String::from_utf8_lossy(filename)).is_ok());
+  match found {
+    Some(filename) => Ok(filename.to_vec()),
+    None => {
+      let mut msg = b"no file '".to_vec();
+      msg.extend(replace(&path, PATH_SEP.as_bytes(), b"'\n\tno file '"));
+      msg.push(b'\'');
+      Err(msg)
+    }
+  }
+}
+
+/// Replace all `pattern` in `s` by `replacement`, as `luaL_gsub`.
+fn replace(s: &[u8], pattern: &[u8], replacement: &[u8]) -> Vec<u8> {
+  let mut result = Vec::with_capacity(s.len());
+  let mut i = 0;
+  while i < s.len() {
+    match s[i..].starts_with(pattern) {
+      true => {
+        result.extend_from_slice(replacement);
+        i += pattern.len();
+      }
+      false => {
+        result.push(s[i]);
+        i += 1;
+      }
+    }
+  }
+  result
+}
+
+/// `package.searchpath(name, path [, sep [, rep]])`
+///
+/// The first readable file for `name` in `path`, or `fail` and the files
+/// tried.
+pub(crate) fn lib_searchpath(state: &mut ExeState) -> Result<i32, LuaError> {
+  let name = state.check_string(1)?;
+  let path = state.check_string(2)?;
+  let sep = state.opt_string(3, ".")?;
+  let dir_sep = state.opt_string(4, DIR_SEP)?;
+  match search_path(&name, &path, &sep, &dir_sep) {
+    Ok(filename) => {
+      state.push(filename);
+      Ok(1)
+    }
+    Err(msg) => {
+      state.push(Value::Nil);
+      state.push(msg);
+      Ok(2)
+    }
+  }
+}
+
+/// Searcher of the loaders in `package.preload`.
+fn searcher_preload(state: &mut ExeState) -> Result<i32, LuaError> {
+  let name = state.check_string(1)?;
+  let loader = match &state.package.preload {
+    Some(preload) => preload.borrow().get(&name.clone().into()),
+    None => Value::Nil,
+  };
+  match loader {
+    Value::Nil => {
+      let name = String::from_utf8_lossy(&name);
+      state.push(format!("no field package.preload['{name}']"));
+      Ok(1)
+    }
+    loader => {
+      state.push(loader);
+      state.push(":preload:");
+      Ok(2)
+    }
+  }
+}
+
+/// Searcher of the Lua files in `package.path`. The loader is the chunk of
+/// the file, and the file name is the extra value.
+fn searcher_lua(state: &mut ExeState) -> Result<i32, LuaError> {
+  let name = state.check_string(1)?;
+  let package = state.package.package.clone();
+  let path = state.get_table(&package, &"path".into())?;
+  let path = match &path {
+    v if v.is_str() => <&[u8]>::from(v).to_vec(),
+    v => match arith::number_to_bytes(v) {
+      Some(path) => path,
+      None => return Err(state.error("'package.path' must be a string")),
+    },
+  };
+  let filename = match search_path(&name, &path, b".", DIR_SEP.as_bytes()) {
+    Ok(filename) => filename,
+    Err(msg) => {
+      state.push(msg);
+      return Ok(1);
+    }
+  };
+  let filename_str = String::from_utf8_lossy(&filename).into_owned();
+  let proto = base::read_chunk_file(Some(&filename_str))
+    .and_then(|(chunk, chunkname)| base::compile_chunk(&chunk, &chunkname, b"bt"));
+  match proto {
+    Ok(proto) => {
+      let globals = state.globals();
+      let f = state.main_closure(&proto, globals);
+      state.push(f);
+      state.push(filename);
+      Ok(2)
+    }
+    Err(msg) => Err(state.error(format!(
+      "error loading module '{}' from file '{filename_str}':\n\t{msg}",
+      String::from_utf8_lossy(&name)
+    ))),
+  }
+}
+
+/// Loader of the native modules, which builds the module named by the first
+/// argument.
+fn native_loader(state: &mut ExeState) -> Result<i32, LuaError> {
+  let name = state.check_string(1)?;
+  let module = match state.package.modules.get(&name) {
+    Some(module) => module.clone(),
+    None => {
+      let name = String::from_utf8_lossy(&name);
+      return Err(state.error(format!("native module '{name}' is not registered")));
+    }
+  };
+  let module = module.build(state);
+  state.push(module);
+  Ok(1)
+}
+
+/// Searcher of the native modules registered by the host.
+fn searcher_native(state: &mut ExeState) -> Result<i32, LuaError> {
+  let name = state.check_string(1)?;
+  match state.package.modules.contains_key(&name) {
+    true => {
+      state.push(Value::Function(native_loader));
+      state.push(":native:");
+      Ok(2)
+    }
+    false => {
+      let name = String::from_utf8_lossy(&name);
+      state.push(format!("no native module '{name}'"));
+      Ok(1)
+    }
+  }
+}
+
+/// Find the loader of `name` by `package.searchers`, and return it with its
+/// extra value.
+fn find_loader(state: &mut ExeState, name: &[u8]) -> Result<(Value, Value), LuaError> {
+  let package = state.package.package.clone();
+  let searchers = match state.get_table(&package, &"searchers".into())? {
+    Value::Table(searchers) => searchers,
+    _ => return Err(state.error("'package.searchers' must be a table")),
+  };
+  let mut msg = Vec::new();
+  for i in 1.. {
+    let searcher = searchers.borrow().get_int(i);
+    if searcher.is_nil() {
+      break;
+    }
+    let mut results = state
+      .call(searcher, vec![name.to_vec().into()])?
+      .into_iter();
+    let loader = results.next().unwrap_or(Value::Nil);
+    let data = results.next().unwrap_or(Value::Nil);
+    match loader {
+      loader if loader.is_function() => return Ok((loader, data)),
+      m if m.is_str() => {
+        msg.extend_from_slice(b"\n\t");
+        msg.extend_from_slice(<&[u8]>::from(&m));
+      }
+      m => {
+        if let Some(m) = arith::number_to_bytes(&m) {
+          msg.extend_from_slice(b"\n\t");
+          msg.extend(m);
+        }
+      }
+    }
+  }
+  Err(state.error(format!(
+    "module '{}' not found:{}",
+    String::from_utf8_lossy(name),
+    String::from_utf8_lossy(&msg)
+  )))
+}
+
+/// `require(modname)`
+///
+/// Load the module `modname` if not yet, and return it with the extra
+/// value from its searcher. The loader is called with `modname` and the
+/// extra value, as the file name for Lua files, and its result is saved
+/// in `package.loaded`, `true` if it returns nothing.
+pub(crate) fn lib_require(state: &mut ExeState) -> Result<i32, LuaError> {
+  let name = state.check_string(1)?;
+  let key: Value = name.clone().into();
+  let Some(loaded) = state.package.loaded.clone() else {
+    return Err(state.error("'package.loaded' is not initialized"));
+  };
+  let module = loaded.borrow().get(&key);
+  if !module.is_falsy() {
+    state.push(module);
+    return Ok(1);
+  }
+  // a loop is not found by `package.loaded` until the module sets it
+  if state.package.loading.contains(&name) {
+    return Err(state.error(format!(
+      "loop loading module '{}'",
+      String::from_utf8_lossy(&name)
+    )));
+  }
+  let (loader, data) = find_loader(state, &name)?;
+  state.package.loading.push(name.clone());
+  let result = state.call(loader, vec![key.clone(), data.clone()]);
+  state.package.loading.retain(|n| *n != name);
+  let module = result?.into_iter().next().unwrap_or(Value::Nil);
+  if !module.is_nil() {
+    loaded.borrow_mut().set(key.clone(), module);
+  }
+  let mut module = loaded.borrow().get(&key);
+  if module.is_nil() {
+    module = Value::Boolean(true);
+    loaded.borrow_mut().set(key, module.clone());
+  }
+  state.push(module);
+  state.push(data);
+  Ok(2)
+}
+
+/// Fill the `package` table registered, and save the standard libraries
+/// in `package.loaded`.
+pub(crate) fn open_package(state: &mut ExeState) {
+  let package = state.globals.borrow().get(&"package".into());
+  let Value::Table(t) = &package else {
+    return;
+  };
+  let (loaded_value, loaded) = new_table(state, Table::new(0, STD_LIBS.len()));
+  for lib in STD_LIBS {
+    let lib_table = state.globals.borrow().get(&lib.into());
+    loaded.borrow_mut().set(lib.into(), lib_table);
+  }
+  let (preload_value, preload) = new_table(state, Table::new(0, 0));
+  let mut searchers = Table::new(3, 0);
+  searchers.set(Value::Integer(1), Value::Function(searcher_preload));
+  searchers.set(Value::Integer(2), Value::Function(searcher_lua));
+  searchers.set(Value::Integer(3), Value::Function(searcher_native));
+  let searchers = state.new_table(searchers);
+  let config = [DIR_SEP, PATH_SEP, PATH_MARK, "!", "-", ""].join("\n");
+  let mut registry = state.registry.borrow_mut();
+  registry.set("_LOADED".into(), loaded_value.clone());
+  registry.set("_PRELOAD".into(), preload_value.clone());
+  drop(registry);
+  let mut t = t.borrow_mut();
+  t.set("config".into(), config.into());
+  t.set("path".into(), env_path(PATH_VAR, PATH_DEFAULT).into());
+  t.set("loaded".into(), loaded_value);
+  t.set("preload".into(), preload_value);
+  t.set("searchers".into(), searchers);
+  drop(t);
+  state.package.package = package;
+  state.package.loaded = Some(loaded);
+  state.package.preload = Some(preload);
+}
@@ -0,0 +1,410 @@
+//! # Pattern
+//!
+//! Matcher of Lua patterns, ported from `lstrlib.c`. It works on bytes,
+//! with the character classes of the C locale.
+//!
+//! As in C, reading the pattern past its end gives `\0`, which never
+//! matches a special item.
+
+use super::*;
+
+/// Max depth of the recursion of `do_match`, as `MAXCCALLS`.
+const MAX_MATCH_DEPTH: usize = 200;
+/// Max count of captures in a pattern, as `LUA_MAXCAPTURES`.
+const MAX_CAPTURES: usize = 32;
+/// Length of a capture which is not closed yet.
+const CAP_UNFINISHED: isize = -1;
+/// Length of a position capture, as `()`.
+const CAP_POSITION: isize = -2;
+/// Escape character of patterns.
+pub(super) const L_ESC: u8 = b'%';
+/// Characters with special meanings in patterns.
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+
+/// Whether `pattern` has no special characters, so it can be searched as
+/// a plain string.
+pub(super) fn no_specials(pattern: &[u8]) -> bool {
+  !pattern.iter().any(|c| SPECIALS.contains(c))
+}
+
+/// Find `needle` in `haystack`, as `lmemfind`.
+pub(super) fn find_plain(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+  if needle.is_empty() {
+    return Some(0);
+  }
+  haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// `isspace` of the C locale, which includes `\v`.
+fn is_space(c: u8) -> bool {
+  matches!(c, b' ' | b'\t' | b'\n' | 0x0b | 0x0c | b'\r')
+}
+
+/// Whether `c` is in the class `%cl`, as `match_class`.
+fn match_class(c: u8, cl: u8) -> bool {
+  let res = match cl.to_ascii_lowercase() {
+    b'a' => c.is_ascii_alphabetic(),
+    b'c' => c.is_ascii_control(),
+    b'd' => c.is_ascii_digit(),
+    b'g' => c.is_ascii_graphic(),
+    b'l' => c.is_ascii_lowercase(),
+    b'p' => c.is_ascii_punctuation(),
+    b's' => is_space(c),
+    b'u' => c.is_ascii_uppercase(),
+    b'w' => c.is_ascii_alphanumeric(),
+    b'x' => c.is_ascii_hexdigit(),
+    _ => return cl == c,
+  };
+  // upper case classes are the complements
+  match cl.is_ascii_uppercase() {
+    true => !res,
+    false => res,
+  }
+}
+
+/// ## MatchState
+///
+/// State of matching a pattern against a subject: the captures found and
+/// the depth of the recursion.
+pub(super) struct MatchState<'a> {
+  src: &'a [u8],
+  pat: &'a [u8],
+  /// Count of the captures, closed or not
+  level: usize,
+  /// `(start, length)` of the captures
+  capture: [(usize, isize); MAX_CAPTURES],
+  /// Remaining depth of the recursion
+  depth: usize,
+}
+
+impl<'a> MatchState<'a> {
+  pub fn new(src: &'a [u8], pat: &'a [u8]) -> Self {
+    Self {
+      src,
+      pat,
+      level: 0,
+      capture: [(0, 0); MAX_CAPTURES],
+      depth: MAX_MATCH_DEPTH,
+    }
+  }
+
+  /// Reset the state before a new match, as `reprepstate`.
+  pub fn reset(&mut self) {
+    self.level = 0;
+    self.depth = MAX_MATCH_DEPTH;
+  }
+
+  /// Byte of the pattern at `p`, `\0` past the end.
+  fn pat_at(&self, p: usize) -> u8 {
+    self.pat.get(p).copied().unwrap_or(0)
+  }
+
+  fn check_capture(&self, l: u8) -> Result<usize, String> {
+    let l = l as i32 - b'1' as i32;
+    if l < 0 || l as usize >= self.level || self.capture[l as usize].1 == CAP_UNFINISHED {
+      return Err(format!("invalid capture index %{}", l + 1));
+    }
+    Ok(l as usize)
+  }
+
+  fn capture_to_close(&self) -> Result<usize, String> {
+    (0..self.level)
+      .rev()
+      .find(|&l| self.capture[l].1 == CAP_UNFINISHED)
+      .ok_or_else(|| "invalid pattern capture".to_owned())
+  }
+
+  /// End of the single character class starting at `p`.
+  fn class_end(&self, p: usize) -> Result<usize, String> {
+    let mut p = p;
+    let c = self.pat_at(p);
+    p += 1;
+    if c == L_ESC {
+      if p >= self.pat.len() {
+        return Err("malformed pattern (ends with '%')".to_owned());
+      }
+      return Ok(p + 1);
+    }
+    if c == b'[' {
+      if self.pat_at(p) == b'^' {
+        p += 1;
+      }
+      // look for a `]`
+      loop {
+        if p >= self.pat.len() {
+          return Err("malformed pattern (missing ']')".to_owned());
+        }
+        let c = self.pat[p];
+        p += 1;
+        // skip escapes, as `%]`
+        if c == L_ESC && p < self.pat.len() {
+          p += 1;
+        }
+        if self.pat_at(p) == b']' {
+          break;
+        }
+      }
+      return Ok(p + 1);
+    }
+    Ok(p)
+  }
+
+  /// Whether `c` is in the set `[...]` from `p` to `ec` (the `]`).
+  fn match_bracket_class(&self, c: u8, p: usize, ec: usize) -> bool {
+    let mut p = p;
+    let mut sig = true;
+    if self.pat_at(p + 1) == b'^' {
+      sig = false;
+      // skip the `^`
+      p += 1;
+    }
+    p += 1;
+    while p < ec {
+      if self.pat[p] == L_ESC {
+        p += 1;
+        if match_class(c, self.pat_at(p)) {
+          return sig;
+        }
+      } else if self.pat_at(p + 1) == b'-' && p + 2 < ec {
+        p += 2;
+        if self.pat[p - 2] <= c && c <= self.pat[p] {
+          return sig;
+        }
+      } else if self.pat[p] == c {
+        return sig;
+      }
+      p += 1;
+    }
+    !sig
+  }
+
+  /// Whether the byte at `s` matches the class from `p` to `ep`.
+  fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+    let Some(&c) = self.src.get(s) else {
+      return false;
+    };
+    match self.pat[p] {
+      b'.' => true,
+      L_ESC => match_class(c, self.pat_at(p + 1)),
+      b'[' => self.match_bracket_class(c, p, ep - 1),
+      pc => pc == c,
+    }
+  }
+
+  /// Match `%bxy` whose `x` is at `p`.
+  fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, String> {
+    if p + 1 >= self.pat.len() {
+      return Err("malformed pattern (missing arguments to '%b')".to_owned());
+    }
+    let (b, e) = (self.pat[p], self.pat[p + 1]);
+    if self.src.get(s) != Some(&b) {
+      return Ok(None);
+    }
+    let mut cont = 1;
+    for (i, &c) in self.src.iter().enumerate().skip(s + 1) {
+      if c == e {
+        cont -= 1;
+        if cont == 0 {
+          return Ok(Some(i + 1));
+        }
+      } else if c == b {
+        cont += 1;
+      }
+    }
+    Ok(None)
+  }
+
+  /// Match the longest repetition of the class from `p` to `ep`, then the
+  /// rest of the pattern.
+  fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, String> {
+    let mut i = 0;
+    while self.single_match(s + i, p, ep) {
+      i += 1;
+    }
+    // try with the max repetitions, then less
+    loop {
+      if let Some(res) = self.do_match(s + i, ep + 1)? {
+        return Ok(Some(res));
+      }
+      if i == 0 {
+        return Ok(None);
+      }
+      i -= 1;
+    }
+  }
+
+  /// Match the shortest repetition of the class from `p` to `ep`, then the
+  /// rest of the pattern.
+  fn min_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, String> {
+    let mut s = s;
+    loop {
+      if let Some(res) = self.do_match(s, ep + 1)? {
+        return Ok(Some(res));
+      }
+      if !self.single_match(s, p, ep) {
+        return Ok(None);
+      }
+      s += 1;
+    }
+  }
+
+  fn start_capture(&mut self, s: usize, p: usize, what: isize) -> Result<Option<usize>, String> {
+    if self.level >= MAX_CAPTURES {
+      return Err("too many captures".to_owned());
+    }
+    self.capture[self.level] = (s, what);
+    self.level += 1;
+    let res = self.do_match(s, p)?;
+    if res.is_none() {
+      // undo capture
+      self.level -= 1;
+    }
+    Ok(res)
+  }
+
+  fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+    let l = self.capture_to_close()?;
+    self.capture[l].1 = (s - self.capture[l].0) as isize;
+    let res = self.do_match(s, p)?;
+    if res.is_none() {
+      // undo capture
+      self.capture[l].1 = CAP_UNFINISHED;
+    }
+    Ok(res)
+  }
+
+  /// Match the back-reference `%l`.
+  fn match_capture(&self, s: usize, l: u8) -> Result<Option<usize>, String> {
+    let l = self.check_capture(l)?;
+    let (init, len) = self.capture[l];
+    // a position capture is never matched, as its length is huge as unsigned
+    let Ok(len) = usize::try_from(len) else {
+      return Ok(None);
+    };
+    let captured = &self.src[init..init + len];
+    match self.src.len() - s >= len && &self.src[s..s + len] == captured {
+      true => Ok(Some(s + len)),
+      false => Ok(None),
+    }
+  }
+
+  /// Match the pattern from `p` against the subject from `s`, return the
+  /// end of the match.
+  pub fn do_match(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+    if self.depth == 0 {
+      return Err("pattern too complex".to_owned());
+    }
+    self.depth -= 1;
+    let (mut s, mut p) = (s, p);
+    let res = loop {
+      if p == self.pat.len() {
+        break Some(s);
+      }
+      match self.pat[p] {
+        b'(' => {
+          break match self.pat_at(p + 1) == b')' {
+            true => self.start_capture(s, p + 2, CAP_POSITION)?,
+            false => self.start_capture(s, p + 1, CAP_UNFINISHED)?,
+          };
+        }
+        b')' => break self.end_capture(s, p + 1)?,
+        b'$' if p + 1 == self.pat.len() => {
+          break (s == self.src.len()).then_some(s);
+        }
+        L_ESC if self.pat_at(p + 1) == b'b' => match self.match_balance(s, p + 2)? {
+          Some(e) => {
+            s = e;
+            p += 4;
+            continue;
+          }
+          None => break None,
+        },
+        L_ESC if self.pat_at(p + 1) == b'f' => {
+          p += 2;
+          if self.pat_at(p) != b'[' {
+            return Err("missing '[' after '%f' in pattern".to_owned());
+          }
+          let ep = self.class_end(p)?;
+          let previous = match s {
+            0 => 0,
+            s => self.src[s - 1],
+          };
+          let current = self.src.get(s).copied().unwrap_or(0);
+          if !self.match_bracket_class(previous, p, ep - 1)
+            && self.match_bracket_class(current, p, ep - 1)
+          {
+            p = ep;
+            continue;
+          }
+          break None;
+        }
+        L_ESC if self.pat_at(p + 1).is_ascii_digit() => {
+          match self.match_capture(s, self.pat[p + 1])? {
+            Some(e) => {
+              s = e;
+              p += 2;
+              continue;
+            }
+            None => break None,
+          }
+        }
+        _ => {
+          let ep = self.class_end(p)?;
+          let suffix = self.pat_at(ep);
+          if !self.single_match(s, p, ep) {
+            // accept empty?
+            if matches!(suffix, b'*' | b'?' | b'-') {
+              p = ep + 1;
+              continue;
+            }
+            break None;
+          }
+          match suffix {
+            b'?' => match self.do_match(s + 1, ep + 1)? {
+              Some(res) => break Some(res),
+              None => {
+                p = ep + 1;
+                continue;
+              }
+            },
+            b'+' => break self.max_expand(s + 1, p, ep)?,
+            b'*' => break self.max_expand(s, p, ep)?,
+            b'-' => break self.min_expand(s, p, ep)?,
+            _ => {
+              s += 1;
+              p = ep;
+            }
+          }
+        }
+      }
+    };
+    self.depth += 1;
+    Ok(res)
+  }
+
+  /// Value of the `i`-th capture (0-based), or the whole match `s..e` if
+  /// there is no capture.
+  pub fn get_capture(&self, i: usize, s: usize, e: usize) -> Result<Value, String> {
+    if i >= self.level {
+      if i != 0 {
+        return Err(format!("invalid capture index %{}", i + 1));
+      }
+      return Ok(self.src[s..e].into());
+    }
+    match self.capture[i] {
+      (_, CAP_UNFINISHED) => Err("unfinished capture".to_owned()),
+      (init, CAP_POSITION) => Ok(Value::Integer(init as i64 + 1)),
+      (init, len) => Ok(self.src[init..init + len as usize].into()),
+    }
+  }
+
+  /// Values of all captures, or the whole match `s..e` if there is no
+  /// capture and `whole` is set.
+  pub fn get_captures(&self, s: usize, e: usize, whole: bool) -> Result<Vec<Value>, String> {
+    let n = match self.level == 0 && whole {
+      true => 1,
+      false => self.level,
+    };
+    (0..n).map(|i| self.get_capture(i, s, e)).collect()
+  }
+}
@@ -0,0 +1,746 @@
+//! # String Library
+//!
+//! String manipulation functions of Lua, which work on bytes, and pattern
+//! matching by [`MatchState`]. All strings share a metatable whose `__index`
+//! is the `string` table, so they can be called as methods, as `s:upper()`.
+
+use super::{
+  format,
+  pack::{self, Header, KOption},
+  pattern::{self, MatchState, L_ESC},
+  *,
+};
+use crate::dump;
+
+/// Max size of the strings built by the library.
+const MAX_STRING_SIZE: usize = i32::MAX as usize;
+
+/// Translate a relative initial position `pos` to an absolute one in a
+/// string of `len` bytes, clipped to `[1, inf)`, as `posrelatI`.
+fn pos_relative_init(pos: i64, len: usize) -> usize {
+  match pos {
+    p if p > 0 => p as usize,
+    0 => 1,
+    p if p < -(len as i64) => 1,
+    p => (len as i64 + p + 1) as usize,
+  }
+}
+
+/// Get the optional `i`-th argument as an end position in a string of
+/// `len` bytes, clipped to `[0, len]`, as `getendpos`.
+fn end_pos(state: &mut ExeState, i: usize, default: i64, len: usize) -> Result<usize, LuaError> {
+  let pos = state.opt_integer(i, default)?;
+  Ok(match pos {
+    p if p > len as i64 => len,
+    p if p >= 0 => p as usize,
+    p if p < -(len as i64) => 0,
+    p => (len as i64 + p + 1) as usize,
+  })
+}
+
+/// `string.len(s)`
+pub(crate) fn lib_len(state: &mut ExeState) -> Result<i32, LuaError> {
+  let s = state.check_string(1)?;
+  state.push(Value::Integer(s.len() as i64));
+  Ok(1)
+}
+
+/// `string.sub(s, i [, j])`
+pub(crate) fn lib_sub(state: &mut ExeState) -> Result<i32, LuaError> {
+  let s = state.check_string(1)?;
+  let start = pos_relative_init(state.check_integer(2)?, s.len());
+  let end = end_pos(state, 3, -1, s.len())?;
+  match start <= end {
+    true => state.push(&s[start - 1..end]),
+    false => state.push(""),
+  }
+  Ok(1)
+}
+
+/// `string.upper(s)`, in the C locale.
+pub(crate) fn lib_upper(state: &mut ExeState) -> Result<i32, LuaError> {
+  let s = state.check_string(1)?;
+  state.push(s.to_ascii_uppercase());
+  Ok(1)
+}
+
+/// `string.lower(s)`, in the C locale.
+pub(crate) fn lib_lower(state: &mut ExeState) -> Result<i32, LuaError> {
+  let s = state.check_string(1)?;
+  state.push(s.to_ascii_lowercase());
+  Ok(1)
+}
+
+/// `string.rep(s, n [, sep])`
+///
+/// `n` copies of `s` separated by `sep`.
+pub(crate) fn lib_rep(state: &mut ExeState) -> Result<i32, LuaError> {
+  let s = state.check_string(1)?;
+  let n = state.check_integer(2)?;
+  let sep = state.opt_string(3, "")?;
+  if n <= 0 {
+    state.push("");
+    return Ok(1);
+  }
+  let total = (s.len() + sep.len())
+    .checked_mul(n as usize)
+    .filter(|total| *total - sep.len() <= MAX_STRING_SIZE);
+  let Some(total) = total else {
+    return Err(state.error("resulting string too large"));
+  };
+  let mut result = Vec::with_capacity(total - sep.len());
+  for i in 0..n {
+    if i > 0 {
+      result.extend_from_slice(&sep);
+    }
+    result.extend_from_slice(&s);
+  }
+  state.push(result);
+  Ok(1)
+}
+
+/// `string.reverse(s)`
+pub(crate) fn lib_reverse(state: &mut ExeState) -> Result<i32, LuaError> {
+  let mut s = state.check_string(1)?;
+  s.reverse();
+  state.push(s);
+  Ok(1)
+}
+
+/// `string.byte(s [, i [, j]])`
+///
+/// The codes of the bytes `s[i..=j]`, where `i` defaults to 1 and `j` to `i`.
+pub(crate) fn lib_byte(state: &mut ExeState) -> Result<i32, LuaError> {
+  let s = state.check_string(1)?;
+  let start = pos_relative_init(state.opt_integer(2, 1)?, s.len());
+  let end = end_pos(state, 3, start as i64, s.len())?;
+  if start > end {
+    return Ok(0);
+  }
+  if end - start >= i32::MAX as usize {
+    return Err(state.error("string slice too long"));
+  }
+  for &c in &s[start - 1..end] {
+    state.push(Value::Integer(c as i64));
+  }
+  Ok((end - start + 1) as i32)
+}
+
+/// `string.char(...)`
+///
+/// The string of the bytes whose codes are the arguments.
+pub(crate) fn lib_char(state: &mut ExeState) -> Result<i32, LuaError> {
+  let n = state.get_top();
+  let mut s = Vec::with_capacity(n);
+  for i in 1..=n {
+    match u8::try_from(state.check_integer(i)?) {
+      Ok(c) => s.push(c),
+      Err(_) => return Err(state.arg_error(i, "value out of range")),
+    }
+  }
+  state.push(s);
+  Ok(1)
+}
+
+/// `string.dump(function [, strip])`
+///
+/// The binary chunk of a Lua function, which `load` takes back. The loaded
+/// function has fresh upvalues.
+pub(crate) fn lib_dump(state: &mut ExeState) -> Result<i32, LuaError> {
+  let strip = !state.arg(2).is_falsy();
+  match state.arg(1) {
+    Value::LuaFunction(c) => {
+      state.push(dump::dump(&c.proto, strip));
+      Ok(1)
+    }
+    f if f.is_function() => Err(state.error("unable to dump given function")),
+    _ => Err(state.type_error(1, "function")),
+  }
+}
+
+/// Split the anchor `^` off `pattern`.
+fn split_anchor(pattern: &[u8]) -> (bool, &[u8]) {
+  match pattern.strip_prefix(b"^") {
+    Some(pattern) => (true, pattern),
+    None => (false, pattern),
+  }
+}
+
+/// Push `values` as results, return the count of them.
+fn push_all(state: &mut ExeState, values: Vec<Value>) -> i32 {
+  let n = values.len() as i32;
+  state.stack.extend(values);
+  n
+}
+
+/// Common part of `string.find` and `string.match`, as `str_find_aux`.
+fn find_aux(state: &mut ExeState, find: bool) -> Result<i32, LuaError> {
+  let s = state.check_string(1)?;
+  let p = state.check_string(2)?;
+  let init = pos_relative_init(state.opt_integer(3, 1)?, s.len()) - 1;
+  if init > s.len() {
+    state.push(Value::Nil);
+    return Ok(1);
+  }
+  if find && (!state.arg(4).is_falsy() || pattern::no_specials(&p)) {
+    // do a plain search
+    if let Some(i) = pattern::find_plain(&s[init..], &p) {
+      state.push(Value::Integer((init + i + 1) as i64));
+      state.push(Value::Integer((init + i + p.len()) as i64));
+      return Ok(2);
+    }
+  } else {
+    let (anchor, pat) = split_anchor(&p);
+    let mut ms = MatchState::new(&s, pat);
+    let mut s1 = init;
+    loop {
+      ms.reset();
+      if let Some(e) = ms.do_match(s1, 0).map_err(|msg| state.error(msg))? {
+        return match find {
+          true => {
+            state.push(Value::Integer(s1 as i64 + 1));
+            state.push(Value::Integer(e as i64));
+            let captures = ms
+              .get_captures(s1, e, false)
+              .map_err(|msg| state.error(msg))?;
+            Ok(2 + push_all(state, captures))
+          }
+          false => {
+            let captures = ms
+              .get_captures(s1, e, true)
+              .map_err(|msg| state.error(msg))?;
+            Ok(push_all(state, captures))
+          }
+        };
+      }
+      s1 += 1;
+      if anchor || s1 > s.len() {
+        break;
+      }
+    }
+  }
+  state.push(Value::Nil);
+  Ok(1)
+}
+
+/// `string.find(s, pattern [, init [, plain]])`
+///
+/// The start and end of the first match of `pattern` in `s`, and its
+/// captures. `plain` turns off the pattern matching.
+pub(crate) fn lib_find(state: &mut ExeState) -> Result<i32, LuaError> {
+  find_aux(state, true)
+}
+
+/// `string.match(s, pattern [, init])`
+///
+/// The captures of the first match of `pattern` in `s`, or the whole
+/// match if there is no capture.
+pub(crate) fn lib_match(state: &mut ExeState) -> Result<i32, LuaError> {
+  find_aux(state, false)
+}
+
+/// The iterator of `string.gmatch`, whose upvalues are the subject, the
+/// pattern, the position to search from and the end of the last match.
+fn gmatch_aux(state: &mut ExeState) -> Result<i32, LuaError> {
+  let (s, p) = (state.native_upvalue(1), state.native_upvalue(2));
+  let (src, pat) = (<&[u8]>::from(&s), <&[u8]>::from(&p));
+  let start = arith::to_integer(&state.native_upvalue(3)).unwrap_or(0) as usize;
+  let last_match = arith::to_integer(&state.native_upvalue(4)).map(|e| e as usize);
+  let mut ms = MatchState::new(src, pat);
+  for s1 in start..=src.len() {
+    ms.reset();
+    match ms.do_match(s1, 0).map_err(|msg| state.error(msg))? {
+      Some(e) if Some(e) != last_match => {
+        state.set_native_upvalue(3, Value::Integer(e as i64));
+        state.set_native_upvalue(4, Value::Integer(e as i64));
+        let captures = ms
+          .get_captures(s1, e, true)
+          .map_err(|msg| state.error(msg))?;
+        return Ok(push_all(state, captures));
+      }
+      _ => (),
+    }
+  }
+  Ok(0)
+}
+
+/// `string.gmatch(s, pattern [, init])`
+///
+/// An iterator over the matches of `pattern` in `s`, which returns the
+/// captures of each match.
+pub(crate) fn lib_gmatch(state: &mut ExeState) -> Result<i32, LuaError> {
+  let s = state.check_string(1)?;
+  let p = state.check_string(2)?;
+  let init = (pos_relative_init(state.opt_integer(3, 1)?, s.len()) - 1).min(s.len());
+  let upvalues = vec![s.into(), p.into(), Value::Integer(init as i64), Value::Nil];
+  let iter = state.new_native_closure(gmatch_aux, upvalues);
+  state.push(iter);
+  Ok(1)
+}
+
+/// Append the replacement string `news` of the match `src[s..e]` to `b`,
+/// with `%0`-`%9` replaced by the captures and `%%` by `%`.
+fn add_s(
+  state: &mut ExeState,
+  ms: &MatchState,
+  b: &mut Vec<u8>,
+  (src, s, e): (&[u8], usize, usize),
+  news: &[u8],
+) -> Result<(), LuaError> {
+  let mut chars = news.iter().copied();
+  while let Some(c) = chars.next() {
+    if c != L_ESC {
+      b.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some(L_ESC) => b.push(L_ESC),
+      Some(b'0') => b.extend_from_slice(&src[s..e]),
+      Some(d) if d.is_ascii_digit() => {
+        let capture = ms
+          .get_capture((d - b'1') as usize, s, e)
+          .map_err(|msg| state.error(msg))?;
+        b.extend(state.to_lua_string(&capture)?);
+      }
+      _ => return Err(state.error("invalid use of '%' in replacement string")),
+    }
+  }
+  Ok(())
+}
+
+/// Append the replacement of the match `src[s..e]` to `b`, return whether
+/// it's changed, as `add_value`.
+fn add_value(
+  state: &mut ExeState,
+  ms: &MatchState,
+  b: &mut Vec<u8>,
+  (src, s, e): (&[u8], usize, usize),
+  repl: &Value,
+) -> Result<bool, LuaError> {
+  let value = match repl {
+    Value::Table(_) => {
+      let key = ms.get_capture(0, s, e).map_err(|msg| state.error(msg))?;
+      state.get_table(repl, &key)?
+    }
+    f if f.is_function() => {
+      let captures = ms
+        .get_captures(s, e, true)
+        .map_err(|msg| state.error(msg))?;
+      let results = state.call(f.clone(), captures)?;
+      results.into_iter().next().unwrap_or(Value::Nil)
+    }
+    news => {
+      let news = match news {
+        Value::Integer(_) | Value::Float(_) => arith::number_to_bytes(news).unwrap(),
+        news => <&[u8]>::from(news).to_vec(),
+      };
+      add_s(state, ms, b, (src, s, e), &news)?;
+      return Ok(true);
+    }
+  };
+  match value {
+    // keep the original text
+    v if v.is_falsy() => {
+      b.extend_from_slice(&src[s..e]);
+      Ok(false)
+    }
+    v if v.is_str() => {
+      b.extend_from_slice((&v).into());
+      Ok(true)
+    }
+    v @ (Value::Integer(_) | Value::Float(_)) => {
+      b.extend(arith::number_to_bytes(&v).unwrap());
+      Ok(true)
+    }
+    v => Err(state.error(format!("invalid replacement value (a {})", v.type_name()))),
+  }
+}
+
+/// `string.gsub(s, pattern, repl [, n])`
+///
+/// Replace the first `n` (all by default) matches of `pattern` in `s` by
+/// `repl`, which is a string with captures `%1`-`%9`, a table indexed by
+/// the first capture, or a function called with the captures. Return the
+/// result and the count of the matches.
+pub(crate) fn lib_gsub(state: &mut ExeState) -> Result<i32, LuaError> {
+  let src = state.check_string(1)?;
+  let p = state.check_string(2)?;
+  let repl = state.arg(3);
+  if !matches!(repl, Value::Integer(_) | Value::Float(_) | Value::Table(_))
+    && !repl.is_str()
+    && !repl.is_function()
+  {
+    return Err(state.type_error(3, "string/function/table"));
+  }
+  let max_n = state.opt_integer(4, src.len() as i64 + 1)?;
+  let (anchor, pat) = split_anchor(&p);
+  let mut ms = MatchState::new(&src, pat);
+  let mut b = vec![];
+  let (mut s, mut last_match) = (0, None);
+  let (mut n, mut changed) = (0, false);
+  while n < max_n {
+    ms.reset();
+    match ms.do_match(s, 0).map_err(|msg| state.error(msg))? {
+      Some(e) if Some(e) != last_match => {
+        n += 1;
+        changed |= add_value(state, &ms, &mut b, (&src, s, e), &repl)?;
+        s = e;
+        last_match = Some(e);
+      }
+      // otherwise, skip one character
+      _ if s < src.len() => {
+        b.push(src[s]);
+        s += 1;
+      }
+      _ => break,
+    }
+    if anchor {
+      break;
+    }
+  }
+  let result = match changed {
+    true => {
+      b.extend_from_slice(&src[s..]);
+      b.into()
+    }
+    false => state.arg(1),
+  };
+  state.push(result);
+  state.push(Value::Integer(n));
+  Ok(2)
+}
+
+/// Max length of a conversion specification, as `MAX_FORMAT`.
+const MAX_FORMAT: usize = 32;
+
+/// Append `s` quoted as a Lua string literal, as `addquoted`.
+fn add_quoted(b: &mut Vec<u8>, s: &[u8]) {
+  b.push(b'"');
+  for (i, &c) in s.iter().enumerate() {
+    match c {
+      b'"' | b'\\' | b'\n' => b.extend([b'\\', c]),
+      c if c.is_ascii_control() => {
+        // keep the escape from taking the following digits
+        let escaped = match s.get(i + 1) {
+          Some(next) if next.is_ascii_digit() => format!("\\{c:03}"),
+          _ => format!("\\{c}"),
+        };
+        b.extend(escaped.into_bytes());
+      }
+      c => b.push(c),
+    }
+  }
+  b.push(b'"');
+}
+
+/// Append the `i`-th argument as a literal which reads back to the same
+/// value, for `%q`, as `addliteral`.
+fn add_literal(state: &mut ExeState, b: &mut Vec<u8>, i: usize) -> Result<(), LuaError> {
+  match state.arg(i) {
+    s if s.is_str() => add_quoted(b, (&s).into()),
+    // hexadecimal, otherwise it reads back as a float
+    Value::Integer(i64::MIN) => b.extend_from_slice(b"0x8000000000000000"),
+    Value::Integer(n) => b.extend(n.to_string().into_bytes()),
+    Value::Float(f) if f == f64::INFINITY => b.extend_from_slice(b"1e9999"),
+    Value::Float(f) if f == f64::NEG_INFINITY => b.extend_from_slice(b"-1e9999"),
+    Value::Float(f) if f.is_nan() => b.extend_from_slice(b"(0/0)"),
+    Value::Float(f) => b.extend(format::Spec::hex_float().format_float(f)),
+    v @ (Value::Nil | Value::Boolean(_)) => b.extend(v.to_string().into_bytes()),
+    _ => return Err(state.arg_error(i, "value has no literal form")),
+  }
+  Ok(())
+}
+
+/// `string.format(formatstring, ...)`
+///
+/// Format the arguments as C `printf`, with `%q` for Lua literals and `%s`
+/// converting any value by `tostring`.
+pub(crate) fn lib_format(state: &mut ExeState) -> Result<i32, LuaError> {
+  let fmt = state.check_string(1)?;
+  let top = state.get_top();
+  let mut arg = 1;
+  let mut b = vec![];
+  let mut i = 0;
+  while i < fmt.len() {
+    let c = fmt[i];
+    i += 1;
+    if c != b'%' {
+      b.push(c);
+      continue;
+    }
+    if fmt.get(i) == Some(&b'%') {
+      b.push(b'%');
+      i += 1;
+      continue;
+    }
+    arg += 1;
+    if arg > top {
+      return Err(state.arg_error(arg, "no value"));
+    }
+    // flags, width and precision, and the conversion following them
+    let len = fmt[i..]
+      .iter()
+      .take_while(|c| b"-+ #0123456789.".contains(c))
+      .count()
+      + 1;
+    if len >= MAX_FORMAT - 10 {
+      return Err(state.error("invalid format string to 'format'"));
+    }
+    let form = &fmt[i..(i + len).min(fmt.len())];
+    i += len;
+    let form_str = || format!("%{}", String::from_utf8_lossy(form));
+    let conversion = match i <= fmt.len() {
+      true => fmt[i - 1],
+      false => b'\0',
+    };
+    let parse = |state: &mut ExeState, flags, precision| {
+      format::Spec::parse(form, flags, precision).ok_or_else(|| {
+        state.error(format!(
+          "invalid conversion specification: '{}'",
+          form_str()
+        ))
+      })
+    };
+    match conversion {
+      b'c' => {
+        let n = state.check_integer(arg)?;
+        b.extend(parse(state, format::FLAGS_CHAR, false)?.format_char(n as u8));
+      }
+      b'd' | b'i' | b'u' | b'o' | b'x' | b'X' => {
+        let n = state.check_integer(arg)?;
+        let flags = match conversion {
+          b'd' | b'i' => format::FLAGS_INT,
+          b'u' => format::FLAGS_UNSIGNED,
+          _ => format::FLAGS_HEX,
+        };
+        b.extend(parse(state, flags, true)?.format_integer(n));
+      }
+      b'a' | b'A' | b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+        let n = state.check_number(arg)?;
+        let f = arith::to_float(&n).unwrap();
+        b.extend(parse(state, format::FLAGS_FLOAT, true)?.format_float(f));
+      }
+      b'p' => {
+        let spec = parse(state, format::FLAGS_CHAR, false)?;
+        let pointer = match state.arg(arg) {
+          Value::Table(t) => format!("{:p}", Rc::as_ptr(&t)),
+          Value::LuaFunction(f) => format!("{:p}", Rc::as_ptr(&f)),
+          Value::NativeClosure(f) => format!("{:p}", Rc::as_ptr(&f)),
+          Value::Function(f) => format!("{:p}", f as *const ()),
+          Value::LongStr(s) => format!("{:p}", Rc::as_ptr(&s)),
+          Value::ShortStr(s) => format!("{:p}", Rc::as_ptr(&s)),
+          Value::UserData(u) => format!("{:p}", Rc::as_ptr(&u)),
+          Value::LightUserData(p) => format!("{p:p}"),
+          _ => "(null)".to_owned(),
+        };
+        b.extend(spec.format_string(pointer.as_bytes()));
+      }
+      b'q' => {
+        if form.len() > 1 {
+          return Err(state.error("specifier '%q' cannot have modifiers"));
+        }
+        add_literal(state, &mut b, arg)?;
+      }
+      b's' => {
+        let v = state.arg(arg);
+        let s = state.to_lua_string(&v)?;
+        if form.len() == 1 {
+          // keep the entire string
+          b.extend(s);
+          continue;
+        }
+        if s.contains(&0) {
+          return Err(state.arg_error(arg, "string contains zeros"));
+        }
+        let spec = parse(state, format::FLAGS_CHAR, true)?;
+        // no precision and the string is too long to be formatted
+        if !form.contains(&b'.') && s.len() >= 100 {
+          b.extend(s);
+        } else {
+          b.extend(spec.format_string(&s));
+        }
+      }
+      _ => {
+        return Err(state.error(format!("invalid conversion '{}' to 'format'", form_str())));
+      }
+    }
+  }
+  state.push(b);
+  Ok(1)
+}
+
+/// `string.pack(fmt, v1, v2, ...)`
+///
+/// Serialize the values into a binary string as `fmt` says.
+pub(crate) fn lib_pack(state: &mut ExeState) -> Result<i32, LuaError> {
+  let fmt = state.check_string(1)?;
+  let mut h = Header::new(&fmt);
+  let mut b = vec![];
+  let mut arg = 1;
+  while !h.is_end() {
+    let (opt, size, to_align) = h.next_option(state, b.len())?;
+    b.resize(b.len() + to_align, 0);
+    arg += 1;
+    match opt {
+      KOption::Int => {
+        let n = state.check_integer(arg)?;
+        if size < 8 {
+          let lim = 1i64 << (size * 8 - 1);
+          if !(-lim..lim).contains(&n) {
+            return Err(state.arg_error(arg, "integer overflow"));
+          }
+        }
+        pack::pack_int(&mut b, n as u64, h.little, size, n < 0);
+      }
+      KOption::Uint => {
+        let n = state.check_integer(arg)?;
+        if size < 8 && (n as u64) >= 1 << (size * 8) {
+          return Err(state.arg_error(arg, "unsigned overflow"));
+        }
+        pack::pack_int(&mut b, n as u64, h.little, size, false);
+      }
+      KOption::Float => {
+        let f = arith::to_float(&state.check_number(arg)?).unwrap() as f32;
+        b.extend(match h.little {
+          true => f.to_le_bytes(),
+          false => f.to_be_bytes(),
+        });
+      }
+      KOption::Number | KOption::Double => {
+        let f = arith::to_float(&state.check_number(arg)?).unwrap();
+        b.extend(match h.little {
+          true => f.to_le_bytes(),
+          false => f.to_be_bytes(),
+        });
+      }
+      KOption::Char => {
+        let s = state.check_string(arg)?;
+        if s.len() > size {
+          return Err(state.arg_error(arg, "string longer than given size"));
+        }
+        b.extend_from_slice(&s);
+        // pad extra space
+        b.resize(b.len() + size - s.len(), 0);
+      }
+      KOption::String => {
+        let s = state.check_string(arg)?;
+        if size < 8 && s.len() as u64 >= 1 << (size * 8) {
+          return Err(state.arg_error(arg, "string length does not fit in given size"));
+        }
+        pack::pack_int(&mut b, s.len() as u64, h.little, size, false);
+        b.extend_from_slice(&s);
+      }
+      KOption::Zstr => {
+        let s = state.check_string(arg)?;
+        if s.contains(&0) {
+          return Err(state.arg_error(arg, "string contains zeros"));
+        }
+        b.extend_from_slice(&s);
+        b.push(0);
+      }
+      KOption::Padding => {
+        b.push(0);
+        arg -= 1;
+      }
+      KOption::PaddAlign | KOption::Nop => arg -= 1,
+    }
+  }
+  state.push(b);
+  Ok(1)
+}
+
+/// `string.packsize(fmt)`
+///
+/// Size of the strings packed by `fmt`, which has no variable-length
+/// option.
+pub(crate) fn lib_packsize(state: &mut ExeState) -> Result<i32, LuaError> {
+  let fmt = state.check_string(1)?;
+  let mut h = Header::new(&fmt);
+  let mut total = 0;
+  while !h.is_end() {
+    let (opt, size, to_align) = h.next_option(state, total)?;
+    if opt == KOption::String || opt == KOption::Zstr {
+      return Err(state.arg_error(1, "variable-length format"));
+    }
+    let size = size + to_align;
+    if total > pack::MAX_SIZE - size {
+      return Err(state.arg_error(1, "format result too large"));
+    }
+    total += size;
+  }
+  state.push(Value::Integer(total as i64));
+  Ok(1)
+}
+
+/// `string.unpack(fmt, s [, pos])`
+///
+/// Deserialize the values in `s` from `pos` as `fmt` says, and return
+/// them and the position after them.
+pub(crate) fn lib_unpack(state: &mut ExeState) -> Result<i32, LuaError> {
+  let fmt = state.check_string(1)?;
+  let data = state.check_string(2)?;
+  let mut pos = pos_relative_init(state.opt_integer(3, 1)?, data.len()) - 1;
+  if pos > data.len() {
+    return Err(state.arg_error(3, "initial position out of string"));
+  }
+  let mut h = Header::new(&fmt);
+  let mut n = 0;
+  while !h.is_end() {
+    let (opt, size, to_align) = h.next_option(state, pos)?;
+    if to_align + size > data.len() - pos {
+      return Err(state.arg_error(2, "data string too short"));
+    }
+    // skip alignment
+    pos += to_align;
+    let bytes = &data[pos..pos + size];
+    let value = match opt {
+      KOption::Int | KOption::Uint => {
+        let signed = opt == KOption::Int;
+        Value::Integer(pack::unpack_int(state, bytes, h.little, signed)?)
+      }
+      KOption::Float => {
+        let bytes = bytes.try_into().unwrap();
+        Value::Float(match h.little {
+          true => f32::from_le_bytes(bytes),
+          false => f32::from_be_bytes(bytes),
+        } as f64)
+      }
+      KOption::Number | KOption::Double => {
+        let bytes = bytes.try_into().unwrap();
+        Value::Float(match h.little {
+          true => f64::from_le_bytes(bytes),
+          false => f64::from_be_bytes(bytes),
+        })
+      }
+      KOption::Char => bytes.into(),
+      KOption::String => {
+        let len = pack::unpack_int(state, bytes, h.little, false)? as u64;
+        if len > (data.len() - pos - size) as u64 {
+          return Err(state.arg_error(2, "data string too short"));
+        }
+        let start = pos + size;
+        // skip the string
+        pos += len as usize;
+        data[start..start + len as usize].into()
+      }
+      KOption::Zstr => {
+        let Some(len) = data[pos..].iter().position(|&c| c == 0) else {
+          return Err(state.arg_error(2, "unfinished string for format 'z'"));
+        };
+        let s = data[pos..pos + len].into();
+        // skip the string and the final `\0`
+        pos += len + 1;
+        s
+      }
+      KOption::Padding | KOption::PaddAlign | KOption::Nop => {
+        pos += size;
+        continue;
+      }
+    };
+    state.push(value);
+    n += 1;
+    pos += size;
+  }
+  state.push(Value::Integer(pos as i64 + 1));
+  Ok(n + 1)
+}
@@ -0,0 +1,337 @@
+//! # Table Library
+//!
+//! Table manipulation functions of Lua, which access the tables with
+//! metamethods as Lua does.
+
+use super::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Check the `i`-th argument is a table, or has all the metamethods of
+/// `events`, as `checktab`.
+fn check_tab(state: &mut ExeState, i: usize, events: &[&str]) -> Result<Value, LuaError> {
+  let t = state.arg(i);
+  if let Value::Table(_) = t {
+    return Ok(t);
+  }
+  if events.iter().all(|e| !state.get_metafield(&t, e).is_nil()) {
+    return Ok(t);
+  }
+  Err(state.type_error(i, "table"))
+}
+
+/// Check the `i`-th argument as `check_tab`, and return its length.
+fn table_len(state: &mut ExeState, i: usize, events: &[&str]) -> Result<(Value, i64), LuaError> {
+  let mut events = events.to_vec();
+  events.push("__len");
+  let t = check_tab(state, i, &events)?;
+  let n = state.obj_len(&t)?;
+  Ok((t, n))
+}
+
+/// `table.insert(list, [pos,] value)`
+pub(crate) fn lib_insert(state: &mut ExeState) -> Result<i32, LuaError> {
+  let (t, n) = table_len(state, 1, &["__index", "__newindex"])?;
+  // first empty element
+  let e = n.wrapping_add(1);
+  let pos = match state.get_top() {
+    2 => e,
+    3 => {
+      let pos = state.check_integer(2)?;
+      // check 1 <= pos <= e
+      if (pos as u64).wrapping_sub(1) >= e as u64 {
+        return Err(state.arg_error(2, "position out of bounds"));
+      }
+      for i in (pos + 1..=e).rev() {
+        let v = state.get_table(&t, &Value::Integer(i - 1))?;
+        state.set_table(&t, Value::Integer(i), v)?;
+      }
+      pos
+    }
+    _ => return Err(state.error("wrong number of arguments to 'insert'")),
+  };
+  let value = state.arg(state.get_top());
+  state.set_table(&t, Value::Integer(pos), value)?;
+  Ok(0)
+}
+
+/// `table.remove(list [, pos])`
+pub(crate) fn lib_remove(state: &mut ExeState) -> Result<i32, LuaError> {
+  let (t, size) = table_len(state, 1, &["__index", "__newindex"])?;
+  let mut pos = state.opt_integer(2, size)?;
+  // validate `pos` if given, which can be `size + 1`
+  if pos != size && (pos as u64).wrapping_sub(1) > size as u64 {
+    return Err(state.arg_error(2, "position out of bounds"));
+  }
+  let result = state.get_table(&t, &Value::Integer(pos))?;
+  while pos < size {
+    let v = state.get_table(&t, &Value::Integer(pos + 1))?;
+    state.set_table(&t, Value::Integer(pos), v)?;
+    pos += 1;
+  }
+  state.set_table(&t, Value::Integer(pos), Value::Nil)?;
+  state.push(result);
+  Ok(1)
+}
+
+/// `table.move(a1, f, e, t [, a2])`
+///
+/// Move `a1[f..=e]` to `a2[t..]`, in the direction which works for
+/// overlapping ranges.
+pub(crate) fn lib_move(state: &mut ExeState) -> Result<i32, LuaError> {
+  let f = state.check_integer(2)?;
+  let e = state.check_integer(3)?;
+  let t = state.check_integer(4)?;
+  let tt = if state.arg(5).is_nil() { 1 } else { 5 };
+  let src = check_tab(state, 1, &["__index"])?;
+  let dst = check_tab(state, tt, &["__newindex"])?;
+  if e >= f {
+    if !(f > 0 || e < i64::MAX + f) {
+      return Err(state.arg_error(3, "too many elements to move"));
+    }
+    let n = e - f;
+    if t > i64::MAX - n {
+      return Err(state.arg_error(4, "destination wrap around"));
+    }
+    if t > e || t <= f || (tt != 1 && src != dst) {
+      for i in 0..=n {
+        let v = state.get_table(&src, &Value::Integer(f + i))?;
+        state.set_table(&dst, Value::Integer(t + i), v)?;
+      }
+    } else {
+      for i in (0..=n).rev() {
+        let v = state.get_table(&src, &Value::Integer(f + i))?;
+        state.set_table(&dst, Value::Integer(t + i), v)?;
+      }
+    }
+  }
+  state.push(dst);
+  Ok(1)
+}
+
+/// `table.concat(list [, sep [, i [, j]]])`
+pub(crate) fn lib_concat(state: &mut ExeState) -> Result<i32, LuaError> {
+  let (t, n) = table_len(state, 1, &["__index"])?;
+  let sep = state.opt_string(2, "")?;
+  let i = state.opt_integer(3, 1)?;
+  let last = state.opt_integer(4, n)?;
+  let mut buf = vec![];
+  let mut i = i;
+  while i <= last {
+    let v = state.get_table(&t, &Value::Integer(i))?;
+    let bytes = match &v {
+      v if v.is_str() => <&[u8]>::from(v).to_vec(),
+      v => match arith::number_to_bytes(v) {
+        Some(bytes) => bytes,
+        None => {
+          let msg = format!("invalid value (at index {i}) in table for 'concat'",);
+          return Err(state.error(msg));
+        }
+      },
+    };
+    buf.extend(bytes);
+    if i == last {
+      break;
+    }
+    buf.extend_from_slice(&sep);
+    i += 1;
+  }
+  state.push(buf);
+  Ok(1)
+}
+
+/// `table.pack(...)`
+///
+/// Return a new table with all arguments, and their count in field `n`.
+pub(crate) fn lib_pack(state: &mut ExeState) -> Result<i32, LuaError> {
+  let n = state.get_top();
+  let mut table = Table::new(n, 1);
+  table
+    .array
+    .extend(state.stack.drain(state.func_index + 1..));
+  table.set("n".into(), Value::Integer(n as i64));
+  let t = state.new_table(table);
+  state.push(t);
+  Ok(1)
+}
+
+/// `table.unpack(list [, i [, j]])`
+pub(crate) fn lib_unpack(state: &mut ExeState) -> Result<i32, LuaError> {
+  let t = state.arg(1);
+  let i = state.opt_integer(2, 1)?;
+  let e = match state.arg(3) {
+    Value::Nil => state.obj_len(&t)?,
+    _ => state.check_integer(3)?,
+  };
+  if i > e {
+    return Ok(0);
+  }
+  let n = (e as u64).wrapping_sub(i as u64);
+  if n >= i32::MAX as u64 || n >= MAX_UNPACK {
+    return Err(state.error("too many results to unpack"));
+  }
+  for k in i..=e {
+    let v = state.get_table(&t, &Value::Integer(k))?;
+    state.push(v);
+  }
+  Ok(n as i32 + 1)
+}
+
+/// Max count of values `unpack` returns, as the limit of Lua stack size.
+const MAX_UNPACK: u64 = 1_000_000;
+
+/// Partitions shorter than it use the middle element as pivot, as
+/// `RANLIMIT`.
+const RANLIMIT: i64 = 100;
+
+/// State of `table.sort`: the table and the order function.
+struct Sort<'a> {
+  state: &'a mut ExeState,
+  t: Value,
+  comp: Option<Value>,
+}
+
+impl Sort<'_> {
+  fn get(&mut self, i: i64) -> Result<Value, LuaError> {
+    self.state.get_table(&self.t, &Value::Integer(i))
+  }
+
+  fn set(&mut self, i: i64, v: Value) -> Result<(), LuaError> {
+    self.state.set_table(&self.t, Value::Integer(i), v)
+  }
+
+  /// `a[i], a[j] = vi, vj`
+  fn set2(&mut self, i: i64, vi: Value, j: i64, vj: Value) -> Result<(), LuaError> {
+    self.set(i, vi)?;
+    self.set(j, vj)
+  }
+
+  /// `a < b` by the order function, or the `<` operator.
+  fn less(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+    match &self.comp {
+      Some(comp) => {
+        let results = self.state.call(comp.clone(), vec![a.clone(), b.clone()])?;
+        Ok(results.first().is_some_and(|v| !v.is_falsy()))
+      }
+      None => self.state.less_than(a, b),
+    }
+  }
+
+  fn invalid_order(&mut self) -> LuaError {
+    self.state.error("invalid order function for sorting")
+  }
+
+  /// Partition `a[lo..=up]` with pivot `p`, which is at `up - 1`, as
+  /// `partition` of Lua.
+  fn partition(&mut self, lo: i64, up: i64, p: &Value) -> Result<i64, LuaError> {
+    let (mut i, mut j) = (lo, up - 1);
+    // invariant: a[lo..=i] <= P <= a[j..=up], a[up - 1] == P
+    loop {
+      // repeat i += 1 while a[i] < P
+      let mut vi = self.get(i + 1)?;
+      i += 1;
+      while self.less(&vi, p)? {
+        if i == up - 1 {
+          // a[i] < P but a[up - 1] == P
+          return Err(self.invalid_order());
+        }
+        i += 1;
+        vi = self.get(i)?;
+      }
+      // repeat j -= 1 while P < a[j]
+      j -= 1;
+      let mut vj = self.get(j)?;
+      while self.less(p, &vj)? {
+        if j < i {
+          // j < i but a[j] > P
+          return Err(self.invalid_order());
+        }
+        j -= 1;
+        vj = self.get(j)?;
+      }
+      if j < i {
+        // swap the pivot a[up - 1] with a[i]
+        self.set2(up - 1, vi, i, p.clone())?;
+        return Ok(i);
+      }
+      self.set2(i, vj, j, vi)?;
+    }
+  }
+
+  /// Sort `a[lo..=up]`, as `auxsort` of Lua: quicksort with the median of
+  /// 3 as pivot, which is randomized for unbalanced partitions.
+  fn sort(&mut self, mut lo: i64, mut up: i64, mut rnd: u64) -> Result<(), LuaError> {
+    while lo < up {
+      // sort a[lo], a[p] and a[up]
+      let (vlo, vup) = (self.get(lo)?, self.get(up)?);
+      if self.less(&vup, &vlo)? {
+        self.set2(lo, vup, up, vlo)?;
+      }
+      if up - lo == 1 {
+        break;
+      }
+      let p = if up - lo < RANLIMIT || rnd == 0 {
+        lo + (up - lo) / 2
+      } else {
+        let r4 = (up - lo) / 4;
+        (rnd % (r4 as u64 * 2)) as i64 + lo + r4
+      };
+      let (vp, vlo) = (self.get(p)?, self.get(lo)?);
+      if self.less(&vp, &vlo)? {
+        self.set2(p, vlo, lo, vp)?;
+      } else {
+        let vup = self.get(up)?;
+        if self.less(&vup, &vp)? {
+          self.set2(p, vup, up, vp)?;
+        }
+      }
+      if up - lo == 2 {
+        break;
+      }
+      // move the pivot to a[up - 1]
+      let pivot = self.get(p)?;
+      let v = self.get(up - 1)?;
+      self.set2(p, v, up - 1, pivot.clone())?;
+      let p = self.partition(lo, up, &pivot)?;
+      // recurse into the smaller interval, and loop for the larger one
+      let n;
+      if p - lo < up - p {
+        self.sort(lo, p - 1, rnd)?;
+        n = p - lo;
+        lo = p + 1;
+      } else {
+        self.sort(p + 1, up, rnd)?;
+        n = up - p;
+        up = p - 1;
+      }
+      if (up - lo) / 128 > n {
+        rnd = random_pivot();
+      }
+    }
+    Ok(())
+  }
+}
+
+/// A random seed for choosing pivots, as `l_randomizePivot`.
+fn random_pivot() -> u64 {
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default();
+  now.as_secs().wrapping_add(now.subsec_nanos() as u64)
+}
+
+/// `table.sort(list [, comp])`
+pub(crate) fn lib_sort(state: &mut ExeState) -> Result<i32, LuaError> {
+  let (t, n) = table_len(state, 1, &["__index", "__newindex"])?;
+  if n > 1 {
+    if n >= i32::MAX as i64 {
+      return Err(state.arg_error(1, "array too big"));
+    }
+    let comp = match state.arg(2) {
+      Value::Nil => None,
+      f if f.is_function() => Some(f),
+      _ => return Err(state.type_error(2, "function")),
+    };
+    Sort { state, t, comp }.sort(1, n, 0)?;
+  }
+  Ok(0)
+}
@@ -0,0 +1,250 @@
+//! # UTF-8 Library
+//!
+//! Functions of Lua over strings of UTF-8, which handle the characters
+//! rather than the bytes, as `lutf8lib.c`. Strict functions accept only
+//! code points up to `0x10FFFF` and not surrogates; with `lax`, the
+//! sequences of up to 6 bytes of the original UTF-8 are accepted too.
+
+use super::*;
+use crate::lex::lexing_methods::utf8_encode;
+
+/// Max code point of Unicode.
+const MAX_UNICODE: u32 = 0x10FFFF;
+/// Max code point of the original UTF-8 of up to 6 bytes.
+const MAX_UTF: u32 = 0x7FFFFFFF;
+/// Message of invalid sequences, as `MSGInvalid`.
+const MSG_INVALID: &str = "invalid UTF-8 code";
+/// Pattern matching exactly one UTF-8 sequence, as `UTF8PATT`.
+pub(crate) const CHAR_PATTERN: &[u8] = b"[\0-\x7F\xC2-\xFD][\x80-\xBF]*";
+
+/// Whether the byte at `i` is a continuation byte. The end of `s` is not.
+fn is_cont(s: &[u8], i: usize) -> bool {
+  s.get(i).is_some_and(|c| c & 0xC0 == 0x80)
+}
+
+/// Translate a relative position `pos` to an absolute one in a string of
+/// `len` bytes, `0` if it's before the start, as `u_posrelat`.
+fn pos_relative(pos: i64, len: usize) -> i64 {
+  match pos {
+    p if p >= 0 => p,
+    p if p.unsigned_abs() > len as u64 => 0,
+    p => len as i64 + p + 1,
+  }
+}
+
+/// Decode the sequence at the start of `s`, return the code point and the
+/// length, or `None` if it's invalid, as `utf8_decode`.
+fn decode(s: &[u8], strict: bool) -> Option<(u32, usize)> {
+  const LIMITS: [u32; 6] = [u32::MAX, 0x80, 0x800, 0x10000, 0x200000, 0x4000000];
+  let mut c = *s.first()? as u32;
+  let (code, len) = match c < 0x80 {
+    true => (c, 1),
+    false => {
+      let mut code = 0;
+      let mut count = 0;
+      // while it needs continuation bytes
+      while c & 0x40 != 0 {
+        count += 1;
+        if !is_cont(s, count) {
+          return None;
+        }
+        code = (code << 6) | (s[count] as u32 & 0x3F);
+        c <<= 1;
+      }
+      // add the bits from the first byte
+      code |= (c & 0x7F) << (count * 5);
+      if count > 5 || code > MAX_UTF || code < LIMITS[count] {
+        return None;
+      }
+      (code, count + 1)
+    }
+  };
+  if strict && (code > MAX_UNICODE || (0xD800..=0xDFFF).contains(&code)) {
+    return None;
+  }
+  Some((code, len))
+}
+
+/// `utf8.char(...)`
+///
+/// The string of the UTF-8 sequences of the code points.
+pub(crate) fn lib_char(state: &mut ExeState) -> Result<i32, LuaError> {
+  let mut s = Vec::new();
+  for i in 1..=state.get_top() {
+    let code = state.check_integer(i)? as u64;
+    if code > MAX_UTF as u64 {
+      return Err(state.arg_error(i, "value out of range"));
+    }
+    utf8_encode(code as u32, &mut s);
+  }
+  state.push(s);
+  Ok(1)
+}
+
+/// `utf8.codepoint(s [, i [, j [, lax]]])`
+///
+/// The code points of the characters starting between byte positions `i`
+/// and `j`, both `i` by default.
+pub(crate) fn lib_codepoint(state: &mut ExeState) -> Result<i32, LuaError> {
+  let s = state.check_string(1)?;
+  let start = pos_relative(state.opt_integer(2, 1)?, s.len());
+  let end = pos_relative(state.opt_integer(3, start)?, s.len());
+  let strict = state.arg(4).is_falsy();
+  if start < 1 {
+    return Err(state.arg_error(2, "out of bounds"));
+  }
+  if end > s.len() as i64 {
+    return Err(state.arg_error(3, "out of bounds"));
+  }
+  if start > end {
+    return Ok(0);
+  }
+  if end - start >= i32::MAX as i64 {
+    return Err(state.error("string slice too long"));
+  }
+  let (mut i, end) = (start as usize - 1, end as usize);
+  let mut n = 0;
+  while i < end {
+    let Some((code, len)) = decode(&s[i..], strict) else {
+      return Err(state.error(MSG_INVALID));
+    };
+    state.push(Value::Integer(code as i64));
+    i += len;
+    n += 1;
+  }
+  Ok(n)
+}
+
+/// `utf8.len(s [, i [, j [, lax]]])`
+///
+/// Count of the characters starting between byte positions `i` and `j`,
+/// or `fail` and the position of the first invalid byte.
+pub(crate) fn lib_len(state: &mut ExeState) -> Result<i32, LuaError> {
+  let s = state.check_string(1)?;
+  let start = pos_relative(state.opt_integer(2, 1)?, s.len());
+  let end = pos_relative(state.opt_integer(3, -1)?, s.len());
+  let strict = state.arg(4).is_falsy();
+  if start < 1 || start - 1 > s.len() as i64 {
+    return Err(state.arg_error(2, "initial position out of bounds"));
+  }
+  if end > s.len() as i64 {
+    return Err(state.arg_error(3, "final position out of bounds"));
+  }
+  let mut i = start - 1;
+  let mut n = 0;
+  while i < end {
+    match decode(&s[i as usize..], strict) {
+      Some((_, len)) => i += len as i64,
+      None => {
+        state.push(Value::Nil);
+        state.push(Value::Integer(i + 1));
+        return Ok(2);
+      }
+    }
+    n += 1;
+  }
+  state.push(Value::Integer(n));
+  Ok(1)
+}
+
+/// `utf8.offset(s, n [, i])`
+///
+/// Byte position where the `n`-th character counted from position `i`
+/// starts, or `fail` if there is no such character. The `0`-th is the one
+/// containing byte `i`.
+pub(crate) fn lib_offset(state: &mut ExeState) -> Result<i32, LuaError> {
+  let s = state.check_string(1)?;
+  let mut n = state.check_integer(2)?;
+  let default = if n >= 0 { 1 } else { s.len() as i64 + 1 };
+  let pos = pos_relative(state.opt_integer(3, default)?, s.len());
+  if pos < 1 || pos - 1 > s.len() as i64 {
+    return Err(state.arg_error(3, "position out of bounds"));
+  }
+  let mut i = pos as usize - 1;
+  if n == 0 {
+    // find the start of the current sequence
+    while i > 0 && is_cont(&s, i) {
+      i -= 1;
+    }
+  } else {
+    if is_cont(&s, i) {
+      return Err(state.error("initial position is a continuation byte"));
+    }
+    if n < 0 {
+      while n < 0 && i > 0 {
+        // find the start of the previous character
+        i -= 1;
+        while i > 0 && is_cont(&s, i) {
+          i -= 1;
+        }
+        n += 1;
+      }
+    } else {
+      // not move for the first character
+      n -= 1;
+      while n > 0 && i < s.len() {
+        // find the start of the next character
+        i += 1;
+        while is_cont(&s, i) {
+          i += 1;
+        }
+        n -= 1;
+      }
+    }
+  }
+  match n {
+    0 => state.push(Value::Integer(i as i64 + 1)),
+    _ => state.push(Value::Nil),
+  }
+  Ok(1)
+}
+
+/// Iterator of `codes`, over string `s` from the character after position
+/// `n`, as `iter_aux`.
+fn codes_aux(state: &mut ExeState, strict: bool) -> Result<i32, LuaError> {
+  let s = state.check_string(1)?;
+  // a negative `n` is huge, as unsigned
+  let mut n = state.check_integer(2)? as u64 as usize;
+  while n < s.len() && is_cont(&s, n) {
+    n += 1;
+  }
+  if n >= s.len() {
+    return Ok(0);
+  }
+  match decode(&s[n..], strict) {
+    Some((code, len)) if !is_cont(&s, n + len) => {
+      state.push(Value::Integer(n as i64 + 1));
+      state.push(Value::Integer(code as i64));
+      Ok(2)
+    }
+    _ => Err(state.error(MSG_INVALID)),
+  }
+}
+
+fn codes_aux_strict(state: &mut ExeState) -> Result<i32, LuaError> {
+  codes_aux(state, true)
+}
+
+fn codes_aux_lax(state: &mut ExeState) -> Result<i32, LuaError> {
+  codes_aux(state, false)
+}
+
+/// `utf8.codes(s [, lax])`
+///
+/// Iterate over the characters of `s`, with their positions and code
+/// points.
+pub(crate) fn lib_codes(state: &mut ExeState) -> Result<i32, LuaError> {
+  let lax = !state.arg(2).is_falsy();
+  let s = state.check_string(1)?;
+  if is_cont(&s, 0) {
+    return Err(state.arg_error(1, MSG_INVALID));
+  }
+  let iter: RustFunction = match lax {
+    true => codes_aux_lax,
+    false => codes_aux_strict,
+  };
+  state.push(Value::Function(iter));
+  state.push(s);
+  state.push(Value::Integer(0));
+  Ok(3)
+}